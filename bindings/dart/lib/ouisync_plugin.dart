@@ -240,11 +240,15 @@ class PeerInfo {
   final PeerStateKind state;
   final String? runtimeId;
 
+  /// Runtime id of the peer that announced this one via peer exchange, if any.
+  final String? introducedBy;
+
   PeerInfo({
     required this.addr,
     required this.source,
     required this.state,
     this.runtimeId,
+    this.introducedBy,
   });
 
   static PeerInfo decode(Object? raw) {
@@ -266,11 +270,17 @@ class PeerInfo {
       throw Exception('invalid peer info state');
     }
 
+    final rawIntroducedBy = list.length > 3 ? list[3] : null;
+    final introducedBy = rawIntroducedBy != null
+        ? HEX.encode(rawIntroducedBy as Uint8List)
+        : null;
+
     return PeerInfo(
       addr: addr,
       source: source,
       state: state,
       runtimeId: runtimeId,
+      introducedBy: introducedBy,
     );
   }
 
@@ -279,7 +289,8 @@ class PeerInfo {
 
   @override
   String toString() =>
-      '$runtimeType(addr: $addr, source: $source, state: $state, runtimeId: $runtimeId)';
+      '$runtimeType(addr: $addr, source: $source, state: $state, '
+      'runtimeId: $runtimeId, introducedBy: $introducedBy)';
 }
 
 /// A reference to a ouisync repository.
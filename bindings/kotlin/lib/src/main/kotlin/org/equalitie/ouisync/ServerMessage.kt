@@ -8,6 +8,7 @@ data class PeerInfo(
     val source: PeerSource,
     val state: PeerStateKind,
     val runtimeId: String?,
+    val introducedBy: String?,
 ) {
     companion object {
         fun unpack(unpacker: MessageUnpacker): PeerInfo {
@@ -43,7 +44,22 @@ data class PeerInfo(
                 else -> throw InvalidResponse()
             }
 
-            return PeerInfo(addr, source, state, runtimeId)
+            var introducedBy: String? = null
+
+            if (count > 3 && !unpacker.tryUnpackNil()) {
+                val length = unpacker.unpackBinaryHeader()
+                val introducedByBytes = unpacker.readPayload(length)
+
+                @OptIn(kotlin.ExperimentalStdlibApi::class)
+                introducedBy = introducedByBytes.toHexString()
+            }
+
+            // Skip fields added in newer versions.
+            for (i in 4 until count) {
+                unpacker.skipValue()
+            }
+
+            return PeerInfo(addr, source, state, runtimeId, introducedBy)
         }
     }
 }
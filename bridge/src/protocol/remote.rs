@@ -1,5 +1,5 @@
 use crate::transport::TransportError;
-use ouisync_lib::ShareToken;
+use ouisync_lib::{BlockId, RawBlock, RepositoryId, ShareToken};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -7,11 +7,26 @@ use thiserror::Error;
 pub enum Request {
     /// Mirror repository on a remote server
     Mirror { share_token: ShareToken },
+    /// Find out which blocks the mirror is missing: those referenced from the part of its index
+    /// synced so far but not yet received. Responds with `BlockIds` containing a page of their
+    /// ids in ascending order, starting after `after`. An empty page means there are no more.
+    MirrorPushQuery {
+        repository_id: RepositoryId,
+        after: Option<BlockId>,
+    },
+    /// Push blocks the mirror is missing. Responds with `BlockIds` containing the ids of the
+    /// blocks that were acknowledged (stored or already present). Blocks not referenced from the
+    /// index of the mirror are not acknowledged and are discarded.
+    MirrorPush {
+        repository_id: RepositoryId,
+        blocks: Vec<RawBlock>,
+    },
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum Response {
     None,
+    BlockIds(Vec<BlockId>),
 }
 
 impl From<()> for Response {
@@ -30,4 +45,8 @@ pub enum ServerError {
     Transport(#[from] TransportError),
     #[error("failed to create repository: {0}")]
     CreateRepository(String),
+    #[error("repository not found")]
+    RepositoryNotFound,
+    #[error("repository error: {0}")]
+    Repository(String),
 }
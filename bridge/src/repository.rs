@@ -7,7 +7,7 @@ use crate::{
 use futures_util::future;
 use ouisync_lib::{
     crypto::Password, Access, AccessMode, AccessSecrets, LocalSecret, ReopenToken, Repository,
-    RepositoryParams, ShareToken, StorageSize,
+    RepositoryParams, ShareToken, StorageSize, StoreError,
 };
 use state_monitor::StateMonitor;
 use std::{borrow::Cow, io, path::PathBuf, sync::Arc, time::Duration};
@@ -34,6 +34,10 @@ pub enum MirrorError {
     Connect(#[source] io::Error),
     #[error("server responded with error")]
     Server(#[source] ServerError),
+    #[error("unexpected response from server")]
+    UnexpectedResponse,
+    #[error("repository error")]
+    Repository(#[from] ouisync_lib::Error),
 }
 
 /// Creates a new repository and set access to it based on the following table:
@@ -303,13 +307,25 @@ pub async fn mirror(
             match client.invoke(request).await.map_err(MirrorError::Server) {
                 Ok(Response::None) => {
                     tracing::info!(host, "mirror request successfull");
-                    Ok(())
+                }
+                Ok(_) => {
+                    tracing::error!(host, "mirror request failed: unexpected response");
+                    return Err(MirrorError::UnexpectedResponse);
                 }
                 Err(error) => {
                     tracing::error!(host, ?error, "mirror request failed");
-                    Err(error)
+                    return Err(error);
                 }
             }
+
+            // The server now pulls the repository from us. Pushing the blocks it's missing on top
+            // of that speeds it up. If it fails (e.g., because the server doesn't support it), the
+            // pull still happens.
+            if let Err(error) = push_mirror(repository, &client).await {
+                tracing::warn!(host, ?error, "mirror push failed");
+            }
+
+            Ok(())
         }
     });
 
@@ -322,6 +338,75 @@ pub async fn mirror(
     }
 }
 
+/// Pushes the blocks of the repository to its mirror on the server. The mirror must already exist
+/// (see [`mirror`]).
+///
+/// The server pulls the index of the repository from us in the background and the push follows
+/// it: the server lists the blocks referenced from the part of its index synced so far that it's
+/// still missing, and we send those we have, page by page. The server acknowledges the blocks it
+/// stored, which are then no longer listed, so an interrupted push resumes where it stopped. The
+/// blocks referenced from the part of the index that's not synced yet are pushed by the next call
+/// (or pulled by the server eventually).
+pub async fn push_mirror(
+    repository: &Repository,
+    client: &RemoteClient,
+) -> Result<(), MirrorError> {
+    let repository_id = *repository.secrets().id();
+    let mut after = None;
+
+    loop {
+        let missing = match client
+            .invoke(Request::MirrorPushQuery {
+                repository_id,
+                after,
+            })
+            .await
+            .map_err(MirrorError::Server)?
+        {
+            Response::BlockIds(ids) => ids,
+            _ => return Err(MirrorError::UnexpectedResponse),
+        };
+
+        let Some(last) = missing.last().copied() else {
+            break;
+        };
+
+        after = Some(last);
+
+        let mut blocks = Vec::with_capacity(missing.len());
+
+        for id in missing {
+            match repository.read_raw_block(&id).await {
+                Ok(block) => blocks.push(block),
+                // We don't have it either.
+                Err(ouisync_lib::Error::Store(StoreError::BlockNotFound)) => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        if blocks.is_empty() {
+            continue;
+        }
+
+        let sent = blocks.len();
+        let acked = match client
+            .invoke(Request::MirrorPush {
+                repository_id,
+                blocks,
+            })
+            .await
+            .map_err(MirrorError::Server)?
+        {
+            Response::BlockIds(ids) => ids.len(),
+            _ => return Err(MirrorError::UnexpectedResponse),
+        };
+
+        tracing::trace!(sent, acked, "mirror push");
+    }
+
+    Ok(())
+}
+
 fn strip_port(s: &str) -> &str {
     if let Some(index) = s.rfind(':') {
         &s[..index]
@@ -310,6 +310,7 @@ mod tests {
             .unwrap()
         {
             Response::None => (),
+            _ => panic!("unexpected response"),
         }
 
         assert_eq!(handler.received(), 1);
@@ -339,6 +340,7 @@ mod tests {
             .unwrap()
         {
             Response::None => (),
+            _ => panic!("unexpected response"),
         }
 
         assert_eq!(handler.received(), 1);
@@ -1,19 +1,31 @@
 use crate::{
     handler::local::LocalHandler,
-    options::Dirs,
+    options::{Dirs, OutputFormat},
     protocol::{Error, Request, Response},
     state::State,
     transport::{local::LocalClient, native::NativeClient},
 };
-use anyhow::Result;
+use anyhow::{format_err, Result};
 use state_monitor::StateMonitor;
 use std::{
     io,
     path::{Path, PathBuf},
+    time::Duration,
 };
-use tokio::io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::{
+    io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader},
+    select, signal, time,
+};
+
+// How often to refresh the output of `stats --watch`.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
 
-pub(crate) async fn run(dirs: Dirs, socket: PathBuf, request: Request) -> Result<()> {
+pub(crate) async fn run(
+    dirs: Dirs,
+    socket: PathBuf,
+    format: OutputFormat,
+    request: Request,
+) -> Result<()> {
     let client = connect(&socket, &dirs).await?;
 
     let request = match request {
@@ -53,17 +65,103 @@ pub(crate) async fn run(dirs: Dirs, socket: PathBuf, request: Request) -> Result
                 password,
             }
         }
+        Request::Cat {
+            name,
+            path,
+            version,
+            ..
+        } => {
+            let result = cat(&client, name, path, version).await;
+            client.close().await;
+            return result;
+        }
+        Request::Stats { repo, watch: true } => {
+            let result = watch_stats(&client, format, repo).await;
+            client.close().await;
+            return result;
+        }
         _ => request,
     };
 
     let response = client.invoke(request).await?;
-    println!("{response}");
+    print_response(format, &response)?;
 
     client.close().await;
 
     Ok(())
 }
 
+fn print_response(format: OutputFormat, response: &Response) -> Result<()> {
+    match format {
+        OutputFormat::Text => println!("{response}"),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&response.to_json())?),
+    }
+
+    Ok(())
+}
+
+/// Prints the stats repeatedly until interrupted.
+async fn watch_stats(client: &Client, format: OutputFormat, repo: Option<String>) -> Result<()> {
+    loop {
+        let request = Request::Stats {
+            repo: repo.clone(),
+            watch: true,
+        };
+
+        let response = client.invoke(request).await?;
+
+        // Clear the screen so the stats are always printed at the same place.
+        if let OutputFormat::Text = format {
+            print!("\x1b[2J\x1b[H");
+        }
+
+        print_response(format, &response)?;
+
+        select! {
+            _ = time::sleep(WATCH_INTERVAL) => (),
+            _ = signal::ctrl_c() => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// Streams the content of the file to stdout, chunk by chunk.
+async fn cat(client: &Client, name: String, path: String, version: Option<String>) -> Result<()> {
+    let mut stdout = stdout();
+    let mut handle = None;
+
+    loop {
+        let request = Request::Cat {
+            name: name.clone(),
+            path: path.clone(),
+            version: version.clone(),
+            handle,
+        };
+
+        let content = match client.invoke(request).await? {
+            Response::Chunk {
+                handle: new_handle,
+                content,
+            } => {
+                handle = Some(new_handle);
+                content
+            }
+            _ => return Err(format_err!("unexpected response")),
+        };
+
+        if content.is_empty() {
+            break;
+        }
+
+        stdout.write_all(&content).await?;
+    }
+
+    stdout.flush().await?;
+
+    Ok(())
+}
+
 async fn connect(path: &Path, dirs: &Dirs) -> Result<Client> {
     match LocalClient::connect(path).await {
         Ok(client) => Ok(Client::Local(client)),
@@ -10,6 +10,9 @@ use std::{
 };
 use tokio::{fs::File, io::AsyncReadExt};
 
+// Path to the geo ip database, relative to the config store root.
+pub(crate) const GEO_IP_PATH: &str = "GeoLite2-Country.mmdb";
+
 pub(crate) struct GeoIp {
     path: PathBuf,
     reader: Option<(Reader<Vec<u8>>, SystemTime)>,
@@ -1,21 +1,44 @@
 use crate::{
-    protocol::{Error, QuotaInfo, Request, Response},
+    geo_ip::{GeoIp, GEO_IP_PATH},
+    protocol::{EntryDetails, EntryInfo, Error, PeerDetails, QuotaInfo, Request, Response, Stats},
     repository::{self, RepositoryHolder, RepositoryName, OPEN_ON_START},
     state::State,
 };
 use async_trait::async_trait;
+use camino::Utf8PathBuf;
 use ouisync_bridge::{network, transport::NotificationSender};
-use ouisync_lib::{PeerAddr, ShareToken};
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use ouisync_lib::{
+    crypto::sign::PublicKey,
+    network::{Network, PeerState},
+    File, JointEntryRef, PeerAddr, Repository, ShareToken, StoreError, BLOCK_SIZE,
+};
+use serde_json::{json, Map};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+// Max size of the file content returned by a single `Cat` request.
+const CAT_CHUNK_SIZE: usize = BLOCK_SIZE;
+
+// Files opened by `Cat` requests are closed when they haven't been read from for this long, in
+// case the client went away before reading them to the end.
+const CAT_FILE_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
 #[derive(Clone)]
 pub(crate) struct LocalHandler {
     state: Arc<State>,
+    cat_files: Arc<Mutex<CatFiles>>,
 }
 
 impl LocalHandler {
     pub fn new(state: Arc<State>) -> Self {
-        Self { state }
+        Self {
+            state,
+            cat_files: Arc::default(),
+        }
     }
 
     pub async fn close(&self) {
@@ -36,6 +59,10 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
     ) -> Result<Self::Response, Self::Error> {
         match request {
             Request::Start { .. } => unimplemented!(),
+            // Generated locally by the client, never sent to the server.
+            Request::Completions { .. } => Err(Error::new(
+                "shell completions can't be generated by the server",
+            )),
             Request::BindRpc { addrs } => Ok(self
                 .state
                 .rpc_servers
@@ -222,15 +249,21 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
 
                 Ok(().into())
             }
-            Request::Mirror { name, host } => {
+            Request::Mirror { name, host, watch } => {
                 let holder = self.state.repositories.find(&name)?;
                 let config = self.state.get_client_config().await?;
 
-                holder.mirror(&host, config).await?;
+                if watch {
+                    holder.watch_mirror(host, config).await?;
+                } else {
+                    holder.mirror(&host, config).await?;
+                }
 
                 Ok(().into())
             }
-            Request::ListRepositories => {
+            // `ls` without a repository name keeps listing the repositories, as it did before it
+            // became an alias of `list-entries`.
+            Request::ListRepositories | Request::ListEntries { name: None, .. } => {
                 let names: Vec<_> = self
                     .state
                     .repositories
@@ -240,6 +273,76 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
                     .collect();
                 Ok(names.into())
             }
+            Request::ListEntries {
+                name: Some(name),
+                path,
+                recursive,
+                long,
+            } => {
+                let holder = self.state.repositories.find(&name)?;
+                let entries = list_entries(&holder.repository, &path, recursive, long).await?;
+
+                Ok(entries.into())
+            }
+            Request::Cat {
+                name,
+                path,
+                version,
+                handle,
+            } => {
+                let (handle, mut file) = if let Some(handle) = handle {
+                    let file = self
+                        .cat_files
+                        .lock()
+                        .unwrap()
+                        .take(handle)
+                        .ok_or_else(|| Error::new("invalid file handle"))?;
+
+                    (handle, file)
+                } else {
+                    let holder = self.state.repositories.find(&name)?;
+
+                    let file = if let Some(version) = version {
+                        let branch_id: PublicKey = version
+                            .parse()
+                            .map_err(|_| Error::new("invalid branch id"))?;
+                        holder
+                            .repository
+                            .open_file_version(&path, &branch_id)
+                            .await?
+                    } else {
+                        holder.repository.open_file(&path).await?
+                    };
+
+                    (self.cat_files.lock().unwrap().next_handle(), file)
+                };
+
+                let mut buffer = vec![0; CAT_CHUNK_SIZE];
+                let len = file.read_all(&mut buffer).await?;
+                buffer.truncate(len);
+
+                // Keep the file open for the next chunk, unless this is the end of it.
+                if len > 0 {
+                    self.cat_files.lock().unwrap().insert(handle, file);
+                }
+
+                Ok(Response::Chunk {
+                    handle,
+                    content: buffer,
+                })
+            }
+            Request::Stats { repo, watch: _ } => {
+                let holders = if let Some(name) = &repo {
+                    vec![self.state.repositories.find(name)?]
+                } else {
+                    self.state.repositories.get_all()
+                };
+
+                // Only include the network stats when not asking for a specific repository.
+                let network = repo.is_none().then_some(&self.state.network);
+
+                Ok(stats(network, &holders).await?.into())
+            }
             Request::Bind { addrs } => {
                 network::bind(&self.state.network, &self.state.config, &addrs).await;
                 Ok(().into())
@@ -308,7 +411,37 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
                 .await;
                 Ok(().into())
             }
-            Request::ListPeers => Ok(self.state.network.peer_info_collector().collect().into()),
+            Request::ListPeers { source } => {
+                let mut geo_ip = GeoIp::new(self.state.config.dir().join(GEO_IP_PATH));
+
+                // The database is optional. Without it the peers are listed without location.
+                if let Err(error) = geo_ip.refresh().await {
+                    tracing::debug!(
+                        ?error,
+                        "Failed to load GeoIP database from {}",
+                        geo_ip.path().display()
+                    );
+                }
+
+                let peers: Vec<_> = self
+                    .state
+                    .network
+                    .peer_info_collector()
+                    .collect()
+                    .into_iter()
+                    .filter(|peer| source.map_or(true, |source| peer.source == source))
+                    .map(|peer| PeerDetails {
+                        uptime: peer.uptime,
+                        country: geo_ip
+                            .lookup(peer.addr.ip())
+                            .ok()
+                            .map(|code| code.to_string()),
+                        info: peer,
+                    })
+                    .collect();
+
+                Ok(peers.into())
+            }
             Request::Dht { name, enabled } => {
                 let holder = self.state.repositories.find(&name)?;
 
@@ -399,3 +532,137 @@ impl ouisync_bridge::transport::Handler for LocalHandler {
         }
     }
 }
+
+/// Files being transferred by `Cat` requests, by their handles.
+#[derive(Default)]
+struct CatFiles {
+    files: HashMap<u64, (File, Instant)>,
+    next_handle: u64,
+}
+
+impl CatFiles {
+    fn next_handle(&mut self) -> u64 {
+        let handle = self.next_handle;
+        self.next_handle = self.next_handle.wrapping_add(1);
+        handle
+    }
+
+    fn insert(&mut self, handle: u64, file: File) {
+        self.close_idle();
+        self.files.insert(handle, (file, Instant::now()));
+    }
+
+    fn take(&mut self, handle: u64) -> Option<File> {
+        self.close_idle();
+        self.files.remove(&handle).map(|(file, _)| file)
+    }
+
+    fn close_idle(&mut self) {
+        self.files
+            .retain(|_, (_, last_used)| last_used.elapsed() < CAT_FILE_IDLE_TIMEOUT);
+    }
+}
+
+async fn list_entries(
+    repository: &Repository,
+    path: &str,
+    recursive: bool,
+    long: bool,
+) -> Result<Vec<EntryInfo>, Error> {
+    let mut entries = Vec::new();
+    let mut dirs = vec![(Utf8PathBuf::new(), repository.cd(path).await?)];
+
+    while let Some((parent, dir)) = dirs.pop() {
+        for entry in dir.entries() {
+            let path = parent.join(entry.unique_name().as_ref());
+            let entry_type = entry.entry_type();
+
+            let details = match entry {
+                JointEntryRef::File(file) => {
+                    if long {
+                        // Files that are not fully synced yet are still listed, only without size.
+                        let size = match file.open().await {
+                            Ok(file) => Some(file.len()),
+                            Err(ouisync_lib::Error::Store(StoreError::BlockNotFound)) => None,
+                            Err(error) => return Err(error.into()),
+                        };
+
+                        Some(EntryDetails {
+                            size,
+                            branch_id: Some(file.branch().id().to_string()),
+                        })
+                    } else {
+                        None
+                    }
+                }
+                JointEntryRef::Directory(subdir) => {
+                    if recursive || long {
+                        let subdir = subdir.open().await?;
+
+                        let details = long.then(|| EntryDetails {
+                            size: Some(subdir.len()),
+                            branch_id: None,
+                        });
+
+                        if recursive {
+                            dirs.push((path.clone(), subdir));
+                        }
+
+                        details
+                    } else {
+                        None
+                    }
+                }
+            };
+
+            entries.push(EntryInfo {
+                path: path.into_string(),
+                entry_type,
+                details,
+            });
+        }
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(entries)
+}
+
+async fn stats(
+    network: Option<&Network>,
+    holders: &[Arc<RepositoryHolder>],
+) -> Result<Stats, Error> {
+    let mut children = Map::new();
+
+    if let Some(network) = network {
+        let peers = network.peer_info_collector().collect();
+        let active_peers = peers
+            .iter()
+            .filter(|peer| matches!(peer.state, PeerState::Active(_)))
+            .count();
+
+        let mut node = network.monitor().to_json();
+        node["values"]["peers"] = json!(peers.len().to_string());
+        node["values"]["active_peers"] = json!(active_peers.to_string());
+
+        children.insert("Network".to_owned(), node);
+    }
+
+    let mut repositories = Map::new();
+
+    for holder in holders {
+        let progress = holder.repository.sync_progress().await?;
+
+        let mut node = holder.repository.monitor().to_json();
+        node["values"]["sync_progress"] = json!(format!("{progress} ({})", progress.percent()));
+
+        repositories.insert(holder.name().to_string(), node);
+    }
+
+    children.insert(
+        "Repositories".to_owned(),
+        json!({ "values": {}, "children": repositories }),
+    );
+
+    Ok(Stats(json!({ "values": {}, "children": children })))
+}
@@ -7,12 +7,15 @@ use ouisync_bridge::{
     protocol::remote::{Request, Response, ServerError},
     transport::NotificationSender,
 };
-use ouisync_lib::{AccessMode, RepositoryId, ShareToken};
+use ouisync_lib::{AccessMode, RepositoryId, ShareToken, StoreError};
 use std::{
     iter,
     sync::{Arc, Weak},
 };
 
+// Max number of missing block ids returned in a single response to a mirror push query.
+const MIRROR_PUSH_PAGE_SIZE: u32 = 32;
+
 #[derive(Clone)]
 pub(crate) struct RemoteHandler {
     state: Weak<State>,
@@ -98,10 +101,55 @@ impl ouisync_bridge::transport::Handler for RemoteHandler {
 
                 Ok(().into())
             }
+            Request::MirrorPushQuery {
+                repository_id,
+                after,
+            } => {
+                let holder = find(&state, &repository_id)?;
+                let missing = holder
+                    .repository
+                    .list_missing_block_ids(after, MIRROR_PUSH_PAGE_SIZE)
+                    .await
+                    .map_err(into_server_error)?;
+
+                Ok(Response::BlockIds(missing))
+            }
+            Request::MirrorPush {
+                repository_id,
+                blocks,
+            } => {
+                let holder = find(&state, &repository_id)?;
+                let mut acked = Vec::with_capacity(blocks.len());
+
+                // The blocks are verified against our index, so there is no need to trust the
+                // client.
+                for block in blocks {
+                    let id = block.id();
+
+                    match holder.repository.receive_raw_block(block).await {
+                        Ok(()) => acked.push(id),
+                        Err(ouisync_lib::Error::Store(StoreError::BlockNotReferenced)) => (),
+                        Err(error) => return Err(into_server_error(error)),
+                    }
+                }
+
+                Ok(Response::BlockIds(acked))
+            }
         }
     }
 }
 
+fn find(state: &State, repository_id: &RepositoryId) -> Result<Arc<RepositoryHolder>, ServerError> {
+    state
+        .repositories
+        .get(make_name(repository_id).as_ref())
+        .ok_or(ServerError::RepositoryNotFound)
+}
+
+fn into_server_error(error: ouisync_lib::Error) -> ServerError {
+    ServerError::Repository(error.to_string())
+}
+
 // Derive name from the hash of repository id
 fn make_name(id: &RepositoryId) -> RepositoryName {
     RepositoryName::try_from(insert_separators(
@@ -11,9 +11,10 @@ mod transport;
 mod utils;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use options::Options;
 use protocol::Request;
+use std::io;
 
 pub(crate) const APP_NAME: &str = "ouisync";
 pub(crate) const DB_EXTENSION: &str = "ouisyncdb";
@@ -22,13 +23,23 @@ pub(crate) const DB_EXTENSION: &str = "ouisyncdb";
 async fn main() -> Result<()> {
     let options = Options::parse();
 
-    if let Request::Start {
-        log_format,
-        log_color,
-    } = &options.request
-    {
-        server::run(options.dirs, options.socket, *log_format, *log_color).await
-    } else {
-        client::run(options.dirs, options.socket, options.request).await
+    match &options.request {
+        Request::Start {
+            log_format,
+            log_color,
+        } => server::run(options.dirs, options.socket, *log_format, *log_color).await,
+        Request::Completions { shell } => {
+            clap_complete::generate(*shell, &mut Options::command(), APP_NAME, &mut io::stdout());
+            Ok(())
+        }
+        _ => {
+            client::run(
+                options.dirs,
+                options.socket,
+                options.format,
+                options.request,
+            )
+            .await
+        }
     }
 }
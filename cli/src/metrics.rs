@@ -1,5 +1,5 @@
 use crate::{
-    geo_ip::{CountryCode, GeoIp},
+    geo_ip::{CountryCode, GeoIp, GEO_IP_PATH},
     state::State,
 };
 use anyhow::Result;
@@ -28,9 +28,6 @@ use tokio::task;
 const BIND_METRICS_KEY: ConfigKey<SocketAddr> =
     ConfigKey::new("bind_metrics", "Addresses to bind the metrics endpoint to");
 
-// Path to the geo ip database, relative to the config store root.
-const GEO_IP_PATH: &str = "GeoLite2-Country.mmdb";
-
 // Rate limit for metrics collection (at most once per this interval)
 const COLLECT_INTERVAL: Duration = Duration::from_secs(10);
 
@@ -1,5 +1,5 @@
 use crate::{protocol::Request, APP_NAME};
-use clap::{Args, Parser};
+use clap::{Args, Parser, ValueEnum};
 use std::{env, path::PathBuf};
 
 #[derive(Parser, Debug)]
@@ -15,10 +15,23 @@ pub(crate) struct Options {
     #[arg(short, long, default_value_os_t = default_socket(), value_name = "PATH")]
     pub socket: PathBuf,
 
+    /// Format of the command output ("text" or "json"). Doesn't affect the `cat` command which
+    /// always outputs the raw file content.
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
     #[command(subcommand)]
     pub request: Request,
 }
 
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human readable text
+    Text,
+    /// JSON with stable field names, suitable for scripting
+    Json,
+}
+
 #[derive(Args, Debug)]
 pub(crate) struct Dirs {
     /// Config directory
@@ -1,7 +1,12 @@
 use clap::{builder::BoolishValueParser, Subcommand};
+use clap_complete::Shell;
 use ouisync_bridge::logger::{LogColor, LogFormat};
-use ouisync_lib::{AccessMode, PeerAddr, PeerInfo, StorageSize};
+use ouisync_lib::{
+    network::{PeerSource, PeerState},
+    AccessMode, EntryType, PeerAddr, PeerInfo, StorageSize,
+};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::{fmt, io, net::SocketAddr, path::PathBuf, time::Duration};
 
 use crate::repository::{FindError, InvalidRepositoryName};
@@ -22,6 +27,13 @@ pub(crate) enum Request {
         #[arg(long, default_value_t)]
         log_color: LogColor,
     },
+    /// Generate shell completion script and print it to stdout
+    #[serde(skip)]
+    Completions {
+        /// Shell to generate the completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
     /// Bind the remote API to the specified addresses.
     ///
     /// Overwrites any previously specified addresses.
@@ -128,10 +140,65 @@ pub(crate) enum Request {
         /// Domain name or network address of the server to host the mirror
         #[arg(short = 'H', long)]
         host: String,
+
+        /// Keep the mirror up to date by repeating the request whenever the repository changes.
+        /// Only has effect when the server is running, and lasts until the repository is closed.
+        #[arg(short, long)]
+        watch: bool,
     },
     /// List open repositories
-    #[command(visible_alias = "ls", alias = "list-repos")]
+    #[command(visible_alias = "list-repos")]
     ListRepositories,
+    /// List entries of a directory in a repository
+    #[command(visible_alias = "ls")]
+    ListEntries {
+        /// Name of the repository. If omitted, lists the open repositories instead
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Path of the directory to list (relative to the repository root)
+        #[arg(default_value = "/")]
+        path: String,
+
+        /// List subdirectories recursively
+        #[arg(short = 'R', long)]
+        recursive: bool,
+
+        /// Show entry type, size and the branch the entry comes from
+        #[arg(short, long)]
+        long: bool,
+    },
+    /// Print contents of a file in a repository
+    Cat {
+        /// Name of the repository
+        #[arg(short, long)]
+        name: String,
+
+        /// Path of the file (relative to the repository root)
+        path: String,
+
+        /// Id of the branch whose version of the file to print. Useful when the file has multiple
+        /// conflicting versions.
+        #[arg(long, value_name = "BRANCH_ID")]
+        version: Option<String>,
+
+        /// Handle of the file being transferred. The file is transferred in chunks, each chunk
+        /// requested by a separate request. The first request opens the file and the subsequent
+        /// ones pass the handle returned by it to continue reading where the previous one stopped.
+        #[arg(skip)]
+        handle: Option<u64>,
+    },
+    /// Print sync progress, peers, job states and metrics
+    Stats {
+        /// Name of the repository to print the stats of. If omitted, prints the stats of the
+        /// network and of all open repositories.
+        #[arg(short, long)]
+        repo: Option<String>,
+
+        /// Keep refreshing the stats every second until interrupted
+        #[arg(short, long)]
+        watch: bool,
+    },
     /// Bind the sync protocol to the specified addresses
     Bind {
         /// Addresses to bind to. PROTO is one of "quic" or "tcp", IP is a IPv4 or IPv6 address and
@@ -167,8 +234,14 @@ pub(crate) enum Request {
         #[arg(required = true, value_name = "PROTO/IP:PORT")]
         addrs: Vec<PeerAddr>,
     },
-    /// List all known peers
-    ListPeers,
+    /// List all known peers, with their location (if the GeoIP database is available) and uptime
+    #[command(visible_alias = "peers")]
+    ListPeers {
+        /// Only list peers from this source ("user_provided", "listener", "local_discovery",
+        /// "dht" or "peer_exchange")
+        #[arg(long, value_parser = parse_peer_source)]
+        source: Option<PeerSource>,
+    },
     /// Enable or disable DHT
     Dht {
         #[arg(short = 'n', long)]
@@ -240,11 +313,94 @@ pub(crate) enum Response {
     Bool(bool),
     String(String),
     Strings(Vec<String>),
-    PeerInfo(Vec<PeerInfo>),
+    PeerInfo(Vec<PeerDetails>),
     SocketAddrs(Vec<SocketAddr>),
     StorageSize(StorageSize),
     QuotaInfo(QuotaInfo),
     BlockExpiration(Option<Duration>),
+    Entries(Vec<EntryInfo>),
+    Chunk { handle: u64, content: Vec<u8> },
+    Stats(Stats),
+}
+
+impl Response {
+    /// Converts the response to JSON. The field names and value formats are part of the CLI
+    /// interface and must be kept stable:
+    ///
+    /// - `None` -> `null`
+    /// - `Bool`, `String` -> boolean, string
+    /// - `Strings`, `SocketAddrs` -> array of strings
+    /// - `PeerInfo` -> array of `{"addr", "source", "state", "runtime_id", "uptime", "country",
+    ///   "introduced_by"}` (`uptime` in seconds, `null` if not active; `introduced_by` is the
+    ///   runtime id of the peer that announced this one via peer exchange, if any)
+    /// - `StorageSize` -> number of bytes
+    /// - `QuotaInfo` -> `{"quota", "size"}` (in bytes, `quota` is `null` if unlimited)
+    /// - `BlockExpiration` -> number of seconds or `null`
+    /// - `Entries` -> array of `{"path", "type", "size", "branch_id"}` (the last two only in the
+    ///   long listing)
+    /// - `Chunk` -> `{"handle", "content"}` (`content` is an array of numbers)
+    /// - `Stats` -> the state monitor tree `{"values": {<name>: <value>}, "children": {<id>: ..}}`
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::None => Value::Null,
+            Self::Bool(value) => json!(value),
+            Self::String(value) => json!(value),
+            Self::Strings(value) => json!(value),
+            Self::PeerInfo(value) => value
+                .iter()
+                .map(|details| {
+                    let peer = &details.info;
+                    let (state, runtime_id) = match peer.state {
+                        PeerState::Known => ("known", None),
+                        PeerState::Connecting => ("connecting", None),
+                        PeerState::Handshaking => ("handshaking", None),
+                        PeerState::Active(id) => ("active", Some(to_hex(id.as_ref()))),
+                    };
+
+                    json!({
+                        "addr": peer.addr.to_string(),
+                        "source": peer_source_name(peer.source),
+                        "state": state,
+                        "runtime_id": runtime_id,
+                        "uptime": details.uptime.map(|uptime| uptime.as_secs()),
+                        "country": details.country,
+                        "introduced_by": peer.introduced_by.map(|id| to_hex(id.as_ref())),
+                    })
+                })
+                .collect(),
+            Self::SocketAddrs(value) => value.iter().map(|addr| json!(addr.to_string())).collect(),
+            Self::StorageSize(value) => json!(value.to_bytes()),
+            Self::QuotaInfo(info) => json!({
+                "quota": info.quota.map(|quota| quota.to_bytes()),
+                "size": info.size.to_bytes(),
+            }),
+            Self::BlockExpiration(value) => json!(value.map(|value| value.as_secs())),
+            Self::Entries(value) => value
+                .iter()
+                .map(|entry| {
+                    let mut object = json!({
+                        "path": entry.path,
+                        "type": match entry.entry_type {
+                            EntryType::File => "file",
+                            EntryType::Directory => "directory",
+                        },
+                    });
+
+                    if let Some(details) = &entry.details {
+                        object["size"] = json!(details.size);
+                        object["branch_id"] = json!(details.branch_id);
+                    }
+
+                    object
+                })
+                .collect(),
+            Self::Chunk { handle, content } => json!({
+                "handle": handle,
+                "content": content,
+            }),
+            Self::Stats(value) => value.0.clone(),
+        }
+    }
 }
 
 impl From<()> for Response {
@@ -271,8 +427,8 @@ impl From<Vec<String>> for Response {
     }
 }
 
-impl From<Vec<PeerInfo>> for Response {
-    fn from(value: Vec<PeerInfo>) -> Self {
+impl From<Vec<PeerDetails>> for Response {
+    fn from(value: Vec<PeerDetails>) -> Self {
         Self::PeerInfo(value)
     }
 }
@@ -295,6 +451,18 @@ impl From<QuotaInfo> for Response {
     }
 }
 
+impl From<Vec<EntryInfo>> for Response {
+    fn from(value: Vec<EntryInfo>) -> Self {
+        Self::Entries(value)
+    }
+}
+
+impl From<Stats> for Response {
+    fn from(value: Stats) -> Self {
+        Self::Stats(value)
+    }
+}
+
 impl fmt::Display for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -310,7 +478,7 @@ impl fmt::Display for Response {
             }
             Self::PeerInfo(value) => {
                 for peer in value {
-                    writeln!(f, "{} ({:?}, {:?})", peer.addr, peer.source, peer.state)?;
+                    writeln!(f, "{peer}")?;
                 }
 
                 Ok(())
@@ -325,6 +493,15 @@ impl fmt::Display for Response {
             Self::StorageSize(value) => write!(f, "{value}"),
             Self::QuotaInfo(info) => write!(f, "{info}"),
             Self::BlockExpiration(info) => write!(f, "{info:?}"),
+            Self::Entries(value) => {
+                for entry in value {
+                    writeln!(f, "{entry}")?;
+                }
+
+                Ok(())
+            }
+            Self::Chunk { content, .. } => write!(f, "{}", String::from_utf8_lossy(content)),
+            Self::Stats(value) => write!(f, "{value}"),
         }
     }
 }
@@ -412,6 +589,153 @@ impl fmt::Display for QuotaInfo {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EntryInfo {
+    /// Path of the entry relative to the listed directory.
+    pub path: String,
+    pub entry_type: EntryType,
+    /// Only present in the long listing.
+    pub details: Option<EntryDetails>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EntryDetails {
+    /// Size in bytes. For directories, this is the size of the directory blob itself. `None` if
+    /// the file is not synced enough to know its size.
+    pub size: Option<u64>,
+    /// Id of the branch the entry comes from. Only for files, because directories are merged from
+    /// all the branches.
+    pub branch_id: Option<String>,
+}
+
+impl fmt::Display for EntryInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let suffix = match self.entry_type {
+            EntryType::File => "",
+            EntryType::Directory => "/",
+        };
+
+        if let Some(details) = &self.details {
+            let entry_type = match self.entry_type {
+                EntryType::File => "file",
+                EntryType::Directory => "dir",
+            };
+
+            // Abbreviate the branch id the same way as in the debug output.
+            let branch_id = details
+                .branch_id
+                .as_deref()
+                .map(|id| &id[..id.len().min(8)])
+                .unwrap_or("-");
+
+            let size = details
+                .size
+                .map(|size| size.to_string())
+                .unwrap_or_else(|| "?".to_owned());
+
+            write!(f, "{entry_type:<4} {size:>12} {branch_id:<8} ")?;
+        }
+
+        write!(f, "{}{suffix}", self.path)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct PeerDetails {
+    pub info: PeerInfo,
+    /// Duplicates `info.uptime` which is not serialized.
+    pub uptime: Option<Duration>,
+    pub country: Option<String>,
+}
+
+impl fmt::Display for PeerDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:?}, {:?})",
+            self.info.addr, self.info.source, self.info.state
+        )?;
+
+        if let Some(uptime) = self.uptime {
+            write!(f, " up {}s", uptime.as_secs())?;
+        }
+
+        if let Some(country) = &self.country {
+            write!(f, " {country}")?;
+        }
+
+        if let Some(introduced_by) = &self.info.introduced_by {
+            write!(f, " via {}", to_hex(introduced_by.as_ref()))?;
+        }
+
+        Ok(())
+    }
+}
+
+const PEER_SOURCES: [PeerSource; 5] = [
+    PeerSource::UserProvided,
+    PeerSource::Listener,
+    PeerSource::LocalDiscovery,
+    PeerSource::Dht,
+    PeerSource::PeerExchange,
+];
+
+fn peer_source_name(source: PeerSource) -> &'static str {
+    match source {
+        PeerSource::UserProvided => "user_provided",
+        PeerSource::Listener => "listener",
+        PeerSource::LocalDiscovery => "local_discovery",
+        PeerSource::Dht => "dht",
+        PeerSource::PeerExchange => "peer_exchange",
+    }
+}
+
+fn parse_peer_source(input: &str) -> Result<PeerSource, String> {
+    PEER_SOURCES
+        .into_iter()
+        .find(|source| peer_source_name(*source) == input)
+        .ok_or_else(|| format!("invalid peer source: {input}"))
+}
+
+/// Snapshot of the state monitor tree, in the form produced by `StateMonitor::to_json`, extended
+/// with the peer counts and the sync progress of each repository.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Stats(pub Value);
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_monitor_node(f, &self.0, 0)
+    }
+}
+
+fn write_monitor_node(f: &mut fmt::Formatter<'_>, node: &Value, depth: usize) -> fmt::Result {
+    let indent = depth * 2;
+
+    if let Some(values) = node["values"].as_object() {
+        for (name, value) in values {
+            // Print strings without the quotes.
+            if let Some(value) = value.as_str() {
+                writeln!(f, "{:indent$}{name}: {value}", "")?;
+            } else {
+                writeln!(f, "{:indent$}{name}: {value}", "")?;
+            }
+        }
+    }
+
+    if let Some(children) = node["children"].as_object() {
+        for (id, child) in children {
+            writeln!(f, "{:indent$}{id}", "")?;
+            write_monitor_node(f, child, depth + 1)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
 fn percent(num: u64, den: u64) -> f64 {
     if den > 0 {
         100.0 * num as f64 / den as f64
@@ -419,3 +743,92 @@ fn percent(num: u64, den: u64) -> f64 {
         0.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_json() {
+        assert_eq!(Response::None.to_json(), json!(null));
+        assert_eq!(
+            Response::Strings(vec!["foo".to_owned(), "bar".to_owned()]).to_json(),
+            json!(["foo", "bar"])
+        );
+        assert_eq!(
+            Response::QuotaInfo(QuotaInfo {
+                quota: None,
+                size: StorageSize::from_bytes(1024),
+            })
+            .to_json(),
+            json!({ "quota": null, "size": 1024 })
+        );
+        assert_eq!(
+            Response::BlockExpiration(Some(Duration::from_secs(60))).to_json(),
+            json!(60)
+        );
+        assert_eq!(
+            Response::Entries(vec![
+                EntryInfo {
+                    path: "a".to_owned(),
+                    entry_type: EntryType::Directory,
+                    details: None,
+                },
+                EntryInfo {
+                    path: "a/b.txt".to_owned(),
+                    entry_type: EntryType::File,
+                    details: Some(EntryDetails {
+                        size: Some(5),
+                        branch_id: Some("abcd".to_owned()),
+                    }),
+                },
+                EntryInfo {
+                    path: "a/c.txt".to_owned(),
+                    entry_type: EntryType::File,
+                    details: Some(EntryDetails {
+                        size: None,
+                        branch_id: Some("abcd".to_owned()),
+                    }),
+                },
+            ])
+            .to_json(),
+            json!([
+                { "path": "a", "type": "directory" },
+                { "path": "a/b.txt", "type": "file", "size": 5, "branch_id": "abcd" },
+                { "path": "a/c.txt", "type": "file", "size": null, "branch_id": "abcd" },
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_peer_source_roundtrip() {
+        for source in PEER_SOURCES {
+            assert_eq!(parse_peer_source(peer_source_name(source)), Ok(source));
+        }
+
+        assert!(parse_peer_source("foo").is_err());
+    }
+
+    #[test]
+    fn format_stats() {
+        let stats = Stats(json!({
+            "values": {},
+            "children": {
+                "Repositories": {
+                    "values": {},
+                    "children": {
+                        "foo": {
+                            "values": { "sync_progress": "1/2 (50%)" },
+                            "children": {},
+                        },
+                    },
+                },
+            },
+        }));
+
+        assert_eq!(
+            stats.to_string(),
+            "Repositories\n  foo\n    sync_progress: 1/2 (50%)\n"
+        );
+    }
+}
@@ -1,16 +1,20 @@
 use crate::{options::Dirs, utils, DB_EXTENSION};
 use anyhow::Result;
+use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
 use camino::Utf8Path;
+use futures_util::stream;
 use ouisync_bridge::{
     config::ConfigStore,
     protocol::remote::{Request, Response},
+    repository::{push_mirror, MirrorError},
     transport::RemoteClient,
 };
 use ouisync_lib::{
     network::{Network, Registration},
-    AccessMode, Repository,
+    AccessMode, Event, Payload, Repository, Throttle,
 };
 use ouisync_vfs::MountGuard;
+use scoped_task::ScopedAbortHandle;
 use state_monitor::StateMonitor;
 use std::{
     borrow::{Borrow, Cow},
@@ -19,16 +23,21 @@ use std::{
     fmt, io, mem,
     ops::{Bound, Deref},
     path::{Path, PathBuf},
+    pin::pin,
     sync::{Arc, Mutex, RwLock},
+    time::Duration,
 };
 use thiserror::Error;
-use tokio::{fs, runtime, task};
+use tokio::{fs, runtime, sync::broadcast::error::RecvError, task, time};
 use tokio_stream::StreamExt;
 
 // Config keys
 pub(crate) const OPEN_ON_START: &str = "open_on_start";
 pub(crate) const MOUNT_POINT: &str = "mount_point";
 
+// Minimal interval between two updates of a watched mirror.
+const MIRROR_UPDATE_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 pub(crate) struct RepositoryName(Arc<str>);
 
@@ -95,6 +104,7 @@ pub(crate) struct RepositoryHolder {
     pub registration: Registration,
     name: RepositoryName,
     mount: Mutex<Option<Mount>>,
+    mirror_watch: Mutex<Option<ScopedAbortHandle>>,
 }
 
 impl RepositoryHolder {
@@ -107,6 +117,7 @@ impl RepositoryHolder {
             registration,
             name,
             mount: Mutex::new(None),
+            mirror_watch: Mutex::new(None),
         }
     }
 
@@ -223,19 +234,30 @@ impl RepositoryHolder {
 
     /// Create a mirror of the repository on the given remote host.
     pub async fn mirror(&self, host: &str, config: Arc<rustls::ClientConfig>) -> Result<()> {
-        let client = RemoteClient::connect(host, config).await?;
-        let request = Request::Mirror {
-            share_token: self
-                .repository
-                .secrets()
-                .with_mode(AccessMode::Blind)
-                .into(),
-        };
-        let response = client.invoke(request).await?;
+        mirror(&self.repository, host, config).await
+    }
 
-        match response {
-            Response::None => Ok(()),
-        }
+    /// Mirrors the repository and then keeps the mirror up to date by repeating the mirror request
+    /// whenever the local branch changes. Replaces any previously started watch on this repository.
+    pub async fn watch_mirror(
+        &self,
+        host: String,
+        config: Arc<rustls::ClientConfig>,
+    ) -> Result<()> {
+        self.mirror(&host, config.clone()).await?;
+
+        let handle = task::spawn(watch_mirror(
+            self.repository.clone(),
+            self.name.clone(),
+            host,
+            config,
+        ))
+        .abort_handle()
+        .into();
+
+        *self.mirror_watch.lock().unwrap() = Some(handle);
+
+        Ok(())
     }
 
     fn resolve_mount_point(&self, mount_point: String, mount_dir: &Path) -> PathBuf {
@@ -247,6 +269,88 @@ impl RepositoryHolder {
     }
 }
 
+async fn mirror(
+    repository: &Repository,
+    host: &str,
+    config: Arc<rustls::ClientConfig>,
+) -> Result<()> {
+    let client = RemoteClient::connect(host, config).await?;
+    let request = Request::Mirror {
+        share_token: repository.secrets().with_mode(AccessMode::Blind).into(),
+    };
+    let response = client.invoke(request).await?;
+
+    match response {
+        Response::None => (),
+        _ => return Err(MirrorError::UnexpectedResponse.into()),
+    }
+
+    // Falls back to the server pulling the repository if this fails.
+    if let Err(error) = push_mirror(repository, &client).await {
+        tracing::warn!(host, ?error, "mirror push failed");
+    }
+
+    Ok(())
+}
+
+async fn watch_mirror(
+    repository: Arc<Repository>,
+    name: RepositoryName,
+    host: String,
+    config: Arc<rustls::ClientConfig>,
+) {
+    // Blind replicas have no local branch. In that case react to changes in any branch.
+    let local_branch_id = repository.local_branch().ok().map(|branch| *branch.id());
+
+    let events = stream::unfold(repository.subscribe(), move |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(Event {
+                    payload: Payload::BranchChanged(branch_id),
+                    ..
+                }) if local_branch_id.map_or(true, |id| id == branch_id) => break Some(((), rx)),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => break Some(((), rx)),
+                Err(RecvError::Closed) => break None,
+            }
+        }
+    });
+    // Coalesce changes that happened in quick succession into a single request.
+    let events = Throttle::new(events, MIRROR_UPDATE_INTERVAL);
+    let mut events = pin!(events);
+
+    while events.next().await.is_some() {
+        let mut backoff = ExponentialBackoffBuilder::new()
+            .with_initial_interval(Duration::from_secs(1))
+            .with_max_interval(Duration::from_secs(60))
+            .with_max_elapsed_time(None)
+            .build();
+
+        loop {
+            match mirror(&repository, &host, config.clone()).await {
+                Ok(()) => {
+                    tracing::info!(repo = %name, host, "mirror updated");
+                    break;
+                }
+                Err(error) => {
+                    // `unwrap` is OK because `max_elapsed_time` is `None`.
+                    let delay = backoff.next_backoff().unwrap();
+
+                    tracing::warn!(
+                        repo = %name,
+                        host,
+                        ?error,
+                        "failed to update mirror, retrying in {:?}",
+                        delay
+                    );
+
+                    time::sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
 impl Drop for RepositoryHolder {
     fn drop(&mut self) {
         let repository = self.repository.clone();
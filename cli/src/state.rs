@@ -21,7 +21,7 @@ use std::{
     sync::Arc,
     time::Duration,
 };
-use tokio::{fs, sync::OnceCell, time};
+use tokio::{fs, sync::OnceCell};
 
 pub(crate) struct State {
     pub config: ConfigStore,
@@ -100,9 +100,16 @@ impl State {
         ));
 
         let shutdown_network = async move {
-            time::timeout(Duration::from_secs(1), self.network.shutdown())
-                .await
-                .ok();
+            let report = self.network.shutdown(Duration::from_secs(1)).await;
+
+            if report.bytes_pending > 0 || report.requests_in_flight > 0 {
+                tracing::warn!(
+                    peers = report.peers_dropped,
+                    requests = report.requests_in_flight,
+                    bytes = report.bytes_pending,
+                    "sync with peers interrupted by shutdown"
+                );
+            }
         };
 
         future::join(close_repositories, shutdown_network).await;
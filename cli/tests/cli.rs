@@ -382,6 +382,79 @@ fn mirror() {
     });
 }
 
+#[test]
+fn list_entries() {
+    let a = Bin::start();
+    a.create(None);
+    a.mount();
+
+    fs::create_dir(a.root().join("dir")).unwrap();
+    fs::write(a.root().join("dir").join("foo.txt"), "hello").unwrap();
+
+    eventually(|| check_eq(a.list_entries(), ["dir/", "dir/foo.txt"]))
+}
+
+#[test]
+fn list_repositories() {
+    let a = Bin::start();
+    a.create(None);
+
+    eventually(|| check_eq(a.list_repositories(), [utils::DEFAULT_REPO]))
+}
+
+#[test]
+fn cat() {
+    let a = Bin::start();
+    a.create(None);
+    a.mount();
+
+    // Spans multiple chunks
+    let content: Vec<u8> = rand::thread_rng()
+        .sample_iter(Standard)
+        .take(3 * 32 * 1024 + 1)
+        .collect();
+    fs::write(a.root().join("foo.dat"), &content).unwrap();
+
+    eventually(|| check_eq(a.cat("foo.dat"), &content[..]))
+}
+
+#[test]
+fn stats() {
+    let a = Bin::start();
+    a.create(None);
+
+    let stats = a.stats();
+    let repo = &stats["children"]["Repositories"]["children"][utils::DEFAULT_REPO];
+
+    assert!(repo["values"]["sync_progress"].is_string(), "{stats}");
+    assert!(repo["children"].is_object(), "{stats}");
+}
+
+#[test]
+fn peers() {
+    let (_a, b) = setup();
+
+    eventually(|| {
+        let peers = b.peers("user_provided");
+        let peer = &peers[0];
+
+        if peer["state"] != "active" {
+            return Err(format_err!("peer not active: {peers}"));
+        }
+
+        check_eq(peer["uptime"].is_u64(), true)?;
+        check_eq(b.peers("dht"), serde_json::json!([]))
+    })
+}
+
+#[test]
+fn completions() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let script = utils::completions(shell);
+        assert!(script.contains("list-entries"), "{shell}");
+    }
+}
+
 fn setup() -> (Bin, Bin) {
     let a = Bin::start();
     a.bind();
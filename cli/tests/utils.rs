@@ -26,7 +26,7 @@ const COMMAND: &str = env!("CARGO_BIN_EXE_ouisync");
 const MOUNT_DIR: &str = "mnt";
 const CONFIG_DIR: &str = "config";
 const API_SOCKET: &str = "api.sock";
-const DEFAULT_REPO: &str = "test";
+pub const DEFAULT_REPO: &str = "test";
 
 static CERT: Lazy<rcgen::Certificate> =
     Lazy::new(|| rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap());
@@ -166,6 +166,93 @@ impl Bin {
         )
     }
 
+    /// List the open repositories using `ls` without a repository name
+    #[track_caller]
+    pub fn list_repositories(&self) -> Vec<String> {
+        self.ls(&[])
+    }
+
+    /// List the entries of the repository recursively
+    #[track_caller]
+    pub fn list_entries(&self) -> Vec<String> {
+        self.ls(&["--name", DEFAULT_REPO, "-R"])
+    }
+
+    #[track_caller]
+    fn ls(&self, args: &[&str]) -> Vec<String> {
+        let output = self.client_command().arg("ls").args(args).output().unwrap();
+
+        if !output.status.success() {
+            fail(&self.id, output);
+        }
+
+        str::from_utf8(&output.stdout)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(ToOwned::to_owned)
+            .collect()
+    }
+
+    /// Read the content of a file in the repository
+    #[track_caller]
+    pub fn cat(&self, path: &str) -> Vec<u8> {
+        let output = self
+            .client_command()
+            .arg("cat")
+            .arg("--name")
+            .arg(DEFAULT_REPO)
+            .arg(path)
+            .output()
+            .unwrap();
+
+        if !output.status.success() {
+            fail(&self.id, output);
+        }
+
+        output.stdout
+    }
+
+    /// Get the stats of the repository as JSON
+    #[track_caller]
+    pub fn stats(&self) -> serde_json::Value {
+        let output = self
+            .client_command()
+            .arg("--format")
+            .arg("json")
+            .arg("stats")
+            .arg("--repo")
+            .arg(DEFAULT_REPO)
+            .output()
+            .unwrap();
+
+        if !output.status.success() {
+            fail(&self.id, output);
+        }
+
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+
+    /// List the peers from the given source as JSON
+    #[track_caller]
+    pub fn peers(&self, source: &str) -> serde_json::Value {
+        let output = self
+            .client_command()
+            .arg("--format")
+            .arg("json")
+            .arg("peers")
+            .arg("--source")
+            .arg(source)
+            .output()
+            .unwrap();
+
+        if !output.status.success() {
+            fail(&self.id, output);
+        }
+
+        serde_json::from_slice(&output.stdout).unwrap()
+    }
+
     #[track_caller]
     pub fn bind_rpc(&self) -> u16 {
         let addr: SocketAddr = parse_prefixed_line(
@@ -360,6 +447,19 @@ fn fail(id: &Id, output: Output) -> ! {
     panic!("[{id}] Failed to run ouisync executable");
 }
 
+/// Generate shell completion script for the given shell.
+pub fn completions(shell: &str) -> String {
+    let output = Command::new(COMMAND)
+        .arg("completions")
+        .arg(shell)
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+
+    String::from_utf8(output.stdout).unwrap()
+}
+
 /// Runs the given closure a couple of times until it succeeds (returns `Ok`) with a short delay
 /// between attempts. Panics (with the last error) if it doesn't succeedd even after all atempts
 /// are exhausted.
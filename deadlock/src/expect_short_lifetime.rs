@@ -2,12 +2,59 @@ use super::timer::{Id, Timer};
 use once_cell::sync::Lazy;
 use std::{
     backtrace::Backtrace,
-    fmt,
+    collections::{BTreeMap, BTreeSet},
+    fmt::{self, Write},
     panic::Location,
+    sync::{Arc, Mutex},
     thread::{self, JoinHandle},
     time::{Duration, Instant},
 };
 
+/// Callback invoked when an object tracked by [`ExpectShortLifetime`] lives longer than expected.
+pub type Observer = Arc<dyn Fn(&LifetimeWarning) + Send + Sync>;
+
+/// Report about an object tracked by [`ExpectShortLifetime`] that lives longer than expected.
+#[derive(Clone, Debug)]
+pub struct LifetimeWarning {
+    /// Id of the tracked object. The same id is used in the log messages.
+    pub id: u64,
+    /// How long the object has been alive when the warning was emitted.
+    pub elapsed: Duration,
+    /// Where the object was created.
+    pub location: &'static Location<'static>,
+    /// Backtrace captured when the object was created. Only actually captured when enabled with
+    /// the `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables.
+    pub backtrace: Arc<Backtrace>,
+}
+
+/// Renders all the currently alive objects tracked by [`ExpectShortLifetime`] (e.g., the currently
+/// held locks) together with where they were created, ordered from the oldest. Unlike the
+/// warnings, this doesn't wait for the objects to exceed their expected lifetime so it can be used
+/// to inspect a hang immediately. Backtraces are included only when enabled with the
+/// `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables.
+pub fn dump() -> String {
+    let mut contexts = BTreeMap::new();
+
+    // Clone the contexts first so the (potentially slow) formatting of the backtraces happens
+    // without holding the locks.
+    TIMER.for_each(|id, context| {
+        contexts.insert(id, context.clone());
+    });
+
+    for (id, context) in &REPORTED.lock().unwrap().alive {
+        contexts.insert(*id, context.clone());
+    }
+
+    let mut output = String::new();
+
+    for (id, context) in contexts {
+        // Writing into a `String` never fails.
+        writeln!(output, "Task (id: {id})\n{context}\n").ok();
+    }
+
+    output
+}
+
 /// Attach this to objects that are expected to be short-lived to be warned when they live longer
 /// than expected.
 pub struct ExpectShortLifetime {
@@ -22,7 +69,16 @@ impl ExpectShortLifetime {
     }
 
     pub fn new_in(max_lifetime: Duration, location: &'static Location<'static>) -> Self {
-        let context = Context::new(location);
+        Self::new_observed(max_lifetime, location, None)
+    }
+
+    /// Like `new_in` but additionally invokes the given observer if the lifetime is exceeded.
+    pub fn new_observed(
+        max_lifetime: Duration,
+        location: &'static Location<'static>,
+        observer: Option<Observer>,
+    ) -> Self {
+        let context = Context::new(location, observer);
         let id = schedule(max_lifetime, context);
 
         Self {
@@ -38,18 +94,21 @@ impl Drop for ExpectShortLifetime {
     }
 }
 
+#[derive(Clone)]
 struct Context {
     start_time: Instant,
     location: &'static Location<'static>,
-    backtrace: Backtrace,
+    backtrace: Arc<Backtrace>,
+    observer: Option<Observer>,
 }
 
 impl Context {
-    fn new(location: &'static Location<'static>) -> Self {
+    fn new(location: &'static Location<'static>, observer: Option<Observer>) -> Self {
         Self {
             start_time: Instant::now(),
             location,
-            backtrace: Backtrace::capture(),
+            backtrace: Arc::new(Backtrace::capture()),
+            observer,
         }
     }
 }
@@ -69,6 +128,18 @@ impl fmt::Display for Context {
 static TIMER: Timer<Context> = Timer::new();
 static WATCHING_THREAD: Lazy<JoinHandle<()>> = Lazy::new(|| thread::spawn(watching_thread));
 
+// Objects that have already been reported as living too long but are still alive.
+static REPORTED: Mutex<Reported> = Mutex::new(Reported {
+    alive: BTreeMap::new(),
+    dropped: BTreeSet::new(),
+});
+
+struct Reported {
+    alive: BTreeMap<Id, Context>,
+    // Objects dropped after being removed from the timer but before being added to `alive`.
+    dropped: BTreeSet<Id>,
+}
+
 fn schedule(duration: Duration, context: Context) -> Id {
     // Make sure the thread is instantiated.
     let _ = *WATCHING_THREAD;
@@ -78,13 +149,23 @@ fn schedule(duration: Duration, context: Context) -> Id {
 }
 
 fn cancel(id: Id, start: Instant) {
-    if TIMER.cancel(id).is_none() {
-        tracing::warn!(
-            "🐢🐢🐢 Previously reported task (id: {}) eventually completed in {:?} 🐢🐢🐢",
-            id,
-            start.elapsed(),
-        );
+    if TIMER.cancel(id).is_some() {
+        return;
+    }
+
+    {
+        let mut reported = REPORTED.lock().unwrap();
+
+        if reported.alive.remove(&id).is_none() {
+            reported.dropped.insert(id);
+        }
     }
+
+    tracing::warn!(
+        "🐢🐢🐢 Previously reported task (id: {}) eventually completed in {:?} 🐢🐢🐢",
+        id,
+        start.elapsed(),
+    );
 }
 
 fn watching_thread() {
@@ -96,5 +177,39 @@ fn watching_thread() {
             id,
             context
         );
+
+        if let Some(observer) = &context.observer {
+            observer(&LifetimeWarning {
+                id,
+                elapsed: context.start_time.elapsed(),
+                location: context.location,
+                backtrace: context.backtrace.clone(),
+            });
+        }
+
+        let mut reported = REPORTED.lock().unwrap();
+
+        if !reported.dropped.remove(&id) {
+            reported.alive.insert(id, context);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_alive() {
+        let tracker = ExpectShortLifetime::new(Duration::from_secs(60));
+        let needle = format!("(id: {})", tracker.id);
+        let output = dump();
+
+        assert!(output.contains(&needle));
+        assert!(output.contains(file!()));
+
+        drop(tracker);
+
+        assert!(!dump().contains(&needle));
     }
 }
@@ -106,6 +106,15 @@ impl<T> Timer<T> {
         }
     }
 
+    /// Calls `f` on every scheduled payload, in the order of their ids.
+    pub fn for_each(&self, mut f: impl FnMut(Id, &T)) {
+        let inner = self.inner.lock().unwrap();
+
+        for (id, holder) in &inner.payloads {
+            f(*id, &holder.payload);
+        }
+    }
+
     #[allow(unused)]
     pub fn is_empty(&self) -> bool {
         self.inner.lock().unwrap().deadlines.is_empty()
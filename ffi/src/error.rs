@@ -78,6 +78,8 @@ impl ToErrorCode for MirrorError {
         match self {
             Self::Connect(error) => error.to_error_code(),
             Self::Server(error) => error.to_error_code(),
+            Self::Repository(error) => error.to_error_code(),
+            Self::UnexpectedResponse => ErrorCode::MalformedMessage,
         }
     }
 }
@@ -93,13 +95,21 @@ impl ToErrorCode for ouisync_lib::Error {
             Self::AmbiguousEntry => ErrorCode::AmbiguousEntry,
             Self::DirectoryNotEmpty => ErrorCode::DirectoryNotEmpty,
             Self::OperationNotSupported => ErrorCode::OperationNotSupported,
-            Self::InvalidArgument | Self::NonUtf8FileName | Self::OffsetOutOfRange => {
-                ErrorCode::InvalidArgument
-            }
+            Self::InvalidArgument
+            | Self::NonUtf8FileName
+            | Self::OffsetOutOfRange
+            | Self::KdfParamsMismatch
+            | Self::PathTooDeep
+            | Self::FileTooLarge => ErrorCode::InvalidArgument,
             Self::StorageVersionMismatch => ErrorCode::StorageVersionMismatch,
-            Self::EntryIsFile | Self::EntryIsDirectory | Self::Writer(_) | Self::Locked => {
-                ErrorCode::Other
-            }
+            Self::EntryIsFile
+            | Self::EntryIsDirectory
+            | Self::Writer(_)
+            | Self::Reader(_)
+            | Self::Io(_)
+            | Self::Locked
+            | Self::Busy
+            | Self::TooManySnapshots => ErrorCode::Other,
         }
     }
 }
@@ -119,7 +129,9 @@ impl ToErrorCode for ServerError {
             Self::ShuttingDown => ErrorCode::Other,
             Self::InvalidArgument => ErrorCode::InvalidArgument,
             Self::Transport(error) => error.to_error_code(),
-            Self::CreateRepository(_) => ErrorCode::Other,
+            Self::CreateRepository(_) | Self::RepositoryNotFound | Self::Repository(_) => {
+                ErrorCode::Other
+            }
         }
     }
 }
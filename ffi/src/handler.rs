@@ -10,7 +10,10 @@ use crate::{
 use async_trait::async_trait;
 use ouisync_bridge::transport::NotificationSender;
 use ouisync_lib::PeerAddr;
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+// How long to wait for the pending messages to be sent to the peers on network shutdown.
+const NETWORK_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
 
 #[derive(Clone)]
 pub(crate) struct Handler {
@@ -338,7 +341,7 @@ impl ouisync_bridge::transport::Handler for Handler {
             Request::NetworkExternalAddrV6 => self.state.network.external_addr_v6().await.into(),
             Request::NetworkNatBehavior => self.state.network.nat_behavior().await.into(),
             Request::NetworkShutdown => {
-                self.state.network.shutdown().await;
+                self.state.network.shutdown(NETWORK_SHUTDOWN_TIMEOUT).await;
                 ().into()
             }
             Request::StateMonitorGet(path) => state_monitor::get(&self.state, path)?.into(),
@@ -547,13 +547,17 @@ mod tests {
                     addr: PeerAddr::Quic(([192, 168, 1, 204], 65535).into()),
                     source: PeerSource::LocalDiscovery,
                     state: PeerState::Connecting,
+                    uptime: None,
+                    introduced_by: None,
                 },
                 PeerInfo {
                     addr: PeerAddr::Quic(
                         ([0x2001, 0xdb8, 0x0, 0x0, 0x0, 0x8a2e, 0x370, 0x7334], 12345).into(),
                     ),
-                    source: PeerSource::Dht,
+                    source: PeerSource::PeerExchange,
                     state: PeerState::Active(SecretRuntimeId::random().public()),
+                    uptime: None,
+                    introduced_by: Some(SecretRuntimeId::random().public()),
                 },
             ]),
             Response::PeerAddrs(vec![PeerAddr::Tcp(([192, 168, 1, 234], 45678).into())]),
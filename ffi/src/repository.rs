@@ -198,7 +198,7 @@ pub(crate) async fn requires_local_password_for_writing(
 /// User is responsible for deallocating the returned string.
 pub(crate) fn info_hash(state: &State, handle: Handle<RepositoryHolder>) -> String {
     let holder = state.get_repository(handle);
-    let info_hash = network::repository_info_hash(holder.repository.secrets().id());
+    let info_hash = network::repository_info_hash(holder.repository.secrets().id(), None);
 
     hex::encode(info_hash)
 }
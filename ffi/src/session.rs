@@ -10,7 +10,7 @@ use ouisync_bridge::logger::{LogColor, LogFormat, Logger};
 use state_monitor::StateMonitor;
 use std::{ffi::c_char, io, path::PathBuf, ptr, str::Utf8Error, sync::Arc, time::Duration};
 use thiserror::Error;
-use tokio::{runtime, time};
+use tokio::runtime;
 
 pub struct Session {
     pub(crate) runtime: runtime::Runtime,
@@ -63,9 +63,7 @@ impl Session {
         } = self;
 
         runtime.block_on(async move {
-            time::timeout(Duration::from_millis(500), state.network.shutdown())
-                .await
-                .unwrap_or(())
+            state.network.shutdown(Duration::from_millis(500)).await;
         });
     }
 }
@@ -8,7 +8,7 @@ pub(crate) fn mode(token: ShareToken) -> u8 {
 /// Returns the info-hash of the repository corresponding to the share token formatted as hex
 /// string.
 pub(crate) fn info_hash(token: ShareToken) -> String {
-    hex::encode(network::repository_info_hash(token.id()).as_ref())
+    hex::encode(network::repository_info_hash(token.id(), None).as_ref())
 }
 
 pub(crate) fn suggested_name(token: ShareToken) -> String {
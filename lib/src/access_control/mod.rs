@@ -2,7 +2,11 @@ mod access_mode;
 mod local_secret;
 mod share_token;
 
-pub use self::{access_mode::AccessMode, local_secret::LocalSecret, share_token::ShareToken};
+pub use self::{
+    access_mode::AccessMode,
+    local_secret::LocalSecret,
+    share_token::{ShareToken, ShareTokenError, ShareTokenInfo},
+};
 
 use crate::{
     crypto::{cipher, sign},
@@ -14,6 +18,7 @@ use rand::{rngs::OsRng, CryptoRng, Rng};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{fmt, str::Utf8Error, string::FromUtf8Error, sync::Arc};
 use thiserror::Error;
+use zeroize::Zeroizing;
 
 /// Secrets for access to a repository.
 #[derive(Clone, Serialize, Deserialize)]
@@ -39,6 +44,16 @@ impl AccessSecrets {
         Self::Write(WriteSecrets::random())
     }
 
+    /// Deterministically derives access secrets with write access from the given seed. The same
+    /// seed always yields the same secrets (and thus the same repository id), which allows
+    /// creating the same repository on multiple devices without exchanging share tokens.
+    ///
+    /// The seed must be kept secret and should have full 256 bits of entropy. It's not a
+    /// password - use a proper password hash first if it's derived from a user input.
+    pub fn derive_from_seed(seed: &[u8; 32]) -> Self {
+        Self::Write(WriteSecrets::derive_from_seed(seed))
+    }
+
     /// Change the access mode of this secrets to the given mode. If the given mode is higher than
     /// self, returns self unchanged.
     pub fn with_mode(&self, mode: AccessMode) -> Self {
@@ -145,6 +160,12 @@ impl WriteSecrets {
     pub fn random() -> Self {
         Self::generate(&mut OsRng)
     }
+
+    /// Deterministically derives write secrets from the given seed. See
+    /// [`AccessSecrets::derive_from_seed`] for details.
+    pub fn derive_from_seed(seed: &[u8; 32]) -> Self {
+        Self::from(derive_write_keys_from_seed(seed))
+    }
 }
 
 impl PartialEq for WriteSecrets {
@@ -220,6 +241,17 @@ impl From<WriteSecrets> for AccessKeys {
     }
 }
 
+fn derive_write_keys_from_seed(seed: &[u8; 32]) -> sign::Keypair {
+    // Use a dedicated context string so the keys derived here never collide with keys derived from
+    // the same input material elsewhere (e.g., from a password).
+    let secret_key = Zeroizing::new(blake3::derive_key(
+        "ouisync repository write keys from seed",
+        seed,
+    ));
+
+    sign::Keypair::from(&*secret_key)
+}
+
 fn derive_read_key_from_write_keys(write_keys: &sign::Keypair) -> cipher::SecretKey {
     cipher::SecretKey::derive_from_key(&write_keys.to_bytes(), b"ouisync repository read key")
 }
@@ -394,3 +426,30 @@ impl Access {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_from_seed() {
+        let seed0 = [0; 32];
+        let seed1 = [1; 32];
+
+        let secrets0a = AccessSecrets::derive_from_seed(&seed0);
+        let secrets0b = AccessSecrets::derive_from_seed(&seed0);
+        let secrets1 = AccessSecrets::derive_from_seed(&seed1);
+
+        assert_eq!(secrets0a.access_mode(), AccessMode::Write);
+        assert_eq!(secrets0a.id(), secrets0b.id());
+        assert_eq!(
+            secrets0a.read_key().map(|key| key.as_array()),
+            secrets0b.read_key().map(|key| key.as_array())
+        );
+        assert_ne!(secrets0a.id(), secrets1.id());
+
+        // The seed is not used as the signing key directly.
+        let keys = sign::Keypair::from(&seed0);
+        assert_ne!(secrets0a.id(), &RepositoryId::from(keys.public_key()));
+    }
+}
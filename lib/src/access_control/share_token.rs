@@ -7,6 +7,7 @@ use std::{
     fmt,
     str::{self, FromStr},
 };
+use thiserror::Error;
 use zeroize::Zeroizing;
 
 pub const PREFIX: &str = "https://ouisync.net/r";
@@ -34,6 +35,11 @@ impl ShareToken {
         self.secrets.id()
     }
 
+    /// Name attached to this token with [`Self::with_name`], if any.
+    pub fn name(&self) -> Option<&str> {
+        (!self.name.is_empty()).then_some(self.name.as_str())
+    }
+
     /// Suggested name of the repository.
     pub fn suggested_name(&self) -> Cow<str> {
         if self.name.is_empty() {
@@ -57,25 +63,30 @@ impl ShareToken {
     pub fn access_mode(&self) -> AccessMode {
         self.secrets.access_mode()
     }
-}
 
-impl From<AccessSecrets> for ShareToken {
-    fn from(secrets: AccessSecrets) -> Self {
-        Self {
-            secrets,
-            name: String::new(),
-        }
+    /// Checks whether the given string is a valid share token and returns the information about
+    /// it, without exposing the secrets. Useful to show to the user what the token grants before
+    /// actually using it.
+    ///
+    /// Note the token doesn't contain any checksum so a token damaged in a way that still decodes
+    /// correctly (e.g., a typo in the name) is not detected.
+    pub fn validate(input: &str) -> Result<ShareTokenInfo, ShareTokenError> {
+        let token = Self::decode(input)?;
+
+        Ok(ShareTokenInfo {
+            access_mode: token.access_mode(),
+            id: *token.id(),
+            name: (!token.name.is_empty()).then_some(token.name),
+        })
     }
-}
-
-impl FromStr for ShareToken {
-    type Err = DecodeError;
 
-    fn from_str(input: &str) -> Result<Self, Self::Err> {
+    fn decode(input: &str) -> Result<Self, ShareTokenError> {
         // Trim from the end as well because reading lines from a file includes the `\n` character.
         // Also the user may accidentally include white space if done from the app.
         let input = input.trim();
-        let input = input.strip_prefix(PREFIX).ok_or(DecodeError)?;
+        let input = input
+            .strip_prefix(PREFIX)
+            .ok_or(ShareTokenError::Malformed)?;
 
         // The '/' before '#...' is optional.
         let input = match input.strip_prefix('/') {
@@ -83,27 +94,73 @@ impl FromStr for ShareToken {
             None => input,
         };
 
-        let input = input.strip_prefix('#').ok_or(DecodeError)?;
+        let input = input.strip_prefix('#').ok_or(ShareTokenError::Malformed)?;
 
         let (input, params) = input.split_once('?').unwrap_or((input, ""));
 
-        let input = Zeroizing::new(base64::decode_config(input, base64::URL_SAFE_NO_PAD)?);
+        let input = Zeroizing::new(
+            base64::decode_config(input, base64::URL_SAFE_NO_PAD)
+                .map_err(|_| ShareTokenError::Malformed)?,
+        );
         let input = decode_version(&input)?;
 
-        let secrets: AccessSecrets = bincode::options().deserialize(input)?;
-        let name = parse_name(params)?;
+        let secrets: AccessSecrets = bincode::options()
+            .deserialize(input)
+            .map_err(|_| ShareTokenError::Malformed)?;
+        let name = parse_name(params).map_err(|_| ShareTokenError::Malformed)?;
 
         Ok(Self::from(secrets).with_name(name))
     }
 }
 
+/// Information about a share token obtained with [`ShareToken::validate`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ShareTokenInfo {
+    /// Access mode the token grants.
+    pub access_mode: AccessMode,
+    /// Id of the shared repository.
+    pub id: RepositoryId,
+    /// Suggested name of the repository, if the token has one.
+    pub name: Option<String>,
+}
+
+/// Reason why a share token is invalid.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Error)]
+pub enum ShareTokenError {
+    #[error("share token is malformed")]
+    Malformed,
+    #[error("share token version {0} is not supported")]
+    UnsupportedVersion(u64),
+}
+
+impl From<AccessSecrets> for ShareToken {
+    fn from(secrets: AccessSecrets) -> Self {
+        Self {
+            secrets,
+            name: String::new(),
+        }
+    }
+}
+
+impl FromStr for ShareToken {
+    type Err = DecodeError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::decode(input).map_err(|_| DecodeError)
+    }
+}
+
 fn parse_name(query: &str) -> Result<String, DecodeError> {
     let value = query
         .split('&')
         .find_map(|param| param.strip_prefix("name="))
         .unwrap_or("");
 
-    Ok(urlencoding::decode(value)?.into_owned())
+    // The name is percent-encoded (as UTF-8) so it never contains a literal `+`. Treat it as a
+    // space to also accept tokens whose query was re-encoded as form data (e.g., by a browser).
+    let value = value.replace('+', " ");
+
+    Ok(urlencoding::decode(&value)?.into_owned())
 }
 
 fn encode_version(output: &mut Vec<u8>, version: u64) {
@@ -111,12 +168,12 @@ fn encode_version(output: &mut Vec<u8>, version: u64) {
     output.extend_from_slice(version.as_ref());
 }
 
-fn decode_version(mut input: &[u8]) -> Result<&[u8], DecodeError> {
-    let version = vint64::decode(&mut input).map_err(|_| DecodeError)?;
+fn decode_version(mut input: &[u8]) -> Result<&[u8], ShareTokenError> {
+    let version = vint64::decode(&mut input).map_err(|_| ShareTokenError::Malformed)?;
     if version == VERSION {
         Ok(input)
     } else {
-        Err(DecodeError)
+        Err(ShareTokenError::UnsupportedVersion(version))
     }
 }
 
@@ -196,6 +253,30 @@ mod tests {
         assert_matches!(decoded.secrets, AccessSecrets::Blind { id } => assert_eq!(id, token_id));
     }
 
+    #[test]
+    fn to_string_from_string_unicode_name() {
+        for name in ["my repo", "žluťoučký kůň", "🦀 & 🐍 = ?", "a+b/c%d#e"] {
+            let token = ShareToken::from(AccessSecrets::Blind {
+                id: RepositoryId::random(),
+            })
+            .with_name(name);
+
+            let encoded = token.to_string();
+            assert!(encoded.is_ascii());
+
+            let decoded: ShareToken = encoded.parse().unwrap();
+            assert_eq!(decoded.name(), Some(name));
+        }
+
+        // Spaces encoded as `+` are accepted too.
+        let encoded = ShareToken::from(AccessSecrets::Blind {
+            id: RepositoryId::random(),
+        })
+        .to_string();
+        let decoded: ShareToken = format!("{encoded}?name=my+repo").parse().unwrap();
+        assert_eq!(decoded.name(), Some("my repo"));
+    }
+
     #[test]
     fn to_string_from_string_reader() {
         let token_id = RepositoryId::random();
@@ -232,4 +313,52 @@ mod tests {
             assert_eq!(access.id, token_id);
         });
     }
+
+    #[test]
+    fn validate() {
+        let secrets = AccessSecrets::Read {
+            id: RepositoryId::random(),
+            read_key: cipher::SecretKey::random(),
+        };
+
+        let token = ShareToken::from(secrets.clone()).with_name("foo");
+        assert_eq!(
+            ShareToken::validate(&token.to_string()),
+            Ok(ShareTokenInfo {
+                access_mode: AccessMode::Read,
+                id: *secrets.id(),
+                name: Some("foo".to_owned()),
+            })
+        );
+
+        let token = ShareToken::from(secrets.clone());
+        assert_eq!(
+            ShareToken::validate(&token.to_string()).map(|info| info.name),
+            Ok(None)
+        );
+
+        assert_eq!(
+            ShareToken::validate("https://ouisync.net/r#garbage!"),
+            Err(ShareTokenError::Malformed)
+        );
+        assert_eq!(
+            ShareToken::validate("something else"),
+            Err(ShareTokenError::Malformed)
+        );
+
+        let mut buffer = Vec::new();
+        encode_version(&mut buffer, VERSION + 1);
+        bincode::options()
+            .serialize_into(&mut buffer, &secrets)
+            .unwrap();
+        let token = format!(
+            "{}#{}",
+            PREFIX,
+            base64::encode_config(buffer, base64::URL_SAFE_NO_PAD)
+        );
+        assert_eq!(
+            ShareToken::validate(&token),
+            Err(ShareTokenError::UnsupportedVersion(VERSION + 1))
+        );
+    }
 }
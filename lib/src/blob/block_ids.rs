@@ -55,7 +55,7 @@ impl BlockIds {
 
         match tx.find_block_at(&self.root_node, &encoded).await {
             Ok(block_id) => {
-                self.locator = self.locator.next();
+                self.locator = self.locator.next()?;
                 Ok(Some(block_id))
             }
             Err(error @ store::Error::LocatorNotFound) => {
@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 define_byte_array_wrapper! {
     /// BlobId is used to identify a blob in a directory
     #[derive(Serialize, Deserialize)]
-    pub(crate) struct BlobId([u8; 32]);
+    pub struct BlobId([u8; 32]);
 }
 
 impl BlobId {
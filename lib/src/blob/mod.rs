@@ -7,7 +7,8 @@ mod position;
 #[cfg(test)]
 mod tests;
 
-pub(crate) use self::{block_ids::BlockIds, id::BlobId};
+pub(crate) use self::block_ids::BlockIds;
+pub use self::id::BlobId;
 
 use self::position::Position;
 use crate::{
@@ -25,7 +26,7 @@ use crate::{
     },
     store::{self, Changeset, ReadTransaction},
 };
-use std::{io::SeekFrom, iter, mem};
+use std::{io::SeekFrom, iter, mem, ops::Range};
 use thiserror::Error;
 
 /// Size of the blob header in bytes.
@@ -33,6 +34,10 @@ use thiserror::Error;
 // a 32bit or 64bit processor (if we want two such replicas to be able to sync).
 pub const HEADER_SIZE: usize = mem::size_of::<u64>();
 
+/// Maximum length of a blob in bytes, given by the maximum number of blocks that can be addressed
+/// (block numbers are `u32`).
+pub(crate) const MAX_LEN: u64 = u32::MAX as u64 * BLOCK_SIZE as u64 - HEADER_SIZE as u64;
+
 // Max number of blocks in the cache. Increasing this number decreases the number of flushes needed
 // during writes but increases the coplexity of the individual flushes.
 const CACHE_CAPACITY: usize = 2048; // 64 MiB
@@ -43,6 +48,8 @@ pub(crate) enum ReadWriteError {
     CacheMiss,
     #[error("cache is full")]
     CacheFull,
+    #[error("blob would exceed the maximum length")]
+    TooLarge,
 }
 
 pub(crate) struct Blob {
@@ -227,6 +234,7 @@ impl Blob {
                     tracing::error!("cache full");
                     return Err(Error::OperationNotSupported);
                 }
+                Err(ReadWriteError::TooLarge) => return Err(Error::FileTooLarge),
             }
         }
 
@@ -271,11 +279,19 @@ impl Blob {
         Ok(buffer)
     }
 
+    /// Writes data from `buffer` into this blob at the current position, advancing it. Returns
+    /// the number of bytes actually written which might be less than `buffer.len()`. Fails with
+    /// `TooLarge`, without writing anything, if writing the whole buffer would make the blob
+    /// longer than [`MAX_LEN`].
     pub fn write(&mut self, buffer: &[u8]) -> Result<usize, ReadWriteError> {
         if buffer.is_empty() {
             return Ok(0);
         }
 
+        if self.position.get().saturating_add(buffer.len() as u64) > MAX_LEN {
+            return Err(ReadWriteError::TooLarge);
+        }
+
         let block = match self.cache.get_mut(&self.position.block) {
             Some(block) => block,
             None => {
@@ -283,7 +299,11 @@ impl Blob {
                     return Err(ReadWriteError::CacheFull);
                 }
 
-                if self.position.get() >= self.len_modified
+                // The block can be created from scratch only if it holds no existing data or if
+                // it's going to be overwritten whole. Otherwise it needs to be loaded first, to not
+                // lose the data before the current position (e.g., when appending to a partially
+                // filled block of a reopened blob).
+                if self.block_start() >= self.len_original.min(self.len_modified)
                     || self.position.offset == 0 && buffer.len() >= BLOCK_SIZE
                 {
                     self.cache.entry(self.position.block).or_default()
@@ -326,6 +346,7 @@ impl Blob {
                 Err(ReadWriteError::CacheFull) => {
                     self.flush(tx, changeset).await?;
                 }
+                Err(ReadWriteError::TooLarge) => return Err(Error::FileTooLarge),
             }
         }
 
@@ -351,7 +372,7 @@ impl Blob {
         match self.cache.entry(self.position.block) {
             Entry::Occupied(_) => (),
             Entry::Vacant(entry) => {
-                let locator = Locator::head(self.id).nth(self.position.block);
+                let locator = Locator::head(self.id).nth(self.position.block)?;
                 let (_, buffer) =
                     read_block(tx, root_node, &locator, self.branch.keys().read()).await?;
                 entry.insert(CachedBlock::from(buffer));
@@ -361,6 +382,58 @@ impl Blob {
         Ok(())
     }
 
+    /// Reloads the length of this blob from the store and discards the cached blocks, to pick up
+    /// the modifications flushed through other instances of the same blob. Only the head block is
+    /// loaded. Must not be called when this blob is dirty because the modifications would be lost.
+    pub async fn reload(&mut self, tx: &mut ReadTransaction) -> Result<()> {
+        debug_assert!(!self.is_dirty());
+
+        let root_node = tx
+            .load_root_node(self.branch.id(), RootNodeFilter::Any)
+            .await?;
+        let (_, buffer) = read_block(
+            tx,
+            &root_node,
+            &Locator::head(self.id),
+            self.branch.keys().read(),
+        )
+        .await?;
+
+        let len = buffer.read_u64(0);
+
+        self.cache.clear();
+        self.cache.insert(0, CachedBlock::from(buffer));
+        self.len_original = len;
+        self.len_modified = len;
+
+        if self.seek_position() > len {
+            self.seek(SeekFrom::Start(len));
+        }
+
+        Ok(())
+    }
+
+    /// Number of the block the current seek position is in.
+    pub fn current_block(&self) -> u32 {
+        self.position.block
+    }
+
+    /// Inserts blocks loaded ahead of time (see [`read_blocks`]) into the cache. Blocks that are
+    /// already cached are not replaced.
+    pub fn insert_prefetched(&mut self, blocks: Vec<(u32, BlockContent)>) {
+        for (number, content) in blocks {
+            if self.cache.contains_key(&number) {
+                continue;
+            }
+
+            if !self.check_cache_capacity() {
+                break;
+            }
+
+            self.cache.insert(number, CachedBlock::from(content));
+        }
+    }
+
     /// Truncate the blob to the given length.
     pub fn truncate(&mut self, len: u64) -> Result<()> {
         if len == self.len() {
@@ -389,7 +462,7 @@ impl Blob {
         changeset: &mut Changeset,
     ) -> Result<()> {
         self.write_len(tx, changeset).await?;
-        self.write_blocks(changeset);
+        self.write_blocks(changeset)?;
 
         Ok(())
     }
@@ -406,6 +479,11 @@ impl Blob {
         }
     }
 
+    // Offset of the start of the current block from the start of the blob.
+    fn block_start(&self) -> u64 {
+        (self.position.block as u64 * BLOCK_SIZE as u64).saturating_sub(HEADER_SIZE as u64)
+    }
+
     fn check_cache_capacity(&mut self) -> bool {
         if self.cache.len() < CACHE_CAPACITY {
             return true;
@@ -454,7 +532,7 @@ impl Blob {
         Ok(())
     }
 
-    fn write_blocks(&mut self, changeset: &mut Changeset) {
+    fn write_blocks(&mut self, changeset: &mut Changeset) -> Result<()> {
         // Poor man's `drain_filter`.
         let cache = mem::take(&mut self.cache);
         let (dirty, clean): (HashMap<_, _>, _) =
@@ -462,7 +540,7 @@ impl Blob {
         self.cache = clean;
 
         for (number, block) in dirty {
-            let locator = Locator::head(self.id).nth(number);
+            let locator = Locator::head(self.id).nth(number)?;
             write_block(
                 changeset,
                 &locator,
@@ -470,6 +548,8 @@ impl Blob {
                 self.branch.keys().read(),
             );
         }
+
+        Ok(())
     }
 }
 
@@ -646,6 +726,78 @@ async fn load_block_count_hint(
     }
 }
 
+/// Loads the blocks of the blob `id` with numbers in `range` from the latest snapshot of `branch`.
+/// Blocks that are not available locally are skipped. Uses only a read transaction.
+pub(crate) async fn read_blocks(
+    branch: Branch,
+    id: BlobId,
+    range: Range<u32>,
+) -> Result<Vec<(u32, BlockContent)>> {
+    let mut tx = branch.store().begin_read().await?;
+    let root_node = tx.load_root_node(branch.id(), RootNodeFilter::Any).await?;
+    let mut blocks = Vec::with_capacity(range.len());
+
+    for number in range {
+        let locator = Locator::head(id).nth(number)?;
+
+        match read_block(&mut tx, &root_node, &locator, branch.keys().read()).await {
+            Ok((_, content)) => blocks.push((number, content)),
+            Err(Error::Store(store::Error::BlockNotFound | store::Error::LocatorNotFound)) => {
+                continue
+            }
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(blocks)
+}
+
+/// Checks whether all the blocks of the blob `id` in the latest snapshot of `branch` are present
+/// locally. Apart from the index, at most the head block is read (to find the blob length if
+/// `known_block_count` is `None`) and no block is requested from peers. If the head block is not
+/// available, the length can't be determined and the blob is reported as incomplete.
+pub(crate) async fn is_complete(
+    branch: &Branch,
+    id: BlobId,
+    known_block_count: Option<u32>,
+) -> Result<bool> {
+    let mut tx = branch.store().begin_read().await?;
+    let root_node = match tx.load_root_node(branch.id(), RootNodeFilter::Any).await {
+        Ok(root_node) => root_node,
+        Err(store::Error::BranchNotFound) => return Ok(false),
+        Err(error) => return Err(error.into()),
+    };
+
+    let block_count = match known_block_count {
+        Some(block_count) => block_count,
+        None => match read_len(&mut tx, &root_node, id, branch.keys().read()).await {
+            Ok(len) => block_count(len),
+            Err(Error::Store(store::Error::BlockNotFound | store::Error::LocatorNotFound)) => {
+                return Ok(false)
+            }
+            Err(error) => return Err(error),
+        },
+    };
+
+    for locator in Locator::head(id).sequence().take(block_count as usize) {
+        let encoded = locator.encode(branch.keys().read());
+
+        let block_id = match tx.find_block_at(&root_node, &encoded).await {
+            Ok(block_id) => block_id,
+            // Missing index entry of a block within the blob length means the index is not fully
+            // synced yet.
+            Err(store::Error::LocatorNotFound) => return Ok(false),
+            Err(error) => return Err(error.into()),
+        };
+
+        if !tx.block_exists(&block_id).await? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
 async fn read_block(
     tx: &mut ReadTransaction,
     root_node: &RootNode,
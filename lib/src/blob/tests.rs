@@ -407,7 +407,7 @@ async fn modify_blob() {
 
     let id = rng.gen();
     let locator0 = Locator::head(id);
-    let locator1 = locator0.next();
+    let locator1 = locator0.next().unwrap();
 
     let content = vec![0; 2 * BLOCK_SIZE];
     let mut changeset = Changeset::new();
@@ -497,6 +497,155 @@ async fn append() {
     store.close().await.unwrap();
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn append_to_partial_block_after_reopen() {
+    let (mut rng, _base_dir, store, [branch]) = setup(0).await;
+
+    let mut tx = store.begin_write().await.unwrap();
+
+    let id = rng.gen();
+    let content = random_bytes(&mut rng, BLOCK_SIZE + 100);
+
+    let mut changeset = Changeset::new();
+    let mut blob = Blob::create(branch.clone(), id);
+    blob.write_all(&mut tx, &mut changeset, &content)
+        .await
+        .unwrap();
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+
+    // Only the head block is loaded on open, so the last (partial) block is not in the cache.
+    let mut changeset = Changeset::new();
+    let mut blob = Blob::open(&mut tx, branch.clone(), id).await.unwrap();
+    blob.seek(SeekFrom::End(0));
+    blob.write_all(&mut tx, &mut changeset, b"bar")
+        .await
+        .unwrap();
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+
+    let mut blob = Blob::open(&mut tx, branch, id).await.unwrap();
+    let actual = blob.read_to_end(&mut tx).await.unwrap();
+
+    let mut expected = content;
+    expected.extend_from_slice(b"bar");
+    similar_asserts::assert_eq!(actual, expected);
+
+    drop(tx);
+    store.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn seek_backward_across_blocks_after_reopen() {
+    let (mut rng, _base_dir, store, [branch]) = setup(0).await;
+
+    let mut tx = store.begin_write().await.unwrap();
+
+    let id = rng.gen();
+    let content = random_bytes(&mut rng, 3 * BLOCK_SIZE);
+
+    let mut changeset = Changeset::new();
+    let mut blob = Blob::create(branch.clone(), id);
+    blob.write_all(&mut tx, &mut changeset, &content)
+        .await
+        .unwrap();
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+
+    let mut blob = Blob::open(&mut tx, branch, id).await.unwrap();
+    let mut buffer = vec![0; 64];
+
+    // Positions around the block boundaries (accounting for the header in the head block),
+    // visited backwards.
+    let positions = [
+        2 * BLOCK_SIZE - HEADER_SIZE + 1,
+        2 * BLOCK_SIZE - HEADER_SIZE - 1,
+        BLOCK_SIZE - HEADER_SIZE,
+        BLOCK_SIZE - HEADER_SIZE - 32,
+        0,
+    ];
+
+    for pos in positions {
+        assert_eq!(blob.seek(SeekFrom::Start(pos as u64)), pos as u64);
+        assert_eq!(blob.seek_position(), pos as u64);
+
+        let len = blob.read_all(&mut tx, &mut buffer).await.unwrap();
+        assert_eq!(len, buffer.len());
+        assert_eq!(buffer, content[pos..pos + len]);
+    }
+
+    drop(tx);
+    store.close().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn seek_into_dirty_block() {
+    let (mut rng, _base_dir, store, [branch]) = setup(0).await;
+
+    let mut tx = store.begin_write().await.unwrap();
+
+    let id = rng.gen();
+    let mut content = random_bytes(&mut rng, 2 * BLOCK_SIZE);
+
+    let mut changeset = Changeset::new();
+    let mut blob = Blob::create(branch.clone(), id);
+    blob.write_all(&mut tx, &mut changeset, &content)
+        .await
+        .unwrap();
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+
+    let mut changeset = Changeset::new();
+    let mut blob = Blob::open(&mut tx, branch.clone(), id).await.unwrap();
+
+    // Modify the second block without flushing.
+    let pos = BLOCK_SIZE + 10;
+    blob.seek(SeekFrom::Start(pos as u64));
+    blob.write_all(&mut tx, &mut changeset, b"xyz")
+        .await
+        .unwrap();
+    content[pos..pos + 3].copy_from_slice(b"xyz");
+
+    // Seek away and back.
+    blob.seek(SeekFrom::Start(0));
+    let mut buffer = vec![0; 16];
+    blob.read_all(&mut tx, &mut buffer).await.unwrap();
+    assert_eq!(buffer, content[..16]);
+
+    blob.seek(SeekFrom::Start(pos as u64 - 5));
+    blob.read_all(&mut tx, &mut buffer).await.unwrap();
+    assert_eq!(buffer, content[pos - 5..pos + 11]);
+
+    // The whole content as seen before flushing...
+    blob.seek(SeekFrom::Start(0));
+    similar_asserts::assert_eq!(blob.read_to_end(&mut tx).await.unwrap(), content);
+
+    // ...and after it.
+    blob.flush(&mut tx, &mut changeset).await.unwrap();
+    changeset
+        .apply(&mut tx, branch.id(), branch.keys().write().unwrap())
+        .await
+        .unwrap();
+
+    let mut blob = Blob::open(&mut tx, branch, id).await.unwrap();
+    similar_asserts::assert_eq!(blob.read_to_end(&mut tx).await.unwrap(), content);
+
+    drop(tx);
+    store.close().await.unwrap();
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn write_reopen_and_read() {
     let (mut rng, _base_dir, store, [branch]) = setup(0).await;
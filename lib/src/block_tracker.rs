@@ -4,7 +4,7 @@ use crate::{
 };
 use deadlock::BlockingMutex;
 use slab::Slab;
-use std::{collections::hash_map::Entry, sync::Arc};
+use std::{collections::hash_map::Entry, fmt, sync::Arc};
 use tokio::sync::watch;
 
 /// Helper for tracking required missing blocks.
@@ -22,6 +22,7 @@ impl BlockTracker {
                 inner: BlockingMutex::new(Inner {
                     missing_blocks: HashMap::default(),
                     offering_clients: Slab::new(),
+                    greedy: false,
                 }),
                 notify_tx,
             }),
@@ -30,6 +31,13 @@ impl BlockTracker {
 
     /// Mark the block with the given id as required.
     pub fn require(&self, block_id: BlockId) {
+        self.require_with_priority(block_id, BlockPriority::Normal)
+    }
+
+    /// Mark the block with the given id as required with the given priority. Offers for higher
+    /// priority blocks are yielded before offers for lower priority ones. If the block is already
+    /// required, its priority can only be raised here (use [`Self::deprioritize`] to lower it).
+    pub fn require_with_priority(&self, block_id: BlockId, priority: BlockPriority) {
         let mut inner = self.shared.inner.lock().unwrap();
 
         let missing_block = inner
@@ -41,13 +49,21 @@ impl BlockTracker {
                     required: false,
                     approved: false,
                 },
+                priority,
             });
 
+        missing_block.priority = missing_block.priority.max(priority);
+
         match &mut missing_block.state {
-            State::Idle { required: true, .. } | State::Accepted(_) => return,
+            State::Idle { required: true, .. } => return,
             State::Idle { required, .. } => {
                 *required = true;
             }
+            State::Accepted { required, .. } => {
+                // Remember it so the block is requested again if this request fails.
+                *required = true;
+                return;
+            }
         }
 
         if !missing_block.offers.is_empty() {
@@ -55,17 +71,32 @@ impl BlockTracker {
         }
     }
 
+    /// Lowers the priority of the block with the given id back to normal, if it's still missing.
+    pub fn deprioritize(&self, block_id: &BlockId) {
+        if let Some(missing_block) = self
+            .shared
+            .inner
+            .lock()
+            .unwrap()
+            .missing_blocks
+            .get_mut(block_id)
+        {
+            missing_block.priority = BlockPriority::Normal;
+        }
+    }
+
     /// Approve the block request if offered. This is called when `quota` is not `None`, otherwise
     /// blocks are pre-approved from `TrackerClient::register(block_id, OfferState::Approved)`.
     pub fn approve(&self, block_id: BlockId) {
         let mut inner = self.shared.inner.lock().unwrap();
+        let greedy = inner.greedy;
 
         let Some(missing_block) = inner.missing_blocks.get_mut(&block_id) else {
             return;
         };
 
         let required = match &mut missing_block.state {
-            State::Idle { approved: true, .. } | State::Accepted(_) => return,
+            State::Idle { approved: true, .. } | State::Accepted { .. } => return,
             State::Idle { approved, required } => {
                 *approved = true;
                 *required
@@ -73,11 +104,42 @@ impl BlockTracker {
         };
 
         // If required and offered, notify the waiting acceptors.
-        if required && !missing_block.offers.is_empty() {
+        if (required || greedy) && !missing_block.offers.is_empty() {
             self.shared.notify();
         }
     }
 
+    /// In greedy mode every offered block is treated as required, even if it wasn't explicitly
+    /// required with `require`. Switching greedy mode off doesn't affect blocks whose requests
+    /// are already in flight, but no new requests are issued for the blocks that aren't
+    /// explicitly required.
+    pub fn set_greedy(&self, greedy: bool) {
+        let mut inner = self.shared.inner.lock().unwrap();
+
+        if inner.greedy == greedy {
+            return;
+        }
+
+        inner.greedy = greedy;
+
+        if greedy {
+            self.shared.notify();
+        }
+    }
+
+    pub fn is_greedy(&self) -> bool {
+        self.shared.inner.lock().unwrap().greedy
+    }
+
+    /// Returns a handle to inspect the number of required blocks that are waiting to be requested,
+    /// per priority. Its `Debug` impl renders the current numbers so it can be put into a
+    /// `StateMonitor`.
+    pub fn queue_depth(&self) -> QueueDepth {
+        QueueDepth {
+            shared: self.shared.clone(),
+        }
+    }
+
     pub fn client(&self) -> TrackerClient {
         let client_id = self
             .shared
@@ -97,6 +159,45 @@ impl BlockTracker {
     }
 }
 
+/// Priority of a required block. Blocks of files being accessed interactively should be requested
+/// before the blocks required by background jobs.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub(crate) enum BlockPriority {
+    Normal,
+    High,
+}
+
+pub(crate) struct QueueDepth {
+    shared: Arc<Shared>,
+}
+
+impl QueueDepth {
+    pub fn get(&self, priority: BlockPriority) -> usize {
+        let inner = self.shared.inner.lock().unwrap();
+
+        inner
+            .missing_blocks
+            .values()
+            .filter(|missing_block| missing_block.priority == priority)
+            .filter(|missing_block| match missing_block.state {
+                State::Idle { required, .. } => required || inner.greedy,
+                State::Accepted { .. } => false,
+            })
+            .count()
+    }
+}
+
+impl fmt::Debug for QueueDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "high: {}, normal: {}",
+            self.get(BlockPriority::High),
+            self.get(BlockPriority::Normal)
+        )
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 pub(crate) enum OfferState {
     Pending,
@@ -139,6 +240,7 @@ impl TrackerClient {
                     required: false,
                     approved: false,
                 },
+                priority: BlockPriority::Normal,
             });
 
         missing_block
@@ -158,11 +260,27 @@ impl TrackerClient {
                     self.shared.notify();
                 }
             }
-            State::Accepted(_) => (),
+            State::Accepted { .. } => (),
         }
 
         true
     }
+
+    /// Returns the number of blocks offered by this client that are still missing and are going
+    /// to be requested (that is, they are required or the tracker is in greedy mode).
+    pub fn missing_count(&self) -> usize {
+        let inner = self.shared.inner.lock().unwrap();
+
+        inner.offering_clients[self.client_id]
+            .iter()
+            .filter_map(|block_id| inner.missing_blocks.get(block_id))
+            .filter(|missing_block| match missing_block.state {
+                State::Idle { required, .. } | State::Accepted { required, .. } => {
+                    required || inner.greedy
+                }
+            })
+            .count()
+    }
 }
 
 impl Drop for TrackerClient {
@@ -214,34 +332,38 @@ impl BlockOffers {
     pub fn try_next(&self) -> Option<BlockOffer> {
         let mut inner = self.shared.inner.lock().unwrap();
         let inner = &mut *inner;
+        let greedy = inner.greedy;
 
-        // TODO: OPTIMIZE (but profile first) this linear lookup
-        for block_id in &inner.offering_clients[self.client_id] {
-            // unwrap is ok because of the invariant in `Inner`
-            let missing_block = inner.missing_blocks.get_mut(block_id).unwrap();
+        // Higher priority offers first.
+        for priority in [BlockPriority::High, BlockPriority::Normal] {
+            // TODO: OPTIMIZE (but profile first) this linear lookup
+            for block_id in &inner.offering_clients[self.client_id] {
+                // unwrap is ok because of the invariant in `Inner`
+                let missing_block = inner.missing_blocks.get_mut(block_id).unwrap();
 
-            match missing_block.state {
-                State::Idle {
-                    required: true,
-                    approved: true,
-                } => (),
-                State::Idle { .. } | State::Accepted(_) => continue,
-            }
+                if missing_block.priority != priority {
+                    continue;
+                }
 
-            // unwrap is ok because of the invariant.
-            let offer = missing_block.offers.get_mut(&self.client_id).unwrap();
-            match offer {
-                Offer::Available => {
-                    *offer = Offer::Proposed;
+                if !missing_block.is_requestable(greedy) {
+                    continue;
                 }
-                Offer::Proposed | Offer::Accepted => continue,
-            }
 
-            return Some(BlockOffer {
-                shared: self.shared.clone(),
-                client_id: self.client_id,
-                block_id: *block_id,
-            });
+                // unwrap is ok because of the invariant.
+                let offer = missing_block.offers.get_mut(&self.client_id).unwrap();
+                match offer {
+                    Offer::Available => {
+                        *offer = Offer::Proposed;
+                    }
+                    Offer::Proposed | Offer::Accepted => continue,
+                }
+
+                return Some(BlockOffer {
+                    shared: self.shared.clone(),
+                    client_id: self.client_id,
+                    block_id: *block_id,
+                });
+            }
         }
 
         None
@@ -261,25 +383,55 @@ impl BlockOffer {
         &self.block_id
     }
 
+    /// Whether the offered block is explicitly required, as opposed to being offered only because
+    /// of the greedy mode.
+    pub fn is_required(&self) -> bool {
+        let inner = self.shared.inner.lock().unwrap();
+
+        inner
+            .missing_blocks
+            .get(&self.block_id)
+            .map(|missing_block| match missing_block.state {
+                State::Idle { required, .. } | State::Accepted { required, .. } => required,
+            })
+            .unwrap_or(false)
+    }
+
+    /// Current priority of the offered block.
+    pub fn priority(&self) -> BlockPriority {
+        self.shared
+            .inner
+            .lock()
+            .unwrap()
+            .missing_blocks
+            .get(&self.block_id)
+            .map(|missing_block| missing_block.priority)
+            .unwrap_or(BlockPriority::Normal)
+    }
+
     /// Accepts the offer. There can be multiple offers for the same block (each from a different
     /// peer) but only one returns `Some` here. The returned `BlockPromise` is a commitment to send
     /// the block request through this client.
     pub fn accept(self) -> Option<BlockPromise> {
         let mut inner = self.shared.inner.lock().unwrap();
+        let greedy = inner.greedy;
 
         let Some(missing_block) = inner.missing_blocks.get_mut(&self.block_id) else {
             return None;
         };
 
-        match missing_block.state {
-            State::Idle {
-                required: true,
-                approved: true,
-            } => (),
-            State::Idle { .. } | State::Accepted(_) => return None,
+        let State::Idle { required, .. } = missing_block.state else {
+            return None;
+        };
+
+        if !missing_block.is_requestable(greedy) {
+            return None;
         }
 
-        missing_block.state = State::Accepted(self.client_id);
+        missing_block.state = State::Accepted {
+            client_id: self.client_id,
+            required,
+        };
         missing_block.offers.insert(self.client_id, Offer::Accepted);
 
         drop(inner);
@@ -368,6 +520,8 @@ impl Shared {
 struct Inner {
     missing_blocks: HashMap<BlockId, MissingBlock>,
     offering_clients: Slab<HashSet<BlockId>>,
+    // Whether all offered blocks should be requested, not just the required ones.
+    greedy: bool,
 }
 
 #[derive(Debug)]
@@ -375,19 +529,34 @@ struct MissingBlock {
     // Clients that offered this block.
     offers: HashMap<ClientId, Offer>,
     state: State,
+    priority: BlockPriority,
 }
 
 impl MissingBlock {
+    // Whether the block can be requested now (not counting the per-client offer state).
+    fn is_requestable(&self, greedy: bool) -> bool {
+        match self.state {
+            State::Idle {
+                required,
+                approved: true,
+            } => required || greedy,
+            State::Idle { .. } | State::Accepted { .. } => false,
+        }
+    }
+
     fn unaccept_by(&mut self, client_id: ClientId) -> bool {
         match self.state {
-            State::Accepted(other_client_id) if other_client_id == client_id => {
+            State::Accepted {
+                client_id: other_client_id,
+                required,
+            } if other_client_id == client_id => {
                 self.state = State::Idle {
-                    required: true,
+                    required,
                     approved: true,
                 };
                 true
             }
-            State::Accepted(_) | State::Idle { .. } => false,
+            State::Accepted { .. } | State::Idle { .. } => false,
         }
     }
 }
@@ -395,7 +564,7 @@ impl MissingBlock {
 #[derive(Debug)]
 enum State {
     Idle { required: bool, approved: bool },
-    Accepted(ClientId),
+    Accepted { client_id: ClientId, required: bool },
 }
 
 #[derive(Debug)]
@@ -452,6 +621,145 @@ mod tests {
         assert!(client.offers().try_next().is_none());
     }
 
+    #[test]
+    fn high_priority_first() {
+        let tracker = BlockTracker::new();
+        let client = tracker.client();
+
+        let block0: Block = rand::random();
+        let block1: Block = rand::random();
+
+        tracker.require(block0.id);
+        tracker.require_with_priority(block1.id, BlockPriority::High);
+
+        client.register(block0.id, OfferState::Approved);
+        client.register(block1.id, OfferState::Approved);
+
+        let queue_depth = tracker.queue_depth();
+        assert_eq!(queue_depth.get(BlockPriority::High), 1);
+        assert_eq!(queue_depth.get(BlockPriority::Normal), 1);
+
+        let offers = client.offers();
+
+        let promise1 = offers.try_next().and_then(BlockOffer::accept);
+        assert_eq!(
+            promise1.as_ref().map(BlockPromise::block_id),
+            Some(&block1.id)
+        );
+        assert_eq!(queue_depth.get(BlockPriority::High), 0);
+
+        let promise0 = offers.try_next().and_then(BlockOffer::accept);
+        assert_eq!(
+            promise0.as_ref().map(BlockPromise::block_id),
+            Some(&block0.id)
+        );
+        assert_eq!(queue_depth.get(BlockPriority::Normal), 0);
+    }
+
+    #[test]
+    fn priority_is_never_lowered() {
+        let tracker = BlockTracker::new();
+        let block: Block = rand::random();
+
+        tracker.require_with_priority(block.id, BlockPriority::High);
+        tracker.require(block.id);
+
+        let queue_depth = tracker.queue_depth();
+        assert_eq!(queue_depth.get(BlockPriority::High), 1);
+        assert_eq!(queue_depth.get(BlockPriority::Normal), 0);
+    }
+
+    #[test]
+    fn deprioritize() {
+        let tracker = BlockTracker::new();
+        let block: Block = rand::random();
+
+        tracker.require_with_priority(block.id, BlockPriority::High);
+        tracker.deprioritize(&block.id);
+
+        let queue_depth = tracker.queue_depth();
+        assert_eq!(queue_depth.get(BlockPriority::High), 0);
+        assert_eq!(queue_depth.get(BlockPriority::Normal), 1);
+    }
+
+    #[test]
+    fn greedy() {
+        let tracker = BlockTracker::new();
+        let client = tracker.client();
+
+        let block0: Block = rand::random();
+        let block1: Block = rand::random();
+
+        client.register(block0.id, OfferState::Approved);
+        client.register(block1.id, OfferState::Approved);
+
+        // Offered but not required blocks are not returned in lazy mode...
+        assert!(client.offers().try_next().is_none());
+
+        // ...but they are in greedy mode.
+        tracker.set_greedy(true);
+        let offer = client.offers().try_next().unwrap();
+        assert!(!offer.is_required());
+        let promise = offer.accept();
+        assert!(promise.is_some());
+
+        // Switching back to lazy mode doesn't cancel the accepted offer but no new ones are
+        // returned.
+        tracker.set_greedy(false);
+        assert!(client.offers().try_next().is_none());
+        assert_eq!(tracker.queue_depth().get(BlockPriority::Normal), 0);
+
+        // Explicitly required blocks are still returned in lazy mode.
+        let other_id = if promise.as_ref().map(BlockPromise::block_id) == Some(&block0.id) {
+            block1.id
+        } else {
+            block0.id
+        };
+
+        tracker.require(other_id);
+        let other_offer = client.offers().try_next().unwrap();
+        assert!(other_offer.is_required());
+        let other_promise = other_offer.accept();
+        assert_eq!(
+            other_promise.as_ref().map(BlockPromise::block_id),
+            Some(&other_id)
+        );
+
+        // A failed request of a block that was requested only because of greedy mode is not
+        // retried in lazy mode.
+        drop(promise);
+        client.register(block0.id, OfferState::Approved);
+        client.register(block1.id, OfferState::Approved);
+        assert!(client.offers().try_next().is_none());
+    }
+
+    #[test]
+    fn missing_count() {
+        let tracker = BlockTracker::new();
+        let client = tracker.client();
+
+        let block0: Block = rand::random();
+        let block1: Block = rand::random();
+
+        client.register(block0.id, OfferState::Approved);
+        client.register(block1.id, OfferState::Approved);
+
+        // Offered but not required blocks are not counted in lazy mode...
+        assert_eq!(client.missing_count(), 0);
+
+        tracker.require(block0.id);
+        assert_eq!(client.missing_count(), 1);
+
+        // ...but they are in greedy mode.
+        tracker.set_greedy(true);
+        assert_eq!(client.missing_count(), 2);
+
+        // Received blocks are no longer counted.
+        let promise = client.offers().try_next().unwrap().accept().unwrap();
+        promise.complete();
+        assert_eq!(client.missing_count(), 1);
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn simple_async() {
         let tracker = BlockTracker::new();
@@ -0,0 +1,243 @@
+//! Synchronous wrappers of the core repository and file operations, for embedders that don't use
+//! async. All the repositories run on a single internal tokio runtime, so the functions in this
+//! module must not be called from within an async context (they panic if they are). Requires the
+//! `blocking` feature.
+
+use crate::{
+    access_control::{Access, AccessMode, LocalSecret},
+    error::{Error, Result},
+    repository::RepositoryParams,
+};
+use camino::Utf8Path;
+use metrics::Recorder;
+use std::{io::SeekFrom, sync::OnceLock};
+use tokio::runtime::{self, Runtime};
+
+// The runtime shared by all the repositories. It's multi-threaded so the background jobs (e.g.,
+// syncing) keep running between the calls. It's never dropped, so it doesn't matter from which
+// context the repositories and files are dropped.
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+/// Blocking version of [`crate::Repository`].
+pub struct Repository {
+    inner: crate::Repository,
+    runtime: &'static Runtime,
+    closed: bool,
+}
+
+impl Repository {
+    /// Creates a new repository (see [`crate::Repository::create`]).
+    pub fn create(params: &RepositoryParams<impl Recorder>, access: Access) -> Result<Self> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::Repository::create(params, access))?;
+
+        Ok(Self::new(inner, runtime))
+    }
+
+    /// Opens an existing repository (see [`crate::Repository::open`]).
+    pub fn open(
+        params: &RepositoryParams<impl Recorder>,
+        local_secret: Option<LocalSecret>,
+        max_access_mode: AccessMode,
+    ) -> Result<Self> {
+        let runtime = runtime()?;
+        let inner = runtime.block_on(crate::Repository::open(
+            params,
+            local_secret,
+            max_access_mode,
+        ))?;
+
+        Ok(Self::new(inner, runtime))
+    }
+
+    /// Creates a new file at the given path.
+    pub fn create_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<File> {
+        let inner = self.runtime.block_on(self.inner.create_file(path))?;
+        Ok(self.wrap_file(inner))
+    }
+
+    /// Opens a file at the given path (relative to the repository root).
+    pub fn open_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<File> {
+        let inner = self.runtime.block_on(self.inner.open_file(path))?;
+        Ok(self.wrap_file(inner))
+    }
+
+    /// Creates a new directory at the given path.
+    pub fn create_directory<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
+        self.runtime.block_on(self.inner.create_directory(path))?;
+        Ok(())
+    }
+
+    /// Removes the file or (empty) directory at the given path.
+    pub fn remove_entry<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
+        self.runtime.block_on(self.inner.remove_entry(path))
+    }
+
+    /// Closes the repository (see [`crate::Repository::close`]).
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.runtime.block_on(self.inner.close())
+    }
+
+    fn new(inner: crate::Repository, runtime: &'static Runtime) -> Self {
+        Self {
+            inner,
+            runtime,
+            closed: false,
+        }
+    }
+
+    fn wrap_file(&self, inner: crate::File) -> File {
+        File {
+            inner,
+            runtime: self.runtime,
+        }
+    }
+}
+
+impl Drop for Repository {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+
+        // `block_on` panics when called from within an async context. The background tasks are
+        // then only aborted and the rest of the resources released as they finish.
+        if runtime::Handle::try_current().is_ok() {
+            tracing::warn!("Repository dropped from within an async context without being closed");
+            return;
+        }
+
+        if let Err(error) = self.runtime.block_on(self.inner.close()) {
+            tracing::error!(?error, "Failed to close repository");
+        }
+    }
+}
+
+/// Blocking version of [`crate::File`].
+pub struct File {
+    inner: crate::File,
+    runtime: &'static Runtime,
+}
+
+impl File {
+    /// Length of this file in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.inner.len()
+    }
+
+    /// Reads data from this file into the buffer. Returns the number of bytes read, zero at the
+    /// end of the file.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        self.runtime.block_on(self.inner.read(buffer))
+    }
+
+    /// Reads the rest of this file, from the current position until the end.
+    pub fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        self.runtime.block_on(self.inner.read_to_end())
+    }
+
+    /// Writes the whole buffer at the current position. The changes are not persisted until
+    /// [`Self::flush`] is called.
+    pub fn write(&mut self, buffer: &[u8]) -> Result<()> {
+        self.runtime.block_on(self.inner.write_all(buffer))
+    }
+
+    /// Seeks to an offset in the file and returns the new position.
+    pub fn seek(&mut self, pos: SeekFrom) -> u64 {
+        self.inner.seek(pos)
+    }
+
+    /// Truncates the file to the given length.
+    pub fn truncate(&mut self, len: u64) -> Result<()> {
+        self.inner.truncate(len)
+    }
+
+    /// Atomically saves any pending modifications.
+    pub fn flush(&mut self) -> Result<()> {
+        self.runtime.block_on(self.inner.flush())
+    }
+
+    /// Flushes the pending modifications and closes the file.
+    pub fn close(self) -> Result<()> {
+        let Self { inner, runtime } = self;
+        runtime.block_on(inner.close())
+    }
+}
+
+fn runtime() -> Result<&'static Runtime> {
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+
+    let runtime = runtime::Builder::new_multi_thread()
+        .enable_all()
+        .thread_name("ouisync-blocking")
+        .build()
+        .map_err(Error::Io)?;
+
+    // If another thread won the race, the runtime created here is dropped (which is fine because
+    // we're not in an async context).
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access_control::WriteSecrets;
+    use tempfile::TempDir;
+
+    #[test]
+    fn sanity_check() {
+        let base_dir = TempDir::new().unwrap();
+        let params = RepositoryParams::new(base_dir.path().join("repo.db"));
+
+        let repo = Repository::create(
+            &params,
+            Access::WriteUnlocked {
+                secrets: WriteSecrets::random(),
+            },
+        )
+        .unwrap();
+
+        let mut file = repo.create_file("test.txt").unwrap();
+        file.write(b"hello world").unwrap();
+        file.close().unwrap();
+
+        let mut file = repo.open_file("test.txt").unwrap();
+        assert_eq!(file.len(), 11);
+        assert_eq!(file.seek(SeekFrom::Start(6)), 6);
+        assert_eq!(file.read_to_end().unwrap(), b"world");
+        drop(file);
+
+        repo.remove_entry("test.txt").unwrap();
+        repo.close().unwrap();
+
+        let repo = Repository::open(&params, None, AccessMode::Write).unwrap();
+        assert!(repo.open_file("test.txt").is_err());
+    }
+
+    #[test]
+    fn drop_in_async_context() {
+        let base_dir = TempDir::new().unwrap();
+        let params = RepositoryParams::new(base_dir.path().join("repo.db"));
+
+        let repo = Repository::create(
+            &params,
+            Access::WriteUnlocked {
+                secrets: WriteSecrets::random(),
+            },
+        )
+        .unwrap();
+        let file = repo.create_file("test.txt").unwrap();
+
+        runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(async move {
+                drop(file);
+                drop(repo);
+            });
+    }
+}
@@ -1,12 +1,15 @@
 use crate::{
     access_control::AccessKeys,
-    blob::lock::{BranchLocker, Locker},
+    blob::{
+        self,
+        lock::{BranchLocker, Locker},
+    },
     crypto::sign::PublicKey,
     debug::DebugPrinter,
     directory::{Directory, DirectoryFallback, DirectoryLocking, EntryRef},
     error::{Error, Result},
     event::{EventScope, EventSender, Payload},
-    file::{File, FileProgressCache},
+    file::{File, FileProgressCache, SharedFiles},
     path,
     protocol::{BlockId, Locator, Proof, RootNodeFilter},
     store::{self, Store},
@@ -157,6 +160,15 @@ impl Branch {
         &self.shared.file_progress_cache
     }
 
+    /// Maximum size of the files written through this branch, in bytes.
+    pub(crate) fn max_file_size(&self) -> u64 {
+        self.shared.max_file_size
+    }
+
+    pub(crate) fn shared_files(&self) -> &SharedFiles {
+        &self.shared.shared_files
+    }
+
     pub(crate) fn notify(&self) -> BranchEventSender {
         BranchEventSender {
             event_tx: self.event_tx.clone(),
@@ -213,6 +225,8 @@ impl Branch {
 pub(crate) struct BranchShared {
     pub locker: Locker,
     pub file_progress_cache: FileProgressCache,
+    pub shared_files: SharedFiles,
+    pub max_file_size: u64,
 }
 
 impl BranchShared {
@@ -220,6 +234,8 @@ impl BranchShared {
         Self {
             locker: Locker::new(),
             file_progress_cache: FileProgressCache::new(),
+            shared_files: SharedFiles::new(),
+            max_file_size: blob::MAX_LEN,
         }
     }
 }
@@ -1,4 +1,5 @@
-use crate::crypto::sign::PublicKey;
+use crate::{crypto::sign::PublicKey, directory::EntryType, version_vector::VersionVector};
+use camino::Utf8PathBuf;
 
 const SUFFIX_LEN: usize = 8;
 const SUFFIX_SEPARATOR: &str = ".v";
@@ -36,6 +37,27 @@ pub fn parse_unique_name(unique_name: &str) -> (&str, Option<[u8; SUFFIX_LEN / 2
     }
 }
 
+/// Entry with multiple concurrent versions which couldn't be merged automatically (e.g., two
+/// concurrently modified versions of the same file or a file and a directory with the same name).
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Conflict {
+    /// Path of the entry within the repository, starting with `/` (the repository root), e.g.
+    /// `/dir/file.txt`. Can be passed directly to the `Repository` functions taking a path.
+    pub path: Utf8PathBuf,
+    /// The conflicting versions.
+    pub versions: Vec<ConflictVersion>,
+}
+
+/// Single version of a conflicting entry.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ConflictVersion {
+    /// Id of the branch this version is in. For directories (whose concurrent versions are always
+    /// merged) this is the id of the first branch the directory is in.
+    pub branch_id: PublicKey,
+    pub entry_type: EntryType,
+    pub version_vector: VersionVector,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
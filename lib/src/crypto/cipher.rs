@@ -1,7 +1,10 @@
 //! Encryption / Decryption utilities.
 
-use super::{hash::Digest, password::PasswordSalt};
-use argon2::Argon2;
+use super::{
+    hash::Digest,
+    password::{KdfParams, PasswordSalt},
+};
+use argon2::{Algorithm, Argon2, Version};
 use chacha20::{
     cipher::{KeyIvInit, StreamCipher},
     ChaCha20,
@@ -18,6 +21,10 @@ use zeroize::{Zeroize, Zeroizing};
 pub(crate) type Nonce = [u8; NONCE_SIZE];
 pub(crate) const NONCE_SIZE: usize =
     <<chacha20::Nonce as GenericSequence<_>>::Length as Unsigned>::USIZE;
+/// Name of the cipher (`ChaCha20`).
+pub(crate) const CIPHER_NAME: &str = "ChaCha20";
+/// Name of the function used by [`SecretKey::derive_from_key`].
+pub(crate) const KEY_DERIVATION_NAME: &str = "BLAKE3 keyed hash";
 
 /// Symmetric encryption/decryption secret key.
 ///
@@ -79,11 +86,20 @@ impl SecretKey {
 
     /// Derive a secret key from user's password and salt.
     pub fn derive_from_password(user_password: &str, salt: &PasswordSalt) -> Self {
+        Self::derive_from_password_with_params(user_password, salt, KdfParams::default())
+    }
+
+    /// Derive a secret key from user's password and salt using the given key derivation params.
+    pub fn derive_from_password_with_params(
+        user_password: &str,
+        salt: &PasswordSalt,
+        params: KdfParams,
+    ) -> Self {
         let mut result = Self::zero();
         // Note: we control the output and salt size. And the only other check that this function
         // does is whether the password isn't too long, but that would have to be more than
         // 0xffffffff so the `.expect` shouldn't be an issue.
-        Argon2::default()
+        Argon2::new(Algorithm::default(), Version::default(), params.to_argon2())
             .hash_password_into(user_password.as_ref(), salt, result.as_mut())
             .expect("failed to hash password");
         result
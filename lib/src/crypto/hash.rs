@@ -25,6 +25,7 @@ pub struct Hash(blake3::Hash);
 
 impl Hash {
     pub const SIZE: usize = blake3::OUT_LEN;
+    pub(crate) const ALGORITHM: &'static str = "BLAKE3";
 }
 
 impl From<[u8; Self::SIZE]> for Hash {
@@ -0,0 +1,64 @@
+use super::{
+    cipher::{self, SecretKey},
+    sign::Signature,
+    Hash, KdfParams,
+};
+use crate::protocol::BLOCK_NONCE_SIZE;
+use argon2::Algorithm;
+
+/// Description of a symmetric cipher.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct CipherInfo {
+    pub name: &'static str,
+    /// Key size in bytes.
+    pub key_size: usize,
+    /// Nonce size in bytes.
+    pub nonce_size: usize,
+    /// Size of the authentication tag in bytes. Zero if the cipher is not authenticated.
+    pub tag_size: usize,
+}
+
+/// Cryptographic primitives and parameters used to store a repository (see
+/// [`Repository::crypto_info`](crate::Repository::crypto_info)).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct RepositoryCryptoInfo {
+    /// Cipher used to encrypt the block contents. It's not authenticated because the integrity of
+    /// the blocks is guaranteed by their ids which are hashes of the encrypted content referenced
+    /// from the signed index.
+    pub block_cipher: CipherInfo,
+    /// Each block is encrypted with its own key derived from the blob key and the block nonce
+    /// using this function. The nonce passed to `block_cipher` is then always zero.
+    pub block_key_derivation: &'static str,
+    /// Size of the block nonce in bytes.
+    pub block_nonce_size: usize,
+    /// Hash function used for the block ids, the index and the key derivation.
+    pub hash: &'static str,
+    /// Size of the hash digest in bytes.
+    pub hash_size: usize,
+    /// Signature scheme used to sign the index.
+    pub signature: &'static str,
+    /// Key derivation function used to derive keys from passwords.
+    pub password_kdf: &'static str,
+    /// Params of `password_kdf` in effect for the repository.
+    pub password_kdf_params: KdfParams,
+}
+
+impl RepositoryCryptoInfo {
+    pub(crate) fn new(password_kdf_params: KdfParams) -> Self {
+        Self {
+            block_cipher: CipherInfo {
+                name: cipher::CIPHER_NAME,
+                key_size: SecretKey::SIZE,
+                nonce_size: cipher::NONCE_SIZE,
+                tag_size: 0,
+            },
+            block_key_derivation: cipher::KEY_DERIVATION_NAME,
+            block_nonce_size: BLOCK_NONCE_SIZE,
+            hash: Hash::ALGORITHM,
+            hash_size: Hash::SIZE,
+            signature: Signature::ALGORITHM,
+            password_kdf: Algorithm::default().as_str(),
+            password_kdf_params,
+        }
+    }
+}
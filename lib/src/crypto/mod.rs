@@ -1,10 +1,12 @@
 pub mod cipher;
 mod hash;
+mod info;
 mod password;
 pub mod sign;
 
 pub(crate) use self::{hash::CacheHash, password::PasswordSalt};
 pub use self::{
     hash::{Digest, Hash, Hashable},
-    password::Password,
+    info::{CipherInfo, RepositoryCryptoInfo},
+    password::{KdfParams, Password},
 };
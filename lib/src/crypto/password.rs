@@ -1,4 +1,4 @@
-use argon2::password_hash;
+use argon2::{password_hash, Params};
 use std::sync::Arc;
 use zeroize::Zeroizing;
 
@@ -21,3 +21,90 @@ impl AsRef<str> for Password {
 
 pub(crate) const PASSWORD_SALT_LEN: usize = password_hash::Salt::RECOMMENDED_LENGTH;
 pub(crate) type PasswordSalt = [u8; PASSWORD_SALT_LEN];
+
+/// Parameters of the key derivation function (Argon2) used to derive keys from passwords.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct KdfParams {
+    memory_cost: u32,
+    time_cost: u32,
+    parallelism: u32,
+}
+
+impl KdfParams {
+    const SIZE: usize = 12;
+
+    /// Creates the params with the given memory size (in KiB), number of iterations and degree of
+    /// parallelism. Returns `None` if any of them is out of the range supported by Argon2.
+    pub fn new(memory_cost: u32, time_cost: u32, parallelism: u32) -> Option<Self> {
+        Params::new(memory_cost, time_cost, parallelism, None).ok()?;
+
+        Some(Self {
+            memory_cost,
+            time_cost,
+            parallelism,
+        })
+    }
+
+    pub fn memory_cost(&self) -> u32 {
+        self.memory_cost
+    }
+
+    pub fn time_cost(&self) -> u32 {
+        self.time_cost
+    }
+
+    pub fn parallelism(&self) -> u32 {
+        self.parallelism
+    }
+
+    pub(crate) fn to_argon2(self) -> Params {
+        // Validated in the constructor.
+        Params::new(self.memory_cost, self.time_cost, self.parallelism, None)
+            .expect("invalid kdf params")
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.memory_cost.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.time_cost.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.parallelism.to_le_bytes());
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: [u8; Self::SIZE]) -> Option<Self> {
+        let [m0, m1, m2, m3, t0, t1, t2, t3, p0, p1, p2, p3] = bytes;
+
+        Self::new(
+            u32::from_le_bytes([m0, m1, m2, m3]),
+            u32::from_le_bytes([t0, t1, t2, t3]),
+            u32::from_le_bytes([p0, p1, p2, p3]),
+        )
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kdf_params_bytes_roundtrip() {
+        let params = KdfParams::new(8 * 1024, 1, 2).unwrap();
+        assert_eq!(KdfParams::from_bytes(params.to_bytes()), Some(params));
+    }
+
+    #[test]
+    fn kdf_params_out_of_range() {
+        assert_eq!(KdfParams::new(8 * 1024, 0, 1), None);
+        assert_eq!(KdfParams::new(8 * 1024, 1, 0), None);
+    }
+}
@@ -198,6 +198,7 @@ pub struct Signature(ext::Signature);
 
 impl Signature {
     pub const SIZE: usize = ext::SIGNATURE_LENGTH;
+    pub(crate) const ALGORITHM: &'static str = "Ed25519";
 
     pub fn to_bytes(&self) -> [u8; Self::SIZE] {
         self.0.to_bytes()
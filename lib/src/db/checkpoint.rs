@@ -0,0 +1,81 @@
+use super::Pool;
+use std::time::Duration;
+use tokio::time::{self, Instant};
+
+// How often to check the number of writes since the last checkpoint.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When to checkpoint the write-ahead log (WAL) of the database.
+///
+/// SQLite checkpoints the WAL automatically, but those checkpoints never shrink the WAL file and
+/// can be starved by concurrent readers so the file can grow large during long running
+/// operations (e.g., a big sync). When enabled, the WAL is periodically checkpointed and
+/// truncated after the given number of writes or the given time interval, whichever comes first.
+///
+/// The default is to rely on the automatic checkpoints only.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct WalCheckpoint {
+    writes: Option<u64>,
+    interval: Option<Duration>,
+}
+
+impl WalCheckpoint {
+    /// Checkpoint after every `writes` committed write transactions.
+    pub fn every_writes(self, writes: u64) -> Self {
+        Self {
+            writes: Some(writes.max(1)),
+            ..self
+        }
+    }
+
+    /// Checkpoint every `interval`, if there were any writes since the last checkpoint.
+    pub fn every(self, interval: Duration) -> Self {
+        Self {
+            interval: Some(interval),
+            ..self
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.writes.is_some() || self.interval.is_some()
+    }
+}
+
+/// Periodically checkpoints the WAL according to the given strategy. Never returns unless the
+/// strategy is disabled.
+pub(crate) async fn run(pool: Pool, strategy: WalCheckpoint) {
+    let poll_interval = match (strategy.writes, strategy.interval) {
+        (Some(_), Some(interval)) => interval.min(POLL_INTERVAL),
+        (Some(_), None) => POLL_INTERVAL,
+        (None, Some(interval)) => interval,
+        (None, None) => return,
+    };
+
+    let mut last_commits = pool.commit_count();
+    let mut last_time = Instant::now();
+
+    loop {
+        time::sleep(poll_interval).await;
+
+        let commits = pool.commit_count();
+        let writes = commits.saturating_sub(last_commits);
+
+        let due = strategy.writes.is_some_and(|limit| writes >= limit)
+            || strategy
+                .interval
+                .is_some_and(|interval| writes > 0 && last_time.elapsed() >= interval);
+
+        if !due {
+            continue;
+        }
+
+        match pool.checkpoint().await {
+            Ok(true) => tracing::trace!(writes, "WAL checkpoint completed"),
+            Ok(false) => tracing::debug!(writes, "WAL checkpoint not completed (database busy)"),
+            Err(error) => tracing::error!(?error, "Failed to checkpoint WAL"),
+        }
+
+        last_commits = commits;
+        last_time = Instant::now();
+    }
+}
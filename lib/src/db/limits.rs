@@ -0,0 +1,106 @@
+use metrics::{Gauge, Key, KeyName, Level, Metadata, Recorder, Unit};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Limits on the resources used by the databases of multiple repositories (see
+/// [`RepositoryParams::with_resource_limits`](crate::RepositoryParams::with_resource_limits)).
+///
+/// Every repository keeps one write connection to its database open for as long as the
+/// repository is open and opens read connections (each one being a file descriptor) on demand, up
+/// to eight of them. Hosting many repositories can then exhaust the file descriptor limit of the
+/// process. Sharing the same `ResourceLimits` among the repositories caps the number of
+/// connections opened by them: the write connection of every open repository takes one unit of
+/// the limit and all the read connections a repository has open at the same time together take
+/// another one. When the cap is reached, opening a repository or acquiring a read connection in a
+/// repository that has none open waits until some other unit is released.
+///
+/// The read connections of a repository share their unit so that a task which begins a read
+/// transaction while already holding one (e.g., while reading from a
+/// [`Snapshot`](crate::Snapshot)) never waits for the limit. Tasks holding read transactions in
+/// multiple repositories at the same time can still wait for each other if the cap is too low, so
+/// keep it well above twice the number of repositories used concurrently.
+///
+/// While limited, read connections are closed when released instead of being kept idle, to make
+/// the number of open file descriptors predictable. This makes acquiring them slightly slower.
+///
+/// Cloning the handle yields another handle to the same limits.
+#[derive(Clone)]
+pub struct ResourceLimits {
+    connections: Arc<Semaphore>,
+    max_connections: usize,
+    connections_open: Gauge,
+}
+
+impl ResourceLimits {
+    /// Limits the number of connection units (see above) taken at the same time to
+    /// `max_connections`. Zero is treated as one.
+    pub fn new(max_connections: usize) -> Self {
+        let max_connections = max_connections.max(1);
+
+        Self {
+            connections: Arc::new(Semaphore::new(max_connections)),
+            max_connections,
+            connections_open: Gauge::noop(),
+        }
+    }
+
+    /// Records the number of taken connection units (see [`Self::connections_open`]) into the
+    /// "db connections open" gauge of the given recorder. Must be called before the limits are
+    /// shared, the existing clones keep recording into the previous gauge.
+    pub fn with_recorder<R: Recorder>(self, recorder: &R) -> Self {
+        let name = KeyName::from("db connections open");
+        recorder.describe_gauge(name.clone(), Some(Unit::Count), "".into());
+
+        let connections_open = recorder.register_gauge(
+            &Key::from_name(name),
+            &Metadata::new(module_path!(), Level::INFO, None),
+        );
+        connections_open.set(self.connections_open() as f64);
+
+        Self {
+            connections_open,
+            ..self
+        }
+    }
+
+    /// Maximum number of connection units taken at the same time.
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    /// Number of connection units currently taken across all the repositories using these
+    /// limits. This is only approximate with respect to the open file descriptors, because a
+    /// released connection is closed asynchronously and so can stay open shortly after it's no
+    /// longer counted.
+    pub fn connections_open(&self) -> usize {
+        self.max_connections - self.connections.available_permits()
+    }
+
+    pub(super) async fn acquire_connection(&self) -> ConnectionPermit {
+        let permit = self
+            .connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+
+        self.connections_open.increment(1.0);
+
+        ConnectionPermit {
+            _permit: permit,
+            connections_open: self.connections_open.clone(),
+        }
+    }
+}
+
+/// One unit of the [`ResourceLimits`], released on drop.
+pub(super) struct ConnectionPermit {
+    _permit: OwnedSemaphorePermit,
+    connections_open: Gauge,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.connections_open.decrement(1.0);
+    }
+}
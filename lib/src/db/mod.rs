@@ -1,14 +1,23 @@
 #[macro_use]
 mod macros;
 
+mod checkpoint;
 mod connection;
 mod id;
+mod limits;
 mod migrations;
 mod mutex;
+mod synchronous;
 mod transaction;
 
+pub use checkpoint::WalCheckpoint;
 pub use id::DatabaseId;
+use limits::ConnectionPermit;
+pub use limits::ResourceLimits;
 pub use migrations::SCHEMA_VERSION;
+pub use synchronous::Synchronous;
+
+pub(crate) use checkpoint::run as run_wal_checkpoint;
 
 use tracing::Span;
 
@@ -16,13 +25,15 @@ use self::{
     mutex::{CommittedMutexTransaction, ConnectionMutex},
     transaction::TransactionWrapper,
 };
-use deadlock::ExpectShortLifetime;
+use deadlock::{ExpectShortLifetime, Observer};
+use metrics::{Gauge, Histogram};
 use ref_cast::RefCast;
 use sqlx::{
     sqlite::{
-        Sqlite, SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous,
+        Sqlite, SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqliteLockingMode,
+        SqlitePoolOptions,
     },
-    Row, SqlitePool,
+    Connection as _, Row, SqlitePool,
 };
 use std::{
     fmt,
@@ -31,14 +42,15 @@ use std::{
     ops::{Deref, DerefMut},
     panic::Location,
     path::Path,
-    time::Duration,
+    sync::{Arc, Mutex, RwLock, Weak},
+    time::{Duration, Instant},
 };
 #[cfg(test)]
 use tempfile::TempDir;
 use thiserror::Error;
-use tokio::{fs, task};
+use tokio::{fs, sync::Mutex as AsyncMutex, task};
 
-const WARN_AFTER_TRANSACTION_LIFETIME: Duration = Duration::from_secs(3);
+pub(crate) const WARN_AFTER_TRANSACTION_LIFETIME: Duration = Duration::from_secs(3);
 
 pub(crate) use self::connection::Connection;
 
@@ -49,27 +61,145 @@ pub(crate) struct Pool {
     reads: SqlitePool,
     // Single writable connection.
     write: ConnectionMutex,
+    // Tracking of transactions (and connections) that live longer than expected.
+    lifetime_tracking: Arc<RwLock<LifetimeTracking>>,
+    // Metrics of the contention on the write connection.
+    write_metrics: Arc<RwLock<WriteMetrics>>,
+    // Whether all the connections (including the "writable" one) are read-only.
+    read_only: bool,
+    // Limits on the number of connections shared with other pools, if any.
+    resource_limits: Arc<RwLock<Option<ResourceLimits>>>,
+    // Unit of the limits taken by the write connection.
+    write_permit: Arc<Mutex<Option<ConnectionPermit>>>,
+    // Unit of the limits shared by the currently acquired read connections.
+    read_permit: Arc<AsyncMutex<Weak<ConnectionPermit>>>,
 }
 
 impl Pool {
-    async fn create(connect_options: SqliteConnectOptions) -> Result<Self, sqlx::Error> {
+    async fn create(
+        connect_options: SqliteConnectOptions,
+        synchronous: Synchronous,
+    ) -> Result<Self, sqlx::Error> {
         let common_options = connect_options
             .journal_mode(SqliteJournalMode::Wal)
-            .synchronous(SqliteSynchronous::Normal)
+            .synchronous(synchronous.into())
             .pragma("recursive_triggers", "ON")
             .optimize_on_close(true, Some(1000));
 
+        Self::connect(common_options, false).await
+    }
+
+    async fn create_read_only(connect_options: SqliteConnectOptions) -> Result<Self, sqlx::Error> {
+        // `immutable` makes sqlite skip locking and the WAL entirely so nothing is written, not
+        // even the shared-memory WAL index.
+        let common_options = connect_options
+            .read_only(true)
+            .immutable(true)
+            .pragma("recursive_triggers", "ON");
+
+        Self::connect(common_options, true).await
+    }
+
+    async fn connect(
+        common_options: SqliteConnectOptions,
+        read_only: bool,
+    ) -> Result<Self, sqlx::Error> {
         let write_options = common_options.clone();
         let write = ConnectionMutex::connect(write_options).await?;
 
+        let resource_limits: Arc<RwLock<Option<ResourceLimits>>> = Arc::default();
+
         let read_options = common_options.read_only(true);
         let reads = SqlitePoolOptions::new()
             .max_connections(8)
             .test_before_acquire(false)
+            .after_release({
+                let resource_limits = resource_limits.clone();
+
+                // Don't keep idle connections while limited, so the number of open connections
+                // equals the number of acquired permits.
+                move |_, _| {
+                    let keep = resource_limits.read().unwrap().is_none();
+                    Box::pin(async move { Ok(keep) })
+                }
+            })
             .connect_with(read_options)
             .await?;
 
-        Ok(Self { reads, write })
+        Ok(Self {
+            reads,
+            write,
+            lifetime_tracking: Arc::new(RwLock::new(LifetimeTracking {
+                max_lifetime: WARN_AFTER_TRANSACTION_LIFETIME,
+                observer: None,
+            })),
+            write_metrics: Arc::new(RwLock::new(WriteMetrics {
+                wait_time: Histogram::noop(),
+                waiters: Gauge::noop(),
+            })),
+            read_only,
+            resource_limits,
+            write_permit: Arc::new(Mutex::new(None)),
+            read_permit: Arc::new(AsyncMutex::new(Weak::new())),
+        })
+    }
+
+    /// Whether this pool was opened with [`open_read_only`]. All attempts to write to such pool
+    /// fail.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Configures how long can a transaction (or connection) live before a warning is emitted and
+    /// an optional observer to be notified about such warnings. Affects only the transactions
+    /// created after this call.
+    pub fn set_lifetime_tracking(&self, max_lifetime: Duration, observer: Option<Observer>) {
+        *self.lifetime_tracking.write().unwrap() = LifetimeTracking {
+            max_lifetime,
+            observer,
+        };
+    }
+
+    /// Configures the metrics recording the time spent waiting in [`Self::begin_write`] and the
+    /// current number of such waiters.
+    pub fn set_write_metrics(&self, wait_time: Histogram, waiters: Gauge) {
+        *self.write_metrics.write().unwrap() = WriteMetrics { wait_time, waiters };
+    }
+
+    /// Limits the number of connections open at the same time, together with all the other pools
+    /// sharing the same limits. Waits until the write connection can be counted in. Affects only
+    /// the read connections acquired after this call. See [`ResourceLimits`] for details.
+    pub async fn set_resource_limits(&self, limits: ResourceLimits) {
+        let write_permit = limits.acquire_connection().await;
+        *self.write_permit.lock().unwrap() = Some(write_permit);
+
+        *self.read_permit.lock().await = Weak::new();
+        *self.resource_limits.write().unwrap() = Some(limits);
+    }
+
+    // Returns the unit of the limits shared by the read connections of this pool, taking it first
+    // if none of them is currently acquired.
+    async fn acquire_read_permit(&self) -> Option<Arc<ConnectionPermit>> {
+        let limits = self.resource_limits.read().unwrap().clone()?;
+        let mut read_permit = self.read_permit.lock().await;
+
+        if let Some(permit) = read_permit.upgrade() {
+            return Some(permit);
+        }
+
+        let permit = Arc::new(limits.acquire_connection().await);
+        *read_permit = Arc::downgrade(&permit);
+
+        Some(permit)
+    }
+
+    fn track_lifetime(&self, location: &'static Location<'static>) -> ExpectShortLifetime {
+        let tracking = self.lifetime_tracking.read().unwrap();
+        ExpectShortLifetime::new_observed(
+            tracking.max_lifetime,
+            location,
+            tracking.observer.clone(),
+        )
     }
 
     /// Acquire a read-only database connection.
@@ -78,14 +208,14 @@ impl Pool {
         let location = Location::caller();
 
         async move {
+            let permit = self.acquire_read_permit().await;
             let conn = self.reads.acquire().await?;
-
-            let track_lifetime =
-                ExpectShortLifetime::new_in(WARN_AFTER_TRANSACTION_LIFETIME, location);
+            let track_lifetime = self.track_lifetime(location);
 
             Ok(PoolConnection {
                 inner: conn,
                 _track_lifetime: track_lifetime,
+                _permit: permit,
             })
         }
     }
@@ -96,14 +226,14 @@ impl Pool {
         let location = Location::caller();
 
         async move {
+            let permit = self.acquire_read_permit().await;
             let tx = self.reads.begin().await?;
-
-            let track_lifetime =
-                ExpectShortLifetime::new_in(WARN_AFTER_TRANSACTION_LIFETIME, location);
+            let track_lifetime = self.track_lifetime(location);
 
             Ok(ReadTransaction {
                 inner: TransactionWrapper::Pool(tx),
                 _track_lifetime: Some(track_lifetime),
+                _permit: permit,
             })
         }
     }
@@ -114,32 +244,83 @@ impl Pool {
         let location = Location::caller();
 
         async move {
-            let tx = self.write.begin().await?;
+            let metrics = self.write_metrics.read().unwrap().clone();
 
-            let track_lifetime =
-                ExpectShortLifetime::new_in(WARN_AFTER_TRANSACTION_LIFETIME, location);
+            let tx = {
+                let _waiter = WriteWaiter::new(&metrics.waiters);
+                let start = Instant::now();
+                let tx = self.write.begin().await;
+                metrics.wait_time.record(start.elapsed());
+                tx?
+            };
+
+            let track_lifetime = self.track_lifetime(location);
 
             Ok(WriteTransaction {
                 inner: ReadTransaction {
                     inner: TransactionWrapper::Mutex(tx),
                     _track_lifetime: Some(track_lifetime),
+                    _permit: None,
                 },
             })
         }
     }
 
+    /// Checkpoints the WAL and truncates it to zero size. Waits for any ongoing write transaction
+    /// to finish first. Returns whether the checkpoint completed (it might not if there are
+    /// concurrent readers).
+    pub async fn checkpoint(&self) -> Result<bool, sqlx::Error> {
+        self.write.checkpoint().await
+    }
+
+    /// Number of write transactions committed since the pool was created.
+    pub fn commit_count(&self) -> u64 {
+        self.write.commit_count()
+    }
+
     pub(crate) async fn close(&self) -> Result<(), sqlx::Error> {
         self.write.close().await;
+        self.write_permit.lock().unwrap().take();
         self.reads.close().await;
 
         Ok(())
     }
 }
 
+struct LifetimeTracking {
+    max_lifetime: Duration,
+    observer: Option<Observer>,
+}
+
+#[derive(Clone)]
+struct WriteMetrics {
+    wait_time: Histogram,
+    waiters: Gauge,
+}
+
+// Counts a task waiting to begin a write transaction for as long as it exists. Using a guard so
+// the count stays correct even if the waiting future is cancelled.
+struct WriteWaiter<'a>(&'a Gauge);
+
+impl<'a> WriteWaiter<'a> {
+    fn new(gauge: &'a Gauge) -> Self {
+        gauge.increment(1.0);
+        Self(gauge)
+    }
+}
+
+impl Drop for WriteWaiter<'_> {
+    fn drop(&mut self) {
+        self.0.decrement(1.0);
+    }
+}
+
 /// Database connection from pool
 pub(crate) struct PoolConnection {
     inner: sqlx::pool::PoolConnection<Sqlite>,
     _track_lifetime: ExpectShortLifetime,
+    // Released after the connection, see `ResourceLimits`.
+    _permit: Option<Arc<ConnectionPermit>>,
 }
 
 impl Deref for PoolConnection {
@@ -166,6 +347,7 @@ impl DerefMut for PoolConnection {
 pub(crate) struct ReadTransaction {
     inner: TransactionWrapper,
     _track_lifetime: Option<ExpectShortLifetime>,
+    _permit: Option<Arc<ConnectionPermit>>,
 }
 
 impl Deref for ReadTransaction {
@@ -301,8 +483,12 @@ impl std::fmt::Debug for WriteTransaction {
 
 impl_executor_by_deref!(WriteTransaction);
 
-/// Creates a new database and opens a connection to it.
-pub(crate) async fn create(path: impl AsRef<Path>) -> Result<Pool, Error> {
+/// Creates a new database and opens a connection to it. See [`open`] for the meaning of
+/// `synchronous`.
+pub(crate) async fn create(
+    path: impl AsRef<Path>,
+    synchronous: Synchronous,
+) -> Result<Pool, Error> {
     let path = path.as_ref();
 
     if fs::metadata(path).await.is_ok() {
@@ -315,7 +501,9 @@ pub(crate) async fn create(path: impl AsRef<Path>) -> Result<Pool, Error> {
         .filename(path)
         .create_if_missing(true);
 
-    let pool = Pool::create(connect_options).await.map_err(Error::Open)?;
+    let pool = Pool::create(connect_options, synchronous)
+        .await
+        .map_err(Error::Open)?;
 
     migrations::run(&pool).await?;
 
@@ -326,21 +514,95 @@ pub(crate) async fn create(path: impl AsRef<Path>) -> Result<Pool, Error> {
 #[cfg(test)]
 pub(crate) async fn create_temp() -> Result<(TempDir, Pool), Error> {
     let temp_dir = TempDir::new().map_err(Error::CreateDirectory)?;
-    let pool = create(temp_dir.path().join("temp.db")).await?;
+    let pool = create(temp_dir.path().join("temp.db"), Synchronous::default()).await?;
 
     Ok((temp_dir, pool))
 }
 
 /// Opens a connection to the specified database. Fails if the db doesn't exist.
-pub(crate) async fn open(path: impl AsRef<Path>) -> Result<Pool, Error> {
+///
+/// NOTE: The temporary files sqlite needs as scratch space (e.g., for large sorts or transactions)
+/// are created in the system temp directory. Sqlite supports only a single, process-wide temp
+/// directory which on unix can be changed by setting the `SQLITE_TMPDIR` environment variable.
+/// It's read only once, so it has to be set by the host process before it starts (it's not safe to
+/// set it at runtime from a multi-threaded process).
+///
+/// `synchronous` controls the durability of the writes (see [`Synchronous`]). It's not stored in
+/// the database so it needs to be passed every time the database is opened.
+pub(crate) async fn open(path: impl AsRef<Path>, synchronous: Synchronous) -> Result<Pool, Error> {
     let connect_options = SqliteConnectOptions::new().filename(path);
-    let pool = Pool::create(connect_options).await.map_err(Error::Open)?;
+    let pool = Pool::create(connect_options, synchronous)
+        .await
+        .map_err(Error::Open)?;
 
     migrations::run(&pool).await?;
 
     Ok(pool)
 }
 
+/// Opens the specified database in read-only mode. Nothing is ever written to the database, which
+/// makes it possible to open databases on read-only filesystems. Fails if the db doesn't exist or
+/// if its schema is outdated, because the migrations can't be applied.
+///
+/// NOTE: Modifications that are still in the write-ahead log (i.e., not yet checkpointed into the
+/// main database file) are not visible.
+pub(crate) async fn open_read_only(path: impl AsRef<Path>) -> Result<Pool, Error> {
+    let connect_options = SqliteConnectOptions::new().filename(path);
+    let pool = Pool::create_read_only(connect_options)
+        .await
+        .map_err(Error::Open)?;
+
+    migrations::check(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Merges the write-ahead log of the specified database into the main database file and removes
+/// it, so that the main file can be moved on its own. Fails with `io::ErrorKind::WouldBlock` if
+/// the database is currently open (by this or any other process).
+pub(crate) async fn close_wal(path: impl AsRef<Path>) -> io::Result<()> {
+    // In the exclusive locking mode the first read requires an exclusive lock on the database file
+    // which can't be acquired while any other connection to it is open, because connections in the
+    // WAL mode keep a shared lock for as long as they are open.
+    let connect_options = SqliteConnectOptions::new()
+        .filename(path)
+        .locking_mode(SqliteLockingMode::Exclusive)
+        .busy_timeout(Duration::ZERO);
+
+    let result = async {
+        let mut conn = SqliteConnection::connect_with(&connect_options).await?;
+
+        sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .execute(&mut conn)
+            .await?;
+
+        // Closing the last connection removes the WAL.
+        conn.close().await
+    }
+    .await;
+
+    match result {
+        Ok(()) => Ok(()),
+        Err(error) if is_busy(&error) => Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "database is in use",
+        )),
+        Err(error) => Err(io::Error::new(io::ErrorKind::Other, error)),
+    }
+}
+
+fn is_busy(error: &sqlx::Error) -> bool {
+    const SQLITE_BUSY: i32 = 5;
+
+    // The code is the extended result code whose lowest byte is the primary result code.
+    error
+        .as_database_error()
+        .and_then(|error| error.code())
+        .and_then(|code| code.parse::<i32>().ok())
+        .map(|code| code & 0xff == SQLITE_BUSY)
+        .unwrap_or(false)
+}
+
 async fn create_directory(path: &Path) -> Result<(), Error> {
     if let Some(dir) = path.parent() {
         fs::create_dir_all(dir)
@@ -373,6 +635,8 @@ pub enum Error {
     Open(#[source] sqlx::Error),
     #[error("failed to execute database query")]
     Query(#[from] sqlx::Error),
+    #[error("database schema is outdated")]
+    OutdatedSchema,
 }
 
 async fn get_pragma(conn: &mut Connection, name: &str) -> Result<u32, Error> {
@@ -409,6 +673,215 @@ mod tests {
         assert_eq!(decode_u64(i64::MAX), u64::MAX / 2);
     }
 
+    #[tokio::test]
+    async fn checkpoint() {
+        let (temp_dir, pool) = create_temp().await.unwrap();
+        let wal_path = temp_dir.path().join("temp.db-wal");
+
+        let commits = pool.commit_count();
+
+        let mut tx = pool.begin_write().await.unwrap();
+        sqlx::query("CREATE TABLE test (value INTEGER)")
+            .execute(&mut tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+
+        assert_eq!(pool.commit_count(), commits + 1);
+        assert!(fs::metadata(&wal_path).await.unwrap().len() > 0);
+
+        assert!(pool.checkpoint().await.unwrap());
+        assert_eq!(fs::metadata(&wal_path).await.unwrap().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn synchronous() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("temp.db");
+
+        async fn get(pool: &Pool) -> u32 {
+            let mut tx = pool.begin_write().await.unwrap();
+            sqlx::query("PRAGMA synchronous")
+                .fetch_one(&mut tx)
+                .await
+                .unwrap()
+                .get(0)
+        }
+
+        let pool = create(&path, Synchronous::Full).await.unwrap();
+        assert_eq!(get(&pool).await, 2);
+        pool.close().await.unwrap();
+
+        let pool = open(&path, Synchronous::Off).await.unwrap();
+        assert_eq!(get(&pool).await, 0);
+    }
+
+    #[tokio::test]
+    async fn resource_limits() {
+        let (_temp_dir_a, pool_a) = create_temp().await.unwrap();
+        let (_temp_dir_b, pool_b) = create_temp().await.unwrap();
+
+        let limits = ResourceLimits::new(3);
+        pool_a.set_resource_limits(limits.clone()).await;
+        pool_b.set_resource_limits(limits.clone()).await;
+
+        // The write connections are counted.
+        assert_eq!(limits.connections_open(), 2);
+
+        let conn = pool_a.acquire().await.unwrap();
+        assert_eq!(limits.connections_open(), 3);
+
+        // The limit is shared by both pools.
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), pool_b.begin_read())
+                .await
+                .is_err()
+        );
+
+        drop(conn);
+
+        let tx = tokio::time::timeout(Duration::from_secs(5), pool_b.begin_read())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(limits.connections_open(), 3);
+
+        drop(tx);
+        assert_eq!(limits.connections_open(), 2);
+
+        pool_a.close().await.unwrap();
+        assert_eq!(limits.connections_open(), 1);
+    }
+
+    #[tokio::test]
+    async fn resource_limits_nested_reads() {
+        let (_temp_dir_a, pool_a) = create_temp().await.unwrap();
+        let (_temp_dir_b, pool_b) = create_temp().await.unwrap();
+
+        let limits = ResourceLimits::new(3);
+        pool_a.set_resource_limits(limits.clone()).await;
+        pool_b.set_resource_limits(limits.clone()).await;
+
+        // The nested read transaction shares the unit of the outer one.
+        let tx0 = pool_a.begin_read().await.unwrap();
+        let tx1 = tokio::time::timeout(Duration::from_secs(5), pool_a.begin_read())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(limits.connections_open(), 3);
+
+        // The unit is released only after both of them are.
+        drop(tx0);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), pool_b.begin_read())
+                .await
+                .is_err()
+        );
+
+        drop(tx1);
+
+        let tx2 = tokio::time::timeout(Duration::from_secs(5), pool_b.begin_read())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(limits.connections_open(), 3);
+
+        drop(tx2);
+        assert_eq!(limits.connections_open(), 2);
+    }
+
+    #[tokio::test]
+    async fn slow_transaction_observer() {
+        let (_temp_dir, pool) = create_temp().await.unwrap();
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let observer: Observer = Arc::new(move |warning: &deadlock::LifetimeWarning| {
+            tx.send(warning.location).ok();
+        });
+        pool.set_lifetime_tracking(Duration::from_millis(10), Some(observer));
+
+        let location = Location::caller();
+        let write_tx = pool.begin_write().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        drop(write_tx);
+
+        let reported = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reported.file(), location.file());
+    }
+
+    #[tokio::test]
+    async fn write_metrics() {
+        let (_temp_dir, pool) = create_temp().await.unwrap();
+
+        let wait_time = Arc::new(TestHistogram::default());
+        let waiters = Arc::new(TestGauge::default());
+        pool.set_write_metrics(
+            Histogram::from_arc(wait_time.clone()),
+            Gauge::from_arc(waiters.clone()),
+        );
+
+        let write_tx = pool.begin_write().await.unwrap();
+        assert_eq!(waiters.get(), 0.0);
+
+        let task = task::spawn({
+            let pool = pool.clone();
+            async move { pool.begin_write().await.unwrap() }
+        });
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while waiters.get() != 1.0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        drop(write_tx);
+        drop(task.await.unwrap());
+
+        assert_eq!(waiters.get(), 0.0);
+
+        let values = wait_time.0.lock().unwrap().clone();
+        assert_eq!(values.len(), 2);
+        assert!(values[1] >= 0.1);
+    }
+
+    #[derive(Default)]
+    struct TestGauge(std::sync::Mutex<f64>);
+
+    impl TestGauge {
+        fn get(&self) -> f64 {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    impl metrics::GaugeFn for TestGauge {
+        fn increment(&self, value: f64) {
+            *self.0.lock().unwrap() += value;
+        }
+
+        fn decrement(&self, value: f64) {
+            *self.0.lock().unwrap() -= value;
+        }
+
+        fn set(&self, value: f64) {
+            *self.0.lock().unwrap() = value;
+        }
+    }
+
+    #[derive(Default)]
+    struct TestHistogram(std::sync::Mutex<Vec<f64>>);
+
+    impl metrics::HistogramFn for TestHistogram {
+        fn record(&self, value: f64) {
+            self.0.lock().unwrap().push(value);
+        }
+    }
+
     #[test]
     fn encode_u64_sanity_check() {
         assert_eq!(encode_u64(0), 0);
@@ -1,10 +1,13 @@
 use sqlx::{
     sqlite::{SqliteConnectOptions, SqliteTransactionManager},
-    Connection, SqliteConnection, TransactionManager,
+    Connection, Row, SqliteConnection, TransactionManager,
 };
 use std::{
     ops::{Deref, DerefMut},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 use tokio::sync::{Mutex, OwnedMutexGuard};
 
@@ -15,24 +18,51 @@ use tokio::sync::{Mutex, OwnedMutexGuard};
 /// delay releasing the connection (unlocking the mutex) even after the transaction itself has been
 /// committed.
 #[derive(Clone)]
-pub(super) struct ConnectionMutex(Arc<Mutex<Option<SqliteConnection>>>);
+pub(super) struct ConnectionMutex {
+    conn: Arc<Mutex<Option<SqliteConnection>>>,
+    // Number of successfully committed transactions.
+    commits: Arc<AtomicU64>,
+}
 
 impl ConnectionMutex {
     pub async fn connect(options: SqliteConnectOptions) -> sqlx::Result<Self> {
         let conn = SqliteConnection::connect_with(&options).await?;
-        Ok(Self(Arc::new(Mutex::new(Some(conn)))))
+        Ok(Self {
+            conn: Arc::new(Mutex::new(Some(conn))),
+            commits: Arc::new(AtomicU64::new(0)),
+        })
     }
 
     /// Begins a transaction.
     pub async fn begin(&self) -> sqlx::Result<MutexTransaction> {
-        let conn = self.0.clone().lock_owned().await;
-        MutexTransaction::begin(conn).await
+        let conn = self.conn.clone().lock_owned().await;
+        MutexTransaction::begin(conn, self.commits.clone()).await
+    }
+
+    /// Checkpoints the WAL and truncates it. Waits for the connection to be released (if checked
+    /// out) so the checkpoint never runs concurrently with a write transaction. Returns whether
+    /// the checkpoint completed (it might not if there are concurrent readers).
+    pub async fn checkpoint(&self) -> sqlx::Result<bool> {
+        let mut conn = self.conn.lock().await;
+        let conn = conn.as_mut().ok_or(sqlx::Error::PoolClosed)?;
+
+        let busy: i64 = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .fetch_one(conn)
+            .await?
+            .get(0);
+
+        Ok(busy == 0)
+    }
+
+    /// Number of transactions successfully committed so far.
+    pub fn commit_count(&self) -> u64 {
+        self.commits.load(Ordering::Relaxed)
     }
 
     /// Waits for the connection to be released (if checked out) and then closes it. Any subsequent
     /// attempts to check the connection out return an error.
     pub async fn close(&self) {
-        let Some(conn) = self.0.lock().await.take() else {
+        let Some(conn) = self.conn.lock().await.take() else {
             return;
         };
 
@@ -45,15 +75,20 @@ impl ConnectionMutex {
 /// Db transaction obtained from the connection in `ConnectionMutex`.
 pub(super) struct MutexTransaction {
     conn: OwnedMutexGuard<Option<SqliteConnection>>,
+    commits: Arc<AtomicU64>,
     closed: bool,
 }
 
 impl MutexTransaction {
-    async fn begin(mut conn: OwnedMutexGuard<Option<SqliteConnection>>) -> sqlx::Result<Self> {
+    async fn begin(
+        mut conn: OwnedMutexGuard<Option<SqliteConnection>>,
+        commits: Arc<AtomicU64>,
+    ) -> sqlx::Result<Self> {
         SqliteTransactionManager::begin(conn.as_mut().ok_or(sqlx::Error::PoolClosed)?).await?;
 
         Ok(Self {
             conn,
+            commits,
             closed: false,
         })
     }
@@ -64,6 +99,7 @@ impl MutexTransaction {
     pub async fn commit(mut self) -> sqlx::Result<CommittedMutexTransaction> {
         SqliteTransactionManager::commit(&mut self).await?;
         self.closed = true;
+        self.commits.fetch_add(1, Ordering::Relaxed);
 
         Ok(CommittedMutexTransaction(self))
     }
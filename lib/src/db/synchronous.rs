@@ -0,0 +1,36 @@
+use sqlx::sqlite::SqliteSynchronous;
+
+/// How often does sqlite wait for the data to be actually written to the disk (`fsync`), trading
+/// durability for write performance. The database always uses the write-ahead log (WAL) and these
+/// levels are described with that in mind.
+///
+/// See <https://www.sqlite.org/pragma.html#pragma_synchronous> for more details.
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub enum Synchronous {
+    /// Never wait for the data to reach the disk. Fastest, but the database can get corrupted if
+    /// the operating system crashes or the power is lost (crash of the app alone is safe).
+    Off,
+    /// Sync only when checkpointing the WAL. The database never gets corrupted, but the most
+    /// recently committed transactions can be rolled back after a power loss or an OS crash. This
+    /// is the default and a good fit for most uses, as the lost changes are re-synced from the
+    /// peers if they've been shared already.
+    #[default]
+    Normal,
+    /// Sync after every committed transaction. Committed transactions survive a power loss too,
+    /// at the cost of slower writes.
+    Full,
+    /// Like `Full`, but additionally syncs the directory containing the database, for extra
+    /// durability on filesystems that need it.
+    Extra,
+}
+
+impl From<Synchronous> for SqliteSynchronous {
+    fn from(value: Synchronous) -> Self {
+        match value {
+            Synchronous::Off => Self::Off,
+            Synchronous::Normal => Self::Normal,
+            Synchronous::Full => Self::Full,
+            Synchronous::Extra => Self::Extra,
+        }
+    }
+}
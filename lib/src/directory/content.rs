@@ -3,6 +3,7 @@
 use super::entry_data::EntryData;
 use crate::{
     blob::BlobId,
+    crypto::sign::PublicKey,
     error::{Error, Result},
     protocol::Bump,
     version_vector::VersionVector,
@@ -12,49 +13,92 @@ use std::{
     cmp::Ordering,
     collections::{
         btree_map::{self, Entry},
-        BTreeMap,
+        BTreeMap, BTreeSet,
     },
 };
 
 /// Version of the Directory serialization format.
 pub const VERSION: u64 = 2;
 
+/// Version of the Directory serialization format used for directories with an ACL. Directories
+/// without an ACL are still written using `VERSION` so they remain readable by older replicas.
+/// Replicas that don't know this version can't read directories with an ACL, which is why the
+/// network protocol version was bumped along with it.
+pub(super) const VERSION_WITH_ACL: u64 = 3;
+
+/// Set of writers allowed to modify the entries of a directory.
+pub type Acl = BTreeSet<PublicKey>;
+
 #[derive(Clone, Debug)]
 pub(super) struct Content {
     entries: v2::Entries,
+    acl: Option<Acl>,
 }
 
 impl Content {
     pub fn empty() -> Self {
         Self {
             entries: BTreeMap::new(),
+            acl: None,
         }
     }
 
     pub fn deserialize(mut input: &[u8]) -> Result<Self> {
         let version = vint64::decode(&mut input).map_err(|_| Error::MalformedDirectory)?;
-        let entries = match version {
-            VERSION => deserialize_entries(input),
-            1 => Ok(v2::from_v1(deserialize_entries(input)?)),
-            0 => Ok(v2::from_v1(v1::from_v0(deserialize_entries(input)?))),
-            _ => Err(Error::StorageVersionMismatch),
+        let (entries, acl) = match version {
+            VERSION_WITH_ACL => {
+                let (entries, acl) = deserialize_entries(input)?;
+                (entries, Some(acl))
+            }
+            VERSION => (deserialize_entries(input)?, None),
+            1 => (v2::from_v1(deserialize_entries(input)?), None),
+            0 => (v2::from_v1(v1::from_v0(deserialize_entries(input)?)), None),
+            _ => return Err(Error::StorageVersionMismatch),
         };
 
-        Ok(Self { entries: entries? })
+        Ok(Self { entries, acl })
     }
 
     pub fn serialize(&self) -> Vec<u8> {
         let mut output = Vec::new();
-        output.extend_from_slice(vint64::encode(VERSION).as_ref());
-        bincode::serialize_into(&mut output, &self.entries)
-            .expect("failed to serialize directory content");
+
+        let result = if let Some(acl) = &self.acl {
+            output.extend_from_slice(vint64::encode(VERSION_WITH_ACL).as_ref());
+            bincode::serialize_into(&mut output, &(&self.entries, acl))
+        } else {
+            output.extend_from_slice(vint64::encode(VERSION).as_ref());
+            bincode::serialize_into(&mut output, &self.entries)
+        };
+
+        result.expect("failed to serialize directory content");
         output
     }
 
+    pub fn acl(&self) -> Option<&Acl> {
+        self.acl.as_ref()
+    }
+
+    pub fn set_acl(&mut self, acl: Option<Acl>) {
+        self.acl = acl;
+    }
+
+    /// Returns whether the given writer is allowed to modify the entries of this directory
+    /// according to its ACL. Always true if the directory has no ACL.
+    pub fn is_writable_by(&self, writer_id: &PublicKey) -> bool {
+        self.acl
+            .as_ref()
+            .map(|acl| acl.contains(writer_id))
+            .unwrap_or(true)
+    }
+
     pub fn iter(&self) -> btree_map::Iter<String, EntryData> {
         self.entries.iter()
     }
 
+    pub fn into_entries(self) -> btree_map::IntoIter<String, EntryData> {
+        self.entries.into_iter()
+    }
+
     pub fn get_key_value(&self, name: &str) -> Option<(&String, &EntryData)> {
         self.entries.get_key_value(name)
     }
@@ -150,6 +194,21 @@ fn deserialize_entries<'a, T: Deserialize<'a>>(input: &'a [u8]) -> Result<T, Err
     bincode::deserialize(input).map_err(|_| Error::MalformedDirectory)
 }
 
+/// Resolves the ACL of a directory being forked from a remote version, given the order of the
+/// remote version relative to the local one.
+pub fn merge_acl(
+    local: Option<&Acl>,
+    remote: Option<&Acl>,
+    order: Option<Ordering>,
+) -> Option<Acl> {
+    match order {
+        Some(Ordering::Greater) => remote.cloned(),
+        Some(Ordering::Equal | Ordering::Less) => local.cloned(),
+        // Concurrent versions: keep the local ACL unless there isn't any.
+        None => local.or(remote).cloned(),
+    }
+}
+
 fn check_replace(old: &EntryData, new: &EntryData) -> Result<Option<BlobId>, EntryExists> {
     // Replace entries only if the new version is more up to date than the old version.
 
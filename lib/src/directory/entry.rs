@@ -9,7 +9,7 @@ use crate::{
     branch::Branch,
     crypto::sign::PublicKey,
     error::{Error, Result},
-    file::File,
+    file::{File, SharedFile},
     protocol::Locator,
     store::ReadTransaction,
     version_vector::VersionVector,
@@ -148,6 +148,18 @@ impl<'a> FileRef<'a> {
         File::open(branch, locator, parent_context).await
     }
 
+    /// Opens the file for sharing among multiple tasks. If the file is already open for sharing,
+    /// returns a new handle to it. See [`SharedFile`] for details.
+    pub async fn open_shared(&self) -> Result<SharedFile> {
+        let shared_files = self.branch().shared_files();
+
+        if let Some(file) = shared_files.get(*self.branch().id(), *self.blob_id()) {
+            return Ok(file);
+        }
+
+        Ok(shared_files.insert(self.open().await?))
+    }
+
     /// Fork the file without opening it.
     pub(crate) async fn fork(&self, dst_branch: &Branch) -> Result<()> {
         if self.branch().id() == dst_branch.id() {
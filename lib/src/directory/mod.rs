@@ -3,20 +3,22 @@ mod entry;
 mod entry_data;
 mod entry_type;
 mod parent_context;
+mod reader;
 #[cfg(test)]
 mod tests;
 
 pub use self::{
-    content::VERSION as DIRECTORY_VERSION,
+    content::{Acl, VERSION as DIRECTORY_VERSION},
     entry::{DirectoryRef, EntryRef, FileRef},
     entry_type::EntryType,
 };
 pub(crate) use self::{
     entry_data::{EntryData, EntryTombstoneData, TombstoneCause},
     parent_context::ParentContext,
+    reader::EntryReader,
 };
 
-use self::content::Content;
+use self::content::{self as dir_content, Content};
 use crate::{
     blob::{lock::ReadLock, Blob, BlobId},
     branch::Branch,
@@ -29,7 +31,7 @@ use crate::{
     version_vector::VersionVector,
 };
 use async_recursion::async_recursion;
-use std::{cmp::Ordering, fmt, mem};
+use std::{cmp::Ordering, collections::HashSet, fmt, mem};
 use tracing::instrument;
 
 #[derive(Clone)]
@@ -130,6 +132,7 @@ impl Directory {
         let mut changeset = Changeset::new();
 
         self.refresh_in(&mut tx).await?;
+        self.check_acl()?;
 
         let blob_id = rand::random();
         let version_vector = self
@@ -183,6 +186,7 @@ impl Directory {
         let mut changeset = Changeset::new();
 
         self.refresh_in(&mut tx).await?;
+        self.check_acl()?;
 
         let (dir, content) = self
             .create_directory_in(lock, &mut tx, &mut changeset, name, blob_id, merge)
@@ -235,6 +239,50 @@ impl Directory {
         )
     }
 
+    /// Returns the set of writers allowed to modify the entries of this directory or `None` if
+    /// the directory is not restricted.
+    ///
+    /// Note the ACL is only advisory: it's enforced by the local replica only and can't prevent
+    /// modifications by replicas that choose to ignore it.
+    pub fn acl(&self) -> Option<&Acl> {
+        self.content.acl()
+    }
+
+    /// Sets the writers allowed to create, remove and move entries in this directory. `None`
+    /// lifts the restriction. Fails with `PermissionDenied` if the directory already has an ACL
+    /// which doesn't include the local writer.
+    pub(crate) async fn set_acl(&mut self, acl: Option<Acl>) -> Result<()> {
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+        self.check_acl()?;
+
+        let mut content = self.content.clone();
+        content.set_acl(acl);
+
+        self.save(&mut tx, &mut changeset, &content).await?;
+        self.bump(
+            &mut tx,
+            &mut changeset,
+            Bump::increment(*self.branch().id()),
+        )
+        .await?;
+        self.commit(tx, changeset).await?;
+        self.finalize(content);
+
+        Ok(())
+    }
+
+    /// Checks that the local writer is allowed to modify the entries of this directory.
+    fn check_acl(&self) -> Result<()> {
+        if self.content.is_writable_by(self.branch().id()) {
+            Ok(())
+        } else {
+            Err(Error::PermissionDenied)
+        }
+    }
+
     /// Removes a file or subdirectory from this directory. If the entry to be removed is a
     /// directory, it needs to be empty or a `DirectoryNotEmpty` error is returned.
     ///
@@ -255,6 +303,9 @@ impl Directory {
         let mut tx = self.branch().store().begin_write().await?;
         let mut changeset = Changeset::new();
 
+        self.refresh_in(&mut tx).await?;
+        self.check_acl()?;
+
         // If we are removing a directory, ensure it's empty (recursive removal can still be
         // implemented at the upper layers).
         self.check_directory_empty(&mut tx, name).await?;
@@ -276,6 +327,55 @@ impl Directory {
         Ok(())
     }
 
+    /// Removes multiple entries in a single transaction. If any of the removals fails, none of the
+    /// entries is removed. The version vector of this directory is bumped only once.
+    pub(crate) async fn remove_entries(
+        &mut self,
+        entries: Vec<(String, PublicKey, VersionVector)>,
+    ) -> Result<()> {
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+        self.check_acl()?;
+
+        for (name, _, _) in &entries {
+            self.check_directory_empty(&mut tx, name).await?;
+        }
+
+        let mut content = self.content.clone();
+        let mut diff = VersionVector::new();
+        let mut removed = HashSet::new();
+
+        for (name, branch_id, mut version_vector) in entries {
+            // If another version of this entry has already been removed in this batch, the entry
+            // has been bumped since the caller obtained `version_vector`, so take that into
+            // account. Otherwise removing multiple concurrent versions of the same entry would
+            // fail or resurrect some of them.
+            if !removed.insert(name.clone()) {
+                if let Some((_, data)) = content.get_key_value(&name) {
+                    version_vector.merge(data.version_vector());
+                }
+            }
+
+            let new_data = self.removed_entry_data(
+                &content,
+                &name,
+                &branch_id,
+                version_vector,
+                TombstoneCause::Removed,
+            );
+            diff += &content.insert(name, new_data)?;
+        }
+
+        self.save(&mut tx, &mut changeset, &content).await?;
+        self.bump(&mut tx, &mut changeset, Bump::Add(diff)).await?;
+        self.commit(tx, changeset).await?;
+        self.finalize(content);
+
+        Ok(())
+    }
+
     /// Creates a tombstone for entry with the given name. If the entry exists, this effectively
     /// removes it. If it doesn't exist, it still creates the tombstone. This method is meant to be
     /// used for merging removed entries from other branches. For removing entries locally, use
@@ -350,6 +450,11 @@ impl Directory {
 
         let mut tx = self.branch().store().begin_write().await?;
 
+        self.refresh_in(&mut tx).await?;
+        self.check_acl()?;
+        dst_dir.refresh_in(&mut tx).await?;
+        dst_dir.check_acl()?;
+
         let mut changeset = Changeset::new();
         let dst_content = dst_dir
             .begin_insert_entry(&mut tx, &mut changeset, dst_name.to_owned(), dst_data)
@@ -405,16 +510,63 @@ impl Directory {
         // at the time it was initially created.
         let (parent, current_vv, initial_vv) = self.prepare_fork().await?;
 
-        if let Some((parent_dir, entry_name)) = parent {
+        let (mut dir, old_vv) = if let Some((parent_dir, entry_name)) = parent {
             let mut parent_dir = parent_dir.fork(dst_branch).await?;
             let blob_id = *self.blob_id();
 
-            parent_dir
-                .fork_into(entry_name, blob_id, current_vv, initial_vv)
-                .await
+            let old_vv = match parent_dir.lookup(entry_name) {
+                Ok(EntryRef::Directory(entry)) => entry.version_vector().clone(),
+                Ok(EntryRef::File(_) | EntryRef::Tombstone(_)) | Err(Error::EntryNotFound) => {
+                    VersionVector::new()
+                }
+                Err(error) => return Err(error),
+            };
+
+            let dir = parent_dir
+                .fork_into(entry_name, blob_id, current_vv.clone(), initial_vv)
+                .await?;
+
+            (dir, old_vv)
         } else {
-            Self::open_or_create_root(dst_branch.clone(), initial_vv).await
+            let old_vv = dst_branch.version_vector().await?;
+            let dir = Self::open_or_create_root(dst_branch.clone(), initial_vv).await?;
+
+            (dir, old_vv)
+        };
+
+        dir.fork_acl(self.content.acl(), current_vv.partial_cmp(&old_vv))
+            .await?;
+
+        Ok(dir)
+    }
+
+    /// Updates the ACL of this (forked) directory from the ACL of the remote version it was
+    /// forked from. `order` is the order of the remote version relative to the local one before
+    /// the fork.
+    async fn fork_acl(&mut self, remote: Option<&Acl>, order: Option<Ordering>) -> Result<()> {
+        // Fast path to avoid the write transaction in the common case of no ACL.
+        if remote.is_none() && self.content.acl().is_none() {
+            return Ok(());
         }
+
+        let mut tx = self.branch().store().begin_write().await?;
+        let mut changeset = Changeset::new();
+
+        self.refresh_in(&mut tx).await?;
+
+        let acl = dir_content::merge_acl(self.content.acl(), remote, order);
+        if acl.as_ref() == self.content.acl() {
+            return Ok(());
+        }
+
+        let mut content = self.content.clone();
+        content.set_acl(acl);
+
+        self.save(&mut tx, &mut changeset, &content).await?;
+        self.commit(tx, changeset).await?;
+        self.finalize(content);
+
+        Ok(())
     }
 
     /// Prepares information needed to fork this directory.
@@ -639,6 +791,24 @@ impl Directory {
         self.parent.is_none()
     }
 
+    /// Opens the directory as of the given snapshot of its branch. The directory has no parent
+    /// context and is meant for reading only.
+    pub(crate) async fn open_at(
+        tx: &mut ReadTransaction,
+        root_node: &RootNode,
+        branch: Branch,
+        blob_id: BlobId,
+    ) -> Result<Self> {
+        let (blob, content) = load_at(tx, root_node, branch, blob_id).await?;
+
+        Ok(Self {
+            blob,
+            parent: None,
+            content,
+            lock: None,
+        })
+    }
+
     async fn open_in(
         lock: Option<ReadLock>,
         tx: &mut ReadTransaction,
@@ -800,28 +970,40 @@ impl Directory {
         version_vector: VersionVector,
         cause: TombstoneCause,
     ) -> Result<Content> {
-        let mut new_data = match self.lookup(name) {
-            Ok(old_entry)
+        let new_data =
+            self.removed_entry_data(&self.content, name, branch_id, version_vector, cause);
+
+        self.begin_insert_entry(tx, changeset, name.to_owned(), new_data)
+            .await
+    }
+
+    // Returns the data to be inserted into `content` in place of the removed entry.
+    fn removed_entry_data(
+        &self,
+        content: &Content,
+        name: &str,
+        branch_id: &PublicKey,
+        version_vector: VersionVector,
+        cause: TombstoneCause,
+    ) -> EntryData {
+        let mut new_data = match content.get_key_value(name) {
+            Some((_, old_data))
                 if branch_id != self.branch().id()
-                    && old_entry
+                    && old_data
                         .version_vector()
                         .partial_cmp(&version_vector)
                         .is_none() =>
             {
-                let mut new_data = old_entry.clone_data();
+                let mut new_data = old_data.clone();
                 new_data.version_vector_mut().merge(&version_vector);
                 new_data
             }
-            Ok(_) | Err(Error::EntryNotFound) => {
-                EntryData::Tombstone(EntryTombstoneData::new(cause, version_vector))
-            }
-            Err(error) => return Err(error),
+            _ => EntryData::Tombstone(EntryTombstoneData::new(cause, version_vector)),
         };
 
         new_data.version_vector_mut().increment(*self.branch().id());
 
-        self.begin_insert_entry(tx, changeset, name.to_owned(), new_data)
-            .await
+        new_data
     }
 
     async fn check_directory_empty(&self, tx: &mut ReadTransaction, name: &str) -> Result<()> {
@@ -63,7 +63,8 @@ impl ParentContext {
     }
 
     /// Atomically forks the blob of this entry into the local branch and returns the updated
-    /// parent context.
+    /// parent context. The `bump` is applied to the version vector of the forked entry (use
+    /// `Bump::default()` to keep the version vector of the source entry).
     // TODO: move this function to the `file` mod.
     #[instrument(
         skip_all,
@@ -73,9 +74,10 @@ impl ParentContext {
         ),
         err(Debug)
     )]
-    pub async fn fork(&self, src_branch: &Branch, dst_branch: &Branch) -> Result<Self> {
+    pub async fn fork(&self, src_branch: &Branch, dst_branch: &Branch, bump: Bump) -> Result<Self> {
         let directory = self.open(src_branch.clone()).await?;
-        let src_entry_data = directory.lookup(&self.entry_name)?.clone_data();
+        let mut src_entry_data = directory.lookup(&self.entry_name)?.clone_data();
+        bump.apply(src_entry_data.version_vector_mut());
         let new_blob_id = *src_entry_data.blob_id().ok_or(Error::EntryNotFound)?;
         Span::current().record("blob_id", field::debug(&new_blob_id));
 
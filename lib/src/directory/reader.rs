@@ -0,0 +1,142 @@
+//! Incremental reading of directory entries.
+
+use super::{
+    content::{Content, VERSION, VERSION_WITH_ACL},
+    entry_data::EntryData,
+};
+use crate::{
+    blob::{lock::ReadLock, Blob, BlobId},
+    branch::Branch,
+    error::{Error, Result},
+    protocol::{RootNode, RootNodeFilter, BLOCK_SIZE},
+};
+use std::{collections::btree_map, io, mem};
+
+// Size of the serialized entry count.
+const COUNT_LEN: usize = mem::size_of::<u64>();
+
+/// Reads the entries of a directory one by one, loading the underlying blob gradually instead of
+/// all at once. The entries are yielded in the order of their names.
+///
+/// The entries are read from the snapshot of the branch that was current at the time the reader
+/// was opened. Directories stored in the legacy formats are loaded in full on open.
+pub(crate) struct EntryReader {
+    blob: Blob,
+    root_node: RootNode,
+    _lock: ReadLock,
+    // Data read from the blob but not decoded yet starts at `offset`.
+    buffer: Vec<u8>,
+    offset: usize,
+    state: State,
+}
+
+enum State {
+    // Number of entries remaining to be decoded from the blob.
+    Streaming(u64),
+    // All entries already decoded.
+    Loaded(btree_map::IntoIter<String, EntryData>),
+}
+
+impl EntryReader {
+    pub async fn open(branch: Branch, blob_id: BlobId) -> Result<Self> {
+        let lock = branch.locker().read(blob_id).await;
+
+        let mut tx = branch.store().begin_read().await?;
+        let root_node = tx.load_root_node(branch.id(), RootNodeFilter::Any).await?;
+        let blob = Blob::open_at(&mut tx, &root_node, branch, blob_id).await?;
+        drop(tx);
+
+        let mut reader = Self {
+            blob,
+            root_node,
+            _lock: lock,
+            buffer: Vec::new(),
+            offset: 0,
+            state: State::Streaming(0),
+        };
+
+        // The header is tiny so it's guaranteed to be in the first chunk.
+        reader.read_chunk().await?;
+
+        let mut input = &reader.buffer[..];
+        let version = vint64::decode(&mut input).map_err(|_| Error::MalformedDirectory)?;
+
+        reader.state = match version {
+            // The entries are serialized first in both these versions, prefixed by their count.
+            VERSION | VERSION_WITH_ACL => {
+                let count: u64 =
+                    bincode::deserialize(input).map_err(|_| Error::MalformedDirectory)?;
+                let header_len = reader.buffer.len() - input.len() + COUNT_LEN;
+
+                reader.offset = header_len;
+                State::Streaming(count)
+            }
+            _ => {
+                while reader.read_chunk().await? > 0 {}
+                State::Loaded(Content::deserialize(&reader.buffer)?.into_entries())
+            }
+        };
+
+        Ok(reader)
+    }
+
+    /// Returns the next entry or `None` if there are no more entries.
+    pub async fn next(&mut self) -> Result<Option<(String, EntryData)>> {
+        let remaining = match &mut self.state {
+            State::Loaded(entries) => return Ok(entries.next()),
+            State::Streaming(0) => return Ok(None),
+            State::Streaming(remaining) => remaining,
+        };
+
+        loop {
+            match bincode::deserialize::<(String, EntryData)>(&self.buffer[self.offset..]) {
+                Ok(entry) => {
+                    // The encoding is fixed-size so the serialized size of the entry is the same
+                    // as the number of bytes consumed when decoding it.
+                    let size =
+                        bincode::serialized_size(&entry).map_err(|_| Error::MalformedDirectory)?;
+
+                    self.offset += size as usize;
+                    *remaining -= 1;
+
+                    return Ok(Some(entry));
+                }
+                Err(error) if is_unexpected_eof(&error) => {
+                    self.buffer.drain(..self.offset);
+                    self.offset = 0;
+
+                    if read_chunk(&mut self.blob, &self.root_node, &mut self.buffer).await? == 0 {
+                        return Err(Error::MalformedDirectory);
+                    }
+                }
+                Err(_) => return Err(Error::MalformedDirectory),
+            }
+        }
+    }
+
+    async fn read_chunk(&mut self) -> Result<usize> {
+        read_chunk(&mut self.blob, &self.root_node, &mut self.buffer).await
+    }
+}
+
+// Reads the next chunk of the blob and appends it to `buffer`. Returns the number of bytes read
+// (zero at the end of the blob).
+async fn read_chunk(blob: &mut Blob, root_node: &RootNode, buffer: &mut Vec<u8>) -> Result<usize> {
+    let mut tx = blob.branch().store().begin_read().await?;
+
+    let offset = buffer.len();
+    buffer.resize(offset + BLOCK_SIZE, 0);
+    let len = blob
+        .read_all_at(&mut tx, root_node, &mut buffer[offset..])
+        .await?;
+    buffer.truncate(offset + len);
+
+    Ok(len)
+}
+
+fn is_unexpected_eof(error: &bincode::Error) -> bool {
+    matches!(
+        &**error,
+        bincode::ErrorKind::Io(error) if error.kind() == io::ErrorKind::UnexpectedEof
+    )
+}
@@ -529,6 +529,45 @@ async fn fork_over_existing_directory() {
     );
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn fork_acl() {
+    let (_base_dir, [branch0, branch1]) = setup_multiple().await;
+
+    let acl: Acl = [*branch0.id()].into();
+
+    let mut root0 = branch0.open_or_create_root().await.unwrap();
+    let mut dir0 = root0
+        .create_directory("dir".into(), rand::random(), &VersionVector::new())
+        .await
+        .unwrap();
+    dir0.set_acl(Some(acl.clone())).await.unwrap();
+
+    // The ACL survives reopening.
+    let mut dir0 = root0
+        .lookup("dir")
+        .unwrap()
+        .directory()
+        .unwrap()
+        .open(DirectoryFallback::Disabled)
+        .await
+        .unwrap();
+    assert_eq!(dir0.acl(), Some(&acl));
+
+    // The ACL is carried over to the forked directory and enforced there.
+    let mut dir1 = dir0.fork(&branch1).await.unwrap();
+    assert_eq!(dir1.acl(), Some(&acl));
+    assert_matches!(
+        dir1.create_file("file.txt".into()).await,
+        Err(Error::PermissionDenied)
+    );
+
+    // Lifting the ACL propagates as well.
+    dir0.set_acl(None).await.unwrap();
+    let mut dir1 = dir0.fork(&branch1).await.unwrap();
+    assert_eq!(dir1.acl(), None);
+    dir1.create_file("file.txt".into()).await.unwrap();
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn modify_directory_concurrently() {
     let (_base_dir, branch) = setup().await;
@@ -612,6 +651,39 @@ async fn remove_concurrent_entry() {
     assert!(*local_vv > remote_vv);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn remove_concurrent_entries_in_one_batch() {
+    let (_base_dir, local_branch) = setup().await;
+    let mut root = local_branch.open_or_create_root().await.unwrap();
+
+    let remote_id = PublicKey::random();
+    let remote_vv = vv![remote_id => 1]; // pretend there are remote files with this vv
+
+    // Remove both versions of the same entry in one batch, in both orders.
+    for (name, local_first) in [("foo.txt", true), ("bar.txt", false)] {
+        root.create_file(name.to_owned()).await.unwrap();
+        let local_vv = root.lookup(name).unwrap().version_vector().clone();
+
+        let local = (name.to_owned(), *local_branch.id(), local_vv.clone());
+        let remote = (name.to_owned(), remote_id, remote_vv.clone());
+        let entries = if local_first {
+            vec![local, remote]
+        } else {
+            vec![remote, local]
+        };
+
+        root.remove_entries(entries).await.unwrap();
+
+        let tombstone_vv = assert_matches!(
+            root.lookup(name),
+            Ok(EntryRef::Tombstone(entry)) => entry.version_vector()
+        );
+
+        assert!(*tombstone_vv > local_vv);
+        assert!(*tombstone_vv > remote_vv);
+    }
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn remove_non_existing_local_entry() {
     let (_base_dir, local_branch) = setup().await;
@@ -39,12 +39,26 @@ pub enum Error {
     DirectoryNotEmpty,
     #[error("operation is not supported")]
     OperationNotSupported,
+    #[error("path is too deep")]
+    PathTooDeep,
     #[error("failed to write into writer")]
     Writer(#[source] io::Error),
+    #[error("failed to read from reader")]
+    Reader(#[source] io::Error),
+    #[error("input/output error")]
+    Io(#[source] io::Error),
     #[error("storage version mismatch")]
     StorageVersionMismatch,
+    #[error("key derivation params mismatch")]
+    KdfParamsMismatch,
     #[error("file or directory is locked")]
     Locked,
+    #[error("operation is already in progress")]
+    Busy,
+    #[error("too many snapshots")]
+    TooManySnapshots,
+    #[error("file is too large")]
+    FileTooLarge,
 }
 
 impl Error {
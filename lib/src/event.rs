@@ -1,7 +1,7 @@
 // Probably false positive triggered by `task_local`
 #![allow(clippy::declare_interior_mutable_const)]
 
-use crate::{crypto::sign::PublicKey, protocol::BlockId};
+use crate::{crypto::sign::PublicKey, protocol::BlockId, storage_size::StorageSize};
 use core::fmt;
 use futures_util::{stream, Stream};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -12,6 +12,14 @@ use tokio::sync::broadcast;
 pub enum Payload {
     /// A new snapshot was created in the specified branch.
     BranchChanged(PublicKey),
+    /// A branch that wasn't known to this replica before was received from a remote replica
+    /// (e.g., a new device started writing to the repository). Followed by `BranchChanged` for the
+    /// same branch.
+    BranchCreated(PublicKey),
+    /// The specified branch was removed from this replica, either because it became outdated (see
+    /// `Repository::set_prune_policy`) or because it was explicitly forgotten (see
+    /// `Repository::forget_writer`).
+    BranchRemoved(PublicKey),
     /// A block with the specified id was received from a remote replica.
     BlockReceived(BlockId),
     /// The `maintain` worker job successfully completed. It won't perform any more work until
@@ -19,6 +27,16 @@ pub enum Payload {
     /// This event is useful mostly for diagnostics or testing and can be safely ignored in other
     /// contexts.
     MaintenanceCompleted,
+    /// A snapshot received from a remote replica was rejected because accepting it would exceed
+    /// the storage quota (see `Repository::set_quota`).
+    QuotaExceeded {
+        /// Branch of the rejected snapshot.
+        branch_id: PublicKey,
+        /// Total size the repository would have if the snapshot was accepted.
+        requested: StorageSize,
+        /// The quota.
+        available: StorageSize,
+    },
 }
 
 /// Notification event
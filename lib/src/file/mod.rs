@@ -1,23 +1,38 @@
 mod progress_cache;
+mod read_ahead;
+mod shared;
 
 pub(crate) use progress_cache::FileProgressCache;
+pub use shared::SharedFile;
+pub(crate) use shared::SharedFiles;
 
+use self::read_ahead::ReadAhead;
 use crate::{
-    blob::{lock::UpgradableLock, Blob, ReadWriteError},
+    blob::{self, lock::UpgradableLock, Blob, BlobId, ReadWriteError},
     branch::Branch,
+    crypto::Hash,
     directory::{Directory, ParentContext},
     error::{Error, Result},
-    protocol::{Bump, Locator, BLOCK_SIZE},
-    store::{Changeset, ReadTransaction},
+    progress::Progress,
+    protocol::{Bump, Locator, RootNodeFilter, BLOCK_SIZE},
+    store::{self, Changeset, ReadTransaction},
     version_vector::VersionVector,
 };
 use std::{fmt, future::Future, io::SeekFrom};
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
+/// Handle to a file in a repository.
+///
+/// Writes are buffered in memory and saved to the repository only on [`Self::flush`] (or
+/// [`Self::close`]). Dropping the file with modifications that were not flushed discards them (a
+/// warning is logged when that happens).
 pub struct File {
     blob: Blob,
     parent: ParentContext,
     lock: UpgradableLock,
+    // Cached result of `content_hash`. Reset on every modification.
+    content_hash: Option<Hash>,
+    read_ahead: ReadAhead,
 }
 
 impl File {
@@ -36,6 +51,8 @@ impl File {
             blob: Blob::open(&mut tx, branch, *locator.blob_id()).await?,
             parent,
             lock,
+            content_hash: None,
+            read_ahead: ReadAhead::default(),
         })
     }
 
@@ -56,6 +73,8 @@ impl File {
             blob: Blob::create(branch, *locator.blob_id()),
             parent,
             lock,
+            content_hash: None,
+            read_ahead: ReadAhead::default(),
         }
     }
 
@@ -63,6 +82,13 @@ impl File {
         self.blob.branch()
     }
 
+    /// Id of the blob holding the content of this file. It doesn't change when the file is moved
+    /// or renamed, so it can be used to open the file later with
+    /// [`Repository::open_blob`](crate::Repository::open_blob).
+    pub fn blob_id(&self) -> &BlobId {
+        self.blob.id()
+    }
+
     pub async fn parent(&self) -> Result<Directory> {
         self.parent.open(self.branch().clone()).await
     }
@@ -91,7 +117,7 @@ impl File {
             let mut count = *entry;
 
             for index in *entry..block_count {
-                let encoded_locator = locator.nth(index).encode(branch.keys().read());
+                let encoded_locator = locator.nth(index)?.encode(branch.keys().read());
                 let block_id = tx.find_block(branch.id(), &encoded_locator).await?;
 
                 if tx.block_exists(&block_id).await? {
@@ -107,18 +133,85 @@ impl File {
         }
     }
 
+    /// Download progress of this file in blocks, that is, how many of its blocks are available
+    /// locally out of all its blocks. Unlike [`Self::progress`], this counts all the present
+    /// blocks, not just the contiguous ones from the start of the file. Blocks whose index entries
+    /// haven't been synced yet are counted as missing.
+    ///
+    /// The total is always known because a file can't be opened until its head block (which
+    /// contains the length) is downloaded. If it's removed in the meantime, it's counted as
+    /// missing as well.
+    ///
+    /// NOTE: Like with [`Self::progress`], the returned future doesn't borrow from `self`.
+    pub fn download_progress(&self) -> impl Future<Output = Result<Progress>> {
+        let branch = self.branch().clone();
+        let locator = Locator::head(*self.blob.id());
+        let block_count = self.blob.block_count();
+
+        async move {
+            let total = block_count.into();
+
+            let mut tx = branch.store().begin_read().await?;
+            let root_node = match tx.load_root_node(branch.id(), RootNodeFilter::Any).await {
+                Ok(root_node) => root_node,
+                Err(store::Error::BranchNotFound) => return Ok(Progress { value: 0, total }),
+                Err(error) => return Err(error.into()),
+            };
+
+            let mut value = 0;
+
+            for index in 0..block_count {
+                let encoded_locator = locator.nth(index)?.encode(branch.keys().read());
+
+                let block_id = match tx.find_block_at(&root_node, &encoded_locator).await {
+                    Ok(block_id) => block_id,
+                    Err(store::Error::LocatorNotFound) => continue,
+                    Err(error) => return Err(error.into()),
+                };
+
+                if tx.block_exists(&block_id).await? {
+                    value += 1;
+                }
+            }
+
+            Ok(Progress { value, total })
+        }
+    }
+
+    /// Checks whether all the blocks of this file are available locally, that is, whether it can be
+    /// read without going online. This is cheap: it looks up only the index, it doesn't read any
+    /// blocks nor does it request any from peers. Modifications that were not flushed yet are not
+    /// considered.
+    pub async fn is_complete(&self) -> Result<bool> {
+        blob::is_complete(
+            self.branch(),
+            *self.blob.id(),
+            Some(self.blob.block_count()),
+        )
+        .await
+    }
+
     /// Reads data from this file. Returns the number of bytes actually read.
     pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
         loop {
             match self.blob.read(buffer) {
-                Ok(len) => return Ok(len),
+                Ok(len) => {
+                    self.read_ahead.start(&self.blob);
+                    return Ok(len);
+                }
                 Err(ReadWriteError::CacheMiss) => {
+                    if let Some(blocks) = self.read_ahead.take(self.blob.current_block()).await {
+                        self.blob.insert_prefetched(blocks);
+                        continue;
+                    }
+
                     let mut tx = self.branch().store().begin_read().await?;
                     self.blob.warmup(&mut tx).await?;
                 }
                 Err(ReadWriteError::CacheFull) => {
                     self.flush().await?;
                 }
+                Err(ReadWriteError::TooLarge) => return Err(Error::FileTooLarge),
             }
         }
     }
@@ -150,8 +243,22 @@ impl File {
     }
 
     /// Writes `buffer` into this file. Returns the number of bytes actually written.
+    ///
+    /// Fails with `Error::FileTooLarge`, without writing anything, if writing the whole buffer
+    /// would grow the file past the maximum file size (see
+    /// [`RepositoryParams::with_max_file_size`](crate::RepositoryParams::with_max_file_size)).
     pub async fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        let end = self
+            .blob
+            .seek_position()
+            .saturating_add(buffer.len() as u64);
+        if end > self.branch().max_file_size() && end > self.len() {
+            return Err(Error::FileTooLarge);
+        }
+
         self.acquire_write_lock()?;
+        self.content_hash = None;
+        self.read_ahead.cancel();
 
         loop {
             match self.blob.write(buffer) {
@@ -163,6 +270,7 @@ impl File {
                 Err(ReadWriteError::CacheFull) => {
                     self.flush().await?;
                 }
+                Err(ReadWriteError::TooLarge) => return Err(Error::FileTooLarge),
             }
         }
     }
@@ -180,14 +288,51 @@ impl File {
         }
     }
 
-    /// Seeks to an offset in the file.
+    /// Appends `buffer` to the end of this file and leaves the seek position at the new end. Apart
+    /// from the head block (which holds the length), only the last block is loaded and only if
+    /// it's partially filled, so the existing content isn't read.
+    ///
+    /// If this file has no unflushed modifications, its length is first reloaded from the store,
+    /// so appending through a file opened before another instance of it was appended to (and
+    /// flushed) doesn't overwrite the data appended by the other instance.
+    pub async fn append(&mut self, buffer: &[u8]) -> Result<()> {
+        self.acquire_write_lock()?;
+
+        if !self.blob.is_dirty() {
+            let mut tx = self.branch().store().begin_read().await?;
+            self.blob.reload(&mut tx).await?;
+        }
+
+        self.seek(SeekFrom::End(0));
+        self.write_all(buffer).await
+    }
+
+    /// Seeks to an offset in the file. Cancels the read-ahead unless it already covers the new
+    /// position.
     pub fn seek(&mut self, pos: SeekFrom) -> u64 {
-        self.blob.seek(pos)
+        let position = self.blob.seek(pos);
+        self.read_ahead.seek(self.blob.current_block());
+        position
+    }
+
+    /// Sets the number of blocks to load in the background ahead of the current read position.
+    /// This speeds up sequential reads of large files. Zero (the default) disables the read-ahead.
+    /// The blocks are loaded only if they are already stored locally.
+    pub fn set_read_ahead(&mut self, blocks: usize) {
+        self.read_ahead
+            .set_window(blocks.try_into().unwrap_or(u32::MAX));
+    }
+
+    /// Returns the current read-ahead window in blocks.
+    pub fn read_ahead(&self) -> usize {
+        self.read_ahead.window() as usize
     }
 
     /// Truncates the file to the given length.
     pub fn truncate(&mut self, len: u64) -> Result<()> {
         self.acquire_write_lock()?;
+        self.content_hash = None;
+        self.read_ahead.cancel();
         self.blob.truncate(len)
     }
 
@@ -198,17 +343,24 @@ impl File {
             return Ok(());
         }
 
+        self.bump(Bump::increment(*self.branch().id())).await
+    }
+
+    /// Flushes any pending modifications and closes this file. Prefer this over just dropping the
+    /// file after writing to it, because dropping discards the modifications that were not
+    /// flushed.
+    pub async fn close(mut self) -> Result<()> {
+        self.flush().await
+    }
+
+    /// Saves any pending modifications and applies `bump` to the version vector of this file.
+    async fn bump(&mut self, bump: Bump) -> Result<()> {
         let mut tx = self.branch().store().begin_write().await?;
         let mut changeset = Changeset::new();
 
         self.blob.flush(&mut tx, &mut changeset).await?;
         self.parent
-            .bump(
-                &mut tx,
-                &mut changeset,
-                self.branch().clone(),
-                Bump::increment(*self.branch().id()),
-            )
+            .bump(&mut tx, &mut changeset, self.branch().clone(), bump)
             .await?;
 
         changeset
@@ -257,6 +409,44 @@ impl File {
         Ok(())
     }
 
+    /// Computes the BLAKE3 hash of the whole content of this file. The hash depends only on the
+    /// plaintext content, so it's the same on every replica that holds the same content. The
+    /// result is cached until the file is modified. Does not change the seek position.
+    pub async fn content_hash(&mut self) -> Result<Hash> {
+        if let Some(hash) = self.content_hash {
+            return Ok(hash);
+        }
+
+        let position = self.blob.seek_position();
+        self.blob.seek(SeekFrom::Start(0));
+
+        let result = self.compute_content_hash().await;
+
+        self.blob.seek(SeekFrom::Start(position));
+
+        let hash = result?;
+        self.content_hash = Some(hash);
+
+        Ok(hash)
+    }
+
+    async fn compute_content_hash(&mut self) -> Result<Hash> {
+        let mut hasher = ContentHasher::new();
+        let mut buffer = vec![0; BLOCK_SIZE];
+
+        loop {
+            let len = self.read(&mut buffer).await?;
+
+            if len == 0 {
+                break;
+            }
+
+            hasher.update(&buffer[..len]);
+        }
+
+        Ok(hasher.finalize())
+    }
+
     /// Forks this file into the given branch. Ensure all its ancestor directories exist and live
     /// in the branch as well. Should be called before any mutable operation.
     pub async fn fork(&mut self, dst_branch: Branch) -> Result<()> {
@@ -266,7 +456,17 @@ impl File {
             return Ok(());
         }
 
-        let parent = self.parent.fork(self.branch(), &dst_branch).await?;
+        self.fork_with(dst_branch, Bump::default()).await
+    }
+
+    /// Forks this file into `dst_branch` and applies `bump` to the version vector of the forked
+    /// entry. If the file is already in `dst_branch`, only the `bump` is applied.
+    pub(crate) async fn fork_with(&mut self, dst_branch: Branch, bump: Bump) -> Result<()> {
+        if self.branch().id() == dst_branch.id() {
+            return self.bump(bump).await;
+        }
+
+        let parent = self.parent.fork(self.branch(), &dst_branch, bump).await?;
 
         let lock = dst_branch.locker().read(*self.blob.id()).await;
         let lock = UpgradableLock::Read(lock);
@@ -276,7 +476,13 @@ impl File {
             Blob::open(&mut tx, dst_branch, *self.blob.id()).await?
         };
 
-        *self = Self { blob, parent, lock };
+        *self = Self {
+            blob,
+            parent,
+            lock,
+            content_hash: self.content_hash,
+            read_ahead: ReadAhead::new(self.read_ahead.window()),
+        };
 
         Ok(())
     }
@@ -287,17 +493,42 @@ impl File {
             .await
     }
 
-    /// BlobId of this file.
-    #[cfg(test)]
-    pub(crate) fn blob_id(&self) -> &crate::blob::BlobId {
-        self.blob.id()
-    }
-
     fn acquire_write_lock(&mut self) -> Result<()> {
         self.lock.upgrade().then_some(()).ok_or(Error::Locked)
     }
 }
 
+/// Computes the same hash as [`File::content_hash`] from content fed to it in chunks. Allows to
+/// compare content from outside of the repository (e.g., a file on the host filesystem) with a
+/// file in it.
+pub(crate) struct ContentHasher(blake3::Hasher);
+
+impl ContentHasher {
+    pub fn new() -> Self {
+        Self(blake3::Hasher::new())
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    pub fn finalize(self) -> Hash {
+        Hash::from(<[u8; Hash::SIZE]>::from(self.0.finalize()))
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        if self.blob.is_dirty() {
+            tracing::warn!(
+                branch_id = ?self.branch().id(),
+                blob_id = ?self.blob.id(),
+                "File dropped with unflushed modifications, they are lost"
+            );
+        }
+    }
+}
+
 impl fmt::Debug for File {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("File")
@@ -317,6 +548,7 @@ mod tests {
         db,
         directory::{DirectoryFallback, DirectoryLocking},
         event::EventSender,
+        joint_directory::JointDirectory,
         store::Store,
         test_utils,
     };
@@ -437,6 +669,33 @@ mod tests {
         assert_matches!(file1.truncate(0), Err(Error::Locked));
     }
 
+    // Separately opened files have independent caches: unflushed writes through one are not
+    // visible through the other.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn independent_handles() {
+        let (_base_dir, [branch]) = setup().await;
+
+        let mut file0 = branch.ensure_file_exists("cat.txt".into()).await.unwrap();
+        file0.flush().await.unwrap();
+
+        let mut file1 = branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("cat.txt")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+
+        file0.write_all(b"meow").await.unwrap();
+        assert_eq!(file0.len(), 4);
+        assert_eq!(file1.len(), 0);
+        assert_eq!(file1.read_to_end().await.unwrap(), b"");
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn copy_to_writer() {
         use tokio::{fs, io::AsyncReadExt};
@@ -460,6 +719,197 @@ mod tests {
         assert_eq!(dst_content, src_content);
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn content_hash() {
+        let (_base_dir, [branch]) = setup().await;
+
+        let mut file0 = branch.ensure_file_exists("a.txt".into()).await.unwrap();
+        file0.write_all(b"hello world").await.unwrap();
+        file0.flush().await.unwrap();
+
+        let mut file1 = branch.ensure_file_exists("b.txt".into()).await.unwrap();
+        file1.write_all(b"hello world").await.unwrap();
+        file1.flush().await.unwrap();
+
+        let hash0 = file0.content_hash().await.unwrap();
+        let hash1 = file1.content_hash().await.unwrap();
+
+        // Same content, same hash, independent of the file identity.
+        assert_eq!(hash0, hash1);
+        assert_eq!(
+            hash0,
+            Hash::from(<[u8; Hash::SIZE]>::from(blake3::hash(b"hello world")))
+        );
+
+        // Seek position is preserved.
+        assert_eq!(
+            file0.seek(SeekFrom::Current(0)),
+            b"hello world".len() as u64
+        );
+
+        // Cached hash is invalidated on write.
+        file1.write_all(b"!").await.unwrap();
+        assert_ne!(file1.content_hash().await.unwrap(), hash0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn append() {
+        let (_base_dir, [branch]) = setup().await;
+
+        let mut file0 = branch.ensure_file_exists("log.txt".into()).await.unwrap();
+        file0.write_all(b"one\n").await.unwrap();
+        file0.flush().await.unwrap();
+
+        // Opened before the other instance appends to the file.
+        let mut file1 = branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("log.txt")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+
+        file0.seek(SeekFrom::Start(0));
+        file0.append(b"two\n").await.unwrap();
+        assert_eq!(file0.seek(SeekFrom::Current(0)), 8);
+        file0.close().await.unwrap();
+
+        // Appending across a block boundary.
+        let line: Vec<u8> = (0..BLOCK_SIZE).map(|i| b'a' + (i % 26) as u8).collect();
+        file1.append(&line).await.unwrap();
+        file1.append(b"three\n").await.unwrap();
+        file1.flush().await.unwrap();
+
+        let mut expected = b"one\ntwo\n".to_vec();
+        expected.extend_from_slice(&line);
+        expected.extend_from_slice(b"three\n");
+
+        assert_eq!(file1.len(), expected.len() as u64);
+        file1.seek(SeekFrom::Start(0));
+        assert_eq!(file1.read_to_end().await.unwrap(), expected);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_ahead() {
+        let (_base_dir, [branch]) = setup().await;
+
+        let content: Vec<u8> = (0..10 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+
+        let mut file = branch.ensure_file_exists("large.dat".into()).await.unwrap();
+        file.write_all(&content).await.unwrap();
+        file.flush().await.unwrap();
+        drop(file);
+
+        let mut file = branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("large.dat")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+        file.set_read_ahead(4);
+
+        assert_eq!(file.read_to_end().await.unwrap(), content);
+
+        // Seeking elsewhere cancels the read-ahead but reading still works.
+        let offset = 3 * BLOCK_SIZE + 17;
+        file.seek(SeekFrom::Start(offset as u64));
+        let mut buffer = vec![0; 2 * BLOCK_SIZE];
+        file.read_all(&mut buffer).await.unwrap();
+        assert_eq!(buffer, content[offset..offset + buffer.len()]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn close() {
+        let (_base_dir, [branch]) = setup().await;
+
+        let mut file = branch.ensure_file_exists("a.txt".into()).await.unwrap();
+        file.write_all(b"hello world").await.unwrap();
+        file.close().await.unwrap();
+
+        let mut file = branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("a.txt")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+        assert_eq!(file.read_to_end().await.unwrap(), b"hello world");
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn download_progress() {
+        let (_base_dir, [branch]) = setup().await;
+
+        let content: Vec<u8> = (0..3 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+
+        let mut file = branch.ensure_file_exists("large.dat".into()).await.unwrap();
+        file.write_all(&content).await.unwrap();
+
+        // Not flushed yet so none of the blocks are in the store.
+        let progress = file.download_progress().await.unwrap();
+        assert_eq!(progress.value, 0);
+        assert_eq!(progress.total, 4);
+
+        file.flush().await.unwrap();
+
+        let progress = file.download_progress().await.unwrap();
+        assert_eq!(progress.value, 4);
+        assert_eq!(progress.total, 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn is_complete() {
+        let (_base_dir, [branch]) = setup().await;
+
+        let content: Vec<u8> = (0..3 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+
+        let mut file = branch.ensure_file_exists("large.dat".into()).await.unwrap();
+        file.write_all(&content).await.unwrap();
+
+        // Not flushed yet so none of the blocks are in the index.
+        assert!(!file.is_complete().await.unwrap());
+
+        file.flush().await.unwrap();
+        assert!(file.is_complete().await.unwrap());
+
+        let root = branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap();
+        let root = JointDirectory::new(Some(branch.clone()), [root]);
+        let entry = root.lookup_unique("large.dat").unwrap();
+        assert!(entry.is_complete().await.unwrap());
+
+        // Remove one of the blocks from the store.
+        let locator = Locator::head(*file.blob.id()).nth(2).unwrap();
+        let mut tx = branch.store().begin_read().await.unwrap();
+        let block_id = tx
+            .find_block(branch.id(), &locator.encode(branch.keys().read()))
+            .await
+            .unwrap();
+        drop(tx);
+
+        let mut tx = branch.store().begin_write().await.unwrap();
+        tx.remove_block(&block_id).await.unwrap();
+        tx.commit().await.unwrap();
+
+        assert!(!file.is_complete().await.unwrap());
+        assert!(!entry.is_complete().await.unwrap());
+    }
+
     async fn setup<const N: usize>() -> (TempDir, [Branch; N]) {
         let (base_dir, pool) = db::create_temp().await.unwrap();
         let store = Store::new(pool);
@@ -0,0 +1,107 @@
+use crate::{
+    blob::{self, Blob},
+    error::Result,
+    protocol::BlockContent,
+};
+use scoped_task::ScopedJoinHandle;
+use std::ops::Range;
+
+/// Loads the blocks following the current read position in the background, so sequential reads
+/// don't have to load each block on demand.
+#[derive(Default)]
+pub(super) struct ReadAhead {
+    // Max number of blocks to load ahead. Zero disables the read-ahead.
+    window: u32,
+    // Blocks below this number were already loaded (or are being loaded).
+    end: u32,
+    pending: Option<Pending>,
+}
+
+struct Pending {
+    range: Range<u32>,
+    // Dropping the handle cancels the task.
+    handle: ScopedJoinHandle<Result<Vec<(u32, BlockContent)>>>,
+}
+
+impl ReadAhead {
+    pub fn new(window: u32) -> Self {
+        Self {
+            window,
+            ..Self::default()
+        }
+    }
+
+    pub fn window(&self) -> u32 {
+        self.window
+    }
+
+    pub fn set_window(&mut self, window: u32) {
+        self.window = window;
+        self.cancel();
+    }
+
+    /// Starts loading the blocks following the current position of `blob`, unless already in
+    /// progress.
+    pub fn start(&mut self, blob: &Blob) {
+        if self.window == 0 || self.pending.is_some() {
+            return;
+        }
+
+        let next = blob.current_block().saturating_add(1);
+        let start = self.end.max(next);
+        let end = next.saturating_add(self.window).min(blob.block_count());
+
+        if start >= end {
+            return;
+        }
+
+        let handle = scoped_task::spawn(blob::read_blocks(
+            blob.branch().clone(),
+            *blob.id(),
+            start..end,
+        ));
+
+        self.pending = Some(Pending {
+            range: start..end,
+            handle,
+        });
+        self.end = end;
+    }
+
+    /// If the pending load includes the block `number`, waits for it to complete and returns the
+    /// loaded blocks.
+    pub async fn take(&mut self, number: u32) -> Option<Vec<(u32, BlockContent)>> {
+        if !self.is_pending(number) {
+            return None;
+        }
+
+        match self.pending.take()?.handle.await {
+            Ok(Ok(blocks)) => Some(blocks),
+            Ok(Err(error)) => {
+                tracing::trace!(?error, "read-ahead failed");
+                None
+            }
+            Err(_) => None,
+        }
+    }
+
+    /// Cancels the pending load, if any, unless it includes the block `number`.
+    pub fn seek(&mut self, number: u32) {
+        if !self.is_pending(number) {
+            self.cancel();
+        }
+    }
+
+    /// Cancels the pending load, if any.
+    pub fn cancel(&mut self) {
+        self.pending = None;
+        self.end = 0;
+    }
+
+    fn is_pending(&self, number: u32) -> bool {
+        self.pending
+            .as_ref()
+            .map(|pending| pending.range.contains(&number))
+            .unwrap_or(false)
+    }
+}
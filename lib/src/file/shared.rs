@@ -0,0 +1,182 @@
+use super::File;
+use crate::{
+    blob::BlobId,
+    collections::HashMap,
+    crypto::{sign::PublicKey, Hash},
+    error::Result,
+    version_vector::VersionVector,
+};
+use deadlock::BlockingMutex;
+use std::{
+    fmt,
+    io::SeekFrom,
+    sync::{Arc, Weak},
+};
+use tokio::sync::{Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard};
+
+/// Handle to a file that can be used by multiple tasks concurrently.
+///
+/// All handles to the same file - obtained either by cloning a handle or by opening the same file
+/// again with [`Repository::open_file_shared`](crate::Repository::open_file_shared) while some
+/// handle to it is still alive - operate on a single underlying [`File`]. This means they:
+///
+/// - see each other's writes immediately, even before they are flushed,
+/// - agree on the length of the file,
+/// - share a single lock on the underlying blob. Once any of them writes to the file, the lock
+///   is upgraded to a write lock which is held until all the handles are dropped. During that
+///   time, writing to the file through any non-shared [`File`] fails with `Error::Locked`.
+///
+/// Each handle has its own seek position (a clone starts at the position of the original). Every
+/// operation is atomic with respect to the other handles: e.g., the whole buffer passed to
+/// [`Self::write_all`] is written without being interleaved with writes from other handles.
+///
+/// Note the sharing is keyed by the branch the file was opened from. Opening a file again after
+/// it's been forked into another branch yields an independent handle.
+#[derive(Clone)]
+pub struct SharedFile {
+    file: Arc<AsyncMutex<File>>,
+    position: u64,
+}
+
+impl SharedFile {
+    /// Length of this file in bytes.
+    pub async fn len(&self) -> u64 {
+        self.file.lock().await.len()
+    }
+
+    /// Returns whether this file is empty.
+    pub async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    /// Reads data from this file at the current position of this handle. Returns the number of
+    /// bytes actually read.
+    pub async fn read(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let mut file = self.lock().await;
+        let result = file.read(buffer).await;
+        self.position = file.seek(SeekFrom::Current(0));
+        result
+    }
+
+    /// Reads from the current position of this handle to the end of the file.
+    pub async fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        let mut file = self.lock().await;
+        let result = file.read_to_end().await;
+        self.position = file.seek(SeekFrom::Current(0));
+        result
+    }
+
+    /// Writes `buffer` into this file at the current position of this handle. Returns the number
+    /// of bytes actually written.
+    pub async fn write(&mut self, buffer: &[u8]) -> Result<usize> {
+        let mut file = self.lock().await;
+        let result = file.write(buffer).await;
+        self.position = file.seek(SeekFrom::Current(0));
+        result
+    }
+
+    /// Writes the whole `buffer` into this file at the current position of this handle.
+    pub async fn write_all(&mut self, buffer: &[u8]) -> Result<()> {
+        let mut file = self.lock().await;
+        let result = file.write_all(buffer).await;
+        self.position = file.seek(SeekFrom::Current(0));
+        result
+    }
+
+    /// Seeks this handle to an offset in the file. Doesn't affect the other handles.
+    pub async fn seek(&mut self, pos: SeekFrom) -> u64 {
+        let mut file = self.lock().await;
+        self.position = file.seek(pos);
+        self.position
+    }
+
+    /// Truncates the file to the given length. The positions of the handles are not changed.
+    pub async fn truncate(&mut self, len: u64) -> Result<()> {
+        self.lock().await.truncate(len)
+    }
+
+    /// Atomically saves any pending modifications (made through any handle) and updates the
+    /// version vectors of this file and all its ancestors.
+    pub async fn flush(&self) -> Result<()> {
+        self.file.lock().await.flush().await
+    }
+
+    pub async fn content_hash(&self) -> Result<Hash> {
+        self.file.lock().await.content_hash().await
+    }
+
+    pub async fn version_vector(&self) -> Result<VersionVector> {
+        self.file.lock().await.version_vector().await
+    }
+
+    /// Returns whether `self` and `other` are handles to the same underlying file.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.file, &other.file)
+    }
+
+    // Locks the underlying file and moves its cursor to the position of this handle.
+    async fn lock(&self) -> AsyncMutexGuard<'_, File> {
+        let mut file = self.file.lock().await;
+        file.seek(SeekFrom::Start(self.position));
+        file
+    }
+}
+
+impl fmt::Debug for SharedFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedFile")
+            .field("position", &self.position)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Registry of the currently open shared files.
+#[derive(Default, Clone)]
+pub(crate) struct SharedFiles {
+    files: Arc<BlockingMutex<HashMap<(PublicKey, BlobId), Weak<AsyncMutex<File>>>>>,
+}
+
+impl SharedFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a new handle to the file with the given id if it's currently open.
+    pub fn get(&self, branch_id: PublicKey, blob_id: BlobId) -> Option<SharedFile> {
+        let mut files = self.files.lock().unwrap();
+        files.retain(|_, file| file.strong_count() > 0);
+
+        files
+            .get(&(branch_id, blob_id))
+            .and_then(Weak::upgrade)
+            .map(|file| SharedFile { file, position: 0 })
+    }
+
+    /// Returns handles to all the currently open files.
+    pub fn all(&self) -> Vec<SharedFile> {
+        let mut files = self.files.lock().unwrap();
+        files.retain(|_, file| file.strong_count() > 0);
+
+        files
+            .values()
+            .filter_map(Weak::upgrade)
+            .map(|file| SharedFile { file, position: 0 })
+            .collect()
+    }
+
+    /// Registers a newly opened file and returns a handle to it. If the file has been registered
+    /// concurrently in the meantime, returns a handle to that one instead and drops `file`.
+    pub fn insert(&self, file: File) -> SharedFile {
+        let key = (*file.branch().id(), *file.blob_id());
+        let mut files = self.files.lock().unwrap();
+
+        if let Some(file) = files.get(&key).and_then(Weak::upgrade) {
+            return SharedFile { file, position: 0 };
+        }
+
+        let file = Arc::new(AsyncMutex::new(file));
+        files.insert(key, Arc::downgrade(&file));
+
+        SharedFile { file, position: 0 }
+    }
+}
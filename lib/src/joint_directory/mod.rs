@@ -1,9 +1,14 @@
+mod read_dir;
 #[cfg(test)]
 mod tests;
 
+pub use self::read_dir::DirEntry;
+pub(crate) use self::read_dir::ReadDir;
+
 use crate::{
+    blob,
     branch::Branch,
-    conflict,
+    conflict::{self, Conflict, ConflictVersion},
     crypto::sign::PublicKey,
     directory::{
         self, Directory, DirectoryFallback, DirectoryRef, EntryRef, EntryTombstoneData, EntryType,
@@ -12,7 +17,7 @@ use crate::{
     error::{Error, Result},
     file::File,
     iterator::{Accumulate, SortedUnion},
-    store,
+    path, store,
     version_vector::VersionVector,
     versioned::{self, PreferBranch},
 };
@@ -88,6 +93,33 @@ impl JointDirectory {
         })
     }
 
+    /// Returns the entries of this directory that have multiple concurrent versions which can't
+    /// be merged automatically. `path` is the path of this directory and is used to build the
+    /// paths of the conflicting entries.
+    pub(crate) fn conflicts<'a>(
+        &'a self,
+        path: &'a Utf8Path,
+    ) -> impl Iterator<Item = Conflict> + 'a {
+        self.merge_entries().filter_map(move |(name, merge)| {
+            let versions: Vec<_> = merge
+                .ignore_tombstones()
+                .map(|entry| ConflictVersion {
+                    branch_id: *entry.first_branch().id(),
+                    entry_type: entry.entry_type(),
+                    version_vector: match &entry {
+                        JointEntryRef::File(entry) => entry.version_vector().clone(),
+                        JointEntryRef::Directory(entry) => entry.version_vector(),
+                    },
+                })
+                .collect();
+
+            (versions.len() > 1).then(|| Conflict {
+                path: path.join(name),
+                versions,
+            })
+        })
+    }
+
     /// Returns all versions of an entry with the given name. Concurrent file versions are returned
     /// separately but concurrent directory versions are merged into a single `JointDirectory`.
     pub fn lookup<'a>(&'a self, name: &'a str) -> impl Iterator<Item = JointEntryRef<'a>> + 'a {
@@ -200,7 +232,25 @@ impl JointDirectory {
     /// Removes the specified entry from this directory, including all its content if it is a
     /// subdirectory.
     pub async fn remove_entry_recursively(&mut self, name: &str) -> Result<()> {
-        self.remove_entries_recursively(Pattern::Unique(name)).await
+        self.remove_entry_recursively_with_max_depth(name, path::DEFAULT_MAX_DEPTH)
+            .await
+    }
+
+    /// Like [Self::remove_entry_recursively] but fails with `Error::PathTooDeep` if the removed
+    /// entry contains directories nested more than `max_depth` levels below this directory.
+    pub(crate) async fn remove_entry_recursively_with_max_depth(
+        &mut self,
+        name: &str,
+        max_depth: usize,
+    ) -> Result<()> {
+        self.remove_entries_recursively(Pattern::Unique(name), max_depth)
+            .await
+    }
+
+    /// Removes the specified entries (directories must be empty) from this directory. Either all of
+    /// them are removed or, in case of error, none.
+    pub async fn remove_entries_atomically(&mut self, names: &[&str]) -> Result<()> {
+        self.remove_entries(Pattern::Many(names)).await
     }
 
     async fn remove_entries(&mut self, pattern: Pattern<'_>) -> Result<()> {
@@ -221,21 +271,26 @@ impl JointDirectory {
             .collect();
 
         let local_version = self.fork().await?;
-
-        for (name, branch_id, vv) in entries {
-            local_version.remove_entry(&name, &branch_id, vv).await?;
-        }
-
-        Ok(())
+        local_version.remove_entries(entries).await
     }
 
+    // `max_depth` is the maximum depth of the removed directories relative to this directory.
     #[async_recursion]
-    async fn remove_entries_recursively<'a>(&'a mut self, pattern: Pattern<'a>) -> Result<()> {
+    async fn remove_entries_recursively<'a>(
+        &'a mut self,
+        pattern: Pattern<'a>,
+        max_depth: usize,
+    ) -> Result<()> {
         for entry in pattern.apply(self)?.filter_map(|e| e.directory().ok()) {
+            if max_depth == 0 {
+                return Err(Error::PathTooDeep);
+            }
+
             let mut dir = entry
                 .open_with(MissingVersionStrategy::Skip, DirectoryFallback::Disabled)
                 .await?;
-            dir.remove_entries_recursively(Pattern::All).await?;
+            dir.remove_entries_recursively(Pattern::All, max_depth - 1)
+                .await?;
         }
 
         if let Some(local_version) = self.local_version_mut() {
@@ -434,6 +489,15 @@ impl<'a> JointEntryRef<'a> {
         }
     }
 
+    /// Checks whether the entry is available locally without reading any blocks. See
+    /// [`JointFileRef::is_complete`] and [`JointDirectoryRef::is_complete`].
+    pub async fn is_complete(&self) -> Result<bool> {
+        match self {
+            Self::File(r) => r.is_complete().await,
+            Self::Directory(r) => r.is_complete().await,
+        }
+    }
+
     pub fn file(self) -> Result<FileRef<'a>> {
         match self {
             Self::File(r) => Ok(r.file),
@@ -486,6 +550,14 @@ impl<'a> JointFileRef<'a> {
         self.file.fork(dst_branch).await
     }
 
+    /// Checks whether all the blocks of the file are available locally, without opening it. Like
+    /// [`File::is_complete`], this looks up mostly the index, the only block read is the head
+    /// block (to find the file length). If the head block itself is not available, the file is
+    /// reported as incomplete.
+    pub async fn is_complete(&self) -> Result<bool> {
+        blob::is_complete(self.branch(), *self.file.blob_id(), None).await
+    }
+
     pub fn version_vector(&self) -> &'a VersionVector {
         self.file.version_vector()
     }
@@ -550,6 +622,18 @@ impl<'a> JointDirectoryRef<'a> {
             })
     }
 
+    /// Checks whether all the versions of the directory are available locally (the listings only,
+    /// not the entries within it). Reads only the head blocks, like [`JointFileRef::is_complete`].
+    pub async fn is_complete(&self) -> Result<bool> {
+        for version in &self.versions {
+            if !blob::is_complete(version.branch(), *version.blob_id(), None).await? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
     pub async fn open(&self) -> Result<JointDirectory> {
         self.open_with(MissingVersionStrategy::Skip, DirectoryFallback::Enabled)
             .await
@@ -717,6 +801,8 @@ enum Pattern<'a> {
     All,
     // Fetch single entry that matches the given unique name
     Unique(&'a str),
+    // Fetch the entries that match the given unique names
+    Many(&'a [&'a str]),
 }
 
 impl<'a> Pattern<'a> {
@@ -725,7 +811,12 @@ impl<'a> Pattern<'a> {
             Self::All => Ok(Either::Left(dir.entries())),
             Self::Unique(name) => dir
                 .lookup_unique(name)
-                .map(|entry| Either::Right(iter::once(entry))),
+                .map(|entry| Either::Right(Either::Left(iter::once(entry)))),
+            Self::Many(names) => names
+                .iter()
+                .map(|name| dir.lookup_unique(name))
+                .collect::<Result<Vec<_>>>()
+                .map(|entries| Either::Right(Either::Right(entries.into_iter()))),
         }
     }
 }
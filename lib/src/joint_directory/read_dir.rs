@@ -0,0 +1,209 @@
+use crate::{
+    blob::BlobId,
+    branch::Branch,
+    conflict,
+    crypto::sign::PublicKey,
+    directory::{EntryData, EntryReader, EntryType},
+    error::{Error, Result},
+    store,
+    version_vector::VersionVector,
+    versioned::{self, BranchItem, PreferBranch, Versioned},
+};
+use std::collections::VecDeque;
+
+/// Owned directory entry yielded by
+/// [`Repository::read_dir_stream`](crate::Repository::read_dir_stream).
+///
+/// Like with [`JointDirectory::entries`](super::JointDirectory::entries), multiple concurrent
+/// versions of the same file are yielded as separate entries, while multiple concurrent versions
+/// of the same directory are yielded as a single entry.
+#[derive(Clone, Debug)]
+pub struct DirEntry {
+    name: String,
+    entry_type: EntryType,
+    version_vector: VersionVector,
+    branch_id: PublicKey,
+    needs_disambiguation: bool,
+}
+
+impl DirEntry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Name of the entry with a disambiguator appended if there are multiple concurrent versions
+    /// of it. See [`JointEntryRef::unique_name`](super::JointEntryRef::unique_name).
+    pub fn unique_name(&self) -> String {
+        if self.needs_disambiguation {
+            conflict::create_unique_name(&self.name, &self.branch_id)
+        } else {
+            self.name.clone()
+        }
+    }
+
+    pub fn entry_type(&self) -> EntryType {
+        self.entry_type
+    }
+
+    /// Version vector of the entry. For directories, this is the merge of the version vectors of
+    /// all the concurrent versions.
+    pub fn version_vector(&self) -> &VersionVector {
+        &self.version_vector
+    }
+
+    /// Id of the branch this entry belongs to. For directories with multiple concurrent versions,
+    /// this is the branch of the first version.
+    pub fn branch_id(&self) -> &PublicKey {
+        &self.branch_id
+    }
+}
+
+/// Reads the entries of multiple versions of a directory incrementally and merges them lazily, one
+/// entry name at a time.
+pub(crate) struct ReadDir {
+    // Reader and the next, not yet merged, entry of each version, ordered by branch id.
+    versions: Vec<(PublicKey, EntryReader, Option<(String, EntryData)>)>,
+    local_branch_id: Option<PublicKey>,
+    ready: VecDeque<DirEntry>,
+}
+
+impl ReadDir {
+    /// Opens the given versions of a directory, each specified by its branch and blob id. Versions
+    /// that don't exist or which are not yet fully downloaded (in case of remote versions) are
+    /// skipped.
+    pub async fn open<I>(local_branch: Option<&Branch>, versions: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (Branch, BlobId)>,
+    {
+        let local_branch_id = local_branch.map(|branch| *branch.id());
+        let mut readers = Vec::new();
+
+        for (branch, blob_id) in versions {
+            let branch_id = *branch.id();
+
+            match EntryReader::open(branch, blob_id).await {
+                Ok(reader) => readers.push((branch_id, reader, None)),
+                Err(Error::Store(store::Error::BranchNotFound)) => continue,
+                Err(Error::Store(store::Error::BlockNotFound))
+                    if Some(branch_id) != local_branch_id =>
+                {
+                    continue
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        readers.sort_by(|(lhs, _, _), (rhs, _, _)| lhs.cmp(rhs));
+
+        Ok(Self {
+            versions: readers,
+            local_branch_id,
+            ready: VecDeque::new(),
+        })
+    }
+
+    /// Returns the next entry or `None` if there are no more entries.
+    pub async fn next(&mut self) -> Result<Option<DirEntry>> {
+        loop {
+            if let Some(entry) = self.ready.pop_front() {
+                return Ok(Some(entry));
+            }
+
+            for (_, reader, next) in &mut self.versions {
+                if next.is_none() {
+                    *next = reader.next().await?;
+                }
+            }
+
+            let Some(name) = self
+                .versions
+                .iter()
+                .filter_map(|(_, _, next)| next.as_ref().map(|(name, _)| name))
+                .min()
+                .cloned()
+            else {
+                return Ok(None);
+            };
+
+            let versions: Vec<_> = self
+                .versions
+                .iter_mut()
+                .filter_map(|(branch_id, _, next)| {
+                    if next.as_ref().map(|(next_name, _)| *next_name == name)? {
+                        next.take().map(|(_, data)| Version {
+                            branch_id: *branch_id,
+                            data,
+                        })
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            self.ready
+                .extend(merge(name, versions, self.local_branch_id.as_ref()));
+        }
+    }
+}
+
+// Merges the versions of a single entry, mirroring the semantics of `JointDirectory::entries`:
+// outdated versions are discarded, concurrent directories are merged into a single entry,
+// concurrent files are kept separate and tombstones are ignored.
+fn merge(
+    name: String,
+    versions: Vec<Version>,
+    local_branch_id: Option<&PublicKey>,
+) -> Vec<DirEntry> {
+    let mut files = Vec::new();
+    let mut directories = Vec::new();
+
+    for version in versioned::keep_maximal(versions, PreferBranch(local_branch_id)) {
+        match version.data {
+            EntryData::File(_) => files.push(version),
+            EntryData::Directory(_) => directories.push(version),
+            EntryData::Tombstone(_) => (),
+        }
+    }
+
+    let needs_disambiguation = files.len() + usize::from(!directories.is_empty()) > 1;
+
+    let directory = directories.first().map(|first| DirEntry {
+        name: name.clone(),
+        entry_type: EntryType::Directory,
+        version_vector: directories
+            .iter()
+            .fold(VersionVector::new(), |vv, version| {
+                vv.merged(version.data.version_vector())
+            }),
+        branch_id: first.branch_id,
+        needs_disambiguation,
+    });
+
+    directory
+        .into_iter()
+        .chain(files.into_iter().map(|version| DirEntry {
+            name: name.clone(),
+            entry_type: EntryType::File,
+            version_vector: version.data.version_vector().clone(),
+            branch_id: version.branch_id,
+            needs_disambiguation,
+        }))
+        .collect()
+}
+
+struct Version {
+    branch_id: PublicKey,
+    data: EntryData,
+}
+
+impl Versioned for Version {
+    fn version_vector(&self) -> &VersionVector {
+        self.data.version_vector()
+    }
+}
+
+impl BranchItem for Version {
+    fn branch_id(&self) -> &PublicKey {
+        &self.branch_id
+    }
+}
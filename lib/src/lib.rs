@@ -7,6 +7,8 @@
 #[macro_use]
 mod macros;
 
+#[cfg(feature = "blocking")]
+pub mod blocking;
 pub mod crypto;
 pub mod network;
 pub mod path;
@@ -32,36 +34,52 @@ mod joint_entry;
 mod progress;
 mod protocol;
 mod repository;
+mod rng;
 mod storage_size;
 mod store;
 mod sync;
 #[cfg(test)]
 mod test_utils;
+mod tracing_targets;
 #[cfg_attr(test, macro_use)]
 mod version_vector;
 mod versioned;
 
+pub use deadlock::{dump as dump_locks, LifetimeWarning};
+
 pub use self::{
-    access_control::{Access, AccessMode, AccessSecrets, LocalSecret, ShareToken, WriteSecrets},
-    blob::HEADER_SIZE as BLOB_HEADER_SIZE,
+    access_control::{
+        Access, AccessMode, AccessSecrets, LocalSecret, ShareToken, ShareTokenError,
+        ShareTokenInfo, WriteSecrets,
+    },
+    blob::{BlobId, HEADER_SIZE as BLOB_HEADER_SIZE},
     branch::Branch,
-    db::SCHEMA_VERSION,
+    conflict::{Conflict, ConflictVersion},
+    db::{ResourceLimits, Synchronous, WalCheckpoint, SCHEMA_VERSION},
     debug::DebugPrinter,
     device_id::DeviceId,
     directory::{Directory, EntryRef, EntryType, DIRECTORY_VERSION},
     error::{Error, Result},
     event::{Event, Payload},
-    file::File,
-    joint_directory::{JointDirectory, JointEntryRef},
+    file::{File, SharedFile},
+    joint_directory::{DirEntry, JointDirectory, JointEntryRef},
     joint_entry::JointEntry,
     network::{peer_addr::PeerAddr, PeerInfo, PeerInfoCollector, PublicRuntimeId, SecretRuntimeId},
     progress::Progress,
-    protocol::BLOCK_SIZE,
+    protocol::{BlockId, RawBlock, BLOCK_NONCE_SIZE, BLOCK_SIZE},
     repository::{
-        delete as delete_repository, Metadata, ReopenToken, Repository, RepositoryHandle,
-        RepositoryId, RepositoryParams,
+        delete as delete_repository, delete_with_block_store as delete_repository_with_block_store,
+        rename as rename_repository, BlockRequestMode, DedupStats, MaintenanceKind, MergeStrategy,
+        Metadata, PrunePolicy, QuotaUsage, ReopenToken, RepairStats, Repository, RepositoryHandle,
+        RepositoryId, RepositoryParams, Snapshot, SnapshotFile, WriterInfo,
     },
+    rng::RngSource,
     storage_size::StorageSize,
-    store::{Error as StoreError, DATA_VERSION},
+    store::{BlockStore, Error as StoreError, IntegrityViolation, SharedCache, DATA_VERSION},
+    sync::stream::Throttle,
+    tracing_targets::tracing_targets,
     version_vector::VersionVector,
 };
+
+#[cfg(feature = "prometheus")]
+pub use metrics_exporter_prometheus::PrometheusHandle;
@@ -1,26 +1,39 @@
 use super::{
     constants::MAX_PENDING_RESPONSES,
     debug_payload::{DebugResponse, PendingDebugRequest},
-    message::{Content, Response, ResponseDisambiguator},
+    message::{Content, Request, Response, ResponseDisambiguator},
     pending::{PendingRequest, PendingRequests, PendingResponse, ProcessedResponse},
+    runtime_id::PublicRuntimeId,
+    sync_state::LinkSyncState,
 };
 use crate::{
-    block_tracker::{BlockPromise, OfferState, TrackerClient},
+    block_tracker::{BlockOffer, BlockPromise, OfferState, TrackerClient},
     crypto::{sign::PublicKey, CacheHash, Hashable},
     error::{Error, Result},
     protocol::{
         Block, BlockId, InnerNodes, LeafNodes, MultiBlockPresence, RootNodeFilter, UntrustedProof,
     },
-    repository::{BlockRequestMode, Vault},
+    repository::{PeerMonitor, Vault},
     store::{self, ReceiveFilter},
 };
-use std::{pin::pin, sync::Arc, time::Instant};
+use std::{
+    cmp::Reverse,
+    collections::VecDeque,
+    mem,
+    pin::pin,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 use tokio::{
     select,
-    sync::{mpsc, Semaphore},
+    sync::{mpsc, OwnedSemaphorePermit, Semaphore},
+    time,
 };
 use tracing::{instrument, Level};
 
+// How often to check whether some of the deferred greedy block requests have become required.
+const DEFERRED_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 pub(super) struct Client {
     inner: Inner,
     rx: mpsc::Receiver<Response>,
@@ -31,13 +44,17 @@ pub(super) struct Client {
 impl Client {
     pub fn new(
         vault: Vault,
+        that_runtime_id: PublicRuntimeId,
         tx: mpsc::Sender<Content>,
         rx: mpsc::Receiver<Response>,
         peer_request_limiter: Arc<Semaphore>,
+        index_request_batch_window: Duration,
+        sync_state: Arc<LinkSyncState>,
     ) -> Self {
-        let pending_requests = PendingRequests::new(vault.monitor.clone());
+        let pending_requests = Arc::new(PendingRequests::new(vault.monitor.clone()));
         let receive_filter = vault.store().receive_filter();
-        let block_tracker = vault.block_tracker.client();
+        let block_tracker = Arc::new(vault.block_tracker.client());
+        let peer_monitor = vault.monitor.peer(that_runtime_id);
 
         // We run the sender in a separate task so we can keep sending requests while we're
         // processing responses (which sometimes takes a while).
@@ -48,12 +65,17 @@ impl Client {
         // responses (so we shoulnd't use unbounded_channel).
         let (recv_queue_tx, recv_queue_rx) = mpsc::channel(2 * MAX_PENDING_RESPONSES);
 
+        sync_state.attach(&pending_requests, &block_tracker);
+
         let inner = Inner {
             vault,
             pending_requests,
             peer_request_limiter,
+            index_request_batch_window,
             receive_filter,
             block_tracker,
+            peer_monitor,
+            sync_state,
             tx,
             send_queue_tx,
             recv_queue_tx,
@@ -83,10 +105,13 @@ impl Client {
 
 struct Inner {
     vault: Vault,
-    pending_requests: PendingRequests,
+    pending_requests: Arc<PendingRequests>,
     peer_request_limiter: Arc<Semaphore>,
+    index_request_batch_window: Duration,
     receive_filter: ReceiveFilter,
-    block_tracker: TrackerClient,
+    block_tracker: Arc<TrackerClient>,
+    peer_monitor: Arc<PeerMonitor>,
+    sync_state: Arc<LinkSyncState>,
     tx: mpsc::Sender<Content>,
     send_queue_tx: mpsc::UnboundedSender<(PendingRequest, Instant)>,
     recv_queue_tx: mpsc::Sender<(PendingResponse, Instant)>,
@@ -146,40 +171,183 @@ impl Inner {
         // Limits requests per link (peer + repo)
         let link_request_limiter = Arc::new(Semaphore::new(MAX_PENDING_RESPONSES));
 
+        // Index requests waiting to be sent together in a single message.
+        let mut batch = Vec::new();
+        let mut batch_deadline = time::Instant::now();
+
+        // Block requests made only because of the greedy mode which are waiting for a greedy
+        // permit. They are set aside instead of waiting for the permit here so they don't hold up
+        // the other requests (the greedy limit can even be zero).
+        let mut deferred = VecDeque::new();
+        // Deferred requests can become required in the meantime (e.g., when the file they belong
+        // to is opened), in which case they must not wait any longer. This periodically triggers
+        // a check for such requests.
+        let mut recheck_interval = time::interval(DEFERRED_RECHECK_INTERVAL);
+        let mut recheck = false;
+
+        // Block requests waiting for a block permit, together with their greedy permits (if any).
+        // Also set aside so they don't hold up the index requests.
+        let mut blocked = VecDeque::new();
+
         loop {
-            let Some((request, timestamp)) = send_queue_rx.recv().await else {
-                break;
+            let required = if recheck {
+                take_required(&mut deferred)
+            } else {
+                None
+            };
+
+            let ((request, timestamp), greedy_permit, block_permit) = if let Some(next) = required {
+                (next, None, None)
+            } else {
+                recheck = false;
+
+                let batch_empty = batch.is_empty();
+                let recv = async {
+                    if batch_empty {
+                        Ok(send_queue_rx.recv().await)
+                    } else {
+                        time::timeout_at(batch_deadline, send_queue_rx.recv()).await
+                    }
+                };
+
+                select! {
+                    permit = self.vault.block_request_limiter.clone().acquire(),
+                        if !blocked.is_empty() =>
+                    {
+                        // unwrap is ok because `blocked` is not empty.
+                        let (next, greedy_permit) = take_blocked(&mut blocked).unwrap();
+                        (next, greedy_permit, Some(permit))
+                    }
+                    permit = self.vault.greedy_block_request_limiter.clone().acquire(),
+                        if !deferred.is_empty() =>
+                    {
+                        // unwrap is ok because `deferred` is not empty.
+                        (deferred.pop_front().unwrap(), Some(permit), None)
+                    }
+                    _ = recheck_interval.tick(), if !deferred.is_empty() => {
+                        recheck = true;
+                        continue;
+                    }
+                    next = recv => match next {
+                        Ok(Some(next)) => (next, None, None),
+                        Ok(None) => break,
+                        Err(_) => {
+                            self.send_batch(&mut batch).await;
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            // Limits block requests per repository (across all peers). Acquired first so that
+            // waiting for it doesn't hold up the other permits. Requests that can't get it right
+            // away wait in `blocked`. Blocks requested only because of the greedy mode are
+            // additionally subject to the greedy limit.
+            let (block_permit, greedy_permit) = match &request {
+                PendingRequest::Block(_, _) if block_permit.is_some() => {
+                    // Already went through the checks below before it was set aside.
+                    self.send_batch(&mut batch).await;
+                    (block_permit, greedy_permit)
+                }
+                PendingRequest::Block(offer, _) => {
+                    let greedy_permit = if greedy_permit.is_some() || offer.is_required() {
+                        greedy_permit
+                    } else if let Some(permit) = self
+                        .vault
+                        .greedy_block_request_limiter
+                        .clone()
+                        .try_acquire()
+                    {
+                        Some(permit)
+                    } else {
+                        deferred.push_back((request, timestamp));
+                        continue;
+                    };
+
+                    // Queue up behind the requests already waiting for a block permit.
+                    let block_permit = if blocked.is_empty() {
+                        self.vault.block_request_limiter.clone().try_acquire()
+                    } else {
+                        None
+                    };
+
+                    let Some(block_permit) = block_permit else {
+                        blocked.push_back(((request, timestamp), greedy_permit));
+                        continue;
+                    };
+
+                    self.send_batch(&mut batch).await;
+
+                    (Some(block_permit), greedy_permit)
+                }
+                PendingRequest::RootNode(..) | PendingRequest::ChildNodes(..) => (None, None),
             };
 
-            // Unwraps OK because we never `close()` the semaphores.
-            //
             // NOTE that the order here is important, we don't want to block the other clients
             // on this peer if we have too many responses queued up (which is what the
             // `link_permit` is responsible for limiting)..
-            let link_permit = link_request_limiter.clone().acquire_owned().await.unwrap();
-
-            let peer_permit = self
-                .peer_request_limiter
-                .clone()
-                .acquire_owned()
-                .await
-                .unwrap();
-
-            self.vault
-                .monitor
-                .request_queue_time
-                .record(timestamp.elapsed());
-
-            let Some(request) = self
-                .pending_requests
-                .insert(request, link_permit, peer_permit)
-            else {
+            let link_permit = self.acquire(&link_request_limiter, &mut batch).await;
+            let peer_permit = self.acquire(&self.peer_request_limiter, &mut batch).await;
+
+            let queue_time = timestamp.elapsed();
+            self.vault.monitor.request_queue_time.record(queue_time);
+            self.peer_monitor.request_queue_time.record(queue_time);
+
+            let Some(request) = self.pending_requests.insert(
+                request,
+                link_permit,
+                peer_permit,
+                block_permit,
+                greedy_permit,
+            ) else {
                 // The same request is already in-flight.
                 continue;
             };
 
-            self.tx.send(Content::Request(request)).await.unwrap_or(());
+            match request {
+                Request::RootNode(..) | Request::ChildNodes(..) => {
+                    if batch.is_empty() {
+                        batch_deadline = time::Instant::now() + self.index_request_batch_window;
+                    }
+
+                    batch.push(request);
+                }
+                Request::Block(..) => {
+                    self.tx.send(Content::Request(request)).await.unwrap_or(());
+                }
+            }
         }
+
+        self.send_batch(&mut batch).await;
+    }
+
+    // Acquires a permit from the semaphore. If none is available, sends the batched requests first
+    // because they are already holding permits which are released only after their responses
+    // arrive.
+    async fn acquire(
+        &self,
+        semaphore: &Arc<Semaphore>,
+        batch: &mut Vec<Request>,
+    ) -> OwnedSemaphorePermit {
+        if let Ok(permit) = semaphore.clone().try_acquire_owned() {
+            return permit;
+        }
+
+        self.send_batch(batch).await;
+
+        // Unwrap OK because we never `close()` the semaphores.
+        semaphore.clone().acquire_owned().await.unwrap()
+    }
+
+    async fn send_batch(&self, batch: &mut Vec<Request>) {
+        let content = match batch.len() {
+            0 => return,
+            1 => Content::Request(batch.remove(0)),
+            _ => Content::Requests(mem::take(batch)),
+        };
+
+        self.vault.monitor.index_requests_sent.increment(1);
+        self.tx.send(content).await.unwrap_or(());
     }
 
     async fn enqueue_responses(&self, rx: &mut mpsc::Receiver<Response>) {
@@ -215,10 +383,9 @@ impl Inner {
         loop {
             match recv_queue_rx.recv().await {
                 Some((response, timestamp)) => {
-                    self.vault
-                        .monitor
-                        .response_queue_time
-                        .record(timestamp.elapsed());
+                    let queue_time = timestamp.elapsed();
+                    self.vault.monitor.response_queue_time.record(queue_time);
+                    self.peer_monitor.response_queue_time.record(queue_time);
 
                     let start = Instant::now();
                     self.handle_response(response).await?;
@@ -276,8 +443,13 @@ impl Inner {
         debug_payload: DebugResponse,
     ) -> Result<()> {
         let hash = proof.hash;
+        let writer_id = proof.writer_id;
+        let version_vector = proof.version_vector.clone();
         let status = self.vault.receive_root_node(proof, block_presence).await?;
 
+        self.sync_state
+            .record_remote_version(writer_id, &version_vector);
+
         if status.request_children {
             self.enqueue_request(PendingRequest::ChildNodes(
                 hash,
@@ -374,19 +546,10 @@ impl Inner {
                 OfferState::Pending
             };
 
-        match self.vault.block_request_mode {
-            BlockRequestMode::Lazy => {
-                for node in status.request_blocks {
-                    self.block_tracker.register(node.block_id, offer_state);
-                }
-            }
-            BlockRequestMode::Greedy => {
-                for node in status.request_blocks {
-                    if self.block_tracker.register(node.block_id, offer_state) {
-                        self.vault.block_tracker.require(node.block_id);
-                    }
-                }
-            }
+        // In greedy mode the tracker treats all offered blocks as required, so registering them is
+        // enough.
+        for node in status.request_blocks {
+            self.block_tracker.register(node.block_id, offer_state);
         }
 
         if quota.is_some() {
@@ -413,16 +576,7 @@ impl Inner {
 
         tracing::trace!(?offer_state, "Received block offer");
 
-        if !self.block_tracker.register(block_id, offer_state) {
-            return Ok(());
-        }
-
-        match self.vault.block_request_mode {
-            BlockRequestMode::Lazy => (),
-            BlockRequestMode::Greedy => {
-                self.vault.block_tracker.require(block_id);
-            }
-        }
+        self.block_tracker.register(block_id, offer_state);
 
         Ok(())
     }
@@ -519,3 +673,41 @@ impl Inner {
         }
     }
 }
+
+// Removes and returns the highest priority deferred request which has become required, if any.
+fn take_required(
+    deferred: &mut VecDeque<(PendingRequest, Instant)>,
+) -> Option<(PendingRequest, Instant)> {
+    let index = position_by_priority(
+        deferred.iter().map(|(request, _)| request),
+        BlockOffer::is_required,
+    )?;
+
+    deferred.remove(index)
+}
+
+// Removes and returns the highest priority request waiting for a block permit, if any.
+fn take_blocked<T>(
+    blocked: &mut VecDeque<((PendingRequest, Instant), T)>,
+) -> Option<((PendingRequest, Instant), T)> {
+    let index = position_by_priority(blocked.iter().map(|((request, _), _)| request), |_| true)?;
+    blocked.remove(index)
+}
+
+// Position of the highest priority block request that passes `filter`. The earliest one if there
+// are more of them.
+fn position_by_priority<'a>(
+    requests: impl Iterator<Item = &'a PendingRequest>,
+    filter: impl Fn(&BlockOffer) -> bool,
+) -> Option<usize> {
+    requests
+        .enumerate()
+        .filter_map(|(index, request)| match request {
+            PendingRequest::Block(offer, _) if filter(offer) => Some((index, offer.priority())),
+            PendingRequest::Block(..)
+            | PendingRequest::RootNode(..)
+            | PendingRequest::ChildNodes(..) => None,
+        })
+        .min_by_key(|(index, priority)| (Reverse(*priority), *index))
+        .map(|(index, _)| index)
+}
@@ -11,6 +11,7 @@ use std::{
         atomic::{AtomicU64, Ordering},
         Arc,
     },
+    time::Instant,
 };
 
 pub(super) type PermitId = u64;
@@ -49,7 +50,13 @@ impl ConnectionDeduplicator {
     /// yet, it returns a `ConnectionPermit` which keeps the connection reserved as long as it
     /// lives. Otherwise it returns `None`. To release a connection the permit needs to be dropped.
     /// Also returns a notification object that can be used to wait until the permit gets released.
-    pub fn reserve(&self, addr: PeerAddr, source: PeerSource) -> ReserveResult {
+    /// `introduced_by` is the peer that told us about this one via peer exchange, if any.
+    pub fn reserve(
+        &self,
+        addr: PeerAddr,
+        source: PeerSource,
+        introduced_by: Option<PublicRuntimeId>,
+    ) -> ReserveResult {
         let info = ConnectionInfo {
             addr,
             dir: ConnectionDirection::from_source(source),
@@ -64,6 +71,8 @@ impl ConnectionDeduplicator {
                     id,
                     state: PeerState::Known,
                     source,
+                    introduced_by,
+                    active_since: None,
                     on_release: on_release_tx,
                 });
                 self.on_change_tx.send(()).unwrap_or(());
@@ -105,7 +114,7 @@ impl ConnectionDeduplicator {
         connections
             .get(&incoming)
             .or_else(|| connections.get(&outgoing))
-            .map(|peer| PeerInfo::new(addr, peer.source, peer.state))
+            .map(|peer| peer.info(addr))
     }
 
     pub fn on_change(&self) -> uninitialized_watch::Receiver<()> {
@@ -128,7 +137,7 @@ impl PeerInfoCollector {
             .lock()
             .unwrap()
             .iter()
-            .map(|(key, peer)| PeerInfo::new(key.addr, peer.source, peer.state))
+            .map(|(key, peer)| peer.info(key.addr))
             .collect()
     }
 }
@@ -137,9 +146,24 @@ pub(super) struct Peer {
     id: PermitId,
     state: PeerState,
     source: PeerSource,
+    introduced_by: Option<PublicRuntimeId>,
+    // When did the peer become active (`None` if not active).
+    active_since: Option<Instant>,
     on_release: DropAwaitable,
 }
 
+impl Peer {
+    fn info(&self, addr: PeerAddr) -> PeerInfo {
+        PeerInfo::new(
+            addr,
+            self.source,
+            self.state,
+            self.active_since.map(|instant| instant.elapsed()),
+            self.introduced_by,
+        )
+    }
+}
+
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Serialize)]
 pub(super) enum ConnectionDirection {
     Incoming,
@@ -205,6 +229,10 @@ impl ConnectionPermit {
 
         if peer.state != new_state {
             peer.state = new_state;
+            peer.active_since = match new_state {
+                PeerState::Active(_) => Some(Instant::now()),
+                PeerState::Known | PeerState::Connecting | PeerState::Handshaking => None,
+            };
             self.on_deduplicator_change.send(()).unwrap_or(());
         }
     }
@@ -231,13 +259,15 @@ impl ConnectionPermit {
     pub fn dummy() -> Self {
         use std::net::Ipv4Addr;
 
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
         Self {
             connections: Arc::new(BlockingMutex::new(HashMap::default())),
             info: ConnectionInfo {
                 addr: PeerAddr::Tcp((Ipv4Addr::UNSPECIFIED, 0).into()),
                 dir: ConnectionDirection::Incoming,
             },
-            id: 0,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
             on_deduplicator_change: Arc::new(uninitialized_watch::channel().0),
         }
     }
@@ -11,8 +11,8 @@ use super::{
     message_dispatcher::{ChannelClosed, ContentSink, ContentStream, ContentStreamError},
     runtime_id::PublicRuntimeId,
 };
-use crate::repository::RepositoryId;
-use noise_protocol::Cipher as _;
+use crate::{crypto::CipherInfo, repository::RepositoryId};
+use noise_protocol::{Cipher as _, Hash as _, DH as _};
 use noise_rust_crypto::{Blake2s, ChaCha20Poly1305, X25519};
 use std::mem;
 use thiserror::Error;
@@ -55,6 +55,50 @@ impl Role {
     }
 }
 
+/// Cryptographic primitives and parameters used to encrypt the communication with the peers (see
+/// [`Network::crypto_info`](super::Network::crypto_info)).
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct NetworkCryptoInfo {
+    /// Full name of the Noise protocol used to establish the encrypted channels (one per peer and
+    /// repository).
+    pub protocol: String,
+    /// Diffie-Hellman function used in the handshake.
+    pub dh: &'static str,
+    /// Size of the Diffie-Hellman public key in bytes.
+    pub dh_public_key_size: usize,
+    /// Cipher used to encrypt the messages. The nonce is the 64-bit message counter.
+    pub cipher: CipherInfo,
+    /// Hash function used in the handshake.
+    pub hash: &'static str,
+    /// Size of the hash digest in bytes.
+    pub hash_size: usize,
+}
+
+impl NetworkCryptoInfo {
+    pub(super) fn new() -> Self {
+        Self {
+            protocol: format!(
+                "Noise_{}_{}_{}_{}",
+                noise_protocol::patterns::noise_nn_psk0().get_name(),
+                X25519::name(),
+                Cipher::name(),
+                Blake2s::name()
+            ),
+            dh: X25519::name(),
+            dh_public_key_size: X25519::pub_len(),
+            cipher: CipherInfo {
+                name: Cipher::name(),
+                key_size: Cipher::key_len(),
+                // ChaCha20-Poly1305 as specified in RFC 8439
+                nonce_size: 12,
+                tag_size: Cipher::tag_len(),
+            },
+            hash: Blake2s::name(),
+            hash_size: Blake2s::hash_len(),
+        }
+    }
+}
+
 // This also determines the maximum number of messages we can send or receive in a single protocol
 // session.
 const MAX_NONCE: u64 = u64::MAX - 1;
@@ -255,3 +299,16 @@ async fn handshake_recv(
     let content = stream.recv().await?;
     Ok(state.read_message_vec(&content)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crypto_info() {
+        let info = NetworkCryptoInfo::new();
+        assert_eq!(info.protocol, "Noise_NNpsk0_25519_ChaChaPoly_BLAKE2s");
+        assert_eq!(info.cipher.key_size, 32);
+        assert_eq!(info.cipher.tag_size, 16);
+    }
+}
@@ -2,7 +2,10 @@ use super::{
     peer_addr::PeerAddr,
     seen_peers::{SeenPeer, SeenPeers},
 };
-use crate::collections::{hash_map, HashMap, HashSet};
+use crate::{
+    collections::{hash_map, HashMap, HashSet},
+    repository::DhtMonitor,
+};
 use async_trait::async_trait;
 use btdht::{InfoHash, MainlineDht};
 use chrono::{offset::Local, DateTime};
@@ -17,7 +20,7 @@ use std::{
     io,
     net::{SocketAddr, SocketAddrV4, SocketAddrV6},
     sync::{
-        atomic::{AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc, Weak,
     },
     time::SystemTime,
@@ -45,6 +48,9 @@ pub const DHT_ROUTERS: &[&str] = &[
 pub const MIN_DHT_ANNOUNCE_DELAY: Duration = Duration::from_secs(3 * 60);
 pub const MAX_DHT_ANNOUNCE_DELAY: Duration = Duration::from_secs(6 * 60);
 
+// How often are the contacts from the DHT routing table saved into the contacts store.
+const SAVE_CONTACTS_INTERVAL: Duration = Duration::from_secs(60);
+
 #[async_trait]
 pub trait DhtContactsStoreTrait: Sync + Send + 'static {
     async fn load_v4(&self) -> io::Result<HashSet<SocketAddrV4>>;
@@ -57,6 +63,8 @@ pub(super) struct DhtDiscovery {
     v4: BlockingMutex<RestartableDht>,
     v6: BlockingMutex<RestartableDht>,
     lookups: Arc<BlockingMutex<Lookups>>,
+    // Whether the lookups also announce us as a peer.
+    announce: Arc<AtomicBool>,
     next_id: AtomicU64,
     main_monitor: StateMonitor,
     lookups_monitor: StateMonitor,
@@ -81,6 +89,7 @@ impl DhtDiscovery {
             v4,
             v6,
             lookups,
+            announce: Arc::new(AtomicBool::new(true)),
             next_id: AtomicU64::new(0),
             span: Span::current(),
             main_monitor: monitor,
@@ -116,16 +125,29 @@ impl DhtDiscovery {
                 dht_v4.clone(),
                 dht_v6.clone(),
                 *info_hash,
+                self.announce.clone(),
                 &self.lookups_monitor,
                 &self.span,
             );
         }
     }
 
+    /// Returns whether the IPv4 and the IPv6 DHT, respectively, have a socket to run on.
+    pub fn is_bound(&self) -> (bool, bool) {
+        (
+            self.v4.lock().unwrap().socket_maker.is_some(),
+            self.v6.lock().unwrap().socket_maker.is_some(),
+        )
+    }
+
+    /// Starts looking up peers for the given info hash, reporting the found ones to
+    /// `found_peers_tx`. The lookup results are recorded in `metrics`. If a lookup for the same
+    /// info hash is already running, it's shared and its original metrics are kept.
     pub fn start_lookup(
         &self,
         info_hash: InfoHash,
         found_peers_tx: mpsc::UnboundedSender<SeenPeer>,
+        metrics: DhtMonitor,
     ) -> LookupRequest {
         let id = self.next_id.fetch_add(1, Ordering::Relaxed);
 
@@ -157,6 +179,8 @@ impl DhtDiscovery {
                         dht_v4,
                         dht_v6,
                         info_hash,
+                        self.announce.clone(),
+                        metrics,
                         &self.lookups_monitor,
                         &self.span,
                     ))
@@ -166,6 +190,86 @@ impl DhtDiscovery {
 
         request
     }
+
+    /// Sets whether the lookups also announce us as a peer. When disabled, the peers are still
+    /// found but the others can't find us. Takes effect from the next round of each lookup.
+    pub fn set_announce(&self, announce: bool) {
+        self.announce.store(announce, Ordering::Relaxed);
+    }
+
+    /// Loads the contacts from the contacts store and returns their number. The contacts are loaded
+    /// also every time a DHT is started, this is to check the store explicitly.
+    pub async fn load_contacts(&self) -> usize {
+        let mut count = 0;
+
+        for (is_v4, dht) in [(true, &self.v4), (false, &self.v6)] {
+            let (contacts_store, persisted_contacts) = {
+                let dht = dht.lock().unwrap();
+                (dht.contacts_store.clone(), dht.persisted_contacts.clone())
+            };
+
+            if let Some(contacts_store) = contacts_store {
+                count += load_contacts(is_v4, &*contacts_store, &persisted_contacts)
+                    .await
+                    .len();
+            }
+        }
+
+        count
+    }
+
+    /// Saves the contacts from the routing tables of the running DHTs into the contacts store and
+    /// returns their number. DHTs that haven't bootstrapped yet or have empty routing tables are
+    /// skipped so they don't overwrite the previously persisted contacts.
+    pub async fn save_contacts(&self) -> io::Result<usize> {
+        let mut count = 0;
+
+        for (is_v4, dht) in [(true, &self.v4), (false, &self.v6)] {
+            let (contacts_store, persisted_contacts, dht) = {
+                let dht = dht.lock().unwrap();
+                (
+                    dht.contacts_store.clone(),
+                    dht.persisted_contacts.clone(),
+                    dht.dht.upgrade(),
+                )
+            };
+
+            let Some(contacts_store) = contacts_store else {
+                continue;
+            };
+
+            // A DHT that is still being created hasn't bootstrapped either.
+            let Some(dht) = dht
+                .as_deref()
+                .and_then(Option::as_ref)
+                .and_then(TaskOrResult::get)
+            else {
+                continue;
+            };
+
+            if !dht.bootstrapped.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let (good, questionable) = dht
+                .dht
+                .load_contacts()
+                .await
+                .map_err(|error| io::Error::new(io::ErrorKind::Other, format!("{error:?}")))?;
+            let contacts = good.union(&questionable).copied();
+
+            if save_contacts(is_v4, contacts, &*contacts_store, &persisted_contacts).await? {
+                count += persisted_contacts.load(Ordering::Relaxed);
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Number of contacts in the contacts store as of the last time they were loaded or saved.
+    pub fn persisted_contacts(&self) -> usize {
+        self.v4.lock().unwrap().persisted_contacts() + self.v6.lock().unwrap().persisted_contacts()
+    }
 }
 
 // Wrapper for a DHT instance that can be stopped and restarted at any point.
@@ -173,6 +277,8 @@ struct RestartableDht {
     socket_maker: Option<quic::SideChannelMaker>,
     dht: Weak<Option<TaskOrResult<MonitoredDht>>>,
     contacts_store: Option<Arc<dyn DhtContactsStoreTrait>>,
+    // Number of contacts of this DHT's address family in the contacts store.
+    persisted_contacts: Arc<AtomicUsize>,
 }
 
 impl RestartableDht {
@@ -184,6 +290,7 @@ impl RestartableDht {
             socket_maker,
             dht: Weak::new(),
             contacts_store,
+            persisted_contacts: Arc::new(AtomicUsize::new(0)),
         }
     }
 
@@ -198,7 +305,13 @@ impl RestartableDht {
             dht
         } else if let Some(maker) = &self.socket_maker {
             let socket = maker.make();
-            let dht = MonitoredDht::start(socket, monitor, span, self.contacts_store.clone());
+            let dht = MonitoredDht::start(
+                socket,
+                monitor,
+                span,
+                self.contacts_store.clone(),
+                self.persisted_contacts.clone(),
+            );
 
             let dht = Arc::new(Some(dht));
 
@@ -214,11 +327,16 @@ impl RestartableDht {
         self.socket_maker = socket_maker;
         self.dht = Weak::new();
     }
+
+    fn persisted_contacts(&self) -> usize {
+        self.persisted_contacts.load(Ordering::Relaxed)
+    }
 }
 
 // Wrapper for a DHT instance that periodically outputs it's state to the provided StateMonitor.
 struct MonitoredDht {
     dht: MainlineDht,
+    bootstrapped: Arc<AtomicBool>,
     _monitoring_task: ScopedJoinHandle<()>,
     _periodic_dht_node_load_task: Option<ScopedJoinHandle<()>>,
 }
@@ -229,6 +347,7 @@ impl MonitoredDht {
         parent_monitor: &StateMonitor,
         span: &Span,
         contacts_store: Option<Arc<dyn DhtContactsStoreTrait>>,
+        persisted_contacts: Arc<AtomicUsize>,
     ) -> TaskOrResult<Self> {
         // TODO: Unwrap
         let local_addr = socket.local_addr().unwrap();
@@ -246,6 +365,7 @@ impl MonitoredDht {
             monitor,
             span,
             contacts_store,
+            persisted_contacts,
         )))
     }
 
@@ -255,22 +375,25 @@ impl MonitoredDht {
         monitor: StateMonitor,
         span: Span,
         contacts_store: Option<Arc<dyn DhtContactsStoreTrait>>,
+        persisted_contacts: Arc<AtomicUsize>,
     ) -> Self {
-        // TODO: load the DHT state from a previous save if it exists.
         let builder = MainlineDht::builder()
             .add_routers(DHT_ROUTERS.iter().copied())
             .set_read_only(false);
 
+        // Load the contacts persisted by a previous run, to report their number.
+        //
         // TODO: The reuse of initial contacts is incorrectly implemented, once the issue
         // https://github.com/equalitie/btdht/issues/8
-        // is fixed, this can be uncommented again.
-        //if let Some(contacts_store) = &contacts_store {
-        //    let initial_contacts = Self::load_initial_contacts(is_v4, &**contacts_store).await;
-
-        //    for contact in initial_contacts {
-        //        builder = builder.add_node(contact);
-        //    }
+        // is fixed (and btdht bumped to include the fix), the loaded contacts can be added again:
+        //for contact in initial_contacts {
+        //    builder = builder.add_node(contact);
         //}
+        if let Some(contacts_store) = &contacts_store {
+            load_contacts(is_v4, &**contacts_store, &persisted_contacts)
+                .instrument(span.clone())
+                .await;
+        }
 
         let dht = builder
             .start(Socket(socket))
@@ -278,9 +401,12 @@ impl MonitoredDht {
             // there but better check.
             .unwrap();
 
+        let bootstrapped_flag = Arc::new(AtomicBool::new(false));
+
         // Spawn a task to monitor the DHT status.
         let monitoring_task = {
             let dht = dht.clone();
+            let bootstrapped_flag = bootstrapped_flag.clone();
 
             let first_bootstrap = monitor.make_value("first_bootstrap", "in progress");
             let probe_counter = monitor.make_value("probe_counter", 0);
@@ -294,6 +420,7 @@ impl MonitoredDht {
                 tracing::info!("bootstrap started");
 
                 if dht.bootstrapped(None).await {
+                    bootstrapped_flag.store(true, Ordering::Relaxed);
                     *first_bootstrap.get() = "done";
                     tracing::info!("bootstrap complete");
                 } else {
@@ -331,27 +458,33 @@ impl MonitoredDht {
 
         let _periodic_dht_node_load_task = contacts_store.map(|contacts_store| {
             scoped_task::spawn(
-                Self::keep_reading_contacts(is_v4, dht.clone(), contacts_store).instrument(span),
+                Self::keep_reading_contacts(is_v4, dht.clone(), contacts_store, persisted_contacts)
+                    .instrument(span),
             )
         });
 
         Self {
             dht,
+            bootstrapped: bootstrapped_flag,
             _monitoring_task: monitoring_task,
             _periodic_dht_node_load_task,
         }
     }
 
-    /// Periodically read contacts from the `dht` and send it to `on_periodic_dht_node_load_tx`.
+    /// Periodically read contacts from the `dht` and save them into `contacts_store`. Nothing is
+    /// saved until the DHT bootstraps and empty routing tables are never saved, so that a DHT that
+    /// is offline or has just started doesn't overwrite the previously persisted contacts.
     async fn keep_reading_contacts(
         is_v4: bool,
         dht: MainlineDht,
         contacts_store: Arc<dyn DhtContactsStoreTrait>,
+        persisted_contacts: Arc<AtomicUsize>,
     ) {
-        let mut reported_failure = false;
+        if !dht.bootstrapped(None).await {
+            return;
+        }
 
-        // Give `MainlineDht` a chance to bootstrap.
-        time::sleep(Duration::from_secs(10)).await;
+        let mut reported_failure = false;
 
         loop {
             let (good, questionable) = match dht.load_contacts().await {
@@ -363,67 +496,103 @@ impl MonitoredDht {
             };
 
             // TODO: Make use of the information which is good and which questionable.
-            let mix = good.union(&questionable);
-
-            if is_v4 {
-                let mix = mix.filter_map(|addr| match addr {
-                    SocketAddr::V4(addr) => Some(*addr),
-                    SocketAddr::V6(_) => None,
-                });
-
-                match contacts_store.store_v4(mix.collect()).await {
-                    Ok(()) => reported_failure = false,
-                    Err(error) => {
-                        if !reported_failure {
-                            reported_failure = true;
-                            tracing::error!("DhtDiscovery failed to write contacts {error:?}");
-                        }
-                    }
-                }
-            } else {
-                let mix = mix.filter_map(|addr| match addr {
-                    SocketAddr::V4(_) => None,
-                    SocketAddr::V6(addr) => Some(*addr),
-                });
-
-                match contacts_store.store_v6(mix.collect()).await {
-                    Ok(()) => reported_failure = false,
-                    Err(error) => {
-                        if !reported_failure {
-                            reported_failure = true;
-                            tracing::error!("DhtDiscovery failed to write contacts {error:?}");
-                        }
+            let mix = good.union(&questionable).copied();
+
+            match save_contacts(is_v4, mix, &*contacts_store, &persisted_contacts).await {
+                Ok(_) => reported_failure = false,
+                Err(error) => {
+                    if !reported_failure {
+                        reported_failure = true;
+                        tracing::error!("DhtDiscovery failed to write contacts {error:?}");
                     }
                 }
             }
 
-            time::sleep(Duration::from_secs(60)).await;
+            time::sleep(SAVE_CONTACTS_INTERVAL).await;
         }
     }
+}
+
+// Loads the persisted contacts of the given address family and records their number in
+// `persisted_contacts`. A contacts store that hasn't been written to yet is treated as empty.
+async fn load_contacts(
+    is_v4: bool,
+    contacts_store: &(impl DhtContactsStoreTrait + ?Sized),
+    persisted_contacts: &AtomicUsize,
+) -> HashSet<SocketAddr> {
+    let result = if is_v4 {
+        contacts_store
+            .load_v4()
+            .await
+            .map(|contacts| contacts.into_iter().map(SocketAddr::V4).collect())
+    } else {
+        contacts_store
+            .load_v6()
+            .await
+            .map(|contacts| contacts.into_iter().map(SocketAddr::V6).collect())
+    };
+
+    let contacts: HashSet<_> = match result {
+        Ok(contacts) => contacts,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => HashSet::default(),
+        Err(error) => {
+            tracing::error!("Failed to load DHT contacts {:?}", error);
+            HashSet::default()
+        }
+    };
+
+    tracing::debug!(count = contacts.len(), "DHT contacts loaded");
+    persisted_contacts.store(contacts.len(), Ordering::Relaxed);
 
-    // TODO: See comment where this function is used (it's also commented out).
-    //async fn load_initial_contacts(
-    //    is_v4: bool,
-    //    contacts_store: &(impl DhtContactsStoreTrait + ?Sized),
-    //) -> HashSet<SocketAddr> {
-    //    if is_v4 {
-    //        match contacts_store.load_v4().await {
-    //            Ok(contacts) => contacts.iter().cloned().map(SocketAddr::V4).collect(),
-    //            Err(error) => {
-    //                tracing::error!("Failed to load DHT IPv4 contacts {:?}", error);
-    //                Default::default()
-    //            }
-    //        }
-    //    } else {
-    //        match contacts_store.load_v6().await {
-    //            Ok(contacts) => contacts.iter().cloned().map(SocketAddr::V6).collect(),
-    //            Err(error) => {
-    //                tracing::error!("Failed to load DHT IPv4 contacts {:?}", error);
-    //                Default::default()
-    //            }
-    //        }
-    //    }
-    //}
+    contacts
+}
+
+// Saves the contacts of the given address family (the others are ignored) and records their number
+// in `persisted_contacts`. If there are no such contacts, the store is left untouched. Returns
+// whether the contacts were saved.
+async fn save_contacts(
+    is_v4: bool,
+    contacts: impl IntoIterator<Item = SocketAddr>,
+    contacts_store: &(impl DhtContactsStoreTrait + ?Sized),
+    persisted_contacts: &AtomicUsize,
+) -> io::Result<bool> {
+    let count = if is_v4 {
+        let contacts: HashSet<_> = contacts
+            .into_iter()
+            .filter_map(|addr| match addr {
+                SocketAddr::V4(addr) => Some(addr),
+                SocketAddr::V6(_) => None,
+            })
+            .collect();
+
+        if contacts.is_empty() {
+            return Ok(false);
+        }
+
+        let count = contacts.len();
+        contacts_store.store_v4(contacts).await?;
+        count
+    } else {
+        let contacts: HashSet<_> = contacts
+            .into_iter()
+            .filter_map(|addr| match addr {
+                SocketAddr::V4(_) => None,
+                SocketAddr::V6(addr) => Some(addr),
+            })
+            .collect();
+
+        if contacts.is_empty() {
+            return Ok(false);
+        }
+
+        let count = contacts.len();
+        contacts_store.store_v6(contacts).await?;
+        count
+    };
+
+    persisted_contacts.store(count, Ordering::Relaxed);
+
+    Ok(true)
 }
 
 type Lookups = HashMap<InfoHash, Lookup>;
@@ -459,6 +628,7 @@ impl Drop for LookupRequest {
 struct Lookup {
     seen_peers: Arc<SeenPeers>,
     requests: Arc<BlockingMutex<HashMap<RequestId, mpsc::UnboundedSender<SeenPeer>>>>,
+    metrics: DhtMonitor,
     wake_up_tx: watch::Sender<()>,
     task: Option<ScopedJoinHandle<()>>,
 }
@@ -468,38 +638,28 @@ impl Lookup {
         dht_v4: Arc<Option<TaskOrResult<MonitoredDht>>>,
         dht_v6: Arc<Option<TaskOrResult<MonitoredDht>>>,
         info_hash: InfoHash,
+        announce: Arc<AtomicBool>,
+        metrics: DhtMonitor,
         monitor: &StateMonitor,
         span: &Span,
     ) -> Self {
-        let (wake_up_tx, mut wake_up_rx) = watch::channel(());
-        // Mark the initial value as seen so the change notification is not triggered immediately
-        // but only when we create the first request.
-        wake_up_rx.borrow_and_update();
-
-        let seen_peers = Arc::new(SeenPeers::new());
-        let requests = Arc::new(BlockingMutex::new(HashMap::default()));
-
-        let task = if dht_v4.is_some() || dht_v6.is_some() {
-            Some(Self::start_task(
-                dht_v4,
-                dht_v6,
-                info_hash,
-                seen_peers.clone(),
-                requests.clone(),
-                wake_up_rx,
-                monitor,
-                span,
-            ))
-        } else {
-            None
-        };
+        // The task subscribes to the wake up notifications itself.
+        let (wake_up_tx, _) = watch::channel(());
 
-        Lookup {
-            seen_peers,
-            requests,
+        let mut lookup = Lookup {
+            seen_peers: Arc::new(SeenPeers::new()),
+            requests: Arc::new(BlockingMutex::new(HashMap::default())),
+            metrics,
             wake_up_tx,
-            task,
+            task: None,
+        };
+
+        if dht_v4.is_some() || dht_v6.is_some() {
+            lookup.task =
+                Some(lookup.start_task(dht_v4, dht_v6, info_hash, announce, monitor, span));
         }
+
+        lookup
     }
 
     // Start this same lookup on different DHT instances
@@ -508,6 +668,7 @@ impl Lookup {
         dht_v4: Arc<Option<TaskOrResult<MonitoredDht>>>,
         dht_v6: Arc<Option<TaskOrResult<MonitoredDht>>>,
         info_hash: InfoHash,
+        announce: Arc<AtomicBool>,
         monitor: &StateMonitor,
         span: &Span,
     ) {
@@ -516,18 +677,7 @@ impl Lookup {
             return;
         }
 
-        let task = Self::start_task(
-            dht_v4,
-            dht_v6,
-            info_hash,
-            self.seen_peers.clone(),
-            self.requests.clone(),
-            self.wake_up_tx.subscribe(),
-            monitor,
-            span,
-        );
-
-        self.task = Some(task);
+        self.task = Some(self.start_task(dht_v4, dht_v6, info_hash, announce, monitor, span));
         self.wake_up_tx.send(()).ok();
     }
 
@@ -542,17 +692,22 @@ impl Lookup {
         self.wake_up_tx.send(()).unwrap_or(());
     }
 
-    #[allow(clippy::too_many_arguments)]
     fn start_task(
+        &self,
         dht_v4: Arc<Option<TaskOrResult<MonitoredDht>>>,
         dht_v6: Arc<Option<TaskOrResult<MonitoredDht>>>,
         info_hash: InfoHash,
-        seen_peers: Arc<SeenPeers>,
-        requests: Arc<BlockingMutex<HashMap<RequestId, mpsc::UnboundedSender<SeenPeer>>>>,
-        mut wake_up: watch::Receiver<()>,
+        announce: Arc<AtomicBool>,
         lookups_monitor: &StateMonitor,
         span: &Span,
     ) -> ScopedJoinHandle<()> {
+        let seen_peers = self.seen_peers.clone();
+        let requests = self.requests.clone();
+        let metrics = self.metrics.clone();
+        // Subscribing marks the current value as seen so the change notification is not triggered
+        // immediately but only when we create the first request.
+        let mut wake_up = self.wake_up_tx.subscribe();
+
         let monitor = lookups_monitor.make_child(format!("{info_hash:?}"));
         let state = monitor.make_value("state", "started");
         let next = monitor.make_value("next", SystemTime::now().into());
@@ -577,13 +732,16 @@ impl Lookup {
                 tracing::debug!(?info_hash, "starting search");
                 *state.get() = "making request";
 
-                // find peers for the repo and also announce that we have it.
+                let mut lookup_monitor = metrics.start_lookup();
+
+                // find peers for the repo and also announce that we have it (unless disabled).
                 let dhts = dht_v4.iter().chain(dht_v6.iter());
+                let announce = announce.load(Ordering::Relaxed);
 
                 let mut peers = Box::pin(stream::iter(dhts).flat_map(|dht| {
-                    stream::once(async {
+                    stream::once(async move {
                         dht.dht.bootstrapped(Some(Duration::from_secs(10))).await;
-                        dht.dht.search(info_hash, true)
+                        dht.dht.search(info_hash, announce)
                     })
                     .flatten()
                 }));
@@ -591,6 +749,10 @@ impl Lookup {
                 *state.get() = "awaiting results";
 
                 while let Some(addr) = peers.next().await {
+                    // Count all the peers found, not just the ones not seen in the previous
+                    // rounds, to see how effective each lookup is on its own.
+                    lookup_monitor.peer_found();
+
                     if let Some(peer) = seen_peers.insert(PeerAddr::Quic(addr)) {
                         for tx in requests.lock().unwrap().values() {
                             tx.send(peer.clone()).unwrap_or(());
@@ -598,6 +760,8 @@ impl Lookup {
                     }
                 }
 
+                lookup_monitor.finish();
+
                 // sleep a random duration before the next search, but wake up if there is a new
                 // request.
                 let duration =
@@ -659,6 +823,11 @@ impl<T> TaskOrResult<T> {
         }
     }
 
+    // Returns the result if it's already available, without waiting for the task.
+    fn get(&self) -> Option<&T> {
+        self.result.get()
+    }
+
     // Note that this function is not cancel safe.
     async fn result(&self) -> &T {
         if let Some(result) = self.result.get() {
@@ -677,3 +846,100 @@ impl<T> TaskOrResult<T> {
         self.result.get().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[derive(Default)]
+    struct MemoryStore {
+        v4: BlockingMutex<Option<HashSet<SocketAddrV4>>>,
+        v6: BlockingMutex<Option<HashSet<SocketAddrV6>>>,
+    }
+
+    #[async_trait]
+    impl DhtContactsStoreTrait for MemoryStore {
+        async fn load_v4(&self) -> io::Result<HashSet<SocketAddrV4>> {
+            self.v4
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        async fn load_v6(&self) -> io::Result<HashSet<SocketAddrV6>> {
+            self.v6
+                .lock()
+                .unwrap()
+                .clone()
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        async fn store_v4(&self, contacts: HashSet<SocketAddrV4>) -> io::Result<()> {
+            *self.v4.lock().unwrap() = Some(contacts);
+            Ok(())
+        }
+
+        async fn store_v6(&self, contacts: HashSet<SocketAddrV6>) -> io::Result<()> {
+            *self.v6.lock().unwrap() = Some(contacts);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn discovery_save_and_load_contacts() {
+        let store = Arc::new(MemoryStore::default());
+        let contact = SocketAddrV4::new(Ipv4Addr::new(192, 0, 2, 1), 6881);
+        *store.v4.lock().unwrap() = Some([contact].into_iter().collect());
+
+        let discovery =
+            DhtDiscovery::new(None, None, Some(store.clone()), StateMonitor::make_root());
+
+        assert_eq!(discovery.load_contacts().await, 1);
+        assert_eq!(discovery.persisted_contacts(), 1);
+
+        // No DHT is running so there is nothing to save and the persisted contacts are kept.
+        assert_eq!(discovery.save_contacts().await.unwrap(), 0);
+        assert_eq!(
+            *store.v4.lock().unwrap(),
+            Some([contact].into_iter().collect())
+        );
+    }
+
+    #[tokio::test]
+    async fn save_and_load_contacts() {
+        let store = MemoryStore::default();
+        let persisted = AtomicUsize::new(0);
+
+        // Nothing persisted yet.
+        assert!(load_contacts(true, &store, &persisted).await.is_empty());
+        assert_eq!(persisted.load(Ordering::Relaxed), 0);
+
+        let contact_v4 = SocketAddr::from((Ipv4Addr::new(192, 0, 2, 1), 6881));
+        let contact_v6 = SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 6881));
+
+        // Only the contacts of the DHT's own family are saved.
+        assert!(
+            save_contacts(true, [contact_v4, contact_v6], &store, &persisted)
+                .await
+                .unwrap()
+        );
+        assert_eq!(persisted.load(Ordering::Relaxed), 1);
+        assert!(store.v6.lock().unwrap().is_none());
+
+        // An empty routing table doesn't overwrite the previously saved contacts.
+        assert!(!save_contacts(true, [], &store, &persisted).await.unwrap());
+        assert!(!save_contacts(true, [contact_v6], &store, &persisted)
+            .await
+            .unwrap());
+        assert_eq!(persisted.load(Ordering::Relaxed), 1);
+
+        let persisted = AtomicUsize::new(0);
+        assert_eq!(
+            load_contacts(true, &store, &persisted).await,
+            [contact_v4].into_iter().collect()
+        );
+        assert_eq!(persisted.load(Ordering::Relaxed), 1);
+    }
+}
@@ -1,4 +1,7 @@
-use super::{ip, peer_addr::PeerAddr, peer_source::PeerSource, raw, seen_peers::SeenPeer};
+use super::{
+    handshake_limiter::HandshakeLimiter, ip, options::ConnectivityMode, peer_addr::PeerAddr,
+    peer_source::PeerSource, raw, seen_peers::SeenPeer,
+};
 use crate::sync::atomic_slot::AtomicSlot;
 use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
 use net::{
@@ -14,7 +17,7 @@ use std::{
 use thiserror::Error;
 use tokio::{
     select,
-    sync::{mpsc, oneshot},
+    sync::{mpsc, oneshot, watch},
     time::{self, Duration},
 };
 use tracing::{field, Instrument, Span};
@@ -23,6 +26,12 @@ use tracing::{field, Instrument, Span};
 pub(super) struct Gateway {
     stacks: AtomicSlot<Stacks>,
     incoming_tx: mpsc::Sender<(raw::Stream, PeerAddr)>,
+    quic_config: Mutex<quic::TransportConfig>,
+    connectivity_mode: Mutex<ConnectivityMode>,
+    handshake_limiter: Arc<HandshakeLimiter>,
+    // Notified when the local network changes (see `rebind`) so pending reconnects can be retried
+    // immediately.
+    network_change_tx: watch::Sender<()>,
 }
 
 impl Gateway {
@@ -36,9 +45,33 @@ impl Gateway {
         Self {
             stacks,
             incoming_tx,
+            quic_config: Mutex::new(quic::TransportConfig::default()),
+            connectivity_mode: Mutex::new(ConnectivityMode::default()),
+            handshake_limiter: Arc::new(HandshakeLimiter::new(None)),
+            network_change_tx: watch::channel(()).0,
         }
     }
 
+    /// Sets the transport config of the QUIC stacks. Applies only to the stacks bound after this
+    /// call.
+    pub fn set_quic_config(&self, config: quic::TransportConfig) {
+        *self.quic_config.lock().unwrap() = config;
+    }
+
+    /// Sets which connections the stacks handle. In `OutboundOnly` mode no TCP listeners are bound
+    /// and the QUIC stacks (whose sockets are still needed for the outgoing connections) refuse
+    /// all incoming connections. Applies only to the stacks bound after this call.
+    pub fn set_connectivity_mode(&self, mode: ConnectivityMode) {
+        *self.connectivity_mode.lock().unwrap() = mode;
+    }
+
+    /// Sets the max number of inbound handshakes per source IP address per second. Connections
+    /// exceeding it are dropped by the listeners right after being accepted. `None` disables the
+    /// limit. Applies immediately to all the listeners.
+    pub fn set_handshake_rate_limit(&self, limit: Option<u32>) {
+        self.handshake_limiter.set_limit(limit);
+    }
+
     pub fn listener_local_addrs(&self) -> Vec<PeerAddr> {
         let stacks = self.stacks.read();
         [
@@ -72,8 +105,16 @@ impl Gateway {
         Option<quic::SideChannelMaker>,
         Option<quic::SideChannelMaker>,
     ) {
-        let (next, side_channel_maker_v4, side_channel_maker_v6) =
-            Stacks::bind(bind, self.incoming_tx.clone()).await;
+        let quic_config = *self.quic_config.lock().unwrap();
+        let connectivity_mode = *self.connectivity_mode.lock().unwrap();
+        let (next, side_channel_maker_v4, side_channel_maker_v6) = Stacks::bind(
+            bind,
+            quic_config,
+            connectivity_mode,
+            self.incoming_tx.clone(),
+            self.handshake_limiter.clone(),
+        )
+        .await;
 
         let prev = self.stacks.swap(next);
         let next = self.stacks.read();
@@ -99,6 +140,31 @@ impl Gateway {
         (side_channel_maker_v4, side_channel_maker_v6)
     }
 
+    /// Closes all the stacks and binds them again to the same addresses (and ports). Meant to be
+    /// called when the local network interfaces change. Also wakes up all pending reconnects.
+    pub async fn rebind(
+        &self,
+    ) -> (
+        Option<quic::SideChannelMaker>,
+        Option<quic::SideChannelMaker>,
+    ) {
+        let bind = self.addresses();
+
+        // Notify before closing the stacks so the connection attempts failing because of it know
+        // to retry.
+        self.network_change_tx.send_replace(());
+
+        // Close the current stacks first to release their ports.
+        self.stacks.swap(Stacks::unbound()).close();
+
+        self.bind(&bind).await
+    }
+
+    /// Subscribe to notifications about the local network changes.
+    pub fn subscribe_network_change(&self) -> watch::Receiver<()> {
+        self.network_change_tx.subscribe()
+    }
+
     pub async fn connect_with_retries(
         &self,
         peer: &SeenPeer,
@@ -117,6 +183,7 @@ impl Gateway {
             .build();
 
         let mut hole_punching_task = None;
+        let mut network_change_rx = self.network_change_tx.subscribe();
 
         loop {
             // Note: This needs to be probed each time the loop starts. When the `addr` fn returns
@@ -139,15 +206,24 @@ impl Gateway {
                 Err(error) => {
                     tracing::debug!(?error, "Connection failed");
 
-                    if error.is_localy_closed() {
-                        // Connector locally closed - no point in retrying.
+                    if error.is_localy_closed() && !network_change_rx.has_changed().unwrap_or(false)
+                    {
+                        // Connector locally closed (and not because of `rebind`) - no point in
+                        // retrying.
                         return None;
                     }
 
                     match backoff.next_backoff() {
                         Some(duration) => {
                             tracing::debug!("Next connection attempt in {:?}", duration);
-                            time::sleep(duration).await;
+
+                            select! {
+                                _ = time::sleep(duration) => (),
+                                _ = network_change_rx.changed() => {
+                                    tracing::debug!("Network changed - retrying immediately");
+                                    backoff.reset();
+                                }
+                            }
                         }
                         // We set max elapsed time to None above.
                         None => unreachable!(),
@@ -202,40 +278,61 @@ impl Stacks {
 
     async fn bind(
         bind: &StackAddresses,
+        quic_config: quic::TransportConfig,
+        connectivity_mode: ConnectivityMode,
         incoming_tx: mpsc::Sender<(raw::Stream, PeerAddr)>,
+        handshake_limiter: Arc<HandshakeLimiter>,
     ) -> (
         Self,
         Option<quic::SideChannelMaker>,
         Option<quic::SideChannelMaker>,
     ) {
+        let accept_incoming = connectivity_mode != ConnectivityMode::OutboundOnly;
+
         let (quic_v4, side_channel_maker_v4) = if let Some(addr) = bind.quic_v4 {
-            QuicStack::new(addr, incoming_tx.clone())
-                .await
-                .map(|(stack, side_channel)| (Some(stack), Some(side_channel)))
-                .unwrap_or((None, None))
+            QuicStack::new(
+                addr,
+                quic_config,
+                accept_incoming,
+                incoming_tx.clone(),
+                handshake_limiter.clone(),
+            )
+            .await
+            .map(|(stack, side_channel)| (Some(stack), Some(side_channel)))
+            .unwrap_or((None, None))
         } else {
             (None, None)
         };
 
         let (quic_v6, side_channel_maker_v6) = if let Some(addr) = bind.quic_v6 {
-            QuicStack::new(addr, incoming_tx.clone())
-                .await
-                .map(|(stack, side_channel)| (Some(stack), Some(side_channel)))
-                .unwrap_or((None, None))
+            QuicStack::new(
+                addr,
+                quic_config,
+                accept_incoming,
+                incoming_tx.clone(),
+                handshake_limiter.clone(),
+            )
+            .await
+            .map(|(stack, side_channel)| (Some(stack), Some(side_channel)))
+            .unwrap_or((None, None))
         } else {
             (None, None)
         };
 
-        let tcp_v4 = if let Some(addr) = bind.tcp_v4 {
-            TcpStack::new(addr, incoming_tx.clone()).await
-        } else {
-            None
+        // TCP stacks are used only for listening (outgoing TCP connections don't need them) so
+        // there is nothing to bind when not accepting incoming connections.
+        let tcp_v4 = match bind.tcp_v4 {
+            Some(addr) if accept_incoming => {
+                TcpStack::new(addr, incoming_tx.clone(), handshake_limiter.clone()).await
+            }
+            _ => None,
         };
 
-        let tcp_v6 = if let Some(addr) = bind.tcp_v6 {
-            TcpStack::new(addr, incoming_tx).await
-        } else {
-            None
+        let tcp_v6 = match bind.tcp_v6 {
+            Some(addr) if accept_incoming => {
+                TcpStack::new(addr, incoming_tx, handshake_limiter).await
+            }
+            _ => None,
         };
 
         let this = Self {
@@ -252,7 +349,7 @@ impl Stacks {
         StackAddresses {
             quic_v4: self.quic_v4.as_ref().map(|stack| stack.listener_local_addr),
             quic_v6: self.quic_v6.as_ref().map(|stack| stack.listener_local_addr),
-            tcp_v4: self.tcp_v6.as_ref().map(|stack| stack.listener_local_addr),
+            tcp_v4: self.tcp_v4.as_ref().map(|stack| stack.listener_local_addr),
             tcp_v6: self.tcp_v6.as_ref().map(|stack| stack.listener_local_addr),
         }
     }
@@ -386,34 +483,40 @@ struct QuicStack {
 impl QuicStack {
     async fn new(
         bind_addr: SocketAddr,
+        config: quic::TransportConfig,
+        accept_incoming: bool,
         incoming_tx: mpsc::Sender<(raw::Stream, PeerAddr)>,
+        handshake_limiter: Arc<HandshakeLimiter>,
     ) -> Option<(Self, quic::SideChannelMaker)> {
         let span = tracing::info_span!("listener", addr = field::Empty);
 
-        let (connector, listener, side_channel_maker) = match quic::configure(bind_addr).await {
-            Ok((connector, listener, side_channel_maker)) => {
-                span.record(
-                    "addr",
-                    field::display(PeerAddr::Quic(*listener.local_addr())),
-                );
-                tracing::info!(parent: &span, "Listener started");
+        let (connector, listener, side_channel_maker) =
+            match quic::configure(bind_addr, config).await {
+                Ok((connector, listener, side_channel_maker)) => {
+                    span.record(
+                        "addr",
+                        field::display(PeerAddr::Quic(*listener.local_addr())),
+                    );
+                    tracing::info!(parent: &span, "Listener started");
 
-                (connector, listener, side_channel_maker)
-            }
-            Err(error) => {
-                tracing::warn!(
-                    parent: &span,
-                    bind_addr = %PeerAddr::Quic(bind_addr),
-                    ?error,
-                    "Failed to start listener"
-                );
-                return None;
-            }
-        };
+                    (connector, listener, side_channel_maker)
+                }
+                Err(error) => {
+                    tracing::warn!(
+                        parent: &span,
+                        bind_addr = %PeerAddr::Quic(bind_addr),
+                        ?error,
+                        "Failed to start listener"
+                    );
+                    return None;
+                }
+            };
 
         let listener_local_addr = *listener.local_addr();
-        let listener_task =
-            scoped_task::spawn(run_quic_listener(listener, incoming_tx).instrument(span));
+        let listener_task = scoped_task::spawn(
+            run_quic_listener(listener, accept_incoming, incoming_tx, handshake_limiter)
+                .instrument(span),
+        );
 
         let hole_puncher = side_channel_maker.make().sender();
 
@@ -442,6 +545,7 @@ impl TcpStack {
     async fn new(
         bind_addr: SocketAddr,
         incoming_tx: mpsc::Sender<(raw::Stream, PeerAddr)>,
+        handshake_limiter: Arc<HandshakeLimiter>,
     ) -> Option<Self> {
         let span = tracing::info_span!("listener", addr = field::Empty);
 
@@ -476,8 +580,9 @@ impl TcpStack {
             }
         };
 
-        let listener_task =
-            scoped_task::spawn(run_tcp_listener(listener, incoming_tx).instrument(span));
+        let listener_task = scoped_task::spawn(
+            run_tcp_listener(listener, incoming_tx, handshake_limiter).instrument(span),
+        );
 
         Some(Self {
             listener_local_addr,
@@ -486,7 +591,11 @@ impl TcpStack {
     }
 }
 
-async fn run_tcp_listener(listener: TcpListener, tx: mpsc::Sender<(raw::Stream, PeerAddr)>) {
+async fn run_tcp_listener(
+    listener: TcpListener,
+    tx: mpsc::Sender<(raw::Stream, PeerAddr)>,
+    handshake_limiter: Arc<HandshakeLimiter>,
+) {
     loop {
         let result = select! {
             result = listener.accept() => result,
@@ -495,6 +604,11 @@ async fn run_tcp_listener(listener: TcpListener, tx: mpsc::Sender<(raw::Stream,
 
         match result {
             Ok((stream, addr)) => {
+                if !handshake_limiter.try_acquire(addr.ip()) {
+                    tracing::debug!(%addr, "Handshake rate limit exceeded - dropping connection");
+                    continue;
+                }
+
                 tx.send((raw::Stream::Tcp(stream), PeerAddr::Tcp(addr)))
                     .await
                     .ok();
@@ -509,7 +623,9 @@ async fn run_tcp_listener(listener: TcpListener, tx: mpsc::Sender<(raw::Stream,
 
 async fn run_quic_listener(
     mut listener: quic::Acceptor,
+    accept_incoming: bool,
     tx: mpsc::Sender<(raw::Stream, PeerAddr)>,
+    handshake_limiter: Arc<HandshakeLimiter>,
 ) {
     // Using `futures_util::stream::FuturesUnordered` may have been a nicer solution but I'm not
     // sure whether `quic::Acceptor::accept()` is cancel safe.
@@ -524,6 +640,18 @@ async fn run_quic_listener(
 
         match result {
             Some(connecting) => {
+                let addr = connecting.remote_address();
+
+                if !accept_incoming {
+                    tracing::debug!(%addr, "Incoming connections disabled - dropping connection");
+                    continue;
+                }
+
+                if !handshake_limiter.try_acquire(addr.ip()) {
+                    tracing::debug!(%addr, "Handshake rate limit exceeded - dropping connection");
+                    continue;
+                }
+
                 // Using this channel to ensure the task is not removed from `connectings` before
                 // it's inserted.
                 let (start_task_tx, start_task_rx) = oneshot::channel();
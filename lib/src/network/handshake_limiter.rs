@@ -0,0 +1,145 @@
+use lru::LruCache;
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    num::NonZeroUsize,
+    sync::Mutex,
+};
+use tokio::time::{Duration, Instant};
+
+// Length of the window in which the handshakes from a single address are counted.
+const WINDOW: Duration = Duration::from_secs(1);
+
+// Max number of tracked addresses. When reached, the least recently seen one is forgotten.
+const CAPACITY: usize = 1024;
+
+// IPv6 addresses are counted per this prefix length because a single host usually gets a whole /64
+// and can rotate the addresses within it freely.
+const IPV6_PREFIX_LEN: u32 = 64;
+
+/// Limits the rate of the inbound handshakes per source IP address. Used by the listeners to drop
+/// the excessive connections before any resources are spent on them, as a basic protection against
+/// connection spam.
+///
+/// Only a bounded number of addresses is tracked, so a spammer rotating through more addresses
+/// than that is not limited. It still can't make the limiter itself expensive though.
+pub(super) struct HandshakeLimiter {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    // Max number of handshakes per address per second, `None` means unlimited.
+    limit: Option<u32>,
+    windows: LruCache<IpAddr, Window>,
+}
+
+struct Window {
+    start: Instant,
+    count: u32,
+}
+
+impl HandshakeLimiter {
+    pub fn new(limit: Option<u32>) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                limit,
+                windows: LruCache::new(
+                    NonZeroUsize::new(CAPACITY).expect("capacity must be non-zero"),
+                ),
+            }),
+        }
+    }
+
+    /// Sets the max number of handshakes per address per second. `None` disables the limit.
+    pub fn set_limit(&self, limit: Option<u32>) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.limit = limit;
+
+        if limit.is_none() {
+            inner.windows.clear();
+        }
+    }
+
+    /// Records a handshake attempt from `ip` and returns whether it's allowed to proceed.
+    pub fn try_acquire(&self, ip: IpAddr) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+
+        let Some(limit) = inner.limit else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let window = inner.windows.get_or_insert_mut(bucket(ip), || Window {
+            start: now,
+            count: 0,
+        });
+
+        if now.duration_since(window.start) >= WINDOW {
+            window.start = now;
+            window.count = 0;
+        }
+
+        window.count = window.count.saturating_add(1);
+        window.count <= limit
+    }
+}
+
+// The address the handshakes from `ip` are counted under.
+fn bucket(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => {
+            let mask = u128::MAX << (128 - IPV6_PREFIX_LEN);
+            IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+    use tokio::time;
+
+    #[tokio::test(start_paused = true)]
+    async fn sanity_check() {
+        let limiter = HandshakeLimiter::new(Some(2));
+
+        let a = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+        assert!(limiter.try_acquire(a));
+        assert!(limiter.try_acquire(a));
+        assert!(!limiter.try_acquire(a));
+
+        // Other addresses are not affected.
+        assert!(limiter.try_acquire(b));
+
+        time::advance(WINDOW).await;
+
+        assert!(limiter.try_acquire(a));
+
+        limiter.set_limit(None);
+
+        for _ in 0..10 {
+            assert!(limiter.try_acquire(a));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ipv6_same_prefix() {
+        let limiter = HandshakeLimiter::new(Some(1));
+
+        let a: IpAddr = "2001:db8:0:1::1".parse().unwrap();
+        let b: IpAddr = "2001:db8:0:1:ffff::2".parse().unwrap();
+        let c: IpAddr = "2001:db8:0:2::1".parse().unwrap();
+
+        assert!(limiter.try_acquire(a));
+
+        // Same /64 as `a`.
+        assert!(!limiter.try_acquire(b));
+
+        // Different /64.
+        assert!(limiter.try_acquire(c));
+    }
+}
@@ -14,6 +14,7 @@ use state_monitor::StateMonitor;
 use std::{
     io,
     net::{Ipv4Addr, SocketAddr},
+    ops::Range,
     sync::Arc,
 };
 use tokio::{
@@ -25,6 +26,10 @@ use tracing::{Instrument, Span};
 // Time to wait when an error occurs on a socket.
 const ERROR_DELAY: Duration = Duration::from_secs(3);
 
+// Interval for the delay between two beacons. The actual delay is an uniformly random value from
+// this interval.
+const BEACON_INTERVAL: Range<Duration> = Duration::from_secs(2)..Duration::from_secs(8);
+
 const PROTOCOL_MAGIC: &[u8; 17] = b"OUISYNC_DISCOVERY";
 const PROTOCOL_VERSION: u8 = 0;
 
@@ -40,7 +45,10 @@ pub(crate) struct LocalDiscovery {
 }
 
 impl LocalDiscovery {
-    pub fn new(listener_port: PeerPort, monitor: StateMonitor) -> Self {
+    /// Starts discovering the peers on the local network. If `listener_port` is `None`, we don't
+    /// announce ourselves (nor reply to the announcements of the others) and only listen for the
+    /// peers that do.
+    pub fn new(listener_port: Option<PeerPort>, monitor: StateMonitor) -> Self {
         let (peer_tx, peer_rx) = mpsc::channel(1);
 
         let work_handle = scoped_task::spawn(
@@ -77,7 +85,7 @@ impl LocalDiscovery {
 }
 
 struct LocalDiscoveryInner {
-    listener_port: PeerPort,
+    listener_port: Option<PeerPort>,
     peer_tx: mpsc::Sender<SeenPeer>,
     per_interface_discovery: HashMap<Ipv4Addr, PerInterfaceLocalDiscovery>,
 }
@@ -128,7 +136,7 @@ struct PerInterfaceLocalDiscovery {
 impl PerInterfaceLocalDiscovery {
     pub fn new(
         peer_tx: mpsc::Sender<SeenPeer>,
-        listener_port: PeerPort,
+        listener_port: Option<PeerPort>,
         interface: Ipv4Addr,
         parent_monitor: &StateMonitor,
     ) -> io::Result<Self> {
@@ -174,7 +182,7 @@ impl PerInterfaceLocalDiscovery {
     async fn run_recv_loop(
         peer_tx: mpsc::Sender<SeenPeer>,
         self_id: InsecureRuntimeId,
-        listener_port: PeerPort,
+        listener_port: Option<PeerPort>,
         socket_provider: Arc<SocketProvider>,
         seen_peers: SeenPeers,
         monitor: StateMonitor,
@@ -235,15 +243,17 @@ impl PerInterfaceLocalDiscovery {
             if is_request {
                 *beacon_requests_received.get() += 1;
 
-                let msg = Message::Reply {
-                    port: listener_port,
-                    id: self_id,
-                };
+                if let Some(listener_port) = listener_port {
+                    let msg = Message::Reply {
+                        port: listener_port,
+                        id: self_id,
+                    };
 
-                // TODO: Consider `spawn`ing this, so it doesn't block this function.
-                if let Err(error) = send(&socket, msg, addr).await {
-                    tracing::error!("Failed to send discovery message: {}", error);
-                    socket_provider.mark_bad(socket).await;
+                    // TODO: Consider `spawn`ing this, so it doesn't block this function.
+                    if let Err(error) = send(&socket, msg, addr).await {
+                        tracing::error!("Failed to send discovery message: {}", error);
+                        socket_provider.mark_bad(socket).await;
+                    }
                 }
             } else {
                 *beacon_responses_received.get() += 1;
@@ -272,10 +282,12 @@ impl Drop for PerInterfaceLocalDiscovery {
     }
 }
 
+// Periodically announces us on the local network. If `listener_port` is `None`, nothing is sent
+// and only the rounds of `seen_peers` are advanced.
 async fn run_beacon(
     socket_provider: Arc<SocketProvider>,
     id: InsecureRuntimeId,
-    listener_port: PeerPort,
+    listener_port: Option<PeerPort>,
     seen_peers: SeenPeers,
     monitor: StateMonitor,
 ) {
@@ -285,10 +297,15 @@ async fn run_beacon(
     let mut error_shown = false;
 
     loop {
-        let socket = socket_provider.provide().await;
-
         seen_peers.start_new_round();
 
+        let Some(listener_port) = listener_port else {
+            sleep(BEACON_INTERVAL.end).await;
+            continue;
+        };
+
+        let socket = socket_provider.provide().await;
+
         let msg = Message::ImHereYouAll {
             id,
             port: listener_port,
@@ -310,8 +327,8 @@ async fn run_beacon(
             }
         }
 
-        let delay = rand::thread_rng().gen_range(2..8);
-        sleep(Duration::from_secs(delay)).await;
+        let delay = rand::thread_rng().gen_range(BEACON_INTERVAL);
+        sleep(delay).await;
     }
 }
 
@@ -211,16 +211,19 @@ impl ArrayWriter<'_> {
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) enum Content {
     Request(Request),
+    // Multiple index requests batched into a single message.
+    Requests(Vec<Request>),
     Response(Response),
     // Peer exchange
     Pex(PexPayload),
 }
 
 #[cfg(test)]
-impl From<Content> for Request {
+impl From<Content> for Vec<Request> {
     fn from(content: Content) -> Self {
         match content {
-            Content::Request(request) => request,
+            Content::Request(request) => vec![request],
+            Content::Requests(requests) => requests,
             Content::Response(_) | Content::Pex(_) => {
                 panic!("not a request: {:?}", content)
             }
@@ -229,11 +232,11 @@ impl From<Content> for Request {
 }
 
 #[cfg(test)]
-impl From<Content> for Response {
+impl From<Content> for Vec<Response> {
     fn from(content: Content) -> Self {
         match content {
-            Content::Response(response) => response,
-            Content::Request(_) | Content::Pex(_) => {
+            Content::Response(response) => vec![response],
+            Content::Request(_) | Content::Requests(_) | Content::Pex(_) => {
                 panic!("not a response: {:?}", content)
             }
         }
@@ -7,10 +7,12 @@ use super::{
     crypto::{self, DecryptingStream, EncryptingSink, EstablishError, RecvError, Role, SendError},
     message::{Content, MessageChannelId, Request, Response},
     message_dispatcher::{ContentSink, ContentStream, MessageDispatcher},
-    peer_exchange::{PexAnnouncer, PexController, PexDiscoverySender},
+    options::NetworkOptions,
+    peer_exchange::{PexAnnouncer, PexConfig, PexController, PexDiscoverySender},
     raw,
     runtime_id::PublicRuntimeId,
     server::Server,
+    sync_state::LinkSyncState,
 };
 use crate::{
     collections::{hash_map::Entry, HashMap},
@@ -40,8 +42,10 @@ pub(super) struct MessageBroker {
     this_runtime_id: PublicRuntimeId,
     that_runtime_id: PublicRuntimeId,
     dispatcher: MessageDispatcher,
-    links: HashMap<LocalId, oneshot::Sender<()>>,
+    links: HashMap<LocalId, Link>,
     request_limiter: Arc<Semaphore>,
+    index_request_batch_window: Duration,
+    pex_config: PexConfig,
     monitor: StateMonitor,
     span: Span,
 }
@@ -52,6 +56,7 @@ impl MessageBroker {
         that_runtime_id: PublicRuntimeId,
         stream: raw::Stream,
         permit: ConnectionPermit,
+        options: &NetworkOptions,
         monitor: StateMonitor,
     ) -> Self {
         let span = tracing::info_span!(
@@ -67,10 +72,13 @@ impl MessageBroker {
             dispatcher: MessageDispatcher::new(),
             links: HashMap::default(),
             request_limiter: Arc::new(Semaphore::new(MAX_REQUESTS_IN_FLIGHT)),
+            index_request_batch_window: options.index_request_batch_window,
+            pex_config: options.pex_config(),
             monitor,
             span,
         };
 
+        this.dispatcher.set_balanced(options.balance_connections);
         this.add_connection(stream, permit);
         this
     }
@@ -103,18 +111,23 @@ impl MessageBroker {
         let span_enter = span.enter();
 
         let (abort_tx, abort_rx) = oneshot::channel();
+        let sync_state = Arc::new(LinkSyncState::default());
+        let link = Link {
+            abort_tx,
+            sync_state: sync_state.clone(),
+        };
 
         match self.links.entry(vault.local_id) {
             Entry::Occupied(mut entry) => {
-                if entry.get().is_closed() {
-                    entry.insert(abort_tx);
+                if entry.get().abort_tx.is_closed() {
+                    entry.insert(link);
                 } else {
                     tracing::warn!("Link not created - already exists");
                     return;
                 }
             }
             Entry::Vacant(entry) => {
-                entry.insert(abort_tx);
+                entry.insert(link);
             }
         }
 
@@ -133,12 +146,22 @@ impl MessageBroker {
 
         let stream = self.dispatcher.open_recv(channel_id);
         let sink = self.dispatcher.open_send(channel_id);
-        let request_limiter = self.request_limiter.clone();
 
-        let pex_discovery_tx = pex.discovery_sender();
-        let pex_announcer = pex.announcer(self.that_runtime_id, self.dispatcher.connection_infos());
+        let pex_announcer = pex.announcer(
+            self.that_runtime_id,
+            self.dispatcher.connection_infos(),
+            self.pex_config,
+        );
 
-        let choker = choke_manager.new_choker();
+        let context = LinkContext {
+            vault,
+            that_runtime_id: self.that_runtime_id,
+            request_limiter: self.request_limiter.clone(),
+            index_request_batch_window: self.index_request_batch_window,
+            pex_discovery_tx: pex.discovery_sender(self.that_runtime_id),
+            choker: choke_manager.new_choker(),
+            sync_state,
+        };
 
         tracing::info!(?role, "Link created");
 
@@ -146,17 +169,7 @@ impl MessageBroker {
 
         let task = async move {
             select! {
-                _ = maintain_link(
-                    role,
-                    stream,
-                    sink,
-                    vault,
-                    request_limiter,
-                    pex_discovery_tx,
-                    pex_announcer,
-                    monitor,
-                    choker,
-                ) => (),
+                _ = maintain_link(role, stream, sink, context, pex_announcer, monitor) => (),
                 _ = abort_rx => (),
             }
 
@@ -173,30 +186,59 @@ impl MessageBroker {
         self.links.remove(&id);
     }
 
+    /// Sync state of the link with the given local repository id, if the link exists.
+    pub fn link_sync_state(&self, id: LocalId) -> Option<Arc<LinkSyncState>> {
+        self.links
+            .get(&id)
+            .filter(|link| !link.abort_tx.is_closed())
+            .map(|link| link.sync_state.clone())
+    }
+
+    /// Waits until all the messages sent so far have been handed over to the connections.
+    pub async fn flush(&self) {
+        self.dispatcher.flush().await;
+    }
+
+    /// Total size of the messages waiting to be sent to the peer.
+    pub fn pending_bytes(&self) -> u64 {
+        self.dispatcher.pending_bytes()
+    }
+
     pub async fn shutdown(&self) {
         self.dispatcher.close().await;
     }
 }
 
+struct Link {
+    abort_tx: oneshot::Sender<()>,
+    sync_state: Arc<LinkSyncState>,
+}
+
 impl Drop for MessageBroker {
     fn drop(&mut self) {
         tracing::info!(parent: &self.span, "Message broker destroyed");
     }
 }
 
+// State of a link that persists across its reestablishments.
+struct LinkContext {
+    vault: Vault,
+    that_runtime_id: PublicRuntimeId,
+    request_limiter: Arc<Semaphore>,
+    index_request_batch_window: Duration,
+    pex_discovery_tx: PexDiscoverySender,
+    choker: choke::Choker,
+    sync_state: Arc<LinkSyncState>,
+}
+
 // Repeatedly establish and run the link until it's explicitly destroyed by calling `destroy_link()`.
-// TODO: Consider consolidating the arguments somehow
-#[allow(clippy::too_many_arguments)]
 async fn maintain_link(
     role: Role,
     mut stream: ContentStream,
     mut sink: ContentSink,
-    vault: Vault,
-    request_limiter: Arc<Semaphore>,
-    pex_discovery_tx: PexDiscoverySender,
+    context: LinkContext,
     mut pex_announcer: PexAnnouncer,
     monitor: StateMonitor,
-    choker: choke::Choker,
 ) {
     #[derive(Debug)]
     enum State {
@@ -235,7 +277,7 @@ async fn maintain_link(
         *state.get() = State::EstablishingChannel;
 
         let (crypto_stream, crypto_sink) =
-            match establish_channel(role, &mut stream, &mut sink, &vault).await {
+            match establish_channel(role, &mut stream, &mut sink, &context.vault).await {
                 Ok(io) => io,
                 Err(EstablishError::Crypto) => continue,
                 Err(EstablishError::Closed) => break,
@@ -244,17 +286,7 @@ async fn maintain_link(
 
         *state.get() = State::Running;
 
-        match run_link(
-            crypto_stream,
-            crypto_sink,
-            &vault,
-            request_limiter.clone(),
-            pex_discovery_tx.clone(),
-            &mut pex_announcer,
-            choker.clone(),
-        )
-        .await
-        {
+        match run_link(crypto_stream, crypto_sink, &context, &mut pex_announcer).await {
             ControlFlow::Continue => continue,
             ControlFlow::Break => break,
         }
@@ -283,11 +315,8 @@ async fn establish_channel<'a>(
 async fn run_link(
     stream: DecryptingStream<'_>,
     sink: EncryptingSink<'_>,
-    repo: &Vault,
-    request_limiter: Arc<Semaphore>,
-    pex_discovery_tx: PexDiscoverySender,
+    context: &LinkContext,
     pex_announcer: &mut PexAnnouncer,
-    choker: choke::Choker,
 ) -> ControlFlow {
     let (request_tx, request_rx) = mpsc::channel(1);
     let (response_tx, response_rx) = mpsc::channel(1);
@@ -295,9 +324,27 @@ async fn run_link(
 
     // Run everything in parallel:
     select! {
-        flow = run_client(repo.clone(), content_tx.clone(), response_rx, request_limiter) => flow,
-        flow = run_server(repo.clone(), content_tx.clone(), request_rx, choker) => flow,
-        flow = recv_messages(stream, request_tx, response_tx, pex_discovery_tx) => flow,
+        flow = run_client(
+            context.vault.clone(),
+            context.that_runtime_id,
+            content_tx.clone(),
+            response_rx,
+            context.request_limiter.clone(),
+            context.index_request_batch_window,
+            context.sync_state.clone(),
+        ) => flow,
+        flow = run_server(
+            context.vault.clone(),
+            content_tx.clone(),
+            request_rx,
+            context.choker.clone(),
+        ) => flow,
+        flow = recv_messages(
+            stream,
+            request_tx,
+            response_tx,
+            context.pex_discovery_tx.clone(),
+        ) => flow,
         flow = send_messages(content_rx, sink) => flow,
         _ = pex_announcer.run(content_tx) => ControlFlow::Continue,
     }
@@ -341,6 +388,11 @@ async fn recv_messages(
 
         match content {
             Content::Request(request) => request_tx.send(request).await.unwrap_or(()),
+            Content::Requests(requests) => {
+                for request in requests {
+                    request_tx.send(request).await.unwrap_or(());
+                }
+            }
             Content::Response(response) => response_tx.send(response).await.unwrap_or(()),
             Content::Pex(payload) => pex_discovery_tx.send(payload).await.unwrap_or(()),
         }
@@ -380,11 +432,22 @@ async fn send_messages(
 // Create and run client. Returns only on error.
 async fn run_client(
     repo: Vault,
+    that_runtime_id: PublicRuntimeId,
     content_tx: mpsc::Sender<Content>,
     response_rx: mpsc::Receiver<Response>,
     request_limiter: Arc<Semaphore>,
+    index_request_batch_window: Duration,
+    sync_state: Arc<LinkSyncState>,
 ) -> ControlFlow {
-    let mut client = Client::new(repo, content_tx, response_rx, request_limiter);
+    let mut client = Client::new(
+        repo,
+        that_runtime_id,
+        content_tx,
+        response_rx,
+        request_limiter,
+        index_request_batch_window,
+        sync_state,
+    );
     let result = client.run().await;
 
     tracing::debug!("Client stopped running with result {:?}", result);
@@ -19,7 +19,7 @@ use std::{
     future::Future,
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
     task::{Context, Poll, Waker},
@@ -35,6 +35,9 @@ use tokio::{
 const KEEP_ALIVE_RECV_INTERVAL: Duration = Duration::from_secs(60);
 // How often to send keep-alive messages if no regular messages have been sent.
 const KEEP_ALIVE_SEND_INTERVAL: Duration = Duration::from_secs(30);
+// Time after which a connection that isn't ready to send is considered stalled and the message is
+// sent over another one (if there is any). Applies only when the connections are balanced.
+const SEND_STALL_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Reads/writes messages from/to the underlying TCP or QUIC streams and dispatches them to
 /// individual streams/sinks based on their channel ids (in the MessageDispatcher's and
@@ -64,6 +67,12 @@ impl MessageDispatcher {
         self.send.add(PermittedSink::new(writer, writer_permit));
     }
 
+    /// Enables or disables balancing of the channels across the connections (see
+    /// [`NetworkOptions::balance_connections`](super::NetworkOptions::balance_connections)).
+    pub fn set_balanced(&self, balanced: bool) {
+        self.send.balanced.store(balanced, Ordering::Relaxed);
+    }
+
     /// Opens a stream for receiving messages with the given id.
     pub fn open_recv(&self, channel: MessageChannelId) -> ContentStream {
         ContentStream::new(channel, self.recv.clone())
@@ -71,10 +80,7 @@ impl MessageDispatcher {
 
     /// Opens a sink for sending messages with the given id.
     pub fn open_send(&self, channel: MessageChannelId) -> ContentSink {
-        ContentSink {
-            channel,
-            state: self.send.clone(),
-        }
+        ContentSink::new(channel, self.send.clone())
     }
 
     /// Returns the active connections of this dispatcher.
@@ -85,6 +91,17 @@ impl MessageDispatcher {
         }
     }
 
+    /// Waits until all the messages sent so far have been handed over to the underlying
+    /// connections (or failed to be).
+    pub async fn flush(&self) {
+        self.send.flush().await
+    }
+
+    /// Total size of the content of the messages that are waiting to be sent.
+    pub fn pending_bytes(&self) -> u64 {
+        self.send.pending_bytes()
+    }
+
     pub async fn close(&self) {
         self.recv.multi_stream.close();
         self.send.close().await;
@@ -185,13 +202,17 @@ pub(super) enum ContentStreamError {
     TransportChanged,
 }
 
-#[derive(Clone)]
 pub(super) struct ContentSink {
     channel: MessageChannelId,
     state: Arc<MultiSink>,
 }
 
 impl ContentSink {
+    fn new(channel: MessageChannelId, state: Arc<MultiSink>) -> Self {
+        state.add_channel(channel);
+        Self { channel, state }
+    }
+
     pub fn channel(&self) -> &MessageChannelId {
         &self.channel
     }
@@ -208,6 +229,18 @@ impl ContentSink {
     }
 }
 
+impl Clone for ContentSink {
+    fn clone(&self) -> Self {
+        Self::new(self.channel, self.state.clone())
+    }
+}
+
+impl Drop for ContentSink {
+    fn drop(&mut self) {
+        self.state.remove_channel(&self.channel);
+    }
+}
+
 //------------------------------------------------------------------------
 // These traits are useful for testing.
 
@@ -388,6 +421,8 @@ impl Stream for PermittedStream {
 struct PermittedSink {
     inner: KeepAliveSink<raw::OwnedWriteHalf>,
     permit: ConnectionPermitHalf,
+    // Serializes the sends over this sink.
+    send_lock: Arc<AsyncMutex<()>>,
 }
 
 impl PermittedSink {
@@ -395,6 +430,7 @@ impl PermittedSink {
         Self {
             inner: KeepAliveSink::new(MessageSink::new(stream), KEEP_ALIVE_SEND_INTERVAL),
             permit,
+            send_lock: Arc::new(AsyncMutex::new(())),
         }
     }
 
@@ -559,18 +595,39 @@ impl Future for Recv<'_> {
 // Sink that writes to multiple underlying TCP streams sequentially until one of them succeeds,
 // automatically removing the failed ones.
 //
+// By default all messages are sent over the first stream. When balanced, each channel is assigned
+// to the stream with the fewest channels and sticks to it until it fails. Messages of a channel
+// must not be spread over multiple streams because the receiver would see them out of order. When
+// balanced, a stream that stalls (isn't ready to send for `SEND_STALL_TIMEOUT`) is also treated
+// like a failed one, except it's not removed.
+//
 // NOTE: Doesn't actually implement the `Sink` trait currently because we don't need it, only
 // provides an async `send` method.
 struct MultiSink {
-    single_send: AsyncMutex<()>,
     sinks: BlockingMutex<Vec<PermittedSink>>,
+    balanced: AtomicBool,
+    // Channels with open `ContentSink`s.
+    channels: BlockingMutex<HashMap<MessageChannelId, SinkChannel>>,
+    // Total size of the content of the messages whose sending hasn't completed yet.
+    pending_bytes: AtomicU64,
+    // Notified when `pending_bytes` drops to zero.
+    flushed: Notify,
+}
+
+struct SinkChannel {
+    reference_count: usize,
+    // Stream (identified by its permit id) the channel is assigned to when balanced.
+    assigned: Option<PermitId>,
 }
 
 impl MultiSink {
     fn new() -> Self {
         Self {
-            single_send: AsyncMutex::new(()),
             sinks: BlockingMutex::new(Vec::new()),
+            balanced: AtomicBool::new(false),
+            channels: BlockingMutex::new(HashMap::default()),
+            pending_bytes: AtomicU64::new(0),
+            flushed: Notify::new(),
         }
     }
 
@@ -578,6 +635,34 @@ impl MultiSink {
         self.sinks.lock().unwrap().push(sink);
     }
 
+    fn add_channel(&self, channel_id: MessageChannelId) {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(channel_id)
+            .or_insert(SinkChannel {
+                reference_count: 0,
+                assigned: None,
+            })
+            .reference_count += 1;
+    }
+
+    fn remove_channel(&self, channel_id: &MessageChannelId) {
+        let mut channels = self.channels.lock().unwrap();
+
+        match channels.entry(*channel_id) {
+            hash_map::Entry::Occupied(mut entry) => {
+                let value = entry.get_mut();
+                assert_ne!(value.reference_count, 0);
+                value.reference_count -= 1;
+                if value.reference_count == 0 {
+                    entry.remove();
+                }
+            }
+            hash_map::Entry::Vacant(_) => unreachable!(),
+        }
+    }
+
     async fn close(&self) {
         // TODO: Other functions should fail if called after the call to this function.
 
@@ -596,12 +681,82 @@ impl MultiSink {
     }
 
     async fn send(&self, message: Message) -> Result<(), ChannelClosed> {
-        let _lock = self.single_send.lock().await;
-        Send {
-            message: Some(message),
-            sinks: &self.sinks,
+        let _pending = PendingGuard::new(self, message.content.len() as u64);
+        let channel = message.channel;
+        let mut message = Some(message);
+        let mut stalled = None;
+
+        loop {
+            let balanced = self.balanced.load(Ordering::Relaxed);
+            let (sink_id, send_lock) = self.select_sink(channel, balanced, stalled)?;
+
+            let send = async {
+                let _lock = send_lock.lock().await;
+
+                Send {
+                    message: &mut message,
+                    sinks: &self.sinks,
+                    sink_id,
+                }
+                .await
+            };
+
+            let result = if balanced {
+                time::timeout(SEND_STALL_TIMEOUT, send).await
+            } else {
+                Ok(send.await)
+            };
+
+            match result {
+                Ok(Ok(())) => return Ok(()),
+                // The sink failed and was removed. Try the next one.
+                Ok(Err(SinkFailed)) => stalled = None,
+                Err(_) => {
+                    tracing::debug!("Connection stalled, trying another one");
+                    stalled = Some(sink_id);
+                }
+            }
         }
-        .await
+    }
+
+    // Returns the permit id and the send lock of the sink to send the message of the given channel
+    // over. If balanced, `stalled` is the id of a sink and there are other sinks, the channel is
+    // moved away from it.
+    fn select_sink(
+        &self,
+        channel: MessageChannelId,
+        balanced: bool,
+        stalled: Option<PermitId>,
+    ) -> Result<(PermitId, Arc<AsyncMutex<()>>), ChannelClosed> {
+        let sinks = self.sinks.lock().unwrap();
+
+        let sink = if balanced {
+            let stalled = stalled.filter(|_| sinks.len() > 1);
+            let mut channels = self.channels.lock().unwrap();
+            select_balanced_sink(&sinks, &mut channels, channel, stalled)
+        } else {
+            sinks.first()
+        };
+
+        sink.map(|sink| (sink.permit.id(), sink.send_lock.clone()))
+            .ok_or(ChannelClosed)
+    }
+
+    async fn flush(&self) {
+        loop {
+            // Create the `Notified` before checking the condition to not miss the notification.
+            let notified = self.flushed.notified();
+
+            if self.pending_bytes() == 0 {
+                break;
+            }
+
+            notified.await;
+        }
+    }
+
+    fn pending_bytes(&self) -> u64 {
+        self.pending_bytes.load(Ordering::Acquire)
     }
 
     fn is_empty(&self) -> bool {
@@ -618,46 +773,116 @@ impl MultiSink {
     }
 }
 
-// Future returned from [`MultiSink::send`].
+// Tracks the size of a message being sent in `MultiSink::pending_bytes`. Decrements it on drop so
+// it's correctly accounted for even if the send gets cancelled.
+struct PendingGuard<'a> {
+    sink: &'a MultiSink,
+    len: u64,
+}
+
+impl<'a> PendingGuard<'a> {
+    fn new(sink: &'a MultiSink, len: u64) -> Self {
+        sink.pending_bytes.fetch_add(len, Ordering::AcqRel);
+        Self { sink, len }
+    }
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if self
+            .sink
+            .pending_bytes
+            .fetch_sub(self.len, Ordering::AcqRel)
+            == self.len
+        {
+            self.sink.flushed.notify_waiters();
+        }
+    }
+}
+
+// Future that sends a message over a single sink of a `MultiSink`. If the sink fails, it's removed
+// and the message is put back so it can be sent over another one.
 struct Send<'a> {
-    message: Option<Message>,
+    message: &'a mut Option<Message>,
     sinks: &'a BlockingMutex<Vec<PermittedSink>>,
+    sink_id: PermitId,
 }
 
+// The sink failed (or was already removed because of a previous failure).
+struct SinkFailed;
+
 impl Future for Send<'_> {
-    type Output = Result<(), ChannelClosed>;
+    type Output = Result<(), SinkFailed>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let mut sinks = self.sinks.lock().unwrap();
 
-        loop {
-            let sink = if let Some(sink) = sinks.first_mut() {
-                sink
-            } else {
-                return Poll::Ready(Err(ChannelClosed));
-            };
+        let Some(index) = sinks
+            .iter()
+            .position(|sink| sink.permit.id() == self.sink_id)
+        else {
+            return Poll::Ready(Err(SinkFailed));
+        };
 
-            let message = match sink.poll_ready_unpin(cx) {
-                Poll::Ready(Ok(())) => self.message.take().expect("polled Send after completion"),
-                Poll::Ready(Err(error)) => {
-                    sinks.swap_remove(0);
-                    self.message = Some(error.message);
-                    continue;
-                }
-                Poll::Pending => return Poll::Pending,
-            };
+        let sink = &mut sinks[index];
 
-            match sink.start_send_unpin(message) {
-                Ok(()) => return Poll::Ready(Ok(())),
-                Err(error) => {
-                    sinks.swap_remove(0);
-                    self.message = Some(error.message);
-                }
+        let message = match sink.poll_ready_unpin(cx) {
+            Poll::Ready(Ok(())) => self.message.take().expect("polled Send after completion"),
+            Poll::Ready(Err(error)) => {
+                sinks.swap_remove(index);
+                *self.message = Some(error.message);
+                return Poll::Ready(Err(SinkFailed));
+            }
+            Poll::Pending => return Poll::Pending,
+        };
+
+        match sink.start_send_unpin(message) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(error) => {
+                sinks.swap_remove(index);
+                *self.message = Some(error.message);
+                Poll::Ready(Err(SinkFailed))
             }
         }
     }
 }
 
+// Returns the sink the channel is assigned to. If it's not assigned yet, its sink is gone or it's
+// the `stalled` one, assigns it to the sink with the fewest assigned channels (other than the
+// `stalled` one) first.
+fn select_balanced_sink<'a>(
+    sinks: &'a [PermittedSink],
+    channels: &mut HashMap<MessageChannelId, SinkChannel>,
+    channel: MessageChannelId,
+    stalled: Option<PermitId>,
+) -> Option<&'a PermittedSink> {
+    let assigned = channels
+        .get(&channel)
+        .and_then(|channel| channel.assigned)
+        .filter(|id| Some(*id) != stalled)
+        .and_then(|id| sinks.iter().find(|sink| sink.permit.id() == id));
+
+    if let Some(sink) = assigned {
+        return Some(sink);
+    }
+
+    let sink = sinks
+        .iter()
+        .filter(|sink| Some(sink.permit.id()) != stalled)
+        .min_by_key(|sink| {
+            channels
+                .values()
+                .filter(|channel| channel.assigned == Some(sink.permit.id()))
+                .count()
+        })?;
+
+    if let Some(channel) = channels.get_mut(&channel) {
+        channel.assigned = Some(sink.permit.id());
+    }
+
+    Some(sink)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -767,6 +992,82 @@ mod tests {
         }
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn send_balanced() {
+        let client = MessageDispatcher::new();
+        let server = MessageDispatcher::new();
+
+        for _ in 0..2 {
+            let (client_socket, server_socket) = create_connected_sockets().await;
+            client.bind(client_socket, ConnectionPermit::dummy());
+            server.bind(server_socket, ConnectionPermit::dummy());
+        }
+
+        client.set_balanced(true);
+
+        let channel0 = MessageChannelId::random();
+        let channel1 = MessageChannelId::random();
+
+        let client_sink0 = client.open_send(channel0);
+        let client_sink1 = client.open_send(channel1);
+        let mut server_stream0 = server.open_recv(channel0);
+        let mut server_stream1 = server.open_recv(channel1);
+
+        for i in 0..10u8 {
+            client_sink0.send(vec![i]).await.unwrap();
+            client_sink1.send(vec![i]).await.unwrap();
+        }
+
+        // Each channel sticks to its connection so the transport never changes.
+        for i in 0..10u8 {
+            assert_eq!(server_stream0.recv().await.unwrap(), [i]);
+            assert_eq!(server_stream1.recv().await.unwrap(), [i]);
+        }
+
+        // The channels are sent over different connections.
+        assert_ne!(
+            server_stream0.last_transport_id,
+            server_stream1.last_transport_id
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn send_balanced_after_channel_closed() {
+        let client = MessageDispatcher::new();
+        let server = MessageDispatcher::new();
+
+        for _ in 0..2 {
+            let (client_socket, server_socket) = create_connected_sockets().await;
+            client.bind(client_socket, ConnectionPermit::dummy());
+            server.bind(server_socket, ConnectionPermit::dummy());
+        }
+
+        client.set_balanced(true);
+
+        let channel0 = MessageChannelId::random();
+        let channel1 = MessageChannelId::random();
+
+        let mut server_stream0 = server.open_recv(channel0);
+        let mut server_stream1 = server.open_recv(channel1);
+
+        let client_sink0 = client.open_send(channel0);
+        client_sink0.send(vec![0]).await.unwrap();
+        assert_eq!(server_stream0.recv().await.unwrap(), [0]);
+
+        // Closing the channel releases its connection so the next channel is assigned to it
+        // instead of to the other, seemingly less used one.
+        drop(client_sink0);
+
+        let client_sink1 = client.open_send(channel1);
+        client_sink1.send(vec![1]).await.unwrap();
+        assert_eq!(server_stream1.recv().await.unwrap(), [1]);
+
+        assert_eq!(
+            server_stream0.last_transport_id,
+            server_stream1.last_transport_id
+        );
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     async fn drop_stream() {
         let (mut client, server) = setup().await;
@@ -844,6 +1145,27 @@ mod tests {
         assert!(stream.recv().await.is_none());
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn flush() {
+        let (client, server) = setup_two_dispatchers().await;
+
+        let channel = MessageChannelId::random();
+        let client_sink = client.open_send(channel);
+        let mut server_stream = server.open_recv(channel);
+
+        assert_eq!(client.pending_bytes(), 0);
+
+        let content = b"hello world";
+        client_sink.send(content.to_vec()).await.unwrap();
+
+        time::timeout(Duration::from_secs(5), client.flush())
+            .await
+            .unwrap();
+        assert_eq!(client.pending_bytes(), 0);
+
+        assert_eq!(server_stream.recv().await.unwrap(), content);
+    }
+
     async fn setup() -> (MessageSink<raw::Stream>, MessageDispatcher) {
         let (client, server) = create_connected_sockets().await;
         let client_writer = MessageSink::new(client);
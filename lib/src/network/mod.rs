@@ -10,6 +10,7 @@ mod constants;
 mod crypto;
 mod debug_payload;
 mod gateway;
+mod handshake_limiter;
 mod interface;
 mod ip;
 mod keep_alive;
@@ -18,6 +19,7 @@ mod message;
 mod message_broker;
 mod message_dispatcher;
 mod message_io;
+mod options;
 mod peer_exchange;
 mod peer_info;
 mod peer_source;
@@ -30,16 +32,20 @@ mod seen_peers;
 mod server;
 mod stun;
 mod stun_server_list;
+mod sync_state;
 #[cfg(test)]
 mod tests;
 mod upnp;
 
 pub use self::{
     connection::PeerInfoCollector,
+    crypto::NetworkCryptoInfo,
+    options::{ConnectivityMode, NetworkOptions},
     peer_info::PeerInfo,
     peer_source::PeerSource,
     peer_state::PeerState,
     runtime_id::{PublicRuntimeId, SecretRuntimeId},
+    sync_state::SyncState,
 };
 pub use net::stun::NatBehavior;
 
@@ -64,6 +70,8 @@ use crate::{
 use backoff::{backoff::Backoff, ExponentialBackoffBuilder};
 use btdht::{self, InfoHash, INFO_HASH_LEN};
 use deadlock::BlockingMutex;
+use futures_util::future;
+use net::quic;
 use scoped_task::ScopedAbortHandle;
 use slab::Slab;
 use state_monitor::StateMonitor;
@@ -76,9 +84,10 @@ use std::{
 use thiserror::Error;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
+    select,
     sync::mpsc,
     task::{AbortHandle, JoinSet},
-    time::Duration,
+    time::{self, Duration, Instant},
 };
 use tracing::{Instrument, Span};
 
@@ -99,7 +108,10 @@ impl Network {
         monitor: StateMonitor,
     ) -> Self {
         let (incoming_tx, incoming_rx) = mpsc::channel(1);
+        let options = NetworkOptions::default();
+
         let gateway = Gateway::new(incoming_tx);
+        gateway.set_handshake_rate_limit(options.handshake_rate_limit);
 
         // Note that we're now only using quic for the transport discovered over the dht.
         // This is because the dht doesn't let us specify whether the remote peer SocketAddr is
@@ -117,11 +129,13 @@ impl Network {
         let (pex_discovery_tx, pex_discovery_rx) = mpsc::channel(1);
 
         let (on_protocol_mismatch_tx, _) = uninitialized_watch::channel();
+        let (on_protocol_version_mismatch_tx, _) = uninitialized_watch::channel();
 
         let user_provided_peers = SeenPeers::new();
 
-        let this_runtime_id = SecretRuntimeId::random();
+        let this_runtime_id = SecretRuntimeId::generate(&mut options.rng_source.make("runtime id"));
         let this_runtime_id_public = this_runtime_id.public();
+        let this_runtime_id = BlockingMutex::new(Arc::new(this_runtime_id));
 
         let connections_monitor = monitor.make_child("Connections");
         let peers_monitor = monitor.make_child("Peers");
@@ -150,10 +164,12 @@ impl Network {
             stun_clients: StunClients::new(),
             connection_deduplicator: ConnectionDeduplicator::new(),
             on_protocol_mismatch_tx,
+            on_protocol_version_mismatch_tx,
             user_provided_peers,
             tasks: Arc::downgrade(&tasks),
             highest_seen_protocol_version: BlockingMutex::new(VERSION),
             our_addresses: BlockingMutex::new(HashSet::default()),
+            options: BlockingMutex::new(options),
         });
 
         inner.spawn(inner.clone().handle_incoming_connections(incoming_rx));
@@ -171,24 +187,105 @@ impl Network {
     /// Binds the network to the specified addresses.
     /// Rebinds if already bound. Unbinds and disables the network if `addrs` is empty.
     ///
+    /// Returns which transports actually came up. Those that failed to bind are missing from it
+    /// (the reason is logged), so it can be compared with `addrs` to detect degraded
+    /// connectivity (e.g., QUIC unavailable so only TCP is used).
+    ///
     /// NOTE: currently at most one address per protocol (QUIC/TCP) and family (IPv4/IPv6) is used
     /// and the rest are ignored, but this might change in the future.
-    pub async fn bind(&self, addrs: &[PeerAddr]) {
-        self.inner.bind(addrs).await
+    pub async fn bind(&self, addrs: &[PeerAddr]) -> BoundTransports {
+        self.inner.bind(addrs).await;
+        self.inner.bound_transports()
     }
 
     pub fn listener_local_addrs(&self) -> Vec<PeerAddr> {
         self.inner.gateway.listener_local_addrs()
     }
 
+    /// Loads the DHT contacts from the contacts store passed to [`Self::new`] and returns their
+    /// number. They are loaded also whenever the DHT starts.
+    pub async fn load_dht_contacts(&self) -> usize {
+        self.inner.dht_discovery.load_contacts().await
+    }
+
+    /// Saves the DHT routing table into the contacts store passed to [`Self::new`] and returns the
+    /// number of contacts saved. Nothing is saved while the DHT is not bootstrapped. Also done
+    /// periodically while the DHT is running and on [`Self::shutdown`].
+    pub async fn save_dht_contacts(&self) -> io::Result<usize> {
+        self.inner.dht_discovery.save_contacts().await
+    }
+
+    /// Number of DHT contacts in the contacts store as of the last time they were loaded or saved.
+    pub fn persisted_dht_contacts(&self) -> usize {
+        self.inner.dht_discovery.persisted_contacts()
+    }
+
+    /// Reacts to a change of the local network (e.g., switching from Wi-Fi to cellular). Rebinds
+    /// the listeners, forgets the addresses previously recognized as our own and retries all
+    /// pending reconnects immediately instead of waiting for their backoff to expire. Meant to be
+    /// called from the OS connectivity change callbacks.
+    pub async fn refresh_connections(&self) {
+        self.inner.refresh_connections().await
+    }
+
+    /// Sets the network options. The transport options apply only to the listeners bound after
+    /// this call, so this should be called before [`bind`](Self::bind). The index request batch
+    /// window applies only to the connections established after this call. The rest apply
+    /// immediately. Changing the rng source generates a new runtime id, so the peers see the
+    /// connections established after this call as coming from a different node than the existing
+    /// ones.
+    pub fn set_options(&self, options: NetworkOptions) {
+        self.inner.gateway.set_quic_config(options.quic_config());
+        self.inner
+            .gateway
+            .set_connectivity_mode(options.connectivity);
+        self.inner
+            .dht_discovery
+            .set_announce(options.connectivity != ConnectivityMode::OutboundOnly);
+        self.inner
+            .gateway
+            .set_handshake_rate_limit(options.handshake_rate_limit);
+
+        if options.allow_self_address_connections {
+            self.inner.our_addresses.lock().unwrap().clear();
+        }
+
+        let mut current = self.inner.options.lock().unwrap();
+
+        if options.rng_source != current.rng_source {
+            tracing::info!(
+                parent: &self.inner.span,
+                "Rng source changed, generating new runtime id"
+            );
+
+            *self.inner.this_runtime_id.lock().unwrap() = Arc::new(SecretRuntimeId::generate(
+                &mut options.rng_source.make("runtime id"),
+            ));
+        }
+
+        let namespace_changed = options.dht_namespace != current.dht_namespace;
+
+        *current = options;
+        drop(current);
+
+        // The info-hashes depend on the namespace so the lookups need to be restarted.
+        if namespace_changed {
+            self.inner.restart_dht_lookups();
+        }
+    }
+
     pub fn set_port_forwarding_enabled(&self, enabled: bool) {
         let mut state = self.inner.port_forwarder_state.lock().unwrap();
 
         if enabled {
-            state.enable(PortMappings::new(
-                &self.inner.port_forwarder,
-                &self.inner.gateway,
-            ));
+            if self.inner.accepts_incoming() {
+                state.enable(PortMappings::new(
+                    &self.inner.port_forwarder,
+                    &self.inner.gateway,
+                ));
+            } else {
+                state.disable(DisableReason::Implicit);
+            }
         } else {
             state.disable(DisableReason::Explicit);
         }
@@ -247,7 +344,18 @@ impl Network {
     }
 
     pub fn this_runtime_id(&self) -> PublicRuntimeId {
-        self.inner.this_runtime_id.public()
+        self.inner.this_runtime_id.lock().unwrap().public()
+    }
+
+    /// Describes the cryptographic primitives and parameters used to encrypt the communication
+    /// with the peers.
+    pub fn crypto_info(&self) -> NetworkCryptoInfo {
+        NetworkCryptoInfo::new()
+    }
+
+    /// Get the state monitor node of this network.
+    pub fn monitor(&self) -> &StateMonitor {
+        &self.inner.main_monitor
     }
 
     pub fn peer_info_collector(&self) -> PeerInfoCollector {
@@ -271,6 +379,14 @@ impl Network {
         self.inner.on_protocol_mismatch_tx.subscribe()
     }
 
+    /// Subscribe to network protocol mismatch events carrying the protocol version of the peer
+    /// that triggered them. Like [`Self::on_protocol_mismatch`], the event is emitted only when
+    /// a peer with a version higher than any seen so far connects, so the received value is
+    /// always the highest seen protocol version at that moment.
+    pub fn on_protocol_version_mismatch(&self) -> uninitialized_watch::Receiver<u32> {
+        self.inner.on_protocol_version_mismatch_tx.subscribe()
+    }
+
     /// Subscribe change in connected peers events.
     pub fn on_peer_set_change(&self) -> uninitialized_watch::Receiver<()> {
         self.inner.connection_deduplicator.on_change()
@@ -281,8 +397,7 @@ impl Network {
     /// the future. The repository is automatically deregistered when the returned handle is
     /// dropped.
     pub async fn register(&self, handle: RepositoryHandle) -> Registration {
-        *handle.vault.monitor.info_hash.get() =
-            Some(repository_info_hash(handle.vault.repository_id()));
+        *handle.vault.monitor.info_hash.get() = Some(self.inner.info_hash(&handle.vault));
 
         let metadata = handle.vault.metadata();
         let dht_enabled = metadata
@@ -297,10 +412,7 @@ impl Network {
             .unwrap_or(false);
 
         let dht = if dht_enabled {
-            Some(
-                self.inner
-                    .start_dht_lookup(repository_info_hash(handle.vault.repository_id())),
-            )
+            Some(self.inner.start_dht_lookup(&handle.vault))
         } else {
             None
         };
@@ -322,6 +434,7 @@ impl Network {
             dht,
             pex,
             choke_manager,
+            suspended: false,
         });
 
         Registration {
@@ -335,24 +448,106 @@ impl Network {
     /// once the keep-alive mechanism kicks in, but in the mean time we will not be able to
     /// reconnect (by starting the app again) because the remote peer will keep dropping new
     /// connections from us.
-    pub async fn shutdown(&self) {
+    ///
+    /// Before disconnecting, attempts to send the pending outgoing messages. Whatever is not sent
+    /// and disconnected within `timeout` is aborted. Returns a summary of what was interrupted.
+    ///
+    /// Also saves the DHT routing table (see [`Self::save_dht_contacts`]) while the messages are
+    /// being sent.
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
         // TODO: Would be a nice-to-have to also wait for all the spawned tasks here (e.g. dicovery
         // mechanisms).
-        let mut message_brokers = {
+        let (message_brokers, requests_in_flight) = {
             let mut state = self.inner.state.lock().unwrap();
-            match state.message_brokers.take() {
+            let message_brokers = match state.message_brokers.take() {
                 Some(brokers) => brokers,
                 None => {
                     tracing::warn!("Network already shut down");
-                    return;
+                    return ShutdownReport::default();
                 }
-            }
+            };
+
+            let requests_in_flight = state
+                .registry
+                .iter()
+                .map(|(_, holder)| holder.vault.monitor.requests_inflight())
+                .sum();
+
+            (message_brokers, requests_in_flight)
         };
 
-        shutdown_brokers(&mut message_brokers).await;
+        let brokers: Vec<_> = message_brokers.into_values().collect();
+        let deadline = Instant::now() + timeout;
+
+        // Give the pending messages a chance to be sent before disconnecting.
+        let (_, save_result) = future::join(
+            time::timeout_at(
+                deadline,
+                future::join_all(brokers.iter().map(|broker| broker.flush())),
+            ),
+            time::timeout_at(deadline, self.inner.dht_discovery.save_contacts()),
+        )
+        .await;
+
+        match save_result {
+            Ok(Ok(count)) => tracing::debug!(count, "DHT contacts saved"),
+            Ok(Err(error)) => tracing::warn!(?error, "Failed to save DHT contacts"),
+            Err(_) => tracing::warn!("Saving DHT contacts timed out"),
+        }
+
+        let report = ShutdownReport {
+            peers_dropped: brokers.len(),
+            requests_in_flight,
+            bytes_pending: brokers.iter().map(|broker| broker.pending_bytes()).sum(),
+        };
+
+        if time::timeout_at(
+            deadline,
+            future::join_all(brokers.iter().map(|broker| broker.shutdown())),
+        )
+        .await
+        .is_err()
+        {
+            tracing::warn!("Network shutdown timed out");
+        }
+
+        if report.bytes_pending > 0 || report.requests_in_flight > 0 {
+            tracing::warn!(?report, "Network shut down with sync in progress");
+        }
+
+        report
     }
 }
 
+/// Summary of what was interrupted by [`Network::shutdown`].
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct ShutdownReport {
+    /// Number of peers that were disconnected.
+    pub peers_dropped: usize,
+    /// Number of index and block requests to which responses haven't been received yet.
+    pub requests_in_flight: u64,
+    /// Total size (in bytes) of the outgoing messages that couldn't be sent before the timeout.
+    pub bytes_pending: u64,
+}
+
+/// Transports that are up after [`Network::bind`].
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct BoundTransports {
+    /// Whether the IPv4 QUIC stack is bound.
+    pub quic_v4: bool,
+    /// Whether the IPv6 QUIC stack is bound.
+    pub quic_v6: bool,
+    /// Whether the IPv4 TCP listener is bound.
+    pub tcp_v4: bool,
+    /// Whether the IPv6 TCP listener is bound.
+    pub tcp_v6: bool,
+    /// Whether the IPv4 DHT is available. It runs on the IPv4 QUIC socket, so it requires
+    /// `quic_v4` and it's also not available when bound to non-global addresses only.
+    pub dht_v4: bool,
+    /// Whether the IPv6 DHT is available. Same conditions as for `dht_v4` apply.
+    pub dht_v6: bool,
+}
+
 pub struct Registration {
     inner: Arc<Inner>,
     key: usize,
@@ -366,10 +561,7 @@ impl Registration {
         let holder = &mut state.registry[self.key];
 
         if enabled {
-            holder.dht = Some(
-                self.inner
-                    .start_dht_lookup(repository_info_hash(holder.vault.repository_id())),
-            );
+            holder.dht = Some(self.inner.start_dht_lookup(&holder.vault));
         } else {
             holder.dht = None;
         }
@@ -396,6 +588,136 @@ impl Registration {
         state.registry[self.key].pex.is_enabled()
     }
 
+    /// Sets the maximum number of block requests for this repository that can be in flight (sent
+    /// but not yet responded to) at the same time, across all peers. Higher values improve
+    /// throughput on high latency links. If the new value is lower than the current number of
+    /// in-flight requests, it takes effect gradually as the responses arrive. By default there is
+    /// no limit other than the per-peer ones. While the limit is reached, the requests to the
+    /// peers of this repository wait for the in-flight block requests to complete.
+    pub fn set_max_inflight_requests(&self, value: usize) {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key]
+            .vault
+            .block_request_limiter
+            .resize(value);
+    }
+
+    /// Returns the maximum number of in-flight block requests of this repository (see
+    /// [`Self::set_max_inflight_requests`]).
+    pub fn max_inflight_requests(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key]
+            .vault
+            .block_request_limiter
+            .capacity()
+    }
+
+    /// Sets the maximum number of in-flight requests for the blocks that are not required locally
+    /// but requested only because the repository is in the greedy block request mode (e.g., blind
+    /// mirrors). This lets a storage node limit how much of a shared uplink a single repository
+    /// can use while it's catching up. The requests are still subject to the limit set with
+    /// [`Self::set_max_inflight_requests`] too. Requests of explicitly required blocks (e.g., of
+    /// the files being read) are not affected. Setting it to zero pauses the greedy requests
+    /// without affecting the other ones.
+    pub fn set_greedy_concurrency(&self, value: usize) {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key]
+            .vault
+            .greedy_block_request_limiter
+            .resize(value);
+    }
+
+    /// Returns the maximum number of in-flight requests for the blocks requested only because of
+    /// the greedy block request mode (see [`Self::set_greedy_concurrency`]).
+    pub fn greedy_concurrency(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key]
+            .vault
+            .greedy_block_request_limiter
+            .capacity()
+    }
+
+    /// Suspends syncing of this repository with all peers without unregistering it. The links to
+    /// the peers are torn down so no more requests are sent or served for this repository and the
+    /// bandwidth is freed for the other ones. Requests in flight are abandoned and will be sent
+    /// again after [`Self::resume`]. The repository remains fully usable locally (including
+    /// reading already downloaded content). Unlike DHT and PEX, this setting is not persisted.
+    pub fn suspend(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        let state = &mut *state;
+        let holder = &mut state.registry[self.key];
+
+        if holder.suspended {
+            return;
+        }
+
+        holder.suspended = true;
+
+        if let Some(brokers) = &mut state.message_brokers {
+            for broker in brokers.values_mut() {
+                broker.destroy_link(holder.vault.local_id);
+            }
+        }
+    }
+
+    /// Resumes syncing of this repository previously suspended with [`Self::suspend`].
+    pub fn resume(&self) {
+        let mut state = self.inner.state.lock().unwrap();
+        let state = &mut *state;
+        let holder = &mut state.registry[self.key];
+
+        if !holder.suspended {
+            return;
+        }
+
+        holder.suspended = false;
+
+        if let Some(brokers) = &mut state.message_brokers {
+            for broker in brokers.values_mut() {
+                broker.create_link(holder.vault.clone(), &holder.pex, &holder.choke_manager);
+            }
+        }
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        let state = self.inner.state.lock().unwrap();
+        state.registry[self.key].suspended
+    }
+
+    /// Returns, for each connected peer this repository is linked with, whether we are caught up
+    /// with them or still pulling from them. Derived from comparing the versions of the branches
+    /// we have with the ones the peer advertised and from the requests to the peer that are still
+    /// outstanding.
+    pub async fn peer_sync_states(&self) -> Vec<(PublicRuntimeId, SyncState)> {
+        let (vault, links) = {
+            let state = self.inner.state.lock().unwrap();
+            let vault = state.registry[self.key].vault.clone();
+            let links: Vec<_> = state
+                .message_brokers
+                .iter()
+                .flatten()
+                .filter_map(|(runtime_id, broker)| {
+                    Some((*runtime_id, broker.link_sync_state(vault.local_id)?))
+                })
+                .collect();
+
+            (vault, links)
+        };
+
+        let local_versions = match vault.load_version_vectors().await {
+            Ok(versions) => versions,
+            Err(error) => {
+                tracing::error!(?error, "Failed to load version vectors");
+                HashMap::default()
+            }
+        };
+
+        links
+            .into_iter()
+            .map(|(runtime_id, sync_state)| (runtime_id, sync_state.get(&local_versions)))
+            .collect()
+    }
+
     async fn set_metadata_bool(&self, name: &str, value: bool) {
         let metadata = self.inner.state.lock().unwrap().registry[self.key]
             .vault
@@ -423,6 +745,7 @@ struct RegistrationHolder {
     dht: Option<dht_discovery::LookupRequest>,
     pex: PexController,
     choke_manager: choke::Manager,
+    suspended: bool,
 }
 
 struct Inner {
@@ -431,17 +754,18 @@ struct Inner {
     peers_monitor: StateMonitor,
     span: Span,
     gateway: Gateway,
-    this_runtime_id: SecretRuntimeId,
+    this_runtime_id: BlockingMutex<Arc<SecretRuntimeId>>,
     state: BlockingMutex<State>,
     port_forwarder: upnp::PortForwarder,
     port_forwarder_state: BlockingMutex<ComponentState<PortMappings>>,
     local_discovery_state: BlockingMutex<ComponentState<ScopedAbortHandle>>,
     dht_discovery: DhtDiscovery,
     dht_discovery_tx: mpsc::UnboundedSender<SeenPeer>,
-    pex_discovery_tx: mpsc::Sender<PexPayload>,
+    pex_discovery_tx: mpsc::Sender<(PublicRuntimeId, PexPayload)>,
     stun_clients: StunClients,
     connection_deduplicator: ConnectionDeduplicator,
     on_protocol_mismatch_tx: uninitialized_watch::Sender<()>,
+    on_protocol_version_mismatch_tx: uninitialized_watch::Sender<u32>,
     user_provided_peers: SeenPeers,
     // Note that unwrapping the upgraded weak pointer should be fine because if the underlying Arc
     // was Dropped, we would not be asking for the upgrade in the first place.
@@ -449,6 +773,7 @@ struct Inner {
     highest_seen_protocol_version: BlockingMutex<Version>,
     // Used to prevent repeatedly connecting to self.
     our_addresses: BlockingMutex<HashSet<PeerAddr>>,
+    options: BlockingMutex<NetworkOptions>,
 }
 
 struct State {
@@ -472,6 +797,10 @@ impl Inner {
         self.state.lock().unwrap().message_brokers.is_none()
     }
 
+    fn accepts_incoming(&self) -> bool {
+        self.options.lock().unwrap().connectivity != ConnectivityMode::OutboundOnly
+    }
+
     async fn bind(self: &Arc<Self>, bind: &[PeerAddr]) {
         let conn = Connectivity::infer(bind);
 
@@ -485,6 +814,53 @@ impl Inner {
         // Gateway
         let side_channel_makers = self.gateway.bind(&bind).instrument(self.span.clone()).await;
 
+        self.on_gateway_bound(conn, side_channel_makers).await;
+    }
+
+    fn bound_transports(&self) -> BoundTransports {
+        let mut transports = BoundTransports::default();
+
+        for addr in self.gateway.listener_local_addrs() {
+            match addr {
+                PeerAddr::Quic(SocketAddr::V4(_)) => transports.quic_v4 = true,
+                PeerAddr::Quic(SocketAddr::V6(_)) => transports.quic_v6 = true,
+                PeerAddr::Tcp(SocketAddr::V4(_)) => transports.tcp_v4 = true,
+                PeerAddr::Tcp(SocketAddr::V6(_)) => transports.tcp_v6 = true,
+            }
+        }
+
+        (transports.dht_v4, transports.dht_v6) = self.dht_discovery.is_bound();
+
+        transports
+    }
+
+    async fn refresh_connections(self: &Arc<Self>) {
+        // Our addresses might have changed so some of the peers previously recognized as
+        // ourselves might now be someone else.
+        self.our_addresses.lock().unwrap().clear();
+
+        let addrs = self.gateway.listener_local_addrs();
+
+        if addrs.is_empty() {
+            return;
+        }
+
+        tracing::info!(parent: &self.span, "Network changed - rebinding");
+
+        let conn = Connectivity::infer(&addrs);
+        let side_channel_makers = self.gateway.rebind().instrument(self.span.clone()).await;
+
+        self.on_gateway_bound(conn, side_channel_makers).await;
+    }
+
+    async fn on_gateway_bound(
+        self: &Arc<Self>,
+        conn: Connectivity,
+        side_channel_makers: (
+            Option<quic::SideChannelMaker>,
+            Option<quic::SideChannelMaker>,
+        ),
+    ) {
         let (side_channel_maker_v4, side_channel_maker_v6) = match conn {
             Connectivity::Full => side_channel_makers,
             Connectivity::LocalOnly | Connectivity::Disabled => (None, None),
@@ -502,13 +878,13 @@ impl Inner {
 
         // Port forwarding
         match conn {
-            Connectivity::Full => {
+            Connectivity::Full if self.accepts_incoming() => {
                 let mut state = self.port_forwarder_state.lock().unwrap();
                 if !state.is_disabled(DisableReason::Explicit) {
                     state.enable(PortMappings::new(&self.port_forwarder, &self.gateway));
                 }
             }
-            Connectivity::LocalOnly | Connectivity::Disabled => {
+            Connectivity::Full | Connectivity::LocalOnly | Connectivity::Disabled => {
                 self.port_forwarder_state
                     .lock()
                     .unwrap()
@@ -560,35 +936,41 @@ impl Inner {
     }
 
     fn spawn_local_discovery(self: &Arc<Self>) -> Option<AbortHandle> {
-        let addrs = self.gateway.listener_local_addrs();
-        let tcp_port = addrs
-            .iter()
-            .find(|addr| matches!(addr, PeerAddr::Tcp(SocketAddr::V4(_))))
-            .map(|addr| PeerPort::Tcp(addr.port()));
-        let quic_port = addrs
-            .iter()
-            .find(|addr| matches!(addr, PeerAddr::Quic(SocketAddr::V4(_))))
-            .map(|addr| PeerPort::Quic(addr.port()));
-
-        // Arbitrary order of preference.
-        // TODO: Should we support all available?
-        let port = tcp_port.or(quic_port);
-
-        if let Some(port) = port {
-            Some(
-                self.spawn(
-                    self.clone()
-                        .run_local_discovery(port)
-                        .instrument(self.span.clone()),
-                ),
-            )
+        // Without incoming connections we don't announce ourselves but still discover the peers
+        // that do.
+        let port = if self.accepts_incoming() {
+            let addrs = self.gateway.listener_local_addrs();
+            let tcp_port = addrs
+                .iter()
+                .find(|addr| matches!(addr, PeerAddr::Tcp(SocketAddr::V4(_))))
+                .map(|addr| PeerPort::Tcp(addr.port()));
+            let quic_port = addrs
+                .iter()
+                .find(|addr| matches!(addr, PeerAddr::Quic(SocketAddr::V4(_))))
+                .map(|addr| PeerPort::Quic(addr.port()));
+
+            // Arbitrary order of preference.
+            // TODO: Should we support all available?
+            let Some(port) = tcp_port.or(quic_port) else {
+                tracing::error!("Not enabling local discovery because there is no IPv4 listener");
+                return None;
+            };
+
+            Some(port)
         } else {
-            tracing::error!("Not enabling local discovery because there is no IPv4 listener");
             None
-        }
+        };
+
+        Some(
+            self.spawn(
+                self.clone()
+                    .run_local_discovery(port)
+                    .instrument(self.span.clone()),
+            ),
+        )
     }
 
-    async fn run_local_discovery(self: Arc<Self>, listener_port: PeerPort) {
+    async fn run_local_discovery(self: Arc<Self>, listener_port: Option<PeerPort>) {
         let mut discovery = LocalDiscovery::new(
             listener_port,
             self.main_monitor.make_child("LocalDiscovery"),
@@ -603,14 +985,36 @@ impl Inner {
 
             self.spawn(
                 self.clone()
-                    .handle_peer_found(peer, PeerSource::LocalDiscovery),
+                    .handle_peer_found(peer, PeerSource::LocalDiscovery, None),
             );
         }
     }
 
-    fn start_dht_lookup(&self, info_hash: InfoHash) -> dht_discovery::LookupRequest {
-        self.dht_discovery
-            .start_lookup(info_hash, self.dht_discovery_tx.clone())
+    fn start_dht_lookup(&self, vault: &Vault) -> dht_discovery::LookupRequest {
+        self.dht_discovery.start_lookup(
+            self.info_hash(vault),
+            self.dht_discovery_tx.clone(),
+            vault.monitor.dht.clone(),
+        )
+    }
+
+    fn restart_dht_lookups(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        for (_, holder) in &mut state.registry {
+            *holder.vault.monitor.info_hash.get() = Some(self.info_hash(&holder.vault));
+
+            if holder.dht.is_some() {
+                holder.dht = Some(self.start_dht_lookup(&holder.vault));
+            }
+        }
+    }
+
+    fn info_hash(&self, vault: &Vault) -> InfoHash {
+        repository_info_hash(
+            vault.repository_id(),
+            self.options.lock().unwrap().dht_namespace.as_ref(),
+        )
     }
 
     async fn run_dht(self: Arc<Self>, mut discovery_rx: mpsc::UnboundedReceiver<SeenPeer>) {
@@ -619,22 +1023,36 @@ impl Inner {
                 break;
             }
 
-            self.spawn(self.clone().handle_peer_found(seen_peer, PeerSource::Dht));
+            self.spawn(
+                self.clone()
+                    .handle_peer_found(seen_peer, PeerSource::Dht, None),
+            );
         }
     }
 
-    async fn run_peer_exchange(self: Arc<Self>, discovery_rx: mpsc::Receiver<PexPayload>) {
+    async fn run_peer_exchange(
+        self: Arc<Self>,
+        discovery_rx: mpsc::Receiver<(PublicRuntimeId, PexPayload)>,
+    ) {
         let mut discovery = PexDiscovery::new(discovery_rx);
 
-        while let Some(peer) = discovery.recv().await {
+        loop {
+            let contact_expiry = self.options.lock().unwrap().pex_config().contact_expiry();
+            discovery.set_contact_expiry(contact_expiry);
+
+            let Some((peer, announcer_id)) = discovery.recv().await else {
+                break;
+            };
+
             if self.is_shutdown() {
                 break;
             }
 
-            self.spawn(
-                self.clone()
-                    .handle_peer_found(peer, PeerSource::PeerExchange),
-            );
+            self.spawn(self.clone().handle_peer_found(
+                peer,
+                PeerSource::PeerExchange,
+                Some(announcer_id),
+            ));
         }
     }
 
@@ -647,7 +1065,7 @@ impl Inner {
 
         self.spawn(
             self.clone()
-                .handle_peer_found(peer, PeerSource::UserProvided),
+                .handle_peer_found(peer, PeerSource::UserProvided, None),
         );
     }
 
@@ -658,7 +1076,7 @@ impl Inner {
         while let Some((stream, addr)) = rx.recv().await {
             match self
                 .connection_deduplicator
-                .reserve(addr, PeerSource::Listener)
+                .reserve(addr, PeerSource::Listener, None)
             {
                 ReserveResult::Permit(permit) => {
                     if self.is_shutdown() {
@@ -687,11 +1105,25 @@ impl Inner {
         }
     }
 
-    async fn handle_peer_found(self: Arc<Self>, peer: SeenPeer, source: PeerSource) {
+    // `introduced_by` is the peer that announced this one via peer exchange, if any.
+    async fn handle_peer_found(
+        self: Arc<Self>,
+        peer: SeenPeer,
+        source: PeerSource,
+        introduced_by: Option<PublicRuntimeId>,
+    ) {
         let monitor = self.span.in_scope(|| {
             ConnectionMonitor::new(&self.connections_monitor, peer.initial_addr(), source)
         });
 
+        if let Some(introduced_by) = &introduced_by {
+            tracing::debug!(
+                parent: monitor.span(),
+                introduced_by = ?introduced_by.as_public_key(),
+                "Peer found via peer exchange"
+            );
+        }
+
         let mut backoff = ExponentialBackoffBuilder::new()
             .with_initial_interval(Duration::from_millis(100))
             .with_max_interval(Duration::from_secs(8))
@@ -699,6 +1131,7 @@ impl Inner {
             .build();
 
         let mut next_sleep = None;
+        let mut network_change_rx = self.gateway.subscribe_network_change();
 
         loop {
             monitor.start();
@@ -710,6 +1143,11 @@ impl Inner {
                 return;
             }
 
+            if self.options.lock().unwrap().connectivity == ConnectivityMode::InboundOnly {
+                tracing::debug!(parent: monitor.span(), "Outgoing connections disabled");
+                return;
+            }
+
             let addr = match peer.addr_if_seen() {
                 Some(addr) => *addr,
                 None => return,
@@ -722,12 +1160,22 @@ impl Inner {
 
             if let Some(sleep) = next_sleep {
                 tracing::debug!(parent: monitor.span(), "Next connection attempt in {:?}", sleep);
-                tokio::time::sleep(sleep).await;
+
+                select! {
+                    _ = time::sleep(sleep) => (),
+                    _ = network_change_rx.changed() => {
+                        tracing::debug!(parent: monitor.span(), "Network changed - reconnecting");
+                        backoff.reset();
+                    }
+                }
             }
 
             next_sleep = backoff.next_backoff();
 
-            let permit = match self.connection_deduplicator.reserve(addr, source) {
+            let permit = match self
+                .connection_deduplicator
+                .reserve(addr, source, introduced_by)
+            {
                 ReserveResult::Permit(permit) => permit,
                 ReserveResult::Occupied(on_release, their_source, permit_id) => {
                     if source == their_source {
@@ -781,7 +1229,8 @@ impl Inner {
         permit.mark_as_handshaking();
         monitor.mark_as_handshaking();
 
-        let handshake_result = perform_handshake(&mut stream, VERSION, &self.this_runtime_id).await;
+        let this_runtime_id = self.this_runtime_id.lock().unwrap().clone();
+        let handshake_result = perform_handshake(&mut stream, VERSION, &this_runtime_id).await;
 
         if let Err(error) = &handshake_result {
             tracing::debug!(parent: monitor.span(), ?error, "Handshake failed");
@@ -799,9 +1248,13 @@ impl Inner {
         };
 
         // prevent self-connections.
-        if that_runtime_id == self.this_runtime_id.public() {
+        if that_runtime_id == this_runtime_id.public() {
             tracing::debug!(parent: monitor.span(), "Connection from self, discarding");
-            self.our_addresses.lock().unwrap().insert(permit.addr());
+
+            if !self.options.lock().unwrap().allow_self_address_connections {
+                self.our_addresses.lock().unwrap().insert(permit.addr());
+            }
+
             return false;
         }
 
@@ -828,12 +1281,14 @@ impl Inner {
                         .peers_monitor
                         .make_child(format!("{:?}", that_runtime_id.as_public_key()));
 
+                    let options = *self.options.lock().unwrap();
                     let mut broker = self.span.in_scope(|| {
                         MessageBroker::new(
-                            self.this_runtime_id.public(),
+                            this_runtime_id.public(),
                             that_runtime_id,
                             stream,
                             permit,
+                            &options,
                             monitor,
                         )
                     });
@@ -842,6 +1297,10 @@ impl Inner {
                     // lookup but make sure we correctly handle edge cases, for example, when we have
                     // more than one repository shared with the peer.
                     for (_, holder) in &state.registry {
+                        if holder.suspended {
+                            continue;
+                        }
+
                         broker.create_link(
                             holder.vault.clone(),
                             &holder.pex,
@@ -875,6 +1334,9 @@ impl Inner {
         if *highest < their_version {
             *highest = their_version;
             self.on_protocol_mismatch_tx.send(()).unwrap_or(());
+            self.on_protocol_version_mismatch_tx
+                .send(their_version.into())
+                .unwrap_or(());
         }
     }
 
@@ -1084,12 +1546,20 @@ impl Connectivity {
     }
 }
 
-pub fn repository_info_hash(id: &RepositoryId) -> InfoHash {
+/// Info-hash under which the repository is announced on the DHT. If `namespace` is given, it's
+/// mixed into the hash so the repository can be found only by the nodes that use the same
+/// namespace (see [`NetworkOptions::dht_namespace`]).
+pub fn repository_info_hash(id: &RepositoryId, namespace: Option<&[u8; 32]>) -> InfoHash {
+    let mut salt = b"ouisync repository info-hash".to_vec();
+
+    if let Some(namespace) = namespace {
+        salt.extend_from_slice(namespace);
+    }
+
     // Calculate the info hash by hashing the id with SHA3-256 and taking the first 20 bytes.
     // (bittorrent uses SHA-1 but that is less secure).
     // `unwrap` is OK because the byte slice has the correct length.
-    InfoHash::try_from(&id.salted_hash(b"ouisync repository info-hash").as_ref()[..INFO_HASH_LEN])
-        .unwrap()
+    InfoHash::try_from(&id.salted_hash(&salt).as_ref()[..INFO_HASH_LEN]).unwrap()
 }
 
 async fn shutdown_brokers(message_brokers: &mut HashMap<PublicRuntimeId, MessageBroker>) {
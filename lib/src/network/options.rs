@@ -0,0 +1,102 @@
+use super::peer_exchange::{self, PexConfig};
+use crate::rng::RngSource;
+use net::quic;
+use std::time::Duration;
+
+/// Options to tune the network transports.
+///
+/// Note all connections are also closed after 60 seconds without receiving anything from the peer
+/// (see the `keep_alive` module), so a QUIC idle timeout longer than that has no effect.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NetworkOptions {
+    /// QUIC idle timeout (the smaller of ours and the peer's is used). Zero disables it.
+    pub quic_idle_timeout: Duration,
+    /// QUIC keep-alive interval on the connections we initiate. `None` disables it.
+    pub quic_keep_alive_interval: Option<Duration>,
+    /// By default, when we connect to an address and the handshake reveals the peer is ourselves,
+    /// the address is remembered and never connected to again (until the network changes). Set
+    /// this to `true` to disable that, e.g. when running multiple instances on the same host for
+    /// testing. Connections to ourselves are still detected and discarded during the handshake.
+    pub allow_self_address_connections: bool,
+    /// Index requests ready within this window after the first one are sent in a single message.
+    pub index_request_batch_window: Duration,
+    /// Max number of inbound handshakes per source IP address per second. Connections exceeding
+    /// it are dropped right after being accepted, before any handshake is performed. This is a
+    /// basic protection against connection spam. `None` (the default) disables the limit.
+    pub handshake_rate_limit: Option<u32>,
+    /// Source of the randomness used to generate the runtime id. The network is always created
+    /// with [`RngSource::Os`]; setting a different source via
+    /// [`set_options`](super::Network::set_options) generates a new runtime id which is used only
+    /// for the connections established after the change, so this should be set before
+    /// [`bind`](super::Network::bind). Nothing else in the network (e.g., the handshake
+    /// challenges or the local discovery ids) uses this source. See [`RngSource`] for details.
+    pub rng_source: RngSource,
+    /// Which connections to establish: incoming, outgoing or both. Applies to the listeners bound
+    /// after the options are set and to all new outgoing connection attempts.
+    pub connectivity: ConnectivityMode,
+    /// Secret mixed into the info-hashes the repositories are announced under on the DHT (see
+    /// [`repository_info_hash`](super::repository_info_hash)). Nodes using different namespaces
+    /// never find each other via the DHT, so a private swarm can use this to prevent anyone who
+    /// knows only the repository id from finding its members. Other discovery mechanisms are not
+    /// affected. Changing it restarts the DHT lookups of the registered repositories.
+    pub dht_namespace: Option<[u8; 32]>,
+    /// How long a peer exchange contact is not re-announced / re-connected to. At least a minute.
+    pub pex_contact_expiry: Duration,
+    /// Max number of contacts (a random subset) sent in a single peer exchange message (1 - 1000).
+    pub pex_max_contacts_per_message: usize,
+    /// Spread the repositories shared with a peer across all the connections to it instead of
+    /// sending everything over the first one.
+    pub balance_connections: bool,
+}
+
+impl NetworkOptions {
+    pub(super) fn quic_config(&self) -> quic::TransportConfig {
+        quic::TransportConfig {
+            idle_timeout: self.quic_idle_timeout,
+            keep_alive_interval: self.quic_keep_alive_interval,
+        }
+    }
+
+    pub(super) fn pex_config(&self) -> PexConfig {
+        PexConfig::new(self.pex_contact_expiry, self.pex_max_contacts_per_message)
+    }
+}
+
+impl Default for NetworkOptions {
+    fn default() -> Self {
+        let config = quic::TransportConfig::default();
+
+        Self {
+            quic_idle_timeout: config.idle_timeout,
+            quic_keep_alive_interval: config.keep_alive_interval,
+            allow_self_address_connections: false,
+            index_request_batch_window: Duration::from_millis(2),
+            handshake_rate_limit: None,
+            rng_source: RngSource::Os,
+            connectivity: ConnectivityMode::default(),
+            dht_namespace: None,
+            pex_contact_expiry: peer_exchange::DEFAULT_CONTACT_EXPIRY,
+            pex_max_contacts_per_message: peer_exchange::DEFAULT_MAX_CONTACTS_PER_MESSAGE,
+            balance_connections: false,
+        }
+    }
+}
+
+/// Direction of the connections a [`Network`](super::Network) establishes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ConnectivityMode {
+    /// Both accept incoming connections and connect to the discovered peers.
+    #[default]
+    InboundOutbound,
+    /// Only connect to the discovered peers, never accept incoming connections. Meant for pure
+    /// clients (e.g., behind a strict NAT). No TCP listeners are bound, UPnP port forwarding is
+    /// disabled and we are not announced on the DHT nor via local discovery, but the peers
+    /// announced by the others are still discovered. The QUIC sockets are still bound because they
+    /// are needed for the outgoing QUIC connections and for the DHT, but they refuse all incoming
+    /// connections.
+    OutboundOnly,
+    /// Only accept incoming connections, never connect to the discovered peers (including the
+    /// user provided ones). Meant for pure servers. The discovery mechanisms still run so the
+    /// other peers can find us.
+    InboundOnly,
+}
@@ -26,22 +26,51 @@ use tokio::{
 };
 use tokio_stream::StreamExt;
 
-// Time interval that affects how often we send contacts to other peers and also how often we
-// accept contacts from others. More specifically:
+// Default of `NetworkOptions::pex_contact_expiry`. Time interval that affects how often we send
+// contacts to other peers and also how often we accept contacts from others. More specifically:
 //
 // 1. It's an interval after a contact is announced to a peer in which the same contact won't be
 //    announced again to the same peer
 // 2. It's the duration of one `SeenPeers` round used for the PEX discovery (see `SeenPeers` for
 //    more details on what this means).
-const CONTACT_EXPIRY: Duration = Duration::from_secs(10 * 60);
+pub(super) const DEFAULT_CONTACT_EXPIRY: Duration = Duration::from_secs(10 * 60);
 
-// Maximum number of contacts sent in the same announce message. If there are more contacts than
-// this, a random subset of this size is chosen.
-const MAX_CONTACTS_PER_MESSAGE: usize = 25;
+// Default of `NetworkOptions::pex_max_contacts_per_message`. Maximum number of contacts sent in
+// the same announce message. If there are more contacts than this, a random subset of this size is
+// chosen.
+pub(super) const DEFAULT_MAX_CONTACTS_PER_MESSAGE: usize = 25;
 
-// Minimal delay between two consecutive messages sent to the same peer.
+// Upper bound of the max contacts per message, to keep the announce messages well below the
+// maximum message size.
+const MAX_CONTACTS_PER_MESSAGE_LIMIT: usize = 1000;
+
+// Minimal delay between two consecutive messages sent to the same peer. Also the lower bound of
+// the contact expiry, because expiring the contacts sooner has no effect on the announcements.
 const MESSAGE_DELAY: Duration = Duration::from_secs(60);
 
+/// Validated peer exchange parameters (see `NetworkOptions::pex_contact_expiry` and
+/// `NetworkOptions::pex_max_contacts_per_message`).
+#[derive(Clone, Copy, Debug)]
+pub(super) struct PexConfig {
+    contact_expiry: Duration,
+    max_contacts_per_message: usize,
+}
+
+impl PexConfig {
+    /// Creates the config, clamping the values into their valid ranges.
+    pub fn new(contact_expiry: Duration, max_contacts_per_message: usize) -> Self {
+        Self {
+            contact_expiry: contact_expiry.max(MESSAGE_DELAY),
+            max_contacts_per_message: max_contacts_per_message
+                .clamp(1, MAX_CONTACTS_PER_MESSAGE_LIMIT),
+        }
+    }
+
+    pub fn contact_expiry(&self) -> Duration {
+        self.contact_expiry
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct PexPayload(HashSet<PeerAddr>);
 
@@ -49,32 +78,42 @@ pub(crate) struct PexPayload(HashSet<PeerAddr>);
 pub(super) struct PexDiscovery {
     rx: PexDiscoveryReceiver,
     seen_peers: SeenPeers,
+    contact_expiry: Duration,
     next_round_time: Instant,
 }
 
 impl PexDiscovery {
-    pub fn new(rx: mpsc::Receiver<PexPayload>) -> Self {
+    pub fn new(rx: mpsc::Receiver<(PublicRuntimeId, PexPayload)>) -> Self {
         Self {
             rx: PexDiscoveryReceiver {
                 inner_rx: rx,
                 buffer: Vec::new(),
             },
             seen_peers: SeenPeers::new(),
-            next_round_time: next_round_time(),
+            contact_expiry: DEFAULT_CONTACT_EXPIRY,
+            next_round_time: Instant::now() + DEFAULT_CONTACT_EXPIRY,
         }
     }
 
-    pub async fn recv(&mut self) -> Option<SeenPeer> {
+    /// Sets the duration of the discovery rounds. Takes effect from the next round.
+    pub fn set_contact_expiry(&mut self, contact_expiry: Duration) {
+        self.contact_expiry = contact_expiry;
+    }
+
+    /// Returns the next discovered peer together with the id of the peer that announced it.
+    pub async fn recv(&mut self) -> Option<(SeenPeer, PublicRuntimeId)> {
         loop {
             select! {
-                addr = self.rx.recv() => {
-                    if let Some(peer) = self.seen_peers.insert(addr?) {
-                        return Some(peer);
+                item = self.rx.recv() => {
+                    let (addr, announcer_id) = item?;
+
+                    if let Some(peer) = self.seen_peers.insert(addr) {
+                        return Some((peer, announcer_id));
                     }
                 }
                 _ = time::sleep_until(self.next_round_time) => {
                     self.seen_peers.start_new_round();
-                    self.next_round_time = next_round_time();
+                    self.next_round_time = Instant::now() + self.contact_expiry;
                     continue;
                 }
             }
@@ -82,23 +121,20 @@ impl PexDiscovery {
     }
 }
 
-fn next_round_time() -> Instant {
-    Instant::now() + CONTACT_EXPIRY
-}
-
 struct PexDiscoveryReceiver {
-    inner_rx: mpsc::Receiver<PexPayload>,
-    buffer: Vec<PeerAddr>,
+    inner_rx: mpsc::Receiver<(PublicRuntimeId, PexPayload)>,
+    buffer: Vec<(PeerAddr, PublicRuntimeId)>,
 }
 
 impl PexDiscoveryReceiver {
-    async fn recv(&mut self) -> Option<PeerAddr> {
+    async fn recv(&mut self) -> Option<(PeerAddr, PublicRuntimeId)> {
         loop {
-            if let Some(addr) = self.buffer.pop() {
-                return Some(addr);
+            if let Some(item) = self.buffer.pop() {
+                return Some(item);
             } else {
+                let (announcer_id, payload) = self.inner_rx.recv().await?;
                 self.buffer
-                    .extend(self.inner_rx.recv().await?.0.into_iter());
+                    .extend(payload.0.into_iter().map(|addr| (addr, announcer_id)));
             }
         }
     }
@@ -106,8 +142,10 @@ impl PexDiscoveryReceiver {
 
 #[derive(Clone)]
 pub(super) struct PexDiscoverySender {
-    inner_tx: mpsc::Sender<PexPayload>,
+    inner_tx: mpsc::Sender<(PublicRuntimeId, PexPayload)>,
     enabled_rx: watch::Receiver<bool>,
+    // Id of the peer whose announcements are being forwarded.
+    peer_id: PublicRuntimeId,
 }
 
 impl PexDiscoverySender {
@@ -116,7 +154,10 @@ impl PexDiscoverySender {
         payload: PexPayload,
     ) -> Result<(), mpsc::error::SendError<PexPayload>> {
         if *self.enabled_rx.borrow() {
-            self.inner_tx.send(payload).await
+            self.inner_tx
+                .send((self.peer_id, payload))
+                .await
+                .map_err(|mpsc::error::SendError((_, payload))| mpsc::error::SendError(payload))
         } else {
             Err(mpsc::error::SendError(payload))
         }
@@ -127,7 +168,7 @@ impl PexDiscoverySender {
 pub(super) struct PexController {
     contacts: Arc<BlockingMutex<ContactSet>>,
     enabled_tx: watch::Sender<bool>,
-    discovery_tx: mpsc::Sender<PexPayload>,
+    discovery_tx: mpsc::Sender<(PublicRuntimeId, PexPayload)>,
     // Notified when the global peer set changes.
     peer_rx: uninitialized_watch::Receiver<()>,
     // Notified when a new link is created in this group.
@@ -137,7 +178,7 @@ pub(super) struct PexController {
 impl PexController {
     pub fn new(
         peer_rx: uninitialized_watch::Receiver<()>,
-        discovery_tx: mpsc::Sender<PexPayload>,
+        discovery_tx: mpsc::Sender<(PublicRuntimeId, PexPayload)>,
     ) -> Self {
         // PEX is disabled initially.
         let (enabled_tx, _) = watch::channel(false);
@@ -157,12 +198,14 @@ impl PexController {
         &self,
         peer_id: PublicRuntimeId,
         connections: LiveConnectionInfoSet,
+        config: PexConfig,
     ) -> PexAnnouncer {
         self.contacts.lock().unwrap().insert(peer_id, connections);
         self.link_tx.send(()).ok();
 
         PexAnnouncer {
             peer_id,
+            config,
             contacts: self.contacts.clone(),
             enabled_rx: self.enabled_tx.subscribe(),
             peer_rx: self.peer_rx.clone(),
@@ -170,11 +213,13 @@ impl PexController {
         }
     }
 
-    /// Create a sender to forward received `PexPayload` messages to `PexDiscovery`.
-    pub fn discovery_sender(&self) -> PexDiscoverySender {
+    /// Create a sender to forward `PexPayload` messages received from the given peer to
+    /// `PexDiscovery`.
+    pub fn discovery_sender(&self, peer_id: PublicRuntimeId) -> PexDiscoverySender {
         PexDiscoverySender {
             inner_tx: self.discovery_tx.clone(),
             enabled_rx: self.enabled_tx.subscribe(),
+            peer_id,
         }
     }
 
@@ -196,6 +241,7 @@ impl PexController {
 /// Utility to announce known contacts to a specific peer.
 pub(super) struct PexAnnouncer {
     peer_id: PublicRuntimeId,
+    config: PexConfig,
     contacts: Arc<BlockingMutex<ContactSet>>,
     enabled_rx: watch::Receiver<bool>,
     peer_rx: uninitialized_watch::Receiver<()>,
@@ -206,7 +252,7 @@ impl PexAnnouncer {
     /// Periodically announces known peer contacts to the bound peer. Runs until the `content_tx`
     /// channel gets closed.
     pub async fn run(&mut self, content_tx: mpsc::Sender<Content>) {
-        let mut recent_filter = RecentFilter::new(CONTACT_EXPIRY);
+        let mut recent_filter = RecentFilter::new(self.config.contact_expiry);
         let mut rng = StdRng::from_entropy();
 
         let rx = stream::select(self.peer_rx.as_stream(), self.link_rx.as_stream())
@@ -251,12 +297,13 @@ impl PexAnnouncer {
                 continue;
             }
 
-            let contacts = if contacts.len() <= MAX_CONTACTS_PER_MESSAGE {
+            let max_contacts = self.config.max_contacts_per_message;
+            let contacts = if contacts.len() <= max_contacts {
                 contacts
             } else {
                 contacts
                     .into_iter()
-                    .choose_multiple(&mut rng, MAX_CONTACTS_PER_MESSAGE)
+                    .choose_multiple(&mut rng, max_contacts)
                     .into_iter()
                     .collect()
             };
@@ -360,6 +407,20 @@ mod tests {
     use std::net::Ipv4Addr;
     use tokio::time;
 
+    #[test]
+    fn config_clamping() {
+        let config = PexConfig::new(Duration::ZERO, 0);
+        assert_eq!(config.contact_expiry, MESSAGE_DELAY);
+        assert_eq!(config.max_contacts_per_message, 1);
+
+        let config = PexConfig::new(Duration::from_secs(3600), usize::MAX);
+        assert_eq!(config.contact_expiry, Duration::from_secs(3600));
+        assert_eq!(
+            config.max_contacts_per_message,
+            MAX_CONTACTS_PER_MESSAGE_LIMIT
+        );
+    }
+
     #[tokio::test(flavor = "current_thread", start_paused = true)]
     async fn recent_filter() {
         let mut filter = RecentFilter::new(Duration::from_millis(1000));
@@ -1,5 +1,9 @@
-use super::{peer_addr::PeerAddr, peer_source::PeerSource, peer_state::PeerState};
+use super::{
+    peer_addr::PeerAddr, peer_source::PeerSource, peer_state::PeerState,
+    runtime_id::PublicRuntimeId,
+};
 use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::time::Duration;
 
 /// Information about a peer.
 #[derive(Eq, PartialEq, Ord, PartialOrd, Serialize, Deserialize)]
@@ -8,14 +12,32 @@ pub struct PeerInfo {
     pub addr: PeerAddr,
     pub source: PeerSource,
     pub state: PeerState,
+    /// How long has the peer been active. `None` if not active. Not serialized to keep the wire
+    /// format compatible with the existing clients.
+    #[serde(skip)]
+    pub uptime: Option<Duration>,
+    /// The peer that told us about this one via peer exchange, if the source is
+    /// [`PeerSource::PeerExchange`]. A separate field instead of being part of the source because
+    /// the sources are serialized as plain numbers. Serialized last so the existing clients that
+    /// don't know about it can ignore it.
+    #[serde(default)]
+    pub introduced_by: Option<PublicRuntimeId>,
 }
 
 impl PeerInfo {
-    pub(super) fn new(addr: PeerAddr, source: PeerSource, state: PeerState) -> Self {
+    pub(super) fn new(
+        addr: PeerAddr,
+        source: PeerSource,
+        state: PeerState,
+        uptime: Option<Duration>,
+        introduced_by: Option<PublicRuntimeId>,
+    ) -> Self {
         Self {
             addr,
             source,
             state,
+            uptime,
+            introduced_by,
         }
     }
 }
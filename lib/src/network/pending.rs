@@ -8,7 +8,7 @@ use crate::{
     crypto::{sign::PublicKey, CacheHash, Hash, Hashable},
     protocol::{Block, BlockId, InnerNodes, LeafNodes, MultiBlockPresence, UntrustedProof},
     repository::RepositoryMonitor,
-    sync::delay_map::DelayMap,
+    sync::{delay_map::DelayMap, resizable_semaphore},
 };
 use deadlock::BlockingMutex;
 use std::{future, sync::Arc, task::ready};
@@ -110,6 +110,8 @@ impl PendingRequests {
         pending_request: PendingRequest,
         link_permit: OwnedSemaphorePermit,
         peer_permit: OwnedSemaphorePermit,
+        block_permit: Option<resizable_semaphore::Permit>,
+        greedy_permit: Option<resizable_semaphore::Permit>,
     ) -> Option<Request> {
         let (key, block_promise, request) = match pending_request {
             PendingRequest::RootNode(public_key, debug) => (
@@ -142,6 +144,8 @@ impl PendingRequests {
                 block_promise,
                 link_permit,
                 _peer_permit: peer_permit,
+                _block_permit: block_permit,
+                _greedy_permit: greedy_permit,
             },
             REQUEST_TIMEOUT,
         );
@@ -160,6 +164,16 @@ impl PendingRequests {
         Some(request)
     }
 
+    /// Number of index (root node and child nodes) requests that haven't been responded to yet.
+    pub fn index_requests(&self) -> usize {
+        self.map
+            .lock()
+            .unwrap()
+            .keys()
+            .filter(|key| matches!(key, Key::RootNode(_) | Key::ChildNodes(..)))
+            .count()
+    }
+
     pub fn remove(&self, response: Response) -> PendingResponse {
         let response = ProcessedResponse::from(response);
         let key = response.to_key();
@@ -173,8 +187,8 @@ impl PendingRequests {
                 .request_latency
                 .record(request_data.timestamp.elapsed());
 
-            // We `drop` the `peer_permit` here but the `Client` will need the `client_permit` and
-            // only `drop` it once the request is processed.
+            // We `drop` the `peer_permit` and `block_permit` here but the `Client` will need the
+            // `client_permit` and only `drop` it once the request is processed.
             let link_permit = Some(ClientPermit(request_data.link_permit, self.monitor.clone()));
             let block_promise = request_data.block_promise;
 
@@ -196,12 +210,13 @@ fn request_added(monitor: &RepositoryMonitor, key: &Key) {
 
     match key {
         Key::RootNode(_) | Key::ChildNodes { .. } => {
-            monitor.index_requests_sent.increment(1);
             monitor.index_requests_inflight.increment(1.0);
+            monitor.request_inflight_added();
         }
         Key::Block(_) => {
             monitor.block_requests_sent.increment(1);
             monitor.block_requests_inflight.increment(1.0);
+            monitor.request_inflight_added();
         }
         Key::BlockOffer(_) => (),
     }
@@ -209,8 +224,14 @@ fn request_added(monitor: &RepositoryMonitor, key: &Key) {
 
 fn request_removed(monitor: &RepositoryMonitor, key: &Key) {
     match key {
-        Key::RootNode(_) | Key::ChildNodes { .. } => monitor.index_requests_inflight.decrement(1.0),
-        Key::Block(_) => monitor.block_requests_inflight.decrement(1.0),
+        Key::RootNode(_) | Key::ChildNodes { .. } => {
+            monitor.index_requests_inflight.decrement(1.0);
+            monitor.request_inflight_removed();
+        }
+        Key::Block(_) => {
+            monitor.block_requests_inflight.decrement(1.0);
+            monitor.request_inflight_removed();
+        }
         Key::BlockOffer(_) => (),
     }
 }
@@ -244,6 +265,8 @@ struct RequestData {
     block_promise: Option<BlockPromise>,
     link_permit: OwnedSemaphorePermit,
     _peer_permit: OwnedSemaphorePermit,
+    _block_permit: Option<resizable_semaphore::Permit>,
+    _greedy_permit: Option<resizable_semaphore::Permit>,
 }
 
 pub(super) struct ClientPermit(OwnedSemaphorePermit, Arc<RepositoryMonitor>);
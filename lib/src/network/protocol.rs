@@ -4,7 +4,9 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 // First string in a handshake, helps with weeding out connections with completely different
 // protocols on the other end.
 pub(super) const MAGIC: &[u8; 7] = b"OUISYNC";
-pub(super) const VERSION: Version = Version(12);
+// Bumped to 14 so replicas that can't read directories with an ACL (`VERSION_WITH_ACL`) refuse to
+// sync with replicas that can write them.
+pub(super) const VERSION: Version = Version(14);
 
 /// Protocol version
 #[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Debug)]
@@ -2,7 +2,7 @@ use crate::crypto::{
     sign::{Keypair, PublicKey, Signature},
     Digest, Hashable,
 };
-use rand::{rngs::OsRng, Rng};
+use rand::{rngs::OsRng, CryptoRng, Rng};
 use serde::{Deserialize, Serialize};
 use std::io;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -22,6 +22,12 @@ impl SecretRuntimeId {
         }
     }
 
+    pub fn generate<R: Rng + CryptoRng>(rng: &mut R) -> Self {
+        Self {
+            keypair: Keypair::generate(rng),
+        }
+    }
+
     pub fn public(&self) -> PublicRuntimeId {
         PublicRuntimeId {
             public: self.keypair.public_key(),
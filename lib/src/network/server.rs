@@ -374,7 +374,10 @@ fn events(rx: broadcast::Receiver<event::Event>) -> impl Stream<Item = Event> {
                     event::Payload::BlockReceived(block_id) => {
                         return Some((Event::BlockReceived(block_id), rx))
                     }
-                    event::Payload::MaintenanceCompleted => continue,
+                    event::Payload::BranchCreated(_)
+                    | event::Payload::BranchRemoved(_)
+                    | event::Payload::MaintenanceCompleted
+                    | event::Payload::QuotaExceeded { .. } => continue,
                 },
                 Err(RecvError::Lagged(_)) => return Some((Event::Unknown, rx)),
                 Err(RecvError::Closed) => return None,
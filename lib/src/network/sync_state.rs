@@ -0,0 +1,179 @@
+use super::pending::PendingRequests;
+use crate::{
+    block_tracker::TrackerClient, collections::HashMap, crypto::sign::PublicKey,
+    version_vector::VersionVector,
+};
+use deadlock::BlockingMutex;
+use std::{
+    cmp::Ordering,
+    sync::{Arc, Weak},
+};
+
+/// How far a repository is synced with a single peer (see
+/// [`Registration::peer_sync_states`](super::Registration::peer_sync_states)).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum SyncState {
+    /// We have everything the peer has and the peer has everything we have (as far as we know).
+    InSync,
+    /// We are still pulling from the peer: some of its snapshots are still being downloaded or
+    /// some of the blocks it offered are still missing.
+    Behind {
+        /// Number of blocks offered by the peer that are missing and are going to be requested
+        /// from it (or from another peer that offered them too). Can be zero while the index is
+        /// still being downloaded.
+        missing_blocks: u64,
+    },
+    /// We have snapshots the peer hasn't received yet.
+    Ahead,
+}
+
+/// Sync progress of a single link (repository + peer). Shared between the link's `Client` and
+/// its `MessageBroker` so it can be inspected from outside of the link.
+#[derive(Default)]
+pub(super) struct LinkSyncState {
+    inner: BlockingMutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // Latest version vectors of the branches advertised by the peer.
+    remote_versions: HashMap<PublicKey, VersionVector>,
+    // Pending requests and block offers of the currently running client, if any.
+    pending_requests: Weak<PendingRequests>,
+    block_tracker: Weak<TrackerClient>,
+}
+
+impl LinkSyncState {
+    /// Attaches the state of a newly created client, replacing the previous one.
+    pub fn attach(
+        &self,
+        pending_requests: &Arc<PendingRequests>,
+        block_tracker: &Arc<TrackerClient>,
+    ) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.pending_requests = Arc::downgrade(pending_requests);
+        inner.block_tracker = Arc::downgrade(block_tracker);
+    }
+
+    /// Records the version of a branch advertised by the peer.
+    pub fn record_remote_version(&self, writer_id: PublicKey, version_vector: &VersionVector) {
+        self.inner
+            .lock()
+            .unwrap()
+            .remote_versions
+            .entry(writer_id)
+            .or_default()
+            .merge(version_vector);
+    }
+
+    /// Determines the sync state by comparing the given versions of the local branches with the
+    /// versions advertised by the peer and by checking the outstanding requests.
+    ///
+    /// The versions are compared merged across all the branches, so a branch that is outdated
+    /// (and so possibly pruned or forgotten on one side) doesn't affect the result.
+    pub fn get(&self, local_versions: &HashMap<PublicKey, VersionVector>) -> SyncState {
+        let local = merge_all(local_versions.values());
+
+        let (pending_requests, block_tracker, behind, ahead) = {
+            let inner = self.inner.lock().unwrap();
+            let remote = merge_all(inner.remote_versions.values());
+
+            let behind = has_changes(&remote, &local);
+            let ahead = has_changes(&local, &remote);
+
+            (
+                inner.pending_requests.upgrade(),
+                inner.block_tracker.upgrade(),
+                behind,
+                ahead,
+            )
+        };
+
+        let index_requests = pending_requests
+            .map(|pending_requests| pending_requests.index_requests())
+            .unwrap_or(0);
+        let missing_blocks = block_tracker
+            .map(|block_tracker| block_tracker.missing_count())
+            .unwrap_or(0);
+
+        if behind || index_requests > 0 || missing_blocks > 0 {
+            SyncState::Behind {
+                missing_blocks: missing_blocks as u64,
+            }
+        } else if ahead {
+            SyncState::Ahead
+        } else {
+            SyncState::InSync
+        }
+    }
+}
+
+fn merge_all<'a>(versions: impl IntoIterator<Item = &'a VersionVector>) -> VersionVector {
+    versions
+        .into_iter()
+        .fold(VersionVector::new(), |vv, version| vv.merged(version))
+}
+
+// Whether `a` contains any changes that `b` doesn't.
+fn has_changes(a: &VersionVector, b: &VersionVector) -> bool {
+    !matches!(a.partial_cmp(b), Some(Ordering::Less | Ordering::Equal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compare_versions() {
+        let a = PublicKey::random();
+        let b = PublicKey::random();
+
+        let state = LinkSyncState::default();
+
+        let local: HashMap<_, _> = [(a, VersionVector::first(a))].into_iter().collect();
+        assert_eq!(state.get(&local), SyncState::Ahead);
+
+        state.record_remote_version(a, &VersionVector::first(a));
+        assert_eq!(state.get(&local), SyncState::InSync);
+
+        state.record_remote_version(b, &VersionVector::first(b));
+        assert_eq!(state.get(&local), SyncState::Behind { missing_blocks: 0 });
+
+        let local: HashMap<_, _> = [
+            (a, VersionVector::first(a).incremented(a)),
+            (b, VersionVector::first(b)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(state.get(&local), SyncState::Ahead);
+    }
+
+    #[test]
+    fn ignore_outdated_branches() {
+        let a = PublicKey::random();
+        let b = PublicKey::random();
+
+        let state = LinkSyncState::default();
+
+        // The peer still has the branch `b` which we've already merged into `a` and pruned.
+        state.record_remote_version(a, &VersionVector::first(a).merged(&VersionVector::first(b)));
+        state.record_remote_version(b, &VersionVector::first(b));
+
+        let local: HashMap<_, _> = [(a, VersionVector::first(a).merged(&VersionVector::first(b)))]
+            .into_iter()
+            .collect();
+        assert_eq!(state.get(&local), SyncState::InSync);
+
+        // And the other way around.
+        let state = LinkSyncState::default();
+        state.record_remote_version(a, &VersionVector::first(a).merged(&VersionVector::first(b)));
+
+        let local: HashMap<_, _> = [
+            (a, VersionVector::first(a).merged(&VersionVector::first(b))),
+            (b, VersionVector::first(b)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(state.get(&local), SyncState::InSync);
+    }
+}
@@ -3,6 +3,8 @@ use super::{
     client::Client,
     constants::MAX_REQUESTS_IN_FLIGHT,
     message::{Content, Request, Response},
+    options::NetworkOptions,
+    runtime_id::SecretRuntimeId,
     server::Server,
 };
 use crate::{
@@ -15,7 +17,7 @@ use crate::{
         Block, BlockId, Bump, RootNode, SingleBlockPresence,
     },
     repository::{BlockRequestMode, RepositoryId, RepositoryMonitor, Vault},
-    store::Changeset,
+    store::{Changeset, Store},
     test_utils,
     version_vector::VersionVector,
 };
@@ -392,6 +394,21 @@ async fn failed_block_other_peer() {
     }
 }
 
+#[test]
+fn info_hash_namespace() {
+    let id = RepositoryId::random();
+    let namespace_a: [u8; 32] = rand::random();
+    let namespace_b: [u8; 32] = rand::random();
+
+    let none = super::repository_info_hash(&id, None);
+    let a = super::repository_info_hash(&id, Some(&namespace_a));
+    let b = super::repository_info_hash(&id, Some(&namespace_b));
+
+    assert_eq!(a, super::repository_info_hash(&id, Some(&namespace_a)));
+    assert_ne!(a, none);
+    assert_ne!(a, b);
+}
+
 async fn create_repository<R: Rng + CryptoRng>(
     rng: &mut R,
     write_keys: &Keypair,
@@ -404,7 +421,7 @@ async fn create_repository<R: Rng + CryptoRng>(
     let state = Vault::new(
         repository_id,
         event_tx,
-        db,
+        Store::new(db),
         BlockRequestMode::Greedy,
         RepositoryMonitor::new(StateMonitor::make_root(), &NoopRecorder),
     );
@@ -616,9 +633,12 @@ fn create_client(repo: Vault) -> ClientData {
     let (recv_tx, recv_rx) = mpsc::channel(CAPACITY);
     let client = Client::new(
         repo,
+        SecretRuntimeId::random().public(),
         send_tx,
         recv_rx,
         Arc::new(Semaphore::new(MAX_REQUESTS_IN_FLIGHT)),
+        NetworkOptions::default().index_request_batch_window,
+        Arc::default(),
     );
 
     (client, send_rx, recv_tx)
@@ -632,11 +652,14 @@ struct Connection<'a, T> {
 
 impl<T> Connection<'_, T>
 where
-    T: From<Content> + fmt::Debug,
+    Vec<T>: From<Content>,
+    T: fmt::Debug,
 {
     async fn run(&mut self) {
         while let Some(content) = self.send_rx.recv().await {
-            self.recv_tx.send(content.into()).await.unwrap();
+            for item in Vec::<T>::from(content) {
+                self.recv_tx.send(item).await.unwrap();
+            }
         }
     }
 }
@@ -1,6 +1,11 @@
 //! Utilities for working with filesystem paths.
 
-use camino::Utf8Path;
+use crate::error::{Error, Result};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+
+/// Default maximum nesting depth of directories in a repository (see
+/// [`RepositoryParams::with_max_path_depth`](crate::RepositoryParams::with_max_path_depth)).
+pub const DEFAULT_MAX_DEPTH: usize = 256;
 
 /// Decomposes `path` into parent and filename. Returns `None` if `path` doesn't have parent
 /// (it's the root).
@@ -10,3 +15,28 @@ pub fn decompose(path: &Utf8Path) -> Option<(&Utf8Path, &str)> {
         _ => None,
     }
 }
+
+/// Removes the root and the current dir (`.`) components of `path`. They don't affect which
+/// directory of the repository the path refers to, so the normalized paths of the same directory
+/// are equal.
+pub(crate) fn normalize(path: &Utf8Path) -> Utf8PathBuf {
+    path.components()
+        .filter(|component| !matches!(component, Utf8Component::RootDir | Utf8Component::CurDir))
+        .collect()
+}
+
+/// Number of named components of `path`. The root has depth zero.
+pub fn depth(path: &Utf8Path) -> usize {
+    path.components()
+        .filter(|component| matches!(component, Utf8Component::Normal(_)))
+        .count()
+}
+
+/// Returns `Error::PathTooDeep` if the depth of the directory `path` exceeds `max_depth`.
+pub(crate) fn check_depth(path: &Utf8Path, max_depth: usize) -> Result<()> {
+    if depth(path) > max_depth {
+        Err(Error::PathTooDeep)
+    } else {
+        Ok(())
+    }
+}
@@ -18,7 +18,8 @@ pub const BLOCK_SIZE: usize = 32 * 1024;
 pub(crate) const BLOCK_RECORD_SIZE: u64 =
     BLOCK_SIZE as u64 + BlockId::SIZE as u64 + BLOCK_NONCE_SIZE as u64;
 
-pub(crate) const BLOCK_NONCE_SIZE: usize = 32;
+/// Block nonce size in bytes.
+pub const BLOCK_NONCE_SIZE: usize = 32;
 pub(crate) type BlockNonce = [u8; BLOCK_NONCE_SIZE];
 
 /// Unique id of a block.
@@ -86,6 +87,46 @@ impl Block {
     }
 }
 
+/// Block in its stored (encrypted) form together with its nonce. Can be used to transfer blocks
+/// between replicas of the same repository outside of the sync protocol (see
+/// [`Repository::read_raw_block`](crate::Repository::read_raw_block) and
+/// [`Repository::receive_raw_block`](crate::Repository::receive_raw_block)).
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RawBlock {
+    content: BlockContent,
+    nonce: BlockNonce,
+}
+
+impl RawBlock {
+    /// Id of this block. Note this is computed from the content and the nonce.
+    pub fn id(&self) -> BlockId {
+        BlockId::new(&self.content, &self.nonce)
+    }
+
+    /// Converts this into `Block`. Returns `None` if the content doesn't have the right size
+    /// which can happen if it comes from an untrusted source.
+    pub(crate) fn into_block(self) -> Option<Block> {
+        (self.content.len() == BLOCK_SIZE).then(|| Block::new(self.content, self.nonce))
+    }
+}
+
+impl From<Block> for RawBlock {
+    fn from(block: Block) -> Self {
+        Self {
+            content: block.content,
+            nonce: block.nonce,
+        }
+    }
+}
+
+impl fmt::Debug for RawBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawBlock")
+            .field("id", &self.id())
+            .finish_non_exhaustive()
+    }
+}
+
 impl Distribution<Block> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Block {
         Block::new(rng.gen(), rng.gen())
@@ -1,6 +1,7 @@
 use crate::{
     blob::BlobId,
     crypto::{cipher::SecretKey, Digest, Hash, Hashable},
+    error::{Error, Result},
 };
 
 /// A type of block identifier similar to `BlockId` but serving a different purpose. While
@@ -52,18 +53,19 @@ impl Locator {
         (self.block..).map(move |block| Self { blob, block })
     }
 
-    pub fn next(&self) -> Self {
+    /// Locator of the block following this one. Fails with `Error::FileTooLarge` if the block
+    /// number would exceed the maximum.
+    pub fn next(&self) -> Result<Self> {
         self.nth(1)
     }
 
-    pub fn nth(&self, n: u32) -> Self {
-        Self {
+    /// Locator of the `n`-th block after this one. Fails with `Error::FileTooLarge` if the block
+    /// number would exceed the maximum.
+    pub fn nth(&self, n: u32) -> Result<Self> {
+        Ok(Self {
             blob: self.blob,
-            block: self
-                .block
-                .checked_add(n)
-                .expect("locator sequence limit exceeded"),
-        }
+            block: self.block.checked_add(n).ok_or(Error::FileTooLarge)?,
+        })
     }
 }
 
@@ -73,3 +75,20 @@ impl Hashable for Locator {
         self.block.update_hash(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+
+    #[test]
+    fn sequence_limit() {
+        let last = Locator::head(rand::random()).nth(u32::MAX).unwrap();
+        assert_eq!(last.number(), u32::MAX);
+        assert_matches!(last.next(), Err(Error::FileTooLarge));
+        assert_matches!(
+            Locator::head(*last.blob_id()).nth(1).unwrap().nth(u32::MAX),
+            Err(Error::FileTooLarge)
+        );
+    }
+}
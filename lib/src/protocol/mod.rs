@@ -13,10 +13,10 @@ mod summary;
 #[cfg(test)]
 pub(crate) mod test_utils;
 
-pub use self::block::BLOCK_SIZE;
+pub use self::block::{BlockId, RawBlock, BLOCK_NONCE_SIZE, BLOCK_SIZE};
 
 pub(crate) use self::{
-    block::{Block, BlockContent, BlockId, BlockNonce, BLOCK_RECORD_SIZE},
+    block::{Block, BlockContent, BlockNonce, BLOCK_RECORD_SIZE},
     bump::Bump,
     inner_node::{get_bucket, InnerNode, InnerNodes, EMPTY_INNER_HASH, INNER_LAYER_COUNT},
     leaf_node::{LeafNode, LeafNodes, EMPTY_LEAF_HASH},
@@ -25,6 +25,3 @@ pub(crate) use self::{
     root_node::{RootNode, RootNodeFilter, RootNodeKind},
     summary::{MultiBlockPresence, NodeState, SingleBlockPresence, Summary},
 };
-
-#[cfg(test)]
-pub(crate) use self::block::BLOCK_NONCE_SIZE;
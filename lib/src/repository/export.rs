@@ -0,0 +1,248 @@
+//! Export of the repository content into a tar archive.
+
+use crate::{
+    crypto::sign::PublicKey,
+    error::{Error, Result},
+    joint_directory::{JointDirectory, JointEntryRef},
+    path,
+};
+use camino::Utf8PathBuf;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+const BLOCK_LEN: usize = 512;
+const NAME_LEN: usize = 100;
+
+// Name of the pseudo-entry of the GNU extension for names longer than `NAME_LEN`.
+const LONG_NAME: &[u8] = b"././@LongLink";
+
+const FILE_MODE: u32 = 0o644;
+const DIRECTORY_MODE: u32 = 0o755;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    File,
+    Directory,
+    LongName,
+}
+
+impl Kind {
+    fn typeflag(self) -> u8 {
+        match self {
+            Self::File => b'0',
+            Self::Directory => b'5',
+            Self::LongName => b'L',
+        }
+    }
+}
+
+/// Writes the whole content of `root` into `writer` as a tar archive. The paths in the archive are
+/// relative to `root`. Files with multiple concurrent versions are exported only once, preferring
+/// the version from the local branch. Fails with `Error::PathTooDeep` if the depth of any
+/// directory relative to `root` exceeds `max_depth`.
+///
+/// Note: ouisync doesn't store modification times or permissions, so all entries get zero mtime
+/// and default permissions.
+pub(super) async fn export_tar<W>(
+    root: JointDirectory,
+    local_branch_id: Option<&PublicKey>,
+    max_depth: usize,
+    writer: &mut W,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut stack = vec![(Utf8PathBuf::new(), root)];
+
+    while let Some((path, dir)) = stack.pop() {
+        for entry in select_entries(&dir, local_branch_id) {
+            let entry_path = path.join(entry.name());
+
+            match entry {
+                JointEntryRef::File(entry) => {
+                    let mut file = entry.open().await?;
+
+                    write_header(writer, entry_path.as_str(), Kind::File, file.len()).await?;
+                    // Streams the content block by block.
+                    file.copy_to_writer(writer).await?;
+                    write_padding(writer, file.len()).await?;
+                }
+                JointEntryRef::Directory(entry) => {
+                    path::check_depth(&entry_path, max_depth)?;
+                    write_header(writer, &format!("{entry_path}/"), Kind::Directory, 0).await?;
+
+                    stack.push((entry_path, entry.open().await?));
+                }
+            }
+        }
+    }
+
+    // End of archive marker.
+    writer
+        .write_all(&[0; 2 * BLOCK_LEN])
+        .await
+        .map_err(Error::Writer)?;
+    writer.flush().await.map_err(Error::Writer)?;
+
+    Ok(())
+}
+
+// Selects one entry per name. Directories are preferred over files (concurrent directory versions
+// are already merged) and the local file version is preferred over the remote ones.
+fn select_entries<'a>(
+    dir: &'a JointDirectory,
+    local_branch_id: Option<&PublicKey>,
+) -> Vec<JointEntryRef<'a>> {
+    let mut selected: Vec<JointEntryRef<'a>> = Vec::new();
+
+    // Entries with the same name are always adjacent.
+    for entry in dir.entries() {
+        match selected.last_mut() {
+            Some(last) if last.name() == entry.name() => {
+                if rank(&entry, local_branch_id) > rank(last, local_branch_id) {
+                    *last = entry;
+                }
+            }
+            _ => selected.push(entry),
+        }
+    }
+
+    selected
+}
+
+fn rank(entry: &JointEntryRef, local_branch_id: Option<&PublicKey>) -> u8 {
+    match entry {
+        JointEntryRef::Directory(_) => 2,
+        JointEntryRef::File(entry) if Some(entry.branch().id()) == local_branch_id => 1,
+        JointEntryRef::File(_) => 0,
+    }
+}
+
+async fn write_header<W>(writer: &mut W, path: &str, kind: Kind, size: u64) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let name = path.as_bytes();
+
+    let name = if name.len() > NAME_LEN {
+        let mut data = name.to_vec();
+        data.push(0);
+
+        write_block(
+            writer,
+            &build_header(LONG_NAME, Kind::LongName, data.len() as u64),
+        )
+        .await?;
+        writer.write_all(&data).await.map_err(Error::Writer)?;
+        write_padding(writer, data.len() as u64).await?;
+
+        &name[..NAME_LEN]
+    } else {
+        name
+    };
+
+    write_block(writer, &build_header(name, kind, size)).await
+}
+
+async fn write_block<W>(writer: &mut W, block: &[u8; BLOCK_LEN]) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(block).await.map_err(Error::Writer)
+}
+
+// Pads the data of the given size to the whole number of blocks.
+async fn write_padding<W>(writer: &mut W, size: u64) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let rem = (size % BLOCK_LEN as u64) as usize;
+
+    if rem > 0 {
+        writer
+            .write_all(&[0; BLOCK_LEN][rem..])
+            .await
+            .map_err(Error::Writer)?;
+    }
+
+    Ok(())
+}
+
+// Builds ustar header.
+fn build_header(name: &[u8], kind: Kind, size: u64) -> [u8; BLOCK_LEN] {
+    let mut header = [0; BLOCK_LEN];
+
+    let mode = match kind {
+        Kind::Directory => DIRECTORY_MODE,
+        Kind::File | Kind::LongName => FILE_MODE,
+    };
+
+    header[..name.len()].copy_from_slice(name);
+    write_octal(&mut header[100..108], mode.into()); // mode
+    write_octal(&mut header[108..116], 0); // uid
+    write_octal(&mut header[116..124], 0); // gid
+    write_size(&mut header[124..136], size);
+    write_octal(&mut header[136..148], 0); // mtime
+    header[156] = kind.typeflag();
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // The checksum is computed with the checksum field itself filled with spaces.
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|byte| u32::from(*byte)).sum();
+    write_octal(&mut header[148..155], checksum.into());
+
+    header
+}
+
+// Writes `value` as zero-padded, nul-terminated octal number.
+fn write_octal(dst: &mut [u8], value: u64) {
+    let digits = dst.len() - 1;
+    let s = format!("{value:0digits$o}");
+    dst[..digits].copy_from_slice(s.as_bytes());
+    dst[digits] = 0;
+}
+
+// Writes the size in octal if it fits, otherwise in the base-256 encoding (GNU extension).
+fn write_size(dst: &mut [u8], size: u64) {
+    if size < 8u64.pow(dst.len() as u32 - 1) {
+        write_octal(dst, size);
+    } else {
+        dst.fill(0);
+        let len = dst.len();
+        dst[len - 8..].copy_from_slice(&size.to_be_bytes());
+        dst[0] = 0x80;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_checksum() {
+        let header = build_header(b"foo.txt", Kind::File, 5);
+
+        let expected: u32 = header[..148]
+            .iter()
+            .chain(&[b' '; 8])
+            .chain(&header[156..])
+            .map(|byte| u32::from(*byte))
+            .sum();
+        let actual = std::str::from_utf8(&header[148..154]).unwrap();
+
+        assert_eq!(u32::from_str_radix(actual, 8).unwrap(), expected);
+        assert_eq!(&header[154..156], b"\0 ");
+    }
+
+    #[test]
+    fn size_encoding() {
+        let mut dst = [0; 12];
+
+        write_size(&mut dst, 0o777);
+        assert_eq!(&dst, b"00000000777\0");
+
+        write_size(&mut dst, 1 << 40);
+        assert_eq!(dst[0], 0x80);
+        assert_eq!(&dst[4..], &(1u64 << 40).to_be_bytes());
+    }
+}
@@ -0,0 +1,190 @@
+//! Import of a directory from the host filesystem into the repository.
+
+use super::Repository;
+use crate::{
+    crypto::Hash,
+    error::{Error, Result},
+    file::{ContentHasher, File},
+    progress::Progress,
+    protocol::BLOCK_SIZE,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+use tokio::{fs, io::AsyncReadExt};
+
+/// Recursively imports the content of the host directory `src` into the repository directory
+/// `dst`. Files whose content is already identical (compared by the content hash) are skipped,
+/// so an interrupted import can be resumed by simply running it again. `on_progress` is called
+/// with the number of bytes processed so far, out of the total size of the source files.
+pub(super) async fn import_dir<F>(
+    repo: &Repository,
+    src: &Path,
+    dst: &Utf8Path,
+    mut on_progress: F,
+) -> Result<()>
+where
+    F: FnMut(Progress),
+{
+    let entries = walk(src).await?;
+
+    let mut progress = Progress {
+        value: 0,
+        total: entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::File { size, .. } => *size,
+                Entry::Directory { .. } => 0,
+            })
+            .sum(),
+    };
+
+    on_progress(progress);
+
+    repo.create_directory(dst).await?;
+
+    for entry in entries {
+        match entry {
+            Entry::Directory { path } => {
+                repo.create_directory(dst.join(path)).await?;
+            }
+            Entry::File {
+                src_path,
+                path,
+                size,
+            } => {
+                import_file(repo, &src_path, &dst.join(path), |value| {
+                    on_progress(Progress {
+                        value: progress.value + value,
+                        total: progress.total,
+                    })
+                })
+                .await?;
+
+                progress.value += size;
+                on_progress(progress);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+enum Entry {
+    // `path` is relative to the import root.
+    Directory {
+        path: Utf8PathBuf,
+    },
+    File {
+        src_path: PathBuf,
+        path: Utf8PathBuf,
+        size: u64,
+    },
+}
+
+// Collects all the files and directories inside `root`. Directories precede their content.
+// Entries that are neither files nor directories (e.g., symlinks) are skipped.
+async fn walk(root: &Path) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    let mut stack = vec![(root.to_owned(), Utf8PathBuf::new())];
+
+    while let Some((src_path, path)) = stack.pop() {
+        let mut read_dir = fs::read_dir(&src_path).await.map_err(Error::Reader)?;
+
+        while let Some(entry) = read_dir.next_entry().await.map_err(Error::Reader)? {
+            let name = entry
+                .file_name()
+                .into_string()
+                .map_err(|_| Error::NonUtf8FileName)?;
+            let path = path.join(name);
+            let file_type = entry.file_type().await.map_err(Error::Reader)?;
+
+            if file_type.is_dir() {
+                entries.push(Entry::Directory { path: path.clone() });
+                stack.push((entry.path(), path));
+            } else if file_type.is_file() {
+                let size = entry.metadata().await.map_err(Error::Reader)?.len();
+                entries.push(Entry::File {
+                    src_path: entry.path(),
+                    path,
+                    size,
+                });
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+async fn import_file<F>(
+    repo: &Repository,
+    src: &Path,
+    dst: &Utf8Path,
+    mut on_progress: F,
+) -> Result<()>
+where
+    F: FnMut(u64),
+{
+    let mut file = match repo.open_file(dst).await {
+        Ok(mut file) => {
+            if file.content_hash().await? == hash_file(src).await? {
+                tracing::trace!(path = %dst, "file unchanged, skipping");
+                return Ok(());
+            }
+
+            file.fork(repo.local_branch()?).await?;
+            file.truncate(0)?;
+            file.seek(SeekFrom::Start(0));
+            file
+        }
+        Err(Error::EntryNotFound) => repo.create_file(dst).await?,
+        Err(error) => return Err(error),
+    };
+
+    copy_file(src, &mut file, &mut on_progress).await?;
+    file.flush().await
+}
+
+async fn copy_file<F>(src: &Path, dst: &mut File, on_progress: &mut F) -> Result<()>
+where
+    F: FnMut(u64),
+{
+    let mut src = fs::File::open(src).await.map_err(Error::Reader)?;
+    let mut buffer = vec![0; BLOCK_SIZE];
+    let mut copied = 0;
+
+    loop {
+        let len = src.read(&mut buffer).await.map_err(Error::Reader)?;
+
+        if len == 0 {
+            break;
+        }
+
+        dst.write_all(&buffer[..len]).await?;
+
+        copied += len as u64;
+        on_progress(copied);
+    }
+
+    Ok(())
+}
+
+async fn hash_file(path: &Path) -> Result<Hash> {
+    let mut file = fs::File::open(path).await.map_err(Error::Reader)?;
+    let mut hasher = ContentHasher::new();
+    let mut buffer = vec![0; BLOCK_SIZE];
+
+    loop {
+        let len = file.read(&mut buffer).await.map_err(Error::Reader)?;
+
+        if len == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..len]);
+    }
+
+    Ok(hasher.finalize())
+}
@@ -1,23 +1,26 @@
+use super::Secrets;
 use crate::{
     access_control::{Access, AccessSecrets, LocalSecret, WriteSecrets},
     crypto::{
-        cipher::{self, Nonce},
-        sign, Hash, Password, PasswordSalt,
+        cipher::{self, Nonce, NONCE_SIZE},
+        sign, Hash, Hashable, KdfParams, Password, PasswordSalt,
     },
     db::{self, DatabaseId},
     device_id::DeviceId,
+    error::{Error, Result},
     repository::RepositoryId,
     store::Error as StoreError,
 };
 use rand::{rngs::OsRng, Rng};
 use sqlx::Row;
-use std::{borrow::Cow, fmt, time::Duration};
+use std::{borrow::Cow, fmt, sync::Arc, time::Duration};
 use tracing::instrument;
 use zeroize::Zeroize;
 
 // Metadata keys
 const REPOSITORY_ID: &[u8] = b"repository_id";
 const PASSWORD_SALT: &[u8] = b"password_salt";
+const KDF_PARAMS: &[u8] = b"kdf_params";
 const WRITER_ID: &[u8] = b"writer_id";
 const READ_KEY: &[u8] = b"read_key";
 const WRITE_KEY: &[u8] = b"write_key";
@@ -36,6 +39,21 @@ const DEPRECATED_ACCESS_KEY: &[u8] = b"access_key"; // read key or write key
 const DEVICE_ID: &[u8] = b"device_id";
 const READ_KEY_VALIDATOR: &[u8] = b"read_key_validator";
 
+// Prefixes of the keys of the additional local unlocks. Each unlock stores its own copy of the
+// access secrets (and the writer id) encrypted with its local key, under the prefix followed by a
+// hash of that key. This way the entries of a given local key can be found directly.
+const UNLOCK_READ_KEY: &[u8] = b"unlock/read_key/";
+const UNLOCK_READ_KEY_VALIDATOR: &[u8] = b"unlock/read_key_validator/";
+const UNLOCK_WRITE_KEY: &[u8] = b"unlock/write_key/";
+const UNLOCK_WRITER_ID: &[u8] = b"unlock/writer_id/";
+
+// Prefix of the keys of the user-defined secret values (see `Metadata::set_secret`), so they can't
+// clash with the internal ones.
+const USER_SECRET: &[u8] = b"user/";
+
+// Counter used to generate the nonces of the secret values (see `make_nonce`).
+const NONCE_COUNTER: &[u8] = b"nonce_counter";
+
 const QUOTA: &[u8] = b"quota";
 const BLOCK_EXPIRATION: &[u8] = b"block_expiration";
 
@@ -47,11 +65,28 @@ const DATA_VERSION: &[u8] = b"data_version";
 // -------------------------------------------------------------------
 pub struct Metadata {
     db: db::Pool,
+    // Secrets of the repository whose read key encrypts the secret values. Looked up on every
+    // access so the access changes (e.g., downgrading to blind) apply to this accessor as well.
+    secrets: Option<Arc<Secrets>>,
 }
 
 impl Metadata {
     pub(crate) fn new(db: db::Pool) -> Self {
-        Self { db }
+        Self { db, secrets: None }
+    }
+
+    pub(super) fn with_secrets(self, secrets: Arc<Secrets>) -> Self {
+        Self {
+            secrets: Some(secrets),
+            ..self
+        }
+    }
+
+    fn read_key(&self) -> Result<&cipher::SecretKey> {
+        self.secrets
+            .as_ref()
+            .and_then(|secrets| secrets.get().read_key())
+            .ok_or(Error::PermissionDenied)
     }
 
     #[instrument(skip(self), fields(value))]
@@ -93,6 +128,47 @@ impl Metadata {
 
         Ok(())
     }
+
+    /// Gets a value previously stored with [`Self::set_secret`]. Fails with `PermissionDenied` if
+    /// the repository is in the blind mode.
+    #[instrument(skip(self), err(Debug))]
+    pub async fn get_secret(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let read_key = self.read_key()?;
+
+        let mut conn = self.db.acquire().await.map_err(StoreError::from)?;
+        let value = get_secret_blob(&mut conn, &user_secret_name(name), read_key).await?;
+
+        Ok(value)
+    }
+
+    /// Stores a value encrypted with the read key of the repository, unlike [`Self::set`] which
+    /// stores it in plaintext. Use this for values that must not be readable on blind replicas.
+    /// The secret values are separate from the plaintext ones, even if they have the same name.
+    /// Fails with `PermissionDenied` if the repository is in the blind mode.
+    #[instrument(skip(self, value), err(Debug))]
+    pub async fn set_secret(&self, name: &str, value: impl AsRef<[u8]>) -> Result<()> {
+        let read_key = self.read_key()?;
+
+        let mut tx = self.db.begin_write().await.map_err(StoreError::from)?;
+        set_secret_blob(&mut tx, &user_secret_name(name), value, read_key).await?;
+        tx.commit().await.map_err(StoreError::from)?;
+
+        Ok(())
+    }
+
+    /// Removes a value previously stored with [`Self::set_secret`].
+    #[instrument(skip(self), err(Debug))]
+    pub async fn remove_secret(&self, name: &str) -> Result<()> {
+        let mut tx = self.db.begin_write().await.map_err(StoreError::from)?;
+        remove_secret_blob(&mut tx, &user_secret_name(name)).await?;
+        tx.commit().await.map_err(StoreError::from)?;
+
+        Ok(())
+    }
+}
+
+fn user_secret_name(name: &str) -> Vec<u8> {
+    [USER_SECRET, name.as_bytes()].concat()
 }
 
 // -------------------------------------------------------------------
@@ -103,9 +179,35 @@ pub(crate) async fn password_to_key(
     password: &Password,
 ) -> Result<cipher::SecretKey, StoreError> {
     let salt = get_or_generate_password_salt(tx).await?;
-    Ok(cipher::SecretKey::derive_from_password(
+    derive_key(tx, password, &salt).await
+}
+
+/// Like `password_to_key` but doesn't store the salt if it doesn't exist yet. A random one is used
+/// instead, which is fine because without the salt no key derived from a password could have been
+/// stored so the resulting key wouldn't unlock anything anyway.
+pub(crate) async fn password_to_key_read_only(
+    conn: &mut db::Connection,
+    password: &Password,
+) -> Result<cipher::SecretKey, StoreError> {
+    let salt = match get_public_blob(conn, PASSWORD_SALT).await? {
+        Some(salt) => salt,
+        None => OsRng.gen(),
+    };
+
+    derive_key(conn, password, &salt).await
+}
+
+async fn derive_key(
+    conn: &mut db::Connection,
+    password: &Password,
+    salt: &PasswordSalt,
+) -> Result<cipher::SecretKey, StoreError> {
+    let params = get_kdf_params(conn).await?;
+
+    Ok(cipher::SecretKey::derive_from_password_with_params(
         password.as_ref(),
-        &salt,
+        salt,
+        params,
     ))
 }
 
@@ -135,6 +237,24 @@ async fn get_or_generate_password_salt(
     Ok(salt)
 }
 
+/// Returns the params of the password key derivation function. Repositories created without
+/// explicit params use the default ones.
+pub(crate) async fn get_kdf_params(conn: &mut db::Connection) -> Result<KdfParams, StoreError> {
+    match get_public_blob(conn, KDF_PARAMS).await? {
+        Some(bytes) => KdfParams::from_bytes(bytes).ok_or(StoreError::MalformedData),
+        None => Ok(KdfParams::default()),
+    }
+}
+
+/// Sets the params of the password key derivation function. This must be done before any key is
+/// derived from a password, because the keys derived with different params won't match.
+pub(crate) async fn set_kdf_params(
+    tx: &mut db::WriteTransaction,
+    params: KdfParams,
+) -> Result<(), StoreError> {
+    set_public_blob(tx, KDF_PARAMS, params.to_bytes()).await
+}
+
 // -------------------------------------------------------------------
 // Database ID
 // -------------------------------------------------------------------
@@ -154,6 +274,12 @@ pub(crate) async fn get_or_generate_database_id(db: &db::Pool) -> Result<Databas
     Ok(database_id)
 }
 
+pub(crate) async fn get_database_id(
+    conn: &mut db::Connection,
+) -> Result<Option<DatabaseId>, StoreError> {
+    get_public_blob(conn, DATABASE_ID).await
+}
+
 // -------------------------------------------------------------------
 // Writer Id
 // -------------------------------------------------------------------
@@ -161,6 +287,13 @@ pub(crate) async fn get_writer_id(
     conn: &mut db::Connection,
     local_key: Option<&cipher::SecretKey>,
 ) -> Result<Option<sign::PublicKey>, StoreError> {
+    if let Some(local_key) = local_key {
+        if has_unlock(conn, local_key).await? {
+            let name = unlock_name(UNLOCK_WRITER_ID, local_key);
+            return get_secret_blob(conn, &name, local_key).await;
+        }
+    }
+
     get_blob(conn, WRITER_ID, local_key).await
 }
 
@@ -169,6 +302,13 @@ pub(crate) async fn set_writer_id(
     writer_id: &sign::PublicKey,
     local_key: Option<&cipher::SecretKey>,
 ) -> Result<(), StoreError> {
+    if let Some(local_key) = local_key {
+        if has_unlock(tx, local_key).await? {
+            let name = unlock_name(UNLOCK_WRITER_ID, local_key);
+            return set_secret_blob(tx, &name, writer_id, local_key).await;
+        }
+    }
+
     set_blob(tx, WRITER_ID, writer_id, local_key).await?;
     Ok(())
 }
@@ -186,6 +326,12 @@ pub(crate) async fn check_device_id(
     Ok(old_device_id.as_ref() == Some(device_id))
 }
 
+pub(crate) async fn get_device_id(
+    conn: &mut db::Connection,
+) -> Result<Option<DeviceId>, StoreError> {
+    get_public_blob(conn, DEVICE_ID).await
+}
+
 pub(crate) async fn set_device_id(
     tx: &mut db::WriteTransaction,
     device_id: &DeviceId,
@@ -194,6 +340,10 @@ pub(crate) async fn set_device_id(
     Ok(())
 }
 
+pub(crate) async fn remove_device_id(tx: &mut db::WriteTransaction) -> Result<(), StoreError> {
+    remove_public(tx, DEVICE_ID).await
+}
+
 // -------------------------------------------------------------------
 // Access secrets
 // -------------------------------------------------------------------
@@ -252,7 +402,8 @@ async fn remove_secret_read_key(tx: &mut db::WriteTransaction) -> Result<(), Sto
 
 pub(crate) async fn remove_read_key(tx: &mut db::WriteTransaction) -> Result<(), StoreError> {
     remove_public_read_key(tx).await?;
-    remove_secret_read_key(tx).await
+    remove_secret_read_key(tx).await?;
+    remove_unlock_read_keys(tx).await
 }
 
 // ------------------------------
@@ -298,7 +449,8 @@ async fn remove_secret_write_key(tx: &mut db::WriteTransaction) -> Result<(), St
 
 pub(crate) async fn remove_write_key(tx: &mut db::WriteTransaction) -> Result<(), StoreError> {
     remove_public_write_key(tx).await?;
-    remove_secret_write_key(tx).await
+    remove_secret_write_key(tx).await?;
+    remove_unlock_write_keys(tx).await
 }
 
 // ------------------------------
@@ -341,6 +493,10 @@ pub(crate) async fn set_access<'a>(
     tx: &mut db::WriteTransaction,
     access: &'a Access,
 ) -> Result<LocalKeys<'a>, StoreError> {
+    // The additional unlocks would otherwise keep granting the previous access.
+    remove_unlock_read_keys(tx).await?;
+    remove_unlock_write_keys(tx).await?;
+
     match access {
         Access::Blind { .. } => {
             remove_public_read_key(tx).await?;
@@ -444,6 +600,12 @@ pub(crate) async fn get_access_secrets(
         return Err(StoreError::MalformedData);
     };
 
+    if let Some(local_key) = local_key {
+        if has_unlock(conn, local_key).await? {
+            return get_unlock_access_secrets(conn, local_key, id).await;
+        }
+    }
+
     match get_write_key(conn, local_key, &id).await {
         Ok(Some(write_keys)) => return Ok(AccessSecrets::Write(WriteSecrets::from(write_keys))),
         Ok(None) => (),
@@ -523,6 +685,168 @@ async fn get_read_key(
     }
 }
 
+// -------------------------------------------------------------------
+// Additional local unlocks
+// -------------------------------------------------------------------
+
+/// Stores a copy of the given secrets encrypted with `local_key`, so the repository can be
+/// unlocked with it too. Replaces any secrets previously stored for the same key.
+pub(crate) async fn add_unlock(
+    tx: &mut db::WriteTransaction,
+    secrets: &AccessSecrets,
+    writer_id: &sign::PublicKey,
+    local_key: &cipher::SecretKey,
+) -> Result<(), StoreError> {
+    remove_unlock(tx, local_key).await?;
+
+    match secrets {
+        AccessSecrets::Blind { .. } => (),
+        AccessSecrets::Read { id, read_key } => {
+            set_unlock_read_key(tx, id, read_key, local_key).await?;
+        }
+        AccessSecrets::Write(secrets) => {
+            set_unlock_read_key(tx, &secrets.id, &secrets.read_key, local_key).await?;
+            set_secret_blob(
+                tx,
+                &unlock_name(UNLOCK_WRITE_KEY, local_key),
+                secrets.write_keys.to_bytes(),
+                local_key,
+            )
+            .await?;
+            set_secret_blob(
+                tx,
+                &unlock_name(UNLOCK_WRITER_ID, local_key),
+                writer_id,
+                local_key,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the secrets stored for the given local key by `add_unlock`. Returns whether there were
+/// any.
+pub(crate) async fn remove_unlock(
+    tx: &mut db::WriteTransaction,
+    local_key: &cipher::SecretKey,
+) -> Result<bool, StoreError> {
+    let mut removed = false;
+
+    for prefix in [
+        UNLOCK_READ_KEY,
+        UNLOCK_READ_KEY_VALIDATOR,
+        UNLOCK_WRITE_KEY,
+        UNLOCK_WRITER_ID,
+    ] {
+        removed |= sqlx::query("DELETE FROM metadata_secret WHERE name = ?")
+            .bind(unlock_name(prefix, local_key))
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            > 0;
+    }
+
+    Ok(removed)
+}
+
+async fn has_unlock(
+    conn: &mut db::Connection,
+    local_key: &cipher::SecretKey,
+) -> Result<bool, StoreError> {
+    let row = sqlx::query("SELECT 0 FROM metadata_secret WHERE name IN (?, ?) LIMIT 1")
+        .bind(unlock_name(UNLOCK_READ_KEY, local_key))
+        .bind(unlock_name(UNLOCK_WRITE_KEY, local_key))
+        .fetch_optional(conn)
+        .await?;
+
+    Ok(row.is_some())
+}
+
+async fn get_unlock_access_secrets(
+    conn: &mut db::Connection,
+    local_key: &cipher::SecretKey,
+    id: RepositoryId,
+) -> Result<AccessSecrets, StoreError> {
+    let write_keys: Option<sign::Keypair> =
+        get_secret_blob(conn, &unlock_name(UNLOCK_WRITE_KEY, local_key), local_key).await?;
+
+    if let Some(write_keys) = write_keys {
+        if RepositoryId::from(write_keys.public_key()) == id {
+            return Ok(AccessSecrets::Write(WriteSecrets::from(write_keys)));
+        }
+    }
+
+    let read_key: Option<cipher::SecretKey> =
+        get_secret_blob(conn, &unlock_name(UNLOCK_READ_KEY, local_key), local_key).await?;
+
+    if let Some(read_key) = read_key {
+        let validator: Option<Hash> = get_secret_blob(
+            conn,
+            &unlock_name(UNLOCK_READ_KEY_VALIDATOR, local_key),
+            &read_key,
+        )
+        .await?;
+
+        if validator == Some(read_key_validator(&id)) {
+            return Ok(AccessSecrets::Read { id, read_key });
+        }
+    }
+
+    Ok(AccessSecrets::Blind { id })
+}
+
+async fn set_unlock_read_key(
+    tx: &mut db::WriteTransaction,
+    id: &RepositoryId,
+    read_key: &cipher::SecretKey,
+    local_key: &cipher::SecretKey,
+) -> Result<(), StoreError> {
+    set_secret_blob(
+        tx,
+        &unlock_name(UNLOCK_READ_KEY, local_key),
+        read_key,
+        local_key,
+    )
+    .await?;
+    set_secret_blob(
+        tx,
+        &unlock_name(UNLOCK_READ_KEY_VALIDATOR, local_key),
+        read_key_validator(id),
+        read_key,
+    )
+    .await
+}
+
+async fn remove_unlock_read_keys(tx: &mut db::WriteTransaction) -> Result<(), StoreError> {
+    remove_secret_by_prefix(tx, UNLOCK_READ_KEY).await?;
+    remove_secret_by_prefix(tx, UNLOCK_READ_KEY_VALIDATOR).await
+}
+
+async fn remove_unlock_write_keys(tx: &mut db::WriteTransaction) -> Result<(), StoreError> {
+    remove_secret_by_prefix(tx, UNLOCK_WRITE_KEY).await?;
+    remove_secret_by_prefix(tx, UNLOCK_WRITER_ID).await
+}
+
+async fn remove_secret_by_prefix(
+    tx: &mut db::WriteTransaction,
+    prefix: &[u8],
+) -> Result<(), StoreError> {
+    sqlx::query("DELETE FROM metadata_secret WHERE substr(name, 1, ?) = ?")
+        .bind(prefix.len() as i64)
+        .bind(prefix)
+        .execute(tx)
+        .await?;
+
+    Ok(())
+}
+
+fn unlock_name(prefix: &[u8], local_key: &cipher::SecretKey) -> Vec<u8> {
+    let hash = (local_key.as_ref(), b"ouisync local unlock".as_slice()).hash();
+    [prefix, hash.as_ref()].concat()
+}
+
 // -------------------------------------------------------------------
 // Storage quota
 // -------------------------------------------------------------------
@@ -776,7 +1100,7 @@ async fn set_secret_blob<T>(
 where
     T: AsRef<[u8]>,
 {
-    let nonce = make_nonce();
+    let nonce = make_nonce(tx).await?;
 
     let mut cypher = blob.as_ref().to_vec();
     local_key.encrypt_no_aead(&nonce, &mut cypher);
@@ -794,11 +1118,35 @@ where
     Ok(())
 }
 
-fn make_nonce() -> Nonce {
-    // Random nonces should be OK given that we're not generating too many of them.
-    // But maybe consider using the mixed approach from this SO post?
-    // https://crypto.stackexchange.com/a/77986
-    rand::random()
+async fn remove_secret_blob(tx: &mut db::WriteTransaction, id: &[u8]) -> Result<(), StoreError> {
+    sqlx::query("DELETE FROM metadata_secret WHERE name = ?")
+        .bind(id)
+        .execute(tx)
+        .await?;
+    Ok(())
+}
+
+// Size of the counter part of the nonce. The rest of the nonce is random.
+const NONCE_COUNTER_SIZE: usize = 8;
+
+// Generates a nonce using the mixed approach from https://crypto.stackexchange.com/a/77986: the
+// first part is a counter persisted in the db, which makes the nonces unique within the db, the
+// rest is random, which protects against reusing a nonce when the counter is rolled back (e.g.,
+// when the db is restored from a backup). The nonce is stored next to the value so the values
+// encrypted with the purely random nonces used before remain readable.
+async fn make_nonce(tx: &mut db::WriteTransaction) -> Result<Nonce, StoreError> {
+    let counter = get_public_blob::<[u8; NONCE_COUNTER_SIZE]>(tx, NONCE_COUNTER)
+        .await?
+        .map(u64::from_be_bytes)
+        .unwrap_or(0);
+    set_public_blob(tx, NONCE_COUNTER, counter.wrapping_add(1).to_be_bytes()).await?;
+
+    let mut nonce = [0; NONCE_SIZE];
+    let (counter_part, random_part) = nonce.split_at_mut(NONCE_COUNTER_SIZE);
+    counter_part.copy_from_slice(&counter.to_be_bytes());
+    OsRng.fill(random_part);
+
+    Ok(nonce)
 }
 
 // String used to validate the read key
@@ -879,6 +1227,68 @@ mod tests {
         assert_eq!(b"world", &v);
     }
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn nonce_uniqueness() {
+        let (_base_dir, pool) = setup().await;
+        let mut tx = pool.begin_write().await.unwrap();
+
+        let key = cipher::SecretKey::random();
+
+        for name in [b"a", b"b", b"c"] {
+            set_secret_blob(&mut tx, name, b"value", &key)
+                .await
+                .unwrap();
+        }
+
+        let nonces: Vec<Vec<u8>> = sqlx::query("SELECT nonce FROM metadata_secret ORDER BY name")
+            .fetch_all(&mut tx)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect();
+
+        let counters: Vec<_> = nonces
+            .iter()
+            .map(|nonce| &nonce[..NONCE_COUNTER_SIZE])
+            .collect();
+        assert_eq!(
+            counters,
+            [
+                0u64.to_be_bytes().as_slice(),
+                1u64.to_be_bytes().as_slice(),
+                2u64.to_be_bytes().as_slice()
+            ]
+        );
+    }
+
+    // Values encrypted with the purely random nonces used previously are still readable.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_random_nonce() {
+        let (_base_dir, pool) = setup().await;
+        let mut tx = pool.begin_write().await.unwrap();
+
+        let key = cipher::SecretKey::random();
+        let nonce: Nonce = rand::random();
+        let mut cypher = b"world".to_vec();
+        key.encrypt_no_aead(&nonce, &mut cypher);
+
+        sqlx::query("INSERT INTO metadata_secret(name, nonce, value) VALUES (?, ?, ?)")
+            .bind(b"hello".as_slice())
+            .bind(&nonce[..])
+            .bind(&cypher)
+            .execute(&mut tx)
+            .await
+            .unwrap();
+
+        let v: [u8; 5] = get_secret_blob(&mut tx, b"hello", &key)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(b"world", &v);
+    }
+
     // Using a bad key should not decrypt properly, but also should not cause an error. This is to
     // let user claim plausible deniability in not knowing the real secret key/password.
     #[tokio::test(flavor = "multi_thread")]
@@ -1,10 +1,15 @@
+mod export;
 mod id;
+mod import;
 mod metadata;
 mod monitor;
 mod params;
 mod reopen_token;
+mod repair;
+mod snapshot;
 mod vault;
 mod worker;
+mod writers;
 
 #[cfg(test)]
 mod tests;
@@ -12,69 +17,103 @@ mod tests;
 mod vault_tests;
 
 pub use self::{
-    id::RepositoryId, metadata::Metadata, params::RepositoryParams, reopen_token::ReopenToken,
+    id::RepositoryId,
+    metadata::Metadata,
+    params::RepositoryParams,
+    reopen_token::ReopenToken,
+    repair::RepairStats,
+    snapshot::{Snapshot, SnapshotFile},
+    vault::{BlockRequestMode, DedupStats, QuotaUsage},
+    worker::{MaintenanceKind, MergeStrategy, PrunePolicy},
+    writers::WriterInfo,
 };
 
 pub(crate) use self::{
     id::LocalId,
     metadata::{data_version, quota},
-    monitor::RepositoryMonitor,
-    vault::{BlockRequestMode, Vault},
+    monitor::{DhtMonitor, PeerMonitor, RepositoryMonitor},
+    vault::Vault,
 };
 
+use self::{params::Options, worker::PruneState};
 use crate::{
     access_control::{Access, AccessMode, AccessSecrets, LocalSecret},
+    blob::{self, BlobId},
     branch::{Branch, BranchShared},
+    conflict::Conflict,
     crypto::{
         cipher,
         sign::{self, PublicKey},
+        KdfParams, RepositoryCryptoInfo,
     },
-    db::{self, DatabaseId},
+    db::{self, DatabaseId, WalCheckpoint},
     debug::DebugPrinter,
     device_id::DeviceId,
     directory::{Directory, DirectoryFallback, DirectoryLocking, EntryRef, EntryType},
     error::{Error, Result},
     event::{Event, EventSender},
-    file::File,
-    joint_directory::{JointDirectory, JointEntryRef, MissingVersionStrategy},
+    file::{File, SharedFile},
+    joint_directory::{DirEntry, JointDirectory, JointEntryRef, MissingVersionStrategy, ReadDir},
     path,
     progress::Progress,
-    protocol::{RootNodeFilter, BLOCK_SIZE},
+    protocol::{Block, BlockContent, BlockId, Bump, RawBlock, RootNodeFilter, BLOCK_SIZE},
+    rng::SourceRng,
     storage_size::StorageSize,
-    store,
+    store::{self, BlockStore, IntegrityViolation, Store},
     sync::stream::Throttle,
     version_vector::VersionVector,
 };
-use camino::Utf8Path;
-use deadlock::BlockingMutex;
-use futures_util::{future, TryStreamExt};
+use camino::{Utf8Path, Utf8PathBuf};
+use deadlock::{BlockingMutex, LifetimeWarning, Observer};
+use futures_util::{future, Stream, TryStreamExt};
 use futures_util::{stream, StreamExt};
 use metrics::Recorder;
+#[cfg(feature = "prometheus")]
+use metrics_exporter_prometheus::PrometheusHandle;
+use rand::{CryptoRng, Rng};
 use scoped_task::ScopedJoinHandle;
 use state_monitor::StateMonitor;
-use std::{io, path::Path, pin::pin, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    io,
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc,
+    },
+};
 use tokio::{
     fs,
-    sync::broadcast::{self, error::RecvError},
-    time::Duration,
+    io::AsyncWrite,
+    select,
+    sync::{
+        broadcast::{self, error::RecvError},
+        watch, Notify, Semaphore,
+    },
+    time::{self, Duration, Instant},
 };
 use tracing::instrument::Instrument;
 
-const EVENT_CHANNEL_CAPACITY: usize = 256;
-
 pub struct Repository {
     shared: Arc<Shared>,
     worker_handle: BlockingMutex<Option<ScopedJoinHandle<()>>>,
     progress_reporter_handle: BlockingMutex<Option<ScopedJoinHandle<()>>>,
+    size_reporter_handle: BlockingMutex<Option<ScopedJoinHandle<()>>>,
+    wal_checkpoint_handle: BlockingMutex<Option<ScopedJoinHandle<()>>>,
 }
 
-/// Delete the repository database
+// Sqlite database consists of up to three files: main db (always present), WAL and WAL-index.
+const STORE_FILE_SUFFIXES: [&str; 3] = ["", "-wal", "-shm"];
+
+const SLOW_TRANSACTION_CHANNEL_CAPACITY: usize = 32;
+
+/// Delete the repository database. If the repository stores its blocks in a custom block store,
+/// use [`delete_with_block_store`] instead, otherwise the blocks are left behind in it.
 pub async fn delete(store: impl AsRef<Path>) -> io::Result<()> {
-    // Sqlite database consists of up to three files: main db (always present), WAL and WAL-index.
-    // Try to delete all of them even if any of them fail then return the first error (if any)
-    future::join_all(["", "-wal", "-shm"].into_iter().map(|suffix| {
-        let mut path = store.as_ref().as_os_str().to_owned();
-        path.push(suffix);
+    // Try to delete all the files even if any of them fail then return the first error (if any)
+    future::join_all(STORE_FILE_SUFFIXES.into_iter().map(|suffix| {
+        let path = store_file_path(store.as_ref(), suffix);
 
         async move {
             match fs::remove_file(&path).await {
@@ -91,21 +130,110 @@ pub async fn delete(store: impl AsRef<Path>) -> io::Result<()> {
     .unwrap_or(Ok(()))
 }
 
+/// Delete the repository database together with all the blocks in the given custom block store
+/// (see [`RepositoryParams::with_block_store`]). The block store must not be shared with other
+/// repositories. Like [`delete`], tries to delete everything even if some of it fails and then
+/// returns the first error (if any).
+pub async fn delete_with_block_store(
+    store: impl AsRef<Path>,
+    block_store: &dyn BlockStore,
+) -> io::Result<()> {
+    let (blocks_result, store_result) = future::join(block_store.clear(), delete(store)).await;
+    blocks_result.and(store_result)
+}
+
+/// Rename (move) the repository database, including its WAL and WAL-index files. The parent
+/// directory of `to` is created if it doesn't exist.
+///
+/// The WAL is merged into the main database file first, so the move is atomic if the filesystem
+/// supports atomic renames (which usually isn't the case when moving between filesystems). Fails
+/// with `io::ErrorKind::WouldBlock` if the repository is currently open and with
+/// `io::ErrorKind::AlreadyExists` if there already is a repository database at `to`.
+pub async fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let from = from.as_ref();
+    let to = to.as_ref();
+
+    // Fail if the source doesn't exist.
+    fs::metadata(from).await?;
+
+    // Check all the destination files, because a stale WAL would be applied to the moved database.
+    for suffix in STORE_FILE_SUFFIXES {
+        if fs::metadata(store_file_path(to, suffix)).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "repository already exists",
+            ));
+        }
+    }
+
+    db::close_wal(from).await?;
+
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+
+    fs::rename(from, to).await?;
+
+    // The WAL is normally removed by `close_wal` but the WAL-index might be left over. Move
+    // whatever remains so it stays next to the main file.
+    for suffix in &STORE_FILE_SUFFIXES[1..] {
+        match fs::rename(store_file_path(from, suffix), store_file_path(to, suffix)).await {
+            Ok(()) => (),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => (),
+            Err(error) => return Err(error),
+        }
+    }
+
+    Ok(())
+}
+
+fn store_file_path(store: &Path, suffix: &str) -> PathBuf {
+    let mut path = store.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
 impl Repository {
     /// Creates a new repository.
+    ///
+    /// The writer id (and the device it's bound to) is generated and stored only if `access`
+    /// grants write access. Repositories created with read or blind access use a throwaway writer
+    /// id, the same as when they are opened, and a persistent one is generated the first time the
+    /// repository is opened with write access.
     pub async fn create(params: &RepositoryParams<impl Recorder>, access: Access) -> Result<Self> {
         let pool = params.create().await?;
         let device_id = params.device_id();
         let monitor = params.monitor();
 
         let mut tx = pool.begin_write().await?;
+
+        if let Some(kdf_params) = params.kdf_params() {
+            metadata::set_kdf_params(&mut tx, kdf_params).await?;
+        }
+
+        let mut rng = params.rng_source().make("repository");
+
         let local_keys = metadata::initialize_access_secrets(&mut tx, &access).await?;
-        let this_writer_id =
-            generate_and_store_writer_id(&mut tx, &device_id, local_keys.write.as_deref()).await?;
+        let secrets = access.secrets();
+
+        let this_writer_id = if secrets.can_write() {
+            generate_and_store_writer_id(&mut tx, &device_id, local_keys.write.as_deref(), &mut rng)
+                .await?
+        } else {
+            generate_writer_id(&mut rng)
+        };
 
         tx.commit().await?;
 
-        Self::new(pool, this_writer_id, access.secrets(), monitor).await
+        Self::new(
+            params.make_store(pool).await?,
+            this_writer_id,
+            secrets,
+            monitor,
+            params.options(),
+            rng,
+        )
+        .await
     }
 
     /// Opens an existing repository.
@@ -114,6 +242,9 @@ impl Repository {
     ///
     /// * `local_secret` - A user provided secret to encrypt the access secrets. If not provided,
     ///                    the repository will be opened as a blind replica.
+    /// * `max_access_mode` - The highest access mode to open the repository in. It's opened in a
+    ///                       lower one if the secrets for it can't be unlocked, use
+    ///                       [`Self::access_mode`] to get the one actually granted.
     pub async fn open(
         params: &RepositoryParams<impl Recorder>,
         local_secret: Option<LocalSecret>,
@@ -124,6 +255,13 @@ impl Repository {
         let monitor = params.monitor();
 
         let mut tx = pool.begin_write().await?;
+
+        if let Some(kdf_params) = params.kdf_params() {
+            if metadata::get_kdf_params(&mut tx).await? != kdf_params {
+                return Err(Error::KdfParamsMismatch);
+            }
+        }
+
         let local_key = if let Some(local_secret) = local_secret {
             let key = match local_secret {
                 LocalSecret::Password(pwd) => metadata::password_to_key(&mut tx, &pwd).await?,
@@ -135,6 +273,7 @@ impl Repository {
         };
 
         let access_secrets = metadata::get_access_secrets(&mut tx, local_key.as_ref()).await?;
+        let mut rng = params.rng_source().make("repository");
 
         // If we are writer, load the writer id from the db, otherwise use a dummy random one.
         let this_writer_id = if access_secrets.can_write() {
@@ -148,17 +287,91 @@ impl Repository {
                 writer_id
             } else {
                 // Replica id changed. Must generate new writer id.
-                generate_and_store_writer_id(&mut tx, &device_id, local_key.as_ref()).await?
+                generate_and_store_writer_id(&mut tx, &device_id, local_key.as_ref(), &mut rng)
+                    .await?
             }
         } else {
-            sign::Keypair::random().public_key()
+            generate_writer_id(&mut rng)
         };
 
         tx.commit().await?;
 
         let access_secrets = access_secrets.with_mode(max_access_mode);
 
-        Self::new(pool, this_writer_id, access_secrets, monitor).await
+        // `with_mode` never upgrades so any difference means the granted mode is lower.
+        if access_secrets.access_mode() != max_access_mode {
+            tracing::debug!(
+                requested = ?max_access_mode,
+                granted = ?access_secrets.access_mode(),
+                "Repository opened with lower access mode than requested",
+            );
+        }
+
+        Self::new(
+            params.make_store(pool).await?,
+            this_writer_id,
+            access_secrets,
+            monitor,
+            params.options(),
+            rng,
+        )
+        .await
+    }
+
+    /// Opens an existing repository without writing anything to its database. This makes it
+    /// possible to open repositories stored on read-only filesystems (e.g., a backup medium).
+    ///
+    /// The repository is opened in at most the read mode and the background maintenance
+    /// (merging, pruning, etc.) is disabled. Any operation that would modify the repository
+    /// (including syncing it with peers) fails. Note the modifications not yet checkpointed from
+    /// the write-ahead log are not visible (see [`WalCheckpoint`]).
+    ///
+    /// Fails if the repository was created by an older version of this library and needs to be
+    /// migrated first.
+    pub async fn open_read_only(
+        params: &RepositoryParams<impl Recorder>,
+        local_secret: Option<LocalSecret>,
+    ) -> Result<Self> {
+        let pool = params.open_read_only().await?;
+        let monitor = params.monitor();
+
+        let mut conn = pool.acquire().await?;
+
+        if let Some(kdf_params) = params.kdf_params() {
+            if metadata::get_kdf_params(&mut conn).await? != kdf_params {
+                return Err(Error::KdfParamsMismatch);
+            }
+        }
+
+        let local_key = match local_secret {
+            Some(LocalSecret::Password(pwd)) => {
+                Some(metadata::password_to_key_read_only(&mut conn, &pwd).await?)
+            }
+            Some(LocalSecret::SecretKey(key)) => Some(key),
+            None => None,
+        };
+
+        let access_secrets = metadata::get_access_secrets(&mut conn, local_key.as_ref()).await?;
+        let access_secrets = access_secrets.with_mode(AccessMode::Read);
+
+        drop(conn);
+
+        let mut rng = params.rng_source().make("repository");
+
+        Self::new(
+            params.make_store(pool).await?,
+            // Not a writer so use a dummy random writer id.
+            generate_writer_id(&mut rng),
+            access_secrets,
+            monitor,
+            Options {
+                // Checkpointing writes to the database.
+                wal_checkpoint: WalCheckpoint::default(),
+                ..params.options()
+            },
+            rng,
+        )
+        .await
     }
 
     /// Reopens an existing repository using a reopen token (see [`Self::reopen_token`]).
@@ -169,30 +382,72 @@ impl Repository {
         let pool = params.open().await?;
         let monitor = params.monitor();
 
-        Self::new(pool, token.writer_id, token.secrets, monitor).await
+        Self::new(
+            params.make_store(pool).await?,
+            token.writer_id,
+            token.secrets,
+            monitor,
+            params.options(),
+            params.rng_source().make("repository"),
+        )
+        .await
     }
 
     async fn new(
-        pool: db::Pool,
+        store: Store,
         this_writer_id: PublicKey,
         secrets: AccessSecrets,
         monitor: RepositoryMonitor,
+        options: Options,
+        mut rng: SourceRng,
     ) -> Result<Self> {
-        let event_tx = EventSender::new(EVENT_CHANNEL_CAPACITY);
+        let Options {
+            wal_checkpoint,
+            slow_transaction_threshold,
+            event_capacity,
+            max_path_depth,
+            max_file_size,
+            sync_progress_interval,
+            max_snapshots,
+        } = options;
+
+        let slow_transactions = monitor.slow_transactions.clone();
+        let (slow_transaction_tx, _) = broadcast::channel(SLOW_TRANSACTION_CHANNEL_CAPACITY);
+        let observer: Observer = Arc::new({
+            let slow_transaction_tx = slow_transaction_tx.clone();
+
+            move |warning: &LifetimeWarning| {
+                slow_transactions.increment(1);
+                // Ignore the error, it just means there are no subscribers.
+                slow_transaction_tx.send(warning.clone()).ok();
+            }
+        });
+        store
+            .db()
+            .set_lifetime_tracking(slow_transaction_threshold, Some(observer));
+        store.db().set_write_metrics(
+            monitor.write_wait_time.clone(),
+            monitor.write_waiters.clone(),
+        );
 
-        let block_request_mode = if secrets.can_read() {
-            BlockRequestMode::Lazy
-        } else {
-            BlockRequestMode::Greedy
-        };
+        let event_tx = EventSender::new(event_capacity);
+        let read_only = store.db().is_read_only();
 
-        let vault = Vault::new(*secrets.id(), event_tx, pool, block_request_mode, monitor);
+        let vault = Vault::new(
+            *secrets.id(),
+            event_tx,
+            store,
+            block_request_mode(secrets.access_mode()),
+            monitor,
+        );
 
         if let Some(keys) = secrets.write_secrets().map(|secrets| &secrets.write_keys) {
             vault.store().migrate_data(this_writer_id, keys).await?;
         }
 
-        {
+        // The block expiration tracker removes the expired blocks so it can't run on a read-only
+        // database.
+        if !read_only {
             let mut conn = vault.store().db().acquire().await?;
             if let Some(block_expiration) = metadata::block_expiration::get(&mut conn).await? {
                 vault.set_block_expiration(Some(block_expiration)).await?;
@@ -206,39 +461,71 @@ impl Repository {
             "Repository opened"
         );
 
+        let (size_tx, size_rx) = watch::channel(StorageSize::from_blocks(0));
+        let size_watch_started = Arc::new(Notify::new());
+        let (progress_tx, progress_rx) = watch::channel(Progress { value: 0, total: 0 });
+
         let shared = Arc::new(Shared {
             vault,
             this_writer_id,
-            secrets,
-            branch_shared: BranchShared::new(),
+            secrets: Arc::new(Secrets::new(secrets)),
+            branch_shared: BranchShared {
+                max_file_size: max_file_size.min(blob::MAX_LEN),
+                ..BranchShared::new()
+            },
+            prune: PruneState::new(),
+            merge_strategy: watch::channel(MergeStrategy::default()).0,
+            ephemeral_database_id: rng.gen(),
+            size_rx,
+            size_watch_started: size_watch_started.clone(),
+            progress_rx,
+            max_path_depth,
+            snapshots: Arc::new(Semaphore::new(max_snapshots)),
+            slow_transaction_tx,
+            rng: BlockingMutex::new(rng),
         });
 
-        let local_branch = if shared.secrets.can_write() {
-            shared.local_branch().ok()
-        } else {
-            None
-        };
+        let worker_handle = BlockingMutex::new((!read_only).then(|| spawn_worker(&shared)));
 
-        let worker_handle = scoped_task::spawn(
-            worker::run(shared.clone(), local_branch)
+        let progress_reporter_handle = scoped_task::spawn(
+            report_sync_progress(shared.vault.clone(), sync_progress_interval, progress_tx)
                 .instrument(shared.vault.monitor.span().clone()),
         );
-        let worker_handle = BlockingMutex::new(Some(worker_handle));
+        let progress_reporter_handle = BlockingMutex::new(Some(progress_reporter_handle));
 
-        let progress_reporter_handle = scoped_task::spawn(
-            report_sync_progress(shared.vault.clone())
+        let size_reporter_handle = scoped_task::spawn(
+            report_size(shared.vault.clone(), size_tx, size_watch_started)
                 .instrument(shared.vault.monitor.span().clone()),
         );
-        let progress_reporter_handle = BlockingMutex::new(Some(progress_reporter_handle));
+        let size_reporter_handle = BlockingMutex::new(Some(size_reporter_handle));
+
+        let wal_checkpoint_handle = wal_checkpoint.is_enabled().then(|| {
+            scoped_task::spawn(
+                db::run_wal_checkpoint(shared.vault.store().db().clone(), wal_checkpoint)
+                    .instrument(shared.vault.monitor.span().clone()),
+            )
+        });
+        let wal_checkpoint_handle = BlockingMutex::new(wal_checkpoint_handle);
 
         Ok(Self {
             shared,
             worker_handle,
             progress_reporter_handle,
+            size_reporter_handle,
+            wal_checkpoint_handle,
         })
     }
 
     pub async fn database_id(&self) -> Result<DatabaseId> {
+        if self.db().is_read_only() {
+            // A newly generated id can't be stored so use one that's stable only while this
+            // repository is open.
+            let mut conn = self.db().acquire().await?;
+            let database_id = metadata::get_database_id(&mut conn).await?;
+
+            return Ok(database_id.unwrap_or(self.shared.ephemeral_database_id));
+        }
+
         Ok(metadata::get_or_generate_database_id(self.db()).await?)
     }
 
@@ -252,8 +539,48 @@ impl Repository {
         Ok(metadata::requires_local_password_for_writing(&mut conn).await?)
     }
 
+    /// Returns the id of the device the writer id of this repository is bound to (see
+    /// [`RepositoryParams::with_device_id`]), or `None` if it's not bound to any device (the
+    /// repository has never been opened for writing, or [`Self::reset_device_id`] was called).
+    pub async fn device_id(&self) -> Result<Option<DeviceId>> {
+        let mut conn = self.db().acquire().await?;
+        Ok(metadata::get_device_id(&mut conn).await?)
+    }
+
+    /// Unbinds the writer id from the current device, so a new writer id is generated the next
+    /// time the repository is opened for writing. Use this after copying the database to another
+    /// device to prevent both copies from writing as the same writer. Note the new writer id means
+    /// a new branch: the changes made from then on are made in it and the old branch is merged
+    /// into it as any other remote branch. The currently open repository keeps its writer id.
+    pub async fn reset_device_id(&self) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        metadata::remove_device_id(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Checks whether the given local secret unlocks the read access to this repository (possibly
+    /// as part of the write access), by validating the read key it decrypts the same way opening
+    /// the repository does. Nothing is modified, so this can be used to check a password entered
+    /// by the user before proceeding. Returns `false` if the read access doesn't require any local
+    /// secret because then no secret unlocks it.
+    pub async fn verify_read_key(&self, local_secret: &LocalSecret) -> Result<bool> {
+        let mut conn = self.db().acquire().await?;
+
+        let local_key = match local_secret {
+            LocalSecret::Password(pwd) => {
+                metadata::password_to_key_read_only(&mut conn, pwd).await?
+            }
+            LocalSecret::SecretKey(key) => key.clone(),
+        };
+
+        let secrets = metadata::get_access_secrets(&mut conn, Some(&local_key)).await?;
+
+        Ok(secrets.access_mode().can_read())
+    }
+
     pub async fn set_access(&self, access: &Access) -> Result<()> {
-        if access.id() != self.shared.secrets.id() {
+        if access.id() != self.shared.vault.repository_id() {
             return Err(Error::PermissionDenied);
         }
 
@@ -263,6 +590,23 @@ impl Repository {
         Ok(())
     }
 
+    /// Changes the params of the function used to derive keys from passwords (see
+    /// [`RepositoryParams::with_kdf_params`]). The keys derived with the previous params would no
+    /// longer match, so the local secrets are reset to those in `access` in the same transaction,
+    /// exactly like [`Self::set_access`] does, and the keys of the passwords in it are derived
+    /// with the new params. Subsequent opens must use the new params (or none).
+    pub async fn set_kdf_params(&self, kdf_params: KdfParams, access: &Access) -> Result<()> {
+        if access.id() != self.shared.vault.repository_id() {
+            return Err(Error::PermissionDenied);
+        }
+
+        let mut tx = self.db().begin_write().await?;
+        metadata::set_kdf_params(&mut tx, kdf_params).await?;
+        metadata::set_access(&mut tx, access).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
     pub async fn set_read_access(
         &self,
         local_read_secret: Option<&LocalSecret>,
@@ -286,7 +630,7 @@ impl Repository {
             None => self.secrets(),
         };
 
-        if secrets.id() != self.shared.secrets.id() {
+        if secrets.id() != self.shared.vault.repository_id() {
             return Err(Error::PermissionDenied);
         }
 
@@ -343,7 +687,7 @@ impl Repository {
             None => self.secrets(),
         };
 
-        if secrets.id() != self.shared.secrets.id() {
+        if secrets.id() != self.shared.vault.repository_id() {
             return Err(Error::PermissionDenied);
         }
 
@@ -365,7 +709,8 @@ impl Repository {
             None
         };
 
-        let writer_id = writer_id.unwrap_or_else(generate_writer_id);
+        let writer_id =
+            writer_id.unwrap_or_else(|| generate_writer_id(&mut *self.shared.rng.lock().unwrap()));
 
         metadata::set_write_key(
             tx,
@@ -404,8 +749,46 @@ impl Repository {
         Ok(())
     }
 
+    /// Adds another local secret which unlocks this repository with its current access mode, in
+    /// addition to the existing ones (e.g., a recovery key besides a password). Adding the same
+    /// local secret again replaces the stored secrets with the current ones.
+    ///
+    /// The additional local secrets don't survive changes to the access, so they can't keep
+    /// granting the previous one: [`Self::set_access`] (and [`Self::set_kdf_params`]) removes all
+    /// of them, [`Self::remove_read_key`] removes their read secrets and
+    /// [`Self::remove_write_key`] their write secrets. They need to be added again afterwards.
+    pub async fn add_local_unlock(&self, local_secret: &LocalSecret) -> Result<()> {
+        let secrets = self.secrets();
+
+        if secrets.access_mode() == AccessMode::Blind {
+            return Err(Error::PermissionDenied);
+        }
+
+        let mut tx = self.db().begin_write().await?;
+        let local_key = metadata::secret_to_key(&mut tx, local_secret).await?;
+        metadata::add_unlock(&mut tx, secrets, &self.shared.this_writer_id, &local_key).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Removes a local secret previously added with [`Self::add_local_unlock`]. Returns
+    /// `Error::EntryNotFound` if there is no such local secret.
+    pub async fn remove_local_unlock(&self, local_secret: &LocalSecret) -> Result<()> {
+        let mut tx = self.db().begin_write().await?;
+        let local_key = metadata::secret_to_key(&mut tx, local_secret).await?;
+
+        if !metadata::remove_unlock(&mut tx, &local_key).await? {
+            return Err(Error::EntryNotFound);
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
     pub fn secrets(&self) -> &AccessSecrets {
-        &self.shared.secrets
+        self.shared.secrets()
     }
 
     pub async fn unlock_secrets(&self, local_secret: LocalSecret) -> Result<AccessSecrets> {
@@ -433,9 +816,13 @@ impl Repository {
     }
 
     /// Get accessor for repository metadata. The metadata are arbitrary key-value entries that are
-    /// stored inside the repository but not synced to other replicas.
+    /// stored inside the repository but not synced to other replicas. The secret entries (see
+    /// [`Metadata::set_secret`]) are encrypted with the read key of the repository.
     pub fn metadata(&self) -> Metadata {
-        self.shared.vault.metadata()
+        self.shared
+            .vault
+            .metadata()
+            .with_secrets(self.shared.secrets.clone())
     }
 
     /// Set the storage quota in bytes. Use `None` to disable quota. Default is `None`.
@@ -448,6 +835,26 @@ impl Repository {
         self.shared.vault.quota().await
     }
 
+    /// Describes the cryptographic primitives and parameters used to store this repository.
+    pub async fn crypto_info(&self) -> Result<RepositoryCryptoInfo> {
+        let mut conn = self.shared.vault.store().db().acquire().await?;
+        let kdf_params = metadata::get_kdf_params(&mut conn).await?;
+
+        Ok(RepositoryCryptoInfo::new(kdf_params))
+    }
+
+    /// Get the storage usage together with the quota and the breakdown of the usage per branch.
+    pub async fn quota_usage(&self) -> Result<QuotaUsage> {
+        self.shared.vault.quota_usage().await
+    }
+
+    /// Get statistics about the blocks shared by multiple branches (e.g., the blocks of a file
+    /// forked from a remote branch into the local one), showing how much storage the sharing
+    /// saves.
+    pub async fn dedup_stats(&self) -> Result<DedupStats> {
+        self.shared.vault.dedup_stats().await
+    }
+
     /// Set the duration after which blocks start to expire (are deleted) when not used. Use `None`
     /// to disable expiration. Default is `None`.
     pub async fn set_block_expiration(&self, block_expiration: Option<Duration>) -> Result<()> {
@@ -472,6 +879,17 @@ impl Repository {
         self.shared.vault.size().await
     }
 
+    /// Subscribes to the changes of the size of this repository (see [`Self::size`]). The size is
+    /// recomputed whenever blocks might have been added or removed (by local writes, received
+    /// blocks or the maintenance) but at most once per second, so rapid changes are coalesced.
+    /// It's computed only while there are subscribers, so the value might be outdated (initially
+    /// zero) until it's recomputed shortly after subscribing.
+    pub fn watch_size(&self) -> watch::Receiver<StorageSize> {
+        let rx = self.shared.size_rx.clone();
+        self.shared.size_watch_started.notify_one();
+        rx
+    }
+
     pub fn handle(&self) -> RepositoryHandle {
         RepositoryHandle {
             vault: self.shared.vault.clone(),
@@ -483,6 +901,14 @@ impl Repository {
         self.shared.vault.monitor.node()
     }
 
+    /// Get the handle to render the metrics of this repository in the Prometheus text format.
+    /// Returns `None` unless the repository was opened with params created using
+    /// [`RepositoryParams::with_prometheus`].
+    #[cfg(feature = "prometheus")]
+    pub fn metrics_handle(&self) -> Option<PrometheusHandle> {
+        self.shared.vault.monitor.prometheus_handle().cloned()
+    }
+
     /// Looks up an entry by its path. The path must be relative to the repository root.
     /// If the entry exists, returns its `JointEntryType`, otherwise returns `EntryNotFound`.
     pub async fn lookup_type<P: AsRef<Utf8Path>>(&self, path: P) -> Result<EntryType> {
@@ -496,6 +922,12 @@ impl Repository {
     }
 
     /// Opens a file at the given path (relative to the repository root)
+    ///
+    /// Opening and reading a file never fails with `Error::Locked`, not even while the file is
+    /// being written through another handle: readers take only a shared lock, which protects the
+    /// file from being removed. Opening waits only while the file is being removed or moved, and
+    /// writing through a handle fails with `Error::Locked` only when another handle is writing
+    /// the same file at the same time.
     pub async fn open_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<File> {
         let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
 
@@ -507,6 +939,56 @@ impl Repository {
             .await
     }
 
+    /// Like [`Self::open_file`] but if the file can't be opened yet because it (or any of its
+    /// parent directories) hasn't been synced, waits for it, up to `timeout`. After the timeout
+    /// fails with the same error `open_file` would.
+    ///
+    /// Note this treats a non-existing file as not yet synced, so opening a file that doesn't
+    /// exist always waits for the whole timeout.
+    pub async fn open_file_wait<P: AsRef<Utf8Path>>(
+        &self,
+        path: P,
+        timeout: Duration,
+    ) -> Result<File> {
+        let path = path.as_ref();
+        let deadline = Instant::now() + timeout;
+
+        // Subscribe before trying so no change is missed.
+        let mut events = self.subscribe();
+
+        loop {
+            match self.open_file(path).await {
+                Ok(file) => return Ok(file),
+                Err(
+                    error @ (Error::EntryNotFound
+                    | Error::Store(
+                        store::Error::BranchNotFound | store::Error::BlockNotFound,
+                    )),
+                ) => match time::timeout_at(deadline, events.recv()).await {
+                    Ok(Ok(_) | Err(RecvError::Lagged(_))) => (),
+                    // Can't happen while `self` exists.
+                    Ok(Err(RecvError::Closed)) => unreachable!(),
+                    Err(_) => return Err(error),
+                },
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Opens a file at the given path for sharing among multiple tasks. Opening the same file
+    /// again while any handle to it is still alive returns a handle to the same underlying file.
+    /// See [`SharedFile`] for details.
+    pub async fn open_file_shared<P: AsRef<Utf8Path>>(&self, path: P) -> Result<SharedFile> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+
+        self.cd(parent)
+            .await?
+            .lookup_unique(name)?
+            .file()?
+            .open_shared()
+            .await
+    }
+
     /// Open a specific version of the file at the given path.
     pub async fn open_file_version<P: AsRef<Utf8Path>>(
         &self,
@@ -522,13 +1004,85 @@ impl Repository {
             .await
     }
 
+    /// Hints that the file at the given path is going to be accessed interactively. Its missing
+    /// blocks are then requested ahead of the blocks required by the background jobs. The hint
+    /// persists until the repository is closed or [`Self::deprioritize`] is called.
+    pub async fn prioritize<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+        let parent = self.cd(parent).await?;
+        let entry = parent.lookup_unique(name)?.file()?;
+        let blob_id = *entry.blob_id();
+
+        self.shared
+            .vault
+            .prioritized_blobs
+            .lock()
+            .unwrap()
+            .insert(blob_id);
+
+        // Don't wait for the next scan, require the blocks right away.
+        worker::require_missing_blocks(&self.shared, entry.branch(), blob_id).await
+    }
+
+    /// Removes the priority hint previously set with [`Self::prioritize`]. The missing blocks of
+    /// the file that are already required go back to the normal priority.
+    pub async fn deprioritize<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+        let parent = self.cd(parent).await?;
+        let entry = parent.lookup_unique(name)?.file()?;
+        let blob_id = *entry.blob_id();
+
+        self.shared
+            .vault
+            .prioritized_blobs
+            .lock()
+            .unwrap()
+            .remove(&blob_id);
+
+        worker::deprioritize_missing_blocks(&self.shared, entry.branch(), blob_id).await
+    }
+
     /// Opens a directory at the given path (relative to the repository root)
     pub async fn open_directory<P: AsRef<Utf8Path>>(&self, path: P) -> Result<JointDirectory> {
         self.cd(path).await
     }
 
+    /// Returns a stream of the entries of the directory at the given path (relative to the
+    /// repository root).
+    ///
+    /// Unlike [`Self::open_directory`], this doesn't load the whole directory into memory. The
+    /// entries are read from the underlying directory versions incrementally and the versions of
+    /// each entry are merged only as it's being yielded, which makes it suitable for directories
+    /// with a large number of entries. The entries are yielded in the same order and with the same
+    /// conflict semantics as [`JointDirectory::entries`]. The ancestors of the directory are still
+    /// opened the usual way.
+    pub fn read_dir_stream<P: AsRef<Utf8Path>>(
+        &self,
+        path: P,
+    ) -> impl Stream<Item = Result<DirEntry>> + '_ {
+        let path = path.as_ref().to_owned();
+
+        stream::try_unfold(None, move |read_dir: Option<ReadDir>| {
+            let path = path.clone();
+
+            async move {
+                // Start lazily, on the first poll.
+                let mut read_dir = match read_dir {
+                    Some(read_dir) => read_dir,
+                    None => self.read_dir(&path).await?,
+                };
+
+                Ok(read_dir.next().await?.map(|entry| (entry, Some(read_dir))))
+            }
+        })
+    }
+
     /// Creates a new file at the given path.
     pub async fn create_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<File> {
+        if let Some(parent) = path.as_ref().parent() {
+            path::check_depth(parent, self.shared.max_path_depth)?;
+        }
+
         let file = self
             .local_branch()?
             .ensure_file_exists(path.as_ref())
@@ -539,6 +1093,8 @@ impl Repository {
 
     /// Creates a new directory at the given path.
     pub async fn create_directory<P: AsRef<Utf8Path>>(&self, path: P) -> Result<Directory> {
+        path::check_depth(path.as_ref(), self.shared.max_path_depth)?;
+
         let dir = self
             .local_branch()?
             .ensure_directory_exists(path.as_ref())
@@ -556,11 +1112,43 @@ impl Repository {
         Ok(())
     }
 
+    /// Removes multiple files or directories (which must be empty). The entries are grouped by
+    /// their parent directories and each group is removed in a single transaction, so a failure
+    /// leaves the affected directory unmodified. Stops on the first failed directory; the ones
+    /// processed before it remain modified.
+    pub async fn remove_entries(&self, paths: &[Utf8PathBuf]) -> Result<()> {
+        let mut groups: BTreeMap<Utf8PathBuf, BTreeSet<&str>> = BTreeMap::new();
+
+        for path in paths {
+            let (parent, name) = path::decompose(path).ok_or(Error::OperationNotSupported)?;
+            groups
+                .entry(path::normalize(parent))
+                .or_default()
+                .insert(name);
+        }
+
+        for (parent, names) in groups {
+            let names: Vec<_> = names.into_iter().collect();
+            let mut parent = self.cd(parent).await?;
+            parent.remove_entries_atomically(&names).await?;
+        }
+
+        Ok(())
+    }
+
     /// Removes the file or directory (including its content) and flushes its parent directory.
     pub async fn remove_entry_recursively<P: AsRef<Utf8Path>>(&self, path: P) -> Result<()> {
-        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::OperationNotSupported)?;
-        let mut parent = self.cd(parent).await?;
-        parent.remove_entry_recursively(name).await?;
+        let path = path.as_ref();
+        path::check_depth(path, self.shared.max_path_depth)?;
+
+        let (parent_path, name) = path::decompose(path).ok_or(Error::OperationNotSupported)?;
+        let mut parent = self.cd(parent_path).await?;
+        parent
+            .remove_entry_recursively_with_max_depth(
+                name,
+                self.shared.max_path_depth - path::depth(parent_path),
+            )
+            .await?;
 
         Ok(())
     }
@@ -651,6 +1239,78 @@ impl Repository {
         Ok(())
     }
 
+    /// Restricts creating, removing and moving entries in the directory at the given path to the
+    /// given writers. `None` lifts the restriction. The directory is merged into the local branch
+    /// first.
+    ///
+    /// The restriction is advisory: it's stored in the directory and enforced by the replicas
+    /// that honor it, returning `Error::PermissionDenied` to writers not listed, but it can't
+    /// prevent modifications by replicas that ignore it. Fails with `Error::PermissionDenied` if
+    /// the directory already has an ACL which doesn't include the local writer.
+    ///
+    /// Replicas running an older version can't read directories with an ACL and so don't sync
+    /// with replicas running this version (see `Network::on_protocol_version_mismatch`).
+    pub async fn set_directory_acl<P: AsRef<Utf8Path>>(
+        &self,
+        path: P,
+        writers: Option<BTreeSet<PublicKey>>,
+    ) -> Result<()> {
+        let mut dir = self.cd(path).await?.merge().await?;
+        dir.set_acl(writers).await
+    }
+
+    /// Resolves a conflict at the given path by picking the version from the `winner` branch.
+    ///
+    /// The winning version is forked into the local branch with a version vector that dominates
+    /// all the other versions, so they are subsequently discarded by the merger. Only a file can
+    /// be picked as the winner (`Error::EntryIsDirectory` is returned otherwise) and the
+    /// resolution is shallow: directory versions are not descended into.
+    pub async fn resolve_conflict<P: AsRef<Utf8Path>>(
+        &self,
+        path: P,
+        winner: &PublicKey,
+    ) -> Result<()> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+        let local_branch = self.local_branch()?;
+        let parent = self.cd(parent).await?;
+
+        resolve_conflict(&parent, name, local_branch, winner).await
+    }
+
+    /// Writes the directory at the given path (including all its subdirectories) into `writer` as
+    /// a tar archive. The file contents are streamed block by block, so even large files are never
+    /// loaded into memory whole. Of the concurrent versions of a file, only one is exported,
+    /// preferring the local version.
+    pub async fn export_tar<P, W>(&self, path: P, writer: &mut W) -> Result<()>
+    where
+        P: AsRef<Utf8Path>,
+        W: AsyncWrite + Unpin,
+    {
+        let path = path.as_ref();
+        let dir = self.cd(path).await?;
+
+        export::export_tar(
+            dir,
+            Some(&self.shared.this_writer_id),
+            self.shared.max_path_depth - path::depth(path),
+            writer,
+        )
+        .await
+    }
+
+    /// Recursively imports the directory `src` from the host filesystem into the repository
+    /// directory `dst`, creating it if it doesn't exist. Files whose content already matches are
+    /// skipped, so an interrupted import can be resumed by running it again. `on_progress` is
+    /// called with the number of bytes imported so far.
+    pub async fn import_dir<S, D, F>(&self, src: S, dst: D, on_progress: F) -> Result<()>
+    where
+        S: AsRef<Path>,
+        D: AsRef<Utf8Path>,
+        F: FnMut(Progress),
+    {
+        import::import_dir(self, src.as_ref(), dst.as_ref(), on_progress).await
+    }
+
     /// Returns the local branch or `Error::PermissionDenied` if this repo doesn't have at least
     /// read access.
     pub fn local_branch(&self) -> Result<Branch> {
@@ -683,9 +1343,45 @@ impl Repository {
         self.shared.vault.event_tx.subscribe()
     }
 
+    /// Subscribe to the warnings about db transactions of this repository held longer than the
+    /// slow transaction threshold (see `RepositoryParams::with_slow_transaction_threshold`).
+    pub fn subscribe_slow_transactions(&self) -> broadcast::Receiver<LifetimeWarning> {
+        self.shared.slow_transaction_tx.subscribe()
+    }
+
     /// Gets the access mode this repository is opened in.
     pub fn access_mode(&self) -> AccessMode {
-        self.shared.secrets.access_mode()
+        self.shared.secrets().access_mode()
+    }
+
+    /// Downgrades the access mode of this repository to `mode` without reopening it. After
+    /// downgrading to `Read`, creating or modifying entries fails with `Error::PermissionDenied`.
+    /// After downgrading to `Blind`, also `local_branch` and reading fail with it. Files and
+    /// directories that are already open keep their original access.
+    ///
+    /// Upgrading is not possible this way (returns `Error::PermissionDenied`). To regain the
+    /// higher access, reopen the repository with the local secret (see [`Self::open`]).
+    pub fn downgrade_access(&self, mode: AccessMode) -> Result<()> {
+        if !self.shared.secrets.downgrade(mode)? {
+            return Ok(());
+        }
+
+        tracing::debug!(
+            parent: self.shared.vault.monitor.span(),
+            access = ?mode,
+            "Access downgraded"
+        );
+
+        self.shared
+            .vault
+            .set_block_request_mode(block_request_mode(mode));
+
+        // Restart the worker so it stops using the local branch and the keys it no longer has
+        // access to. Dropping the old handle aborts it.
+        let worker_handle = (!self.db().is_read_only()).then(|| spawn_worker(&self.shared));
+        *self.worker_handle.lock().unwrap() = worker_handle;
+
+        Ok(())
     }
 
     /// Gets the syncing progress of this repository (number of downloaded blocks / number of
@@ -694,36 +1390,228 @@ impl Repository {
         Ok(self.shared.vault.store().sync_progress().await?)
     }
 
+    /// Subscribes to the changes of the syncing progress of this repository (see
+    /// [`Self::sync_progress`]). The progress is recomputed whenever the repository changes but at
+    /// most once per the interval set with
+    /// [`RepositoryParams::with_sync_progress_interval`]. The initial value is zero until the
+    /// progress is computed for the first time.
+    pub fn watch_sync_progress(&self) -> watch::Receiver<Progress> {
+        self.shared.progress_rx.clone()
+    }
+
+    /// Waits until this repository is fully synced, that is, all the blocks referenced from the
+    /// branches known to this replica are downloaded and there are no requests to the peers in
+    /// flight. Returns immediately if that's already the case.
+    ///
+    /// Note that the repository might go out of sync again right after this returns if the peers
+    /// send new data. Call this again to wait for that data as well.
+    ///
+    /// If the blocks are not being requested (e.g. because this is a blind replica or the block
+    /// request mode is lazy) this might never return.
+    pub async fn wait_until_synced(&self) -> Result<()> {
+        // Subscribe before checking so no change is missed.
+        let mut events = self.subscribe();
+        let mut requests_idle = self.shared.vault.monitor.subscribe_requests_idle();
+
+        loop {
+            let progress = self.sync_progress().await?;
+
+            if progress.value == progress.total
+                && self.shared.vault.monitor.requests_inflight() == 0
+            {
+                return Ok(());
+            }
+
+            select! {
+                event = events.recv() => match event {
+                    Ok(_) | Err(RecvError::Lagged(_)) => (),
+                    // Can't happen while `self` exists.
+                    Err(RecvError::Closed) => unreachable!(),
+                },
+                _ = requests_idle.changed() => (),
+            }
+        }
+    }
+
     /// Check integrity of the stored data.
-    // TODO: Return more detailed info about any integrity violation.
     pub async fn check_integrity(&self) -> Result<bool> {
-        Ok(self.shared.vault.store().check_integrity().await?)
+        Ok(self.check_integrity_detailed().await?.is_empty())
+    }
+
+    /// Check integrity of the stored data and return all the found violations (empty if the data
+    /// is intact).
+    pub async fn check_integrity_detailed(&self) -> Result<Vec<IntegrityViolation>> {
+        Ok(self
+            .shared
+            .vault
+            .store()
+            .check_integrity(self.shared.vault.repository_id())
+            .await?)
+    }
+
+    /// Like [`Self::check_integrity_detailed`] but checks a consistent snapshot of the index taken
+    /// when the check starts, so the changes committed while it runs (e.g., by the ongoing sync)
+    /// are neither checked nor reported as violations. Neither check blocks the writes. Note the
+    /// snapshot prevents the database log from being fully checkpointed until the check completes.
+    ///
+    /// The snapshot covers only the database. When the blocks are kept in a custom
+    /// [`BlockStore`](crate::BlockStore), they are read from it as they are at the time each of
+    /// them is checked, so a block removed in the meantime is reported as missing.
+    pub async fn check_integrity_snapshot(&self) -> Result<Vec<IntegrityViolation>> {
+        Ok(self
+            .shared
+            .vault
+            .store()
+            .check_integrity_snapshot(self.shared.vault.repository_id())
+            .await?)
+    }
+
+    /// Repairs the missing and corrupted blocks found by [`Self::check_integrity_detailed`]: the
+    /// corrupted blocks are removed, and all of them are marked as missing and requested again
+    /// from the connected peers. Waits up to `timeout` for the blocks to be received and returns
+    /// how many were repaired and how many are still missing. The still missing ones continue to
+    /// be requested after this function returns, as any other missing block.
+    pub async fn repair(&self, timeout: Duration) -> Result<RepairStats> {
+        repair::repair(&self.shared, timeout).await
+    }
+
+    /// Runs the given maintenance job immediately (instead of waiting for it to be triggered by
+    /// a repository change) and waits for it to complete. Returns `Error::Busy` if the job is
+    /// already running.
+    pub async fn run_maintenance(&self, kind: MaintenanceKind) -> Result<()> {
+        worker::run_maintenance(&self.shared, kind).await
+    }
+
+    /// Sets the policy controlling which outdated branches are pruned. This can be used to keep the
+    /// branches of writers that were only briefly offline around, so they don't need to be synced
+    /// again from scratch. The policy is not persisted: it's reset to the default (prune all
+    /// outdated branches immediately) when the repository is reopened.
+    pub fn set_prune_policy(&self, policy: PrunePolicy) {
+        *self.shared.vault.monitor.prune_policy.get() = policy.clone();
+        self.shared.prune.policy.send_replace(policy);
+    }
+
+    /// Gets the current prune policy.
+    pub fn prune_policy(&self) -> PrunePolicy {
+        self.shared.prune.policy.borrow().clone()
+    }
+
+    /// Sets the strategy the merger uses for concurrent file versions that can't be merged
+    /// automatically. Like the prune policy, the strategy is not persisted: it's reset to the
+    /// default (`MergeStrategy::KeepAllVersions`) when the repository is reopened.
+    pub fn set_merge_strategy(&self, strategy: MergeStrategy) {
+        self.shared.merge_strategy.send_replace(strategy);
+    }
+
+    /// Gets the current merge strategy.
+    pub fn merge_strategy(&self) -> MergeStrategy {
+        *self.shared.merge_strategy.borrow()
+    }
+
+    /// Sets which blocks to request from the peers. This takes effect immediately and can be used
+    /// to pause bulk block downloads (by switching to `Lazy`) and resume them later (by switching
+    /// back to `Greedy`). Requests already in flight are not cancelled and blocks that are
+    /// required (e.g., by reading a file) are always requested. Note a blind replica can't
+    /// require any blocks so it doesn't request any in `Lazy` mode.
+    ///
+    /// The mode is not persisted and is reset to the default for the access mode (`Greedy` for
+    /// blind replicas, `Lazy` otherwise) when the repository is reopened or its access is
+    /// downgraded.
+    pub fn set_block_request_mode(&self, mode: BlockRequestMode) {
+        self.shared.vault.set_block_request_mode(mode);
+    }
+
+    /// Gets the current block request mode.
+    pub fn block_request_mode(&self) -> BlockRequestMode {
+        self.shared.vault.block_request_mode()
+    }
+
+    /// Lists the writers whose branches are stored in this repository, including the local one.
+    pub async fn list_writers(&self) -> Result<Vec<WriterInfo>> {
+        writers::list(&self.shared).await
+    }
+
+    /// Removes the branch of the given writer from this replica. This is useful to clean up
+    /// branches of devices that no longer exist. Only branches whose changes are already included
+    /// in the local branch can be removed, otherwise `Error::OperationNotSupported` is returned
+    /// (also when trying to forget the local writer). Returns `Error::Locked` if any file or
+    /// directory from the branch is currently open.
+    ///
+    /// Note this only affects the local data. If the writer is still active (or any peer still has
+    /// its branch), the branch reappears on the next sync.
+    pub async fn forget_writer(&self, writer_id: &PublicKey) -> Result<()> {
+        writers::forget(&self.shared, writer_id).await
+    }
+
+    /// Opens the file whose content is stored in the blob with the given id (see
+    /// [`File::blob_id`]), regardless of its current path. The file is looked up in all the
+    /// branches. If more than one version of the file references the blob (e.g., when it was
+    /// forked into another branch without being modified), any of them is opened. Returns
+    /// `Error::EntryNotFound` if no branch references the blob anymore (e.g., the file was removed
+    /// or its branch pruned).
+    ///
+    /// NOTE: The whole directory tree is searched, so this can be slow for large repositories.
+    pub async fn open_blob(&self, blob_id: BlobId) -> Result<File> {
+        let mut dirs = VecDeque::from([self.root().await?]);
+
+        while let Some(dir) = dirs.pop_front() {
+            for entry in dir.entries() {
+                match entry {
+                    JointEntryRef::File(entry) if *entry.inner().blob_id() == blob_id => {
+                        return entry.open().await;
+                    }
+                    JointEntryRef::File(_) => (),
+                    JointEntryRef::Directory(entry) => dirs.push_back(entry.open().await?),
+                }
+            }
+        }
+
+        Err(Error::EntryNotFound)
+    }
+
+    /// Returns a stream of all entries in this repository that have multiple concurrent versions
+    /// which couldn't be merged automatically. The repository is traversed lazily, as the stream is
+    /// being consumed.
+    pub fn conflicts(&self) -> impl Stream<Item = Result<Conflict>> + '_ {
+        stream::try_unfold(
+            (None, VecDeque::new()),
+            move |(dirs, mut found): (
+                Option<VecDeque<(Utf8PathBuf, JointDirectory)>>,
+                VecDeque<Conflict>,
+            )| async move {
+                // Start lazily, on the first poll.
+                let mut dirs = match dirs {
+                    Some(dirs) => dirs,
+                    None => VecDeque::from([(Utf8PathBuf::from("/"), self.root().await?)]),
+                };
+
+                loop {
+                    if let Some(conflict) = found.pop_front() {
+                        return Ok(Some((conflict, (Some(dirs), found))));
+                    }
+
+                    let Some((path, dir)) = dirs.pop_front() else {
+                        return Ok(None);
+                    };
+
+                    found.extend(dir.conflicts(&path));
+
+                    for entry in dir.entries() {
+                        if let JointEntryRef::Directory(entry) = entry {
+                            dirs.push_back((path.join(entry.name()), entry.open().await?));
+                        }
+                    }
+                }
+            },
+        )
     }
 
     // Opens the root directory across all branches as JointDirectory.
     async fn root(&self) -> Result<JointDirectory> {
         let local_branch = self.local_branch()?;
-        let branches = self.shared.load_branches().await?;
-
-        // If we are writer and the local branch doesn't exist yet in the db we include it anyway.
-        // This fixes a race condition when the local branch doesn't exist yet at the moment we
-        // load the branches but is subsequently created by merging a remote branch and the remote
-        // branch is then pruned.
-        let branches = if local_branch.keys().write().is_some()
-            && branches
-                .iter()
-                .all(|branch| branch.id() != local_branch.id())
-        {
-            let mut branches = branches;
-            branches.push(local_branch.clone());
-            branches
-        } else {
-            branches
-        };
-
         let mut dirs = Vec::new();
 
-        for branch in branches {
+        for branch in self.root_branches(&local_branch).await? {
             let dir = match branch
                 .open_root(DirectoryLocking::Enabled, DirectoryFallback::Enabled)
                 .await
@@ -765,7 +1653,82 @@ impl Repository {
         Ok(JointDirectory::new(Some(local_branch), dirs))
     }
 
+    // Returns the branches whose root directories make up the root `JointDirectory`.
+    async fn root_branches(&self, local_branch: &Branch) -> Result<Vec<Branch>> {
+        let mut branches = self.shared.load_branches().await?;
+
+        // If we are writer and the local branch doesn't exist yet in the db we include it anyway.
+        // This fixes a race condition when the local branch doesn't exist yet at the moment we
+        // load the branches but is subsequently created by merging a remote branch and the remote
+        // branch is then pruned.
+        if local_branch.keys().write().is_some()
+            && branches
+                .iter()
+                .all(|branch| branch.id() != local_branch.id())
+        {
+            branches.push(local_branch.clone());
+        }
+
+        Ok(branches)
+    }
+
+    // Opens the directory at the given path for reading its entries incrementally.
+    async fn read_dir(&self, path: &Utf8Path) -> Result<ReadDir> {
+        let local_branch = self.local_branch()?;
+
+        let versions: Vec<_> = if let Some((parent, name)) = path::decompose(path) {
+            self.cd(parent)
+                .await?
+                .lookup_unique(name)?
+                .directory()?
+                .versions()
+                .iter()
+                .map(|version| (version.branch().clone(), *version.blob_id()))
+                .collect()
+        } else {
+            self.root_branches(&local_branch)
+                .await?
+                .into_iter()
+                .map(|branch| (branch, BlobId::ROOT))
+                .collect()
+        };
+
+        ReadDir::open(Some(&local_branch), versions).await
+    }
+
+    /// Takes a read-only snapshot of the current content of the repository. Reading from the
+    /// snapshot is not affected by the subsequent changes, so it can be used for example to make
+    /// consistent backups while the repository keeps syncing. See [`Snapshot`] for details.
+    ///
+    /// Returns [`Error::TooManySnapshots`] if there are already as many snapshots of this
+    /// repository as allowed (see [`RepositoryParams::with_max_snapshots`]).
+    pub async fn snapshot(&self) -> Result<Snapshot> {
+        let permit = self
+            .shared
+            .snapshots
+            .clone()
+            .try_acquire_owned()
+            .map_err(|_| Error::TooManySnapshots)?;
+
+        let mut tx = self.shared.vault.store().begin_read().await?;
+        let root_nodes: Vec<_> = tx.load_root_nodes().try_collect().await?;
+        let mut branches = Vec::with_capacity(root_nodes.len());
+
+        for root_node in root_nodes {
+            let branch = self.shared.get_branch(root_node.proof.writer_id)?;
+            branches.push((branch, root_node));
+        }
+
+        Ok(Snapshot::new(
+            tx,
+            branches,
+            self.shared.max_path_depth,
+            permit,
+        ))
+    }
+
     pub async fn cd<P: AsRef<Utf8Path>>(&self, path: P) -> Result<JointDirectory> {
+        path::check_depth(path.as_ref(), self.shared.max_path_depth)?;
         self.root().await?.cd(path).await
     }
 
@@ -774,7 +1737,12 @@ impl Repository {
     pub async fn close(&self) -> Result<()> {
         // Abort and *await* the tasks to make sure that the state they are holding is definitely
         // dropped before we return from this function.
-        for task in [&self.worker_handle, &self.progress_reporter_handle] {
+        for task in [
+            &self.worker_handle,
+            &self.progress_reporter_handle,
+            &self.size_reporter_handle,
+            &self.wal_checkpoint_handle,
+        ] {
             let task = task.lock().unwrap().take();
             if let Some(task) = task {
                 task.abort();
@@ -787,6 +1755,36 @@ impl Repository {
         Ok(())
     }
 
+    /// Flushes the pending modifications of all the currently open shared files (see
+    /// [`Self::open_file_shared`]) and then closes the repository like [`Self::close`]. The files
+    /// are flushed even if some of them fail, in which case the first error is returned after the
+    /// repository is closed.
+    ///
+    /// Only the shared files are tracked by the repository. The pending modifications of the
+    /// files opened with [`Self::open_file`] or [`Self::create_file`] are NOT flushed, because
+    /// those files are owned exclusively by the caller - use [`File::close`] on them before
+    /// closing the repository.
+    ///
+    /// Each file is flushed atomically so cancelling this function leaves every file either
+    /// flushed or not, never partially.
+    pub async fn flush_shared_files_and_close(&self) -> Result<()> {
+        let mut result = Ok(());
+
+        for file in self.shared.branch_shared.shared_files.all() {
+            if let Err(error) = file.flush().await {
+                tracing::error!(?error, "Failed to flush file on close");
+
+                if result.is_ok() {
+                    result = Err(error);
+                }
+            }
+        }
+
+        self.close().await?;
+
+        result
+    }
+
     pub async fn debug_print_root(&self) {
         self.debug_print(DebugPrinter::new()).await
     }
@@ -834,6 +1832,45 @@ impl Repository {
         Ok(self.shared.vault.store().count_blocks().await?)
     }
 
+    /// Returns up to `limit` ids of the blocks that are referenced from the complete snapshots of
+    /// this repository but not present locally, in ascending order, starting after `after` (or
+    /// from the lowest one if `None`). These are the blocks that can be received with
+    /// [`Self::receive_raw_block`], so this can be used to pull them from another replica
+    /// incrementally. A block that's been received is no longer listed, so an interrupted transfer
+    /// resumes where it stopped.
+    pub async fn list_missing_block_ids(
+        &self,
+        after: Option<BlockId>,
+        limit: u32,
+    ) -> Result<Vec<BlockId>> {
+        let page = self.shared.vault.store().block_ids(limit).missing();
+        let mut page = match after {
+            Some(after) => page.starting_after(after),
+            None => page,
+        };
+
+        Ok(page.next().await?.into_iter().collect())
+    }
+
+    /// Reads the block with the given id in its encrypted form. Doesn't require any access to the
+    /// repository, so this works in blind replicas too.
+    pub async fn read_raw_block(&self, id: &BlockId) -> Result<RawBlock> {
+        let mut reader = self.shared.vault.store().acquire_read().await?;
+        let mut content = BlockContent::new();
+        let nonce = reader.read_block(id, &mut content).await?;
+
+        Ok(Block::new(content, nonce).into())
+    }
+
+    /// Stores a block obtained from another replica of this repository with
+    /// [`Self::read_raw_block`]. The block is accepted only if it's referenced from the index of
+    /// this replica, otherwise this fails with `StoreError::BlockNotReferenced`. Does nothing if
+    /// the block is already present.
+    pub async fn receive_raw_block(&self, block: RawBlock) -> Result<()> {
+        let block = block.into_block().ok_or(Error::MalformedData)?;
+        self.shared.vault.receive_block(&block, None).await
+    }
+
     fn db(&self) -> &db::Pool {
         self.shared.vault.store().db()
     }
@@ -846,17 +1883,34 @@ pub struct RepositoryHandle {
 struct Shared {
     vault: Vault,
     this_writer_id: PublicKey,
-    secrets: AccessSecrets,
+    secrets: Arc<Secrets>,
     branch_shared: BranchShared,
+    prune: PruneState,
+    merge_strategy: watch::Sender<MergeStrategy>,
+    // Database id to use when the database is read-only and doesn't have one stored yet.
+    ephemeral_database_id: DatabaseId,
+    size_rx: watch::Receiver<StorageSize>,
+    // Notified when someone subscribes to the size changes so it's recomputed right away.
+    size_watch_started: Arc<Notify>,
+    progress_rx: watch::Receiver<Progress>,
+    max_path_depth: usize,
+    // Bounds the number of live snapshots as each of them holds a read connection.
+    snapshots: Arc<Semaphore>,
+    slow_transaction_tx: broadcast::Sender<LifetimeWarning>,
+    rng: BlockingMutex<SourceRng>,
 }
 
 impl Shared {
+    pub fn secrets(&self) -> &AccessSecrets {
+        self.secrets.get()
+    }
+
     pub fn local_branch(&self) -> Result<Branch> {
         self.get_branch(self.this_writer_id)
     }
 
     pub fn get_branch(&self, id: PublicKey) -> Result<Branch> {
-        let keys = self.secrets.keys().ok_or(Error::PermissionDenied)?;
+        let keys = self.secrets().keys().ok_or(Error::PermissionDenied)?;
 
         // Only the local branch is writable.
         let keys = if id == self.this_writer_id {
@@ -887,26 +1941,117 @@ impl Shared {
     }
 }
 
+// Access secrets that can be downgraded while the repository is open. The secrets for all the modes
+// are derived upfront so the current ones can be borrowed without holding a lock.
+struct Secrets {
+    // Indexed by the access mode. The modes above the original one hold the original secrets.
+    by_mode: [AccessSecrets; 3],
+    mode: AtomicU8,
+}
+
+impl Secrets {
+    fn new(secrets: AccessSecrets) -> Self {
+        let mode = AtomicU8::new(secrets.access_mode() as u8);
+
+        Self {
+            by_mode: [
+                secrets.with_mode(AccessMode::Blind),
+                secrets.with_mode(AccessMode::Read),
+                secrets,
+            ],
+            mode,
+        }
+    }
+
+    fn get(&self) -> &AccessSecrets {
+        &self.by_mode[self.mode.load(Ordering::Acquire) as usize]
+    }
+
+    // Returns whether the mode changed, or `PermissionDenied` if `mode` is higher than the current
+    // one.
+    fn downgrade(&self, mode: AccessMode) -> Result<bool> {
+        let new = mode as u8;
+
+        match self
+            .mode
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |old| {
+                (new <= old).then_some(new)
+            }) {
+            Ok(old) => Ok(old != new),
+            Err(_) => Err(Error::PermissionDenied),
+        }
+    }
+}
+
+fn spawn_worker(shared: &Arc<Shared>) -> ScopedJoinHandle<()> {
+    let local_branch = if shared.secrets().can_write() {
+        shared.local_branch().ok()
+    } else {
+        None
+    };
+
+    scoped_task::spawn(
+        worker::run(shared.clone(), local_branch).instrument(shared.vault.monitor.span().clone()),
+    )
+}
+
+// Resolves the conflict of the entry `name` in `parent` by forking the version from the `winner`
+// branch into the local branch with a version vector that dominates all the other versions.
+async fn resolve_conflict(
+    parent: &JointDirectory,
+    name: &str,
+    local_branch: Branch,
+    winner: &PublicKey,
+) -> Result<()> {
+    // The local version is included even if it's a tombstone, so the forked entry replaces it.
+    let local_vv = parent
+        .local_version()
+        .and_then(|dir| dir.lookup(name).ok())
+        .map(|entry| entry.version_vector().clone())
+        .unwrap_or_default();
+    let vv = parent
+        .lookup(name)
+        .fold(local_vv, |vv, entry| vv.merged(&entry.version_vector()))
+        .incremented(*local_branch.id());
+
+    let mut file = parent.lookup_version(name, winner)?.open().await?;
+
+    file.fork_with(local_branch, Bump::Merge(vv)).await
+}
+
+fn block_request_mode(access_mode: AccessMode) -> BlockRequestMode {
+    // Blind replicas can't tell which blocks are needed so they request all of them.
+    match access_mode {
+        AccessMode::Blind => BlockRequestMode::Greedy,
+        AccessMode::Read | AccessMode::Write => BlockRequestMode::Lazy,
+    }
+}
+
 // TODO: Writer IDs are currently practically just UUIDs with no real security (any replica with a
 // write access may impersonate any other replica).
-fn generate_writer_id() -> sign::PublicKey {
-    sign::Keypair::random().public_key()
+fn generate_writer_id<R: Rng + CryptoRng>(rng: &mut R) -> sign::PublicKey {
+    sign::Keypair::generate(rng).public_key()
 }
 
 async fn generate_and_store_writer_id(
     tx: &mut db::WriteTransaction,
     device_id: &DeviceId,
     local_key: Option<&cipher::SecretKey>,
+    rng: &mut SourceRng,
 ) -> Result<sign::PublicKey> {
-    let writer_id = generate_writer_id();
+    let writer_id = generate_writer_id(rng);
     metadata::set_writer_id(tx, &writer_id, local_key).await?;
     metadata::set_device_id(tx, device_id).await?;
     Ok(writer_id)
 }
 
-async fn report_sync_progress(vault: Vault) {
-    let mut prev_progress = Progress { value: 0, total: 0 };
-
+async fn report_size(
+    vault: Vault,
+    size_tx: watch::Sender<StorageSize>,
+    watch_started: Arc<Notify>,
+) {
+    // Lagging doesn't matter here because the size is recomputed from scratch anyway. It's also
+    // not counted into `events_lagged` because that's already done in `report_sync_progress`.
     let events = stream::unfold(vault.event_tx.subscribe(), |mut rx| async move {
         match rx.recv().await {
             Ok(_) | Err(RecvError::Lagged(_)) => Some(((), rx)),
@@ -916,6 +2061,66 @@ async fn report_sync_progress(vault: Vault) {
     let events = Throttle::new(events, Duration::from_secs(1));
     let mut events = pin!(events);
 
+    loop {
+        select! {
+            event = events.next() => {
+                if event.is_none() {
+                    break;
+                }
+            }
+            _ = watch_started.notified() => (),
+        }
+
+        // Computing the size requires scanning the blocks so skip it if no one is watching. One
+        // receiver is always kept in `Shared`.
+        if size_tx.receiver_count() <= 1 {
+            continue;
+        }
+
+        let size = match vault.size().await {
+            Ok(size) => size,
+            Err(error) => {
+                tracing::error!("Failed to retrieve repository size: {:?}", error);
+                continue;
+            }
+        };
+
+        size_tx.send_if_modified(|prev_size| {
+            if *prev_size != size {
+                *prev_size = size;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+async fn report_sync_progress(
+    vault: Vault,
+    interval: Duration,
+    progress_tx: watch::Sender<Progress>,
+) {
+    let events_lagged = vault.monitor.events_lagged.clone();
+    let events = stream::unfold(vault.event_tx.subscribe(), move |mut rx| {
+        let events_lagged = events_lagged.clone();
+
+        async move {
+            match rx.recv().await {
+                Ok(_) => Some(((), rx)),
+                Err(RecvError::Lagged(count)) => {
+                    events_lagged.increment(count);
+                    Some(((), rx))
+                }
+                Err(RecvError::Closed) => None,
+            }
+        }
+    });
+    // Compute the initial progress right away.
+    let events = stream::once(future::ready(())).chain(events);
+    let events = Throttle::new(events, interval);
+    let mut events = pin!(events);
+
     while events.next().await.is_some() {
         let next_progress = match vault.store().sync_progress().await {
             Ok(progress) => progress,
@@ -925,12 +2130,20 @@ async fn report_sync_progress(vault: Vault) {
             }
         };
 
-        if next_progress != prev_progress {
-            prev_progress = next_progress;
+        let changed = progress_tx.send_if_modified(|prev_progress| {
+            if *prev_progress != next_progress {
+                *prev_progress = next_progress;
+                true
+            } else {
+                false
+            }
+        });
+
+        if changed {
             tracing::debug!(
                 "Sync progress: {} bytes ({:.1})",
-                prev_progress * BLOCK_SIZE as u64,
-                prev_progress.percent()
+                next_progress * BLOCK_SIZE as u64,
+                next_progress.percent()
             );
         }
     }
@@ -1,12 +1,24 @@
+use super::PrunePolicy;
+use crate::{
+    collections::{hash_map::Entry, HashMap},
+    network::PublicRuntimeId,
+};
 use btdht::InfoHash;
+use chrono::{DateTime, Local};
+use deadlock::BlockingMutex;
 use metrics::{
     Counter, Gauge, Histogram, Key, KeyName, Level, Metadata, Recorder, SharedString, Unit,
 };
-use state_monitor::{MonitoredValue, StateMonitor};
+#[cfg(feature = "prometheus")]
+use metrics_exporter_prometheus::PrometheusHandle;
+use state_monitor::{metrics::MetricsRecorder, MonitoredValue, StateMonitor};
 use std::{
     fmt,
     future::Future,
-    sync::atomic::{AtomicU64, Ordering},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
     time::{Duration, Instant},
 };
 use tokio::{
@@ -19,8 +31,12 @@ use tracing::{Instrument, Span};
 
 pub(crate) struct RepositoryMonitor {
     pub info_hash: MonitoredValue<Option<InfoHash>>,
+    pub prune_policy: MonitoredValue<PrunePolicy>,
+    // When are the outdated branches kept because of the prune policy going to be pruned.
+    pub next_prune: MonitoredValue<Option<DateTime<Local>>>,
 
-    // Total number of index requests sent.
+    // Total number of messages with index requests sent. Multiple index requests can be batched
+    // into a single message (see `NetworkOptions::index_request_batch_window`).
     pub index_requests_sent: Counter,
     // Current number of sent index request for which responses haven't been received yet.
     pub index_requests_inflight: Gauge,
@@ -28,6 +44,11 @@ pub(crate) struct RepositoryMonitor {
     pub block_requests_sent: Counter,
     // Current number of sent block request for which responses haven't been received yet.
     pub block_requests_inflight: Gauge,
+    // Current number of sent index and block requests for which responses haven't been received
+    // yet. Same as the sum of the above two gauges but readable.
+    requests_inflight_count: AtomicU64,
+    // Notified when `requests_inflight_count` drops to zero.
+    requests_idle_tx: watch::Sender<()>,
     // Total number of received requests
     pub requests_received: Counter,
     // Current number of send requests (index + block) for which responses haven't been handled yet
@@ -49,13 +70,31 @@ pub(crate) struct RepositoryMonitor {
     // Time to handle a response.
     pub response_handle_time: Histogram,
 
+    // Total number of db transactions that took longer than expected.
+    pub slow_transactions: Counter,
+    // Time spent waiting to begin a db write transaction (only one can exist at a time).
+    pub write_wait_time: Histogram,
+    // Current number of tasks waiting to begin a db write transaction.
+    pub write_waiters: Gauge,
+    // Total number of events missed by the repository's own event subscriber because it fell
+    // behind by more than the event channel capacity.
+    pub events_lagged: Counter,
+
+    pub dht: DhtMonitor,
+
     pub scan_job: JobMonitor,
     pub merge_job: JobMonitor,
     pub prune_job: JobMonitor,
     pub trash_job: JobMonitor,
 
+    // Metrics of the currently connected peers.
+    peers: Arc<BlockingMutex<HashMap<PublicRuntimeId, Weak<PeerMonitor>>>>,
+    peers_node: StateMonitor,
+
     span: Span,
     node: StateMonitor,
+    #[cfg(feature = "prometheus")]
+    prometheus_handle: Option<PrometheusHandle>,
 }
 
 impl RepositoryMonitor {
@@ -66,6 +105,8 @@ impl RepositoryMonitor {
         let span = tracing::info_span!("repo", message = node.id().name());
 
         let info_hash = node.make_value("info-hash", None);
+        let prune_policy = node.make_value("prune-policy", PrunePolicy::default());
+        let next_prune = node.make_value("next-prune", None);
 
         let index_requests_sent = create_counter(recorder, "index requests sent", Unit::Count);
         let index_requests_inflight =
@@ -86,18 +127,31 @@ impl RepositoryMonitor {
         let response_handle_time =
             create_histogram(recorder, "response handle time", Unit::Seconds);
 
+        let slow_transactions = create_counter(recorder, "slow transactions", Unit::Count);
+        let write_wait_time = create_histogram(recorder, "write wait time", Unit::Seconds);
+        let write_waiters = create_gauge(recorder, "write waiters", Unit::Count);
+        let events_lagged = create_counter(recorder, "events lagged", Unit::Count);
+
+        let dht = DhtMonitor::new(recorder);
+
         let scan_job = JobMonitor::new(&node, recorder, "scan");
         let merge_job = JobMonitor::new(&node, recorder, "merge");
         let prune_job = JobMonitor::new(&node, recorder, "prune");
         let trash_job = JobMonitor::new(&node, recorder, "trash");
 
+        let peers_node = node.make_child("peers");
+
         Self {
             info_hash,
+            prune_policy,
+            next_prune,
 
             index_requests_sent,
             index_requests_inflight,
             block_requests_sent,
             block_requests_inflight,
+            requests_inflight_count: AtomicU64::new(0),
+            requests_idle_tx: watch::channel(()).0,
             requests_received,
             requests_pending,
             request_latency,
@@ -109,14 +163,76 @@ impl RepositoryMonitor {
             response_queue_time,
             response_handle_time,
 
+            slow_transactions,
+            write_wait_time,
+            write_waiters,
+            events_lagged,
+
+            dht,
+
             scan_job,
             merge_job,
             prune_job,
             trash_job,
 
+            peers: Arc::new(BlockingMutex::new(HashMap::default())),
+            peers_node,
+
             span,
             node,
+            #[cfg(feature = "prometheus")]
+            prometheus_handle: None,
+        }
+    }
+
+    #[cfg(feature = "prometheus")]
+    pub fn with_prometheus_handle(self, prometheus_handle: Option<PrometheusHandle>) -> Self {
+        Self {
+            prometheus_handle,
+            ..self
+        }
+    }
+
+    #[cfg(feature = "prometheus")]
+    pub fn prometheus_handle(&self) -> Option<&PrometheusHandle> {
+        self.prometheus_handle.as_ref()
+    }
+
+    pub fn request_inflight_added(&self) {
+        self.requests_inflight_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn request_inflight_removed(&self) {
+        if self.requests_inflight_count.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.requests_idle_tx.send_replace(());
+        }
+    }
+
+    /// Current number of sent index and block requests for which responses haven't been received
+    /// yet.
+    pub fn requests_inflight(&self) -> u64 {
+        self.requests_inflight_count.load(Ordering::Relaxed)
+    }
+
+    /// Get notified whenever the number of inflight requests drops to zero.
+    pub fn subscribe_requests_idle(&self) -> watch::Receiver<()> {
+        self.requests_idle_tx.subscribe()
+    }
+
+    /// Returns the metrics of the given peer. They are kept only while the returned monitor is
+    /// alive (that is, while the peer is connected) so their number is bounded by the number of
+    /// connected peers.
+    pub fn peer(&self, id: PublicRuntimeId) -> Arc<PeerMonitor> {
+        let mut peers = self.peers.lock().unwrap();
+
+        if let Some(monitor) = peers.get(&id).and_then(Weak::upgrade) {
+            return monitor;
         }
+
+        let monitor = Arc::new(PeerMonitor::new(&self.peers_node, id, self.peers.clone()));
+        peers.insert(id, Arc::downgrade(&monitor));
+
+        monitor
     }
 
     pub fn span(&self) -> &Span {
@@ -132,6 +248,111 @@ impl RepositoryMonitor {
     }
 }
 
+/// Metrics of a single peer. Recorded only into the state monitor (under `peers/<runtime id>`),
+/// not into the repository's recorder, because the recorded series can't be removed from it once
+/// the peer disconnects.
+pub(crate) struct PeerMonitor {
+    id: PublicRuntimeId,
+    peers: Arc<BlockingMutex<HashMap<PublicRuntimeId, Weak<PeerMonitor>>>>,
+    // Time a request to this peer spends in the send queue.
+    pub request_queue_time: Histogram,
+    // Time a response from this peer spends in the receive queue.
+    pub response_queue_time: Histogram,
+}
+
+impl PeerMonitor {
+    fn new(
+        parent_node: &StateMonitor,
+        id: PublicRuntimeId,
+        peers: Arc<BlockingMutex<HashMap<PublicRuntimeId, Weak<PeerMonitor>>>>,
+    ) -> Self {
+        let recorder =
+            MetricsRecorder::new(parent_node.make_child(format!("{:?}", id.as_public_key())));
+
+        Self {
+            id,
+            peers,
+            request_queue_time: create_histogram(&recorder, "request queue time", Unit::Seconds),
+            response_queue_time: create_histogram(&recorder, "response queue time", Unit::Seconds),
+        }
+    }
+}
+
+impl Drop for PeerMonitor {
+    fn drop(&mut self) {
+        let mut peers = self.peers.lock().unwrap();
+
+        // Don't remove the monitor of the same peer which reconnected in the meantime.
+        if let Entry::Occupied(entry) = peers.entry(self.id) {
+            if entry.get().strong_count() == 0 {
+                entry.remove();
+            }
+        }
+    }
+}
+
+/// Metrics of the DHT lookups of a repository.
+#[derive(Clone)]
+pub(crate) struct DhtMonitor {
+    // Total number of lookups started, including the periodic re-announces.
+    pub lookups_started: Counter,
+    // Total number of lookups that completed without finding any peer.
+    pub lookups_empty: Counter,
+    // Number of peers found per lookup.
+    pub peers_found: Histogram,
+    // Time from starting a lookup to finding its first peer.
+    pub time_to_first_peer: Histogram,
+}
+
+impl DhtMonitor {
+    fn new<R>(recorder: &R) -> Self
+    where
+        R: Recorder + ?Sized,
+    {
+        Self {
+            lookups_started: create_counter(recorder, "dht lookups started", Unit::Count),
+            lookups_empty: create_counter(recorder, "dht lookups empty", Unit::Count),
+            peers_found: create_histogram(recorder, "dht peers found", Unit::Count),
+            time_to_first_peer: create_histogram(recorder, "dht time to first peer", Unit::Seconds),
+        }
+    }
+
+    /// Records the start of a lookup. The returned monitor records its results.
+    pub fn start_lookup(&self) -> DhtLookupMonitor<'_> {
+        self.lookups_started.increment(1);
+
+        DhtLookupMonitor {
+            monitor: self,
+            start: Instant::now(),
+            found: 0,
+        }
+    }
+}
+
+pub(crate) struct DhtLookupMonitor<'a> {
+    monitor: &'a DhtMonitor,
+    start: Instant,
+    found: usize,
+}
+
+impl DhtLookupMonitor<'_> {
+    pub fn peer_found(&mut self) {
+        if self.found == 0 {
+            self.monitor.time_to_first_peer.record(self.start.elapsed());
+        }
+
+        self.found += 1;
+    }
+
+    pub fn finish(self) {
+        self.monitor.peers_found.record(self.found as f64);
+
+        if self.found == 0 {
+            self.monitor.lookups_empty.increment(1);
+        }
+    }
+}
+
 pub(crate) struct JobMonitor {
     tx: watch::Sender<bool>,
     name: String,
@@ -194,29 +415,52 @@ impl JobMonitor {
         F: Future<Output = Result<(), E>>,
         E: fmt::Debug,
     {
-        if self.tx.send_replace(true) {
-            panic!("job monitor can monitor at most one job at a time");
+        self.try_run(f)
+            .await
+            .expect("job monitor can monitor at most one job at a time")
+            .is_ok()
+    }
+
+    /// Like `run` but returns `None` instead of panicking if another job is already being
+    /// monitored. Otherwise returns the result of the job.
+    pub(crate) async fn try_run<F, E>(&self, f: F) -> Option<Result<(), E>>
+    where
+        F: Future<Output = Result<(), E>>,
+        E: fmt::Debug,
+    {
+        let started = self.tx.send_if_modified(|running| {
+            if *running {
+                false
+            } else {
+                *running = true;
+                true
+            }
+        });
+
+        if !started {
+            return None;
         }
 
-        async move {
+        let result = async move {
             let guard = JobGuard::start(self);
             let start = Instant::now();
 
             let result = f.await;
-            let is_ok = result.is_ok();
 
             self.time.record(start.elapsed());
 
-            guard.complete(result);
+            guard.complete(&result);
 
-            is_ok
+            result
         }
         .instrument(tracing::info_span!(
             "job",
             message = self.name,
             id = self.counter.fetch_add(1, Ordering::Relaxed),
         ))
-        .await
+        .await;
+
+        Some(result)
     }
 }
 
@@ -239,7 +483,7 @@ impl<'a> JobGuard<'a> {
         }
     }
 
-    fn complete<E: fmt::Debug>(mut self, result: Result<(), E>) {
+    fn complete<E: fmt::Debug>(mut self, result: &Result<(), E>) {
         self.completed = true;
         tracing::trace!(parent: &self.span, ?result, "Job completed");
     }
@@ -307,3 +551,51 @@ fn create_histogram<R: Recorder + ?Sized, N: Into<SharedString>>(
         &Metadata::new(module_path!(), Level::INFO, None),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::SecretRuntimeId;
+    use metrics::NoopRecorder;
+    use metrics_ext::WatchRecorder;
+
+    #[tokio::test]
+    async fn peer_monitors_are_removed_on_disconnect() {
+        let monitor = RepositoryMonitor::new(StateMonitor::make_root(), &NoopRecorder);
+        let id_a = SecretRuntimeId::random().public();
+        let id_b = SecretRuntimeId::random().public();
+
+        let peer_a = monitor.peer(id_a);
+        let peer_b = monitor.peer(id_b);
+
+        // Registering the same peer again returns the same monitor.
+        assert!(Arc::ptr_eq(&peer_a, &monitor.peer(id_a)));
+        assert!(!Arc::ptr_eq(&peer_a, &peer_b));
+        assert_eq!(monitor.peers.lock().unwrap().len(), 2);
+
+        drop(peer_a);
+        assert_eq!(monitor.peers.lock().unwrap().len(), 1);
+        assert!(monitor.peers.lock().unwrap().contains_key(&id_b));
+    }
+
+    #[test]
+    fn dht_lookups() {
+        let recorder = WatchRecorder::new();
+        let subscriber = recorder.subscriber();
+        let started = subscriber.counter("dht lookups started".into());
+        let empty = subscriber.counter("dht lookups empty".into());
+
+        let monitor = DhtMonitor::new(&recorder);
+
+        monitor.start_lookup().finish();
+        assert_eq!(*started.borrow(), 1);
+        assert_eq!(*empty.borrow(), 1);
+
+        let mut lookup = monitor.start_lookup();
+        lookup.peer_found();
+        lookup.peer_found();
+        lookup.finish();
+        assert_eq!(*started.borrow(), 2);
+        assert_eq!(*empty.borrow(), 1);
+    }
+}
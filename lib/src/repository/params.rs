@@ -1,17 +1,48 @@
 use super::RepositoryMonitor;
-use crate::{db, device_id::DeviceId, error::Result};
+use crate::{
+    crypto::KdfParams,
+    db::{self, ResourceLimits, Synchronous, WalCheckpoint},
+    device_id::DeviceId,
+    error::Result,
+    path,
+    rng::RngSource,
+    store::{self, BlockStore, SharedCache},
+};
 use metrics::{NoopRecorder, Recorder};
+#[cfg(feature = "prometheus")]
+use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle, PrometheusRecorder};
 use state_monitor::{metrics::MetricsRecorder, StateMonitor};
 use std::{
     borrow::Cow,
     path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
 
+const DEFAULT_EVENT_CAPACITY: usize = 256;
+const DEFAULT_SYNC_PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_MAX_SNAPSHOTS: usize = 2;
+
 pub struct RepositoryParams<R> {
     store: Store,
     device_id: DeviceId,
+    kdf_params: Option<KdfParams>,
+    wal_checkpoint: WalCheckpoint,
+    synchronous: Synchronous,
+    slow_transaction_threshold: Duration,
+    event_capacity: usize,
+    max_path_depth: usize,
+    max_file_size: u64,
+    sync_progress_interval: Duration,
+    max_snapshots: usize,
+    rng_source: RngSource,
     parent_monitor: Option<StateMonitor>,
+    block_store: Option<Arc<dyn BlockStore>>,
+    block_cache: Option<SharedCache>,
+    resource_limits: Option<ResourceLimits>,
     recorder: Option<R>,
+    #[cfg(feature = "prometheus")]
+    prometheus_handle: Option<PrometheusHandle>,
 }
 
 impl<R> RepositoryParams<R> {
@@ -19,6 +50,159 @@ impl<R> RepositoryParams<R> {
         Self { device_id, ..self }
     }
 
+    /// Params of the function used to derive keys from passwords. When creating a repository they
+    /// are stored in it and used for all subsequent password derivations. When opening one, they
+    /// are checked against the stored ones and `Error::KdfParamsMismatch` is returned if they
+    /// differ. If not set, the default params are used when creating and no check is done when
+    /// opening. Use [`Repository::set_kdf_params`](super::Repository::set_kdf_params) to change
+    /// the params of an existing repository.
+    pub fn with_kdf_params(self, kdf_params: KdfParams) -> Self {
+        Self {
+            kdf_params: Some(kdf_params),
+            ..self
+        }
+    }
+
+    /// Strategy for checkpointing the database write-ahead log. See [`WalCheckpoint`] for more
+    /// details.
+    pub fn with_wal_checkpoint(self, wal_checkpoint: WalCheckpoint) -> Self {
+        Self {
+            wal_checkpoint,
+            ..self
+        }
+    }
+
+    /// How durable are the writes to the database, i.e. how often does the database wait for the
+    /// data to reach the disk. Higher levels survive power losses and OS crashes better but make
+    /// writes slower. See [`Synchronous`] for the available levels and their trade-offs. Applies to
+    /// both creating and opening a repository. Defaults to [`Synchronous::Normal`].
+    pub fn with_synchronous(self, synchronous: Synchronous) -> Self {
+        Self {
+            synchronous,
+            ..self
+        }
+    }
+
+    /// How long can a db transaction be held before it's reported as slow. Slow transactions are
+    /// logged, counted in the `slow transactions` metric and sent to the subscribers of
+    /// [`Repository::subscribe_slow_transactions`](crate::Repository::subscribe_slow_transactions).
+    pub fn with_slow_transaction_threshold(self, slow_transaction_threshold: Duration) -> Self {
+        Self {
+            slow_transaction_threshold,
+            ..self
+        }
+    }
+
+    /// Capacity of the channel used to deliver events to the subscribers of
+    /// [`Repository::subscribe`](crate::Repository::subscribe). Subscribers that fall behind by
+    /// more than this many events miss the oldest ones and receive `RecvError::Lagged` instead.
+    /// The number of events missed by the repository itself is counted in the `events lagged`
+    /// metric. Defaults to 256.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `event_capacity` is zero.
+    pub fn with_event_capacity(self, event_capacity: usize) -> Self {
+        assert!(
+            event_capacity > 0,
+            "event capacity must be greater than zero"
+        );
+
+        Self {
+            event_capacity,
+            ..self
+        }
+    }
+
+    /// Maximum nesting depth of directories in the repository, i.e. the maximum number of
+    /// components of a path to a directory. Creating, opening or recursively walking directories
+    /// deeper than this fails with `Error::PathTooDeep`. This protects against unbounded recursion
+    /// when processing directory trees crafted by malicious writers. Defaults to 256.
+    pub fn with_max_path_depth(self, max_path_depth: usize) -> Self {
+        Self {
+            max_path_depth,
+            ..self
+        }
+    }
+
+    /// Maximum size of a file in bytes. Writes that would grow a file past this size fail with
+    /// `Error::FileTooLarge`. Applies only to the files written through this repository instance,
+    /// not to the files received from other replicas. Defaults to the largest size the repository
+    /// format supports (about 128 TiB).
+    pub fn with_max_file_size(self, max_file_size: u64) -> Self {
+        Self {
+            max_file_size,
+            ..self
+        }
+    }
+
+    /// Minimal interval between two updates of the sync progress reported by
+    /// [`Repository::watch_sync_progress`](crate::Repository::watch_sync_progress) (and logged).
+    /// The progress is recomputed after the repository changes but at most this often. Shorter
+    /// interval makes the progress more responsive at the cost of more database queries during
+    /// sync. Defaults to one second.
+    pub fn with_sync_progress_interval(self, sync_progress_interval: Duration) -> Self {
+        Self {
+            sync_progress_interval,
+            ..self
+        }
+    }
+
+    /// Maximum number of [`Snapshot`](crate::Snapshot)s of the repository that can exist at the
+    /// same time. Taking another one fails with `Error::TooManySnapshots`. Each snapshot holds a
+    /// database read connection, so raising this slows down the other reads. Defaults to 2.
+    pub fn with_max_snapshots(self, max_snapshots: usize) -> Self {
+        Self {
+            max_snapshots,
+            ..self
+        }
+    }
+
+    /// Source of the randomness used to generate the writer ids and the ephemeral database id of
+    /// the repository. Defaults to [`RngSource::Os`]. Setting it to [`RngSource::Seeded`] makes
+    /// them reproducible, which is useful in tests and simulations but must never be used in
+    /// production.
+    ///
+    /// Everything else is still generated by the OS generator regardless of this setting, most
+    /// notably the blob ids, the password salt, the database id and the nonces of the encrypted
+    /// metadata values. The block nonces are derived from the content, so they are reproducible
+    /// anyway.
+    pub fn with_rng_source(self, rng_source: RngSource) -> Self {
+        Self { rng_source, ..self }
+    }
+
+    /// Store the block contents in the given custom store instead of the repository database. The
+    /// index and the metadata are still stored in the database. The same block store must be used
+    /// every time the repository is opened, otherwise its content appears missing. Delete the
+    /// repository with [`crate::delete_repository_with_block_store`] so the blocks are removed
+    /// from the custom store as well.
+    pub fn with_block_store(self, block_store: impl BlockStore) -> Self {
+        Self {
+            block_store: Some(Arc::new(block_store)),
+            ..self
+        }
+    }
+
+    /// Look up the blocks in the given in-memory cache before reading them from the store. The
+    /// same cache can be passed to multiple repositories so that they share a single memory budget
+    /// and the blocks they have in common are cached only once. See [`SharedCache`] for details.
+    pub fn with_shared_cache(self, block_cache: SharedCache) -> Self {
+        Self {
+            block_cache: Some(block_cache),
+            ..self
+        }
+    }
+
+    /// Limit the number of database connections of this repository, together with all the other
+    /// repositories using the same limits. Opening the repository waits until its write connection
+    /// fits in the limits. See [`ResourceLimits`] for details.
+    pub fn with_resource_limits(self, resource_limits: ResourceLimits) -> Self {
+        Self {
+            resource_limits: Some(resource_limits),
+            ..self
+        }
+    }
+
     pub fn with_parent_monitor(self, parent_monitor: StateMonitor) -> Self {
         Self {
             parent_monitor: Some(parent_monitor),
@@ -30,14 +214,47 @@ impl<R> RepositoryParams<R> {
         RepositoryParams {
             store: self.store,
             device_id: self.device_id,
+            kdf_params: self.kdf_params,
+            wal_checkpoint: self.wal_checkpoint,
+            synchronous: self.synchronous,
+            slow_transaction_threshold: self.slow_transaction_threshold,
+            event_capacity: self.event_capacity,
+            max_path_depth: self.max_path_depth,
+            max_file_size: self.max_file_size,
+            sync_progress_interval: self.sync_progress_interval,
+            max_snapshots: self.max_snapshots,
+            rng_source: self.rng_source,
             parent_monitor: self.parent_monitor,
+            block_store: self.block_store,
+            block_cache: self.block_cache,
+            resource_limits: self.resource_limits,
             recorder: Some(recorder),
+            #[cfg(feature = "prometheus")]
+            prometheus_handle: None,
+        }
+    }
+
+    /// Record the repository metrics into a Prometheus recorder. The metrics can then be rendered
+    /// in the Prometheus text format using the handle returned from
+    /// [`Repository::metrics_handle`](crate::Repository::metrics_handle).
+    ///
+    /// Metric names are derived from the names the metrics are registered with, with any
+    /// characters not allowed by Prometheus replaced by underscores (e.g. "block requests sent"
+    /// becomes `block_requests_sent`).
+    #[cfg(feature = "prometheus")]
+    pub fn with_prometheus(self) -> RepositoryParams<PrometheusRecorder> {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let prometheus_handle = recorder.handle();
+
+        RepositoryParams {
+            prometheus_handle: Some(prometheus_handle),
+            ..self.with_recorder(recorder)
         }
     }
 
     pub(super) async fn create(&self) -> Result<db::Pool, db::Error> {
         match &self.store {
-            Store::Path(path) => db::create(path).await,
+            Store::Path(path) => db::create(path, self.synchronous).await,
             #[cfg(test)]
             Store::Pool { pool, .. } => Ok(pool.clone()),
         }
@@ -45,15 +262,62 @@ impl<R> RepositoryParams<R> {
 
     pub(super) async fn open(&self) -> Result<db::Pool, db::Error> {
         match &self.store {
-            Store::Path(path) => db::open(path).await,
+            Store::Path(path) => db::open(path, self.synchronous).await,
             #[cfg(test)]
             Store::Pool { pool, .. } => Ok(pool.clone()),
         }
     }
 
+    pub(super) async fn open_read_only(&self) -> Result<db::Pool, db::Error> {
+        match &self.store {
+            Store::Path(path) => db::open_read_only(path).await,
+            #[cfg(test)]
+            Store::Pool { pool, .. } => Ok(pool.clone()),
+        }
+    }
+
+    pub(super) async fn make_store(&self, pool: db::Pool) -> Result<store::Store, store::Error> {
+        if let Some(resource_limits) = &self.resource_limits {
+            pool.set_resource_limits(resource_limits.clone()).await;
+        }
+
+        let store = store::Store::new(pool);
+        let store = if let Some(block_cache) = &self.block_cache {
+            store.with_block_cache(block_cache.clone())
+        } else {
+            store
+        };
+
+        if let Some(block_store) = &self.block_store {
+            store.with_block_store(block_store.clone()).await
+        } else {
+            Ok(store)
+        }
+    }
+
     pub(super) fn device_id(&self) -> DeviceId {
         self.device_id
     }
+
+    pub(super) fn kdf_params(&self) -> Option<KdfParams> {
+        self.kdf_params
+    }
+
+    pub(super) fn options(&self) -> Options {
+        Options {
+            wal_checkpoint: self.wal_checkpoint,
+            slow_transaction_threshold: self.slow_transaction_threshold,
+            event_capacity: self.event_capacity,
+            max_path_depth: self.max_path_depth,
+            max_file_size: self.max_file_size,
+            sync_progress_interval: self.sync_progress_interval,
+            max_snapshots: self.max_snapshots,
+        }
+    }
+
+    pub(super) fn rng_source(&self) -> RngSource {
+        self.rng_source
+    }
 }
 
 impl<R> RepositoryParams<R>
@@ -69,15 +333,25 @@ where
             StateMonitor::make_root()
         };
 
-        if let Some(recorder) = &self.recorder {
+        let monitor = if let Some(recorder) = &self.recorder {
             RepositoryMonitor::new(monitor, recorder)
         } else {
             RepositoryMonitor::new(monitor.clone(), &MetricsRecorder::new(monitor))
-        }
+        };
+
+        #[cfg(feature = "prometheus")]
+        let monitor = monitor.with_prometheus_handle(self.prometheus_handle.clone());
+
+        monitor
     }
 }
 
 impl RepositoryParams<NoopRecorder> {
+    /// Params for the repository database at `path`.
+    ///
+    /// NOTE: The database creates its temporary files (e.g., for large transactions) in the system
+    /// temp directory. To use a different one, set the `SQLITE_TMPDIR` environment variable (unix
+    /// only) before the process starts. It can't be set safely at runtime.
     pub fn new(path: impl AsRef<Path>) -> Self {
         Self::with_store(Store::Path(path.as_ref().to_path_buf()))
     }
@@ -94,12 +368,38 @@ impl RepositoryParams<NoopRecorder> {
         Self {
             store,
             device_id: rand::random(),
+            kdf_params: None,
+            wal_checkpoint: WalCheckpoint::default(),
+            synchronous: Synchronous::default(),
+            slow_transaction_threshold: db::WARN_AFTER_TRANSACTION_LIFETIME,
+            event_capacity: DEFAULT_EVENT_CAPACITY,
+            max_path_depth: path::DEFAULT_MAX_DEPTH,
+            max_file_size: u64::MAX,
+            sync_progress_interval: DEFAULT_SYNC_PROGRESS_INTERVAL,
+            max_snapshots: DEFAULT_MAX_SNAPSHOTS,
+            rng_source: RngSource::Os,
             parent_monitor: None,
+            block_store: None,
+            block_cache: None,
+            resource_limits: None,
             recorder: None,
+            #[cfg(feature = "prometheus")]
+            prometheus_handle: None,
         }
     }
 }
 
+/// The params that stay in effect for as long as the repository is open.
+pub(super) struct Options {
+    pub wal_checkpoint: WalCheckpoint,
+    pub slow_transaction_threshold: Duration,
+    pub event_capacity: usize,
+    pub max_path_depth: usize,
+    pub max_file_size: u64,
+    pub sync_progress_interval: Duration,
+    pub max_snapshots: usize,
+}
+
 enum Store {
     Path(PathBuf),
     #[cfg(test)]
@@ -0,0 +1,120 @@
+//! Repair of missing and corrupted blocks by re-downloading them from peers.
+
+use super::Shared;
+use crate::{
+    collections::HashSet,
+    error::Result,
+    event::{Event, Payload},
+    protocol::BlockId,
+    store::IntegrityViolation,
+};
+use futures_util::TryStreamExt;
+use std::time::Duration;
+use tokio::{
+    sync::broadcast::error::RecvError,
+    time::{self, Instant},
+};
+
+/// Outcome of [`Repository::repair`](crate::Repository::repair).
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct RepairStats {
+    /// Number of missing or corrupted blocks that were successfully re-downloaded.
+    pub repaired: usize,
+    /// Number of missing or corrupted blocks that haven't been re-downloaded (yet).
+    pub missing: usize,
+}
+
+pub(super) async fn repair(shared: &Shared, timeout: Duration) -> Result<RepairStats> {
+    let store = shared.vault.store();
+
+    let block_ids: HashSet<BlockId> = store
+        .check_integrity(shared.vault.repository_id())
+        .await?
+        .into_iter()
+        .filter_map(|violation| match violation {
+            IntegrityViolation::MissingBlock { block_id }
+            | IntegrityViolation::BlockIdMismatch { block_id } => Some(block_id),
+            IntegrityViolation::InvalidSignature { .. }
+            | IntegrityViolation::NodeHashMismatch { .. }
+            | IntegrityViolation::DanglingInnerNode { .. }
+            | IntegrityViolation::DanglingLeafNode { .. }
+            | IntegrityViolation::UnreferencedBlock { .. } => None,
+        })
+        .collect();
+
+    if block_ids.is_empty() {
+        return Ok(RepairStats::default());
+    }
+
+    // Subscribe before requesting the blocks so we don't miss any.
+    let mut rx = shared.vault.event_tx.subscribe();
+
+    // Remove the corrupted blocks and mark them all as missing in the index.
+    let mut tx = store.begin_write().await?;
+
+    for block_id in &block_ids {
+        tx.remove_block(block_id).await?;
+    }
+
+    let branch_ids: Vec<_> = tx
+        .load_root_nodes()
+        .map_ok(|node| node.proof.writer_id)
+        .try_collect()
+        .await?;
+
+    tx.commit().await?;
+
+    tracing::debug!(
+        count = block_ids.len(),
+        "Requesting missing or corrupted blocks"
+    );
+
+    // Make the clients reload the indices from the peers so they learn which peers have the
+    // blocks, then request them.
+    for branch_id in &branch_ids {
+        store.client_reload_index_tx.insert(branch_id);
+    }
+
+    for block_id in &block_ids {
+        shared.vault.block_tracker.require(*block_id);
+    }
+
+    // Wait for the blocks to be received. The received blocks are verified in the regular receive
+    // path.
+    let total = block_ids.len();
+    let mut pending = block_ids;
+    let deadline = Instant::now() + timeout;
+
+    while !pending.is_empty() {
+        match time::timeout_at(deadline, rx.recv()).await {
+            Ok(Ok(Event {
+                payload: Payload::BlockReceived(block_id),
+                ..
+            })) => {
+                pending.remove(&block_id);
+            }
+            Ok(Ok(_)) => (),
+            Ok(Err(RecvError::Lagged(_))) => {
+                // Some events might have been missed, check the pending blocks directly.
+                let mut reader = store.acquire_read().await?;
+                let mut received = Vec::new();
+
+                for block_id in &pending {
+                    if reader.block_exists(block_id).await? {
+                        received.push(*block_id);
+                    }
+                }
+
+                for block_id in received {
+                    pending.remove(&block_id);
+                }
+            }
+            Ok(Err(RecvError::Closed)) | Err(_) => break,
+        }
+    }
+
+    Ok(RepairStats {
+        repaired: total - pending.len(),
+        missing: pending.len(),
+    })
+}
@@ -0,0 +1,227 @@
+//! Read-only snapshots of the repository content.
+
+use crate::{
+    blob::{Blob, BlobId},
+    branch::Branch,
+    crypto::sign::PublicKey,
+    directory::{Directory, EntryType},
+    error::{Error, Result},
+    joint_directory::JointDirectory,
+    path,
+    protocol::RootNode,
+    store::{self, ReadTransaction},
+    version_vector::VersionVector,
+};
+use camino::{Utf8Component, Utf8Path};
+use std::{io::SeekFrom, sync::Arc};
+use tokio::sync::{Mutex as AsyncMutex, OwnedSemaphorePermit};
+
+/// Read-only view of the repository content as of the moment it was taken (see
+/// [`Repository::snapshot`](crate::Repository::snapshot)). Reading from the snapshot is not
+/// affected by the changes made to the repository afterwards, whether locally or by syncing with
+/// other replicas.
+///
+/// The snapshot keeps a database read transaction open for as long as it or any file opened from
+/// it exists. The transaction occupies one of the few pooled read connections of the repository
+/// (and counts towards the [`ResourceLimits`](crate::ResourceLimits) if the repository uses them)
+/// which slows down all the other reads, including the ones done by the sync. It also prevents the
+/// database write-ahead log from being checkpointed. The snapshot should therefore be dropped as
+/// soon as it's no longer needed and only a few snapshots of a repository can exist at the same
+/// time (see [`RepositoryParams`](crate::RepositoryParams::with_max_snapshots)). Note also that
+/// blocks stored in a custom [`BlockStore`](crate::BlockStore) are not covered by the transaction
+/// and so reading them can fail if they are removed in the meantime.
+#[derive(Clone)]
+pub struct Snapshot {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    tx: AsyncMutex<ReadTransaction>,
+    // Root nodes of the branches at the time the snapshot was taken.
+    branches: Vec<(Branch, RootNode)>,
+    max_path_depth: usize,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Snapshot {
+    pub(super) fn new(
+        tx: ReadTransaction,
+        branches: Vec<(Branch, RootNode)>,
+        max_path_depth: usize,
+        permit: OwnedSemaphorePermit,
+    ) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                tx: AsyncMutex::new(tx),
+                branches,
+                max_path_depth,
+                _permit: permit,
+            }),
+        }
+    }
+
+    /// Version vector of the snapshot, i.e. the merge of the version vectors of all its branches.
+    pub fn version_vector(&self) -> VersionVector {
+        self.shared
+            .branches
+            .iter()
+            .fold(VersionVector::new(), |vv, (_, root_node)| {
+                vv.merged(&root_node.proof.version_vector)
+            })
+    }
+
+    /// Lists the entries of the directory at the given path as of this snapshot. Returns their
+    /// names and types. If an entry has multiple concurrent versions, its name is disambiguated so
+    /// it can be passed to [`Self::open_file`].
+    pub async fn read_dir<P: AsRef<Utf8Path>>(&self, path: P) -> Result<Vec<(String, EntryType)>> {
+        let dir = self.cd(path).await?;
+
+        Ok(dir
+            .entries()
+            .map(|entry| (entry.unique_name().into_owned(), entry.entry_type()))
+            .collect())
+    }
+
+    // Opens the directory at the given path as of this snapshot. Note that opening the entries of
+    // the returned directory directly would yield their current versions, not the ones from this
+    // snapshot, so it must not be exposed.
+    async fn cd<P: AsRef<Utf8Path>>(&self, path: P) -> Result<JointDirectory> {
+        let path = path.as_ref();
+        path::check_depth(path, self.shared.max_path_depth)?;
+
+        let mut tx = self.shared.tx.lock().await;
+
+        let mut curr = self
+            .open_directory(
+                &mut tx,
+                self.shared
+                    .branches
+                    .iter()
+                    .map(|(branch, _)| (branch.clone(), BlobId::ROOT)),
+            )
+            .await?;
+
+        for component in path.components() {
+            match component {
+                Utf8Component::RootDir | Utf8Component::CurDir => (),
+                Utf8Component::Normal(name) => {
+                    let versions: Vec<_> = curr
+                        .lookup(name)
+                        .find_map(|entry| entry.directory().ok())
+                        .ok_or(Error::EntryNotFound)?
+                        .versions()
+                        .iter()
+                        .map(|version| (version.branch().clone(), *version.blob_id()))
+                        .collect();
+
+                    curr = self.open_directory(&mut tx, versions).await?;
+                }
+                Utf8Component::ParentDir | Utf8Component::Prefix(_) => {
+                    return Err(Error::OperationNotSupported)
+                }
+            }
+        }
+
+        Ok(curr)
+    }
+
+    /// Opens the file at the given path as of this snapshot. Like with
+    /// [`Repository::open_file`](crate::Repository::open_file), if the file has multiple
+    /// concurrent versions, the name must be disambiguated to select one of them.
+    pub async fn open_file<P: AsRef<Utf8Path>>(&self, path: P) -> Result<SnapshotFile> {
+        let (parent, name) = path::decompose(path.as_ref()).ok_or(Error::EntryIsDirectory)?;
+        let parent = self.cd(parent).await?;
+        let entry = parent.lookup_unique(name)?.file()?;
+
+        let root_node = self.root_node(entry.branch().id())?.clone();
+        let mut tx = self.shared.tx.lock().await;
+        let blob = Blob::open_at(
+            &mut tx,
+            &root_node,
+            entry.branch().clone(),
+            *entry.blob_id(),
+        )
+        .await?;
+
+        Ok(SnapshotFile {
+            snapshot: self.clone(),
+            root_node,
+            blob,
+        })
+    }
+
+    // Opens the given versions of a directory, each specified by its branch and blob id. Versions
+    // that are not yet fully downloaded are skipped unless all of them are.
+    async fn open_directory<I>(
+        &self,
+        tx: &mut ReadTransaction,
+        versions: I,
+    ) -> Result<JointDirectory>
+    where
+        I: IntoIterator<Item = (Branch, BlobId)>,
+    {
+        let mut dirs = Vec::new();
+        let mut last_error = None;
+
+        for (branch, blob_id) in versions {
+            let root_node = self.root_node(branch.id())?;
+
+            match Directory::open_at(tx, root_node, branch, blob_id).await {
+                Ok(dir) => dirs.push(dir),
+                Err(error @ Error::Store(store::Error::BlockNotFound)) => {
+                    last_error = Some(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        match last_error {
+            Some(error) if dirs.is_empty() => Err(error),
+            _ => Ok(JointDirectory::new(None, dirs)),
+        }
+    }
+
+    fn root_node(&self, branch_id: &PublicKey) -> Result<&RootNode> {
+        self.shared
+            .branches
+            .iter()
+            .find(|(branch, _)| branch.id() == branch_id)
+            .map(|(_, root_node)| root_node)
+            .ok_or(Error::Store(store::Error::BranchNotFound))
+    }
+}
+
+/// File opened from a [`Snapshot`]. Its content is read as of the snapshot.
+pub struct SnapshotFile {
+    snapshot: Snapshot,
+    root_node: RootNode,
+    blob: Blob,
+}
+
+impl SnapshotFile {
+    /// Length of this file in bytes.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.blob.len()
+    }
+
+    /// Sets the offset for the next read and returns the new offset.
+    pub fn seek(&mut self, pos: SeekFrom) -> u64 {
+        self.blob.seek(pos)
+    }
+
+    /// Reads data from this file into `buffer`, filling it whole unless the end of the file is
+    /// reached first. Returns the number of bytes read.
+    pub async fn read_all(&mut self, buffer: &mut [u8]) -> Result<usize> {
+        let mut tx = self.snapshot.shared.tx.lock().await;
+        self.blob
+            .read_all_at(&mut tx, &self.root_node, buffer)
+            .await
+    }
+
+    /// Reads the content of this file from the current offset until the end.
+    pub async fn read_to_end(&mut self) -> Result<Vec<u8>> {
+        let mut tx = self.snapshot.shared.tx.lock().await;
+        self.blob.read_to_end_at(&mut tx, &self.root_node).await
+    }
+}
@@ -1,11 +1,16 @@
 use super::*;
 use crate::{
-    blob, db,
+    blob,
+    collections::HashMap,
+    crypto::{KdfParams, Password},
+    db,
     protocol::{BlockId, BLOCK_NONCE_SIZE, BLOCK_SIZE},
-    test_utils, WriteSecrets,
+    test_utils, BlockStore, RngSource, SharedCache, WriteSecrets,
 };
 use assert_matches::assert_matches;
+use async_trait::async_trait;
 use rand::Rng;
+use sqlx::Row;
 use std::{future::Future, io::SeekFrom};
 use tempfile::TempDir;
 use tokio::{
@@ -551,6 +556,120 @@ async fn blind_access_non_empty_repo() {
     }
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn open_with_lower_access_mode() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+    let local_secret = LocalSecret::random();
+
+    let repo = Repository::create(
+        &params,
+        Access::ReadLocked {
+            id: RepositoryId::random(),
+            local_secret: local_secret.clone(),
+            read_key: cipher::SecretKey::random(),
+        },
+    )
+    .await
+    .unwrap();
+    drop(repo);
+
+    let repo = Repository::open(&params, Some(local_secret.clone()), AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Read);
+    drop(repo);
+
+    let repo = Repository::open(&params, Some(local_secret), AccessMode::Blind)
+        .await
+        .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Blind);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn kdf_params() {
+    test_utils::init_log();
+
+    let base_dir = TempDir::new().unwrap();
+    let device_id = rand::random();
+    let make_params = |kdf_params| {
+        RepositoryParams::new(base_dir.path().join("repo.db"))
+            .with_device_id(device_id)
+            .with_kdf_params(kdf_params)
+    };
+
+    let kdf_params = KdfParams::new(1024, 1, 1).unwrap();
+    let local_secret = LocalSecret::Password(Password::from("supersecret".to_owned()));
+    let secrets = WriteSecrets::random();
+    let access = || Access::WriteLocked {
+        local_read_secret: local_secret.clone(),
+        local_write_secret: local_secret.clone(),
+        secrets: secrets.clone(),
+    };
+
+    let repo = Repository::create(&make_params(kdf_params), access())
+        .await
+        .unwrap();
+    repo.close().await.unwrap();
+    drop(repo);
+
+    // Same params
+    let repo = Repository::open(
+        &make_params(kdf_params),
+        Some(local_secret.clone()),
+        AccessMode::Write,
+    )
+    .await
+    .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Write);
+    assert_eq!(
+        repo.crypto_info().await.unwrap().password_kdf_params,
+        kdf_params
+    );
+
+    // Different params
+    let other_kdf_params = KdfParams::new(2048, 1, 1).unwrap();
+    assert_matches!(
+        Repository::open(
+            &make_params(other_kdf_params),
+            Some(local_secret.clone()),
+            AccessMode::Write
+        )
+        .await,
+        Err(Error::KdfParamsMismatch)
+    );
+
+    // Change the params, re-deriving the keys with the new ones
+    repo.set_kdf_params(other_kdf_params, &access())
+        .await
+        .unwrap();
+    repo.close().await.unwrap();
+    drop(repo);
+
+    let repo = Repository::open(
+        &make_params(other_kdf_params),
+        Some(local_secret.clone()),
+        AccessMode::Write,
+    )
+    .await
+    .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Write);
+    repo.close().await.unwrap();
+    drop(repo);
+
+    assert_matches!(
+        Repository::open(
+            &make_params(kdf_params),
+            Some(local_secret),
+            AccessMode::Write
+        )
+        .await,
+        Err(Error::KdfParamsMismatch)
+    );
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn blind_access_empty_repo() {
     test_utils::init_log();
@@ -582,71 +701,70 @@ async fn blind_access_empty_repo() {
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn read_access_same_replica() {
+async fn open_read_only() {
     test_utils::init_log();
 
-    let (_base_dir, pool) = db::create_temp().await.unwrap();
-    let params = RepositoryParams::with_pool(pool, "test");
+    let base_dir = TempDir::new().unwrap();
+    let store = base_dir.path().join("repo.db");
+    let params = RepositoryParams::new(&store);
+    let local_secret = LocalSecret::Password(Password::from("supersecret".to_owned()));
 
     let repo = Repository::create(
         &params,
-        Access::WriteUnlocked {
+        Access::WriteLocked {
+            local_read_secret: local_secret.clone(),
+            local_write_secret: local_secret.clone(),
             secrets: WriteSecrets::random(),
         },
     )
     .await
     .unwrap();
 
-    let mut file = repo.create_file("public.txt").await.unwrap();
+    let mut file = repo.create_file("test.txt").await.unwrap();
     file.write_all(b"hello world").await.unwrap();
     file.flush().await.unwrap();
-
     drop(file);
+
+    repo.close().await.unwrap();
     drop(repo);
 
-    // Reopen the repo in read-only mode.
-    let repo = Repository::open(&params, None, AccessMode::Read)
+    let content_before = std::fs::read(&store).unwrap();
+
+    let repo = Repository::open_read_only(&params, Some(local_secret))
         .await
         .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Read);
 
-    // Reading files is allowed.
-    let mut file = repo.open_file("public.txt").await.unwrap();
-
-    let content = file.read_to_end().await.unwrap();
-    assert_eq!(content, b"hello world");
-
-    // Writing is not allowed.
-    file.seek(SeekFrom::Start(0));
-    // short writes that don't cross block boundaries don't trigger the permission check which is
-    // why the following works...
-    file.write_all(b"hello universe").await.unwrap();
-    // ...but flushing the file is not allowed.
-    assert_matches!(file.flush().await, Err(Error::PermissionDenied));
-
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"hello world");
     drop(file);
 
-    // Creating files is not allowed.
-    assert_matches!(
-        repo.create_file("hack.txt").await,
-        Err(Error::PermissionDenied)
+    assert_eq!(
+        repo.database_id().await.unwrap(),
+        repo.database_id().await.unwrap()
     );
-
-    // Removing files is not allowed.
     assert_matches!(
-        repo.remove_entry("public.txt").await,
+        repo.create_file("other.txt").await,
         Err(Error::PermissionDenied)
     );
+
+    repo.close().await.unwrap();
+    drop(repo);
+
+    assert_eq!(std::fs::read(&store).unwrap(), content_before);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn read_access_different_replica() {
+async fn custom_block_store() {
     test_utils::init_log();
 
-    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let base_dir = TempDir::new().unwrap();
+    let blocks = MemoryBlockStore::default();
+    let params =
+        RepositoryParams::new(base_dir.path().join("repo.db")).with_block_store(blocks.clone());
 
-    let params_a = RepositoryParams::with_pool(pool.clone(), "test").with_device_id(rand::random());
     let repo = Repository::create(
-        &params_a,
+        &params,
         Access::WriteUnlocked {
             secrets: WriteSecrets::random(),
         },
@@ -654,403 +772,2079 @@ async fn read_access_different_replica() {
     .await
     .unwrap();
 
-    let mut file = repo.create_file("public.txt").await.unwrap();
-    file.write_all(b"hello world").await.unwrap();
+    let content = random_bytes(3 * BLOCK_SIZE);
+
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.write_all(&content).await.unwrap();
     file.flush().await.unwrap();
+    drop(file);
 
+    let mut file = repo.open_file("test.dat").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), content);
     drop(file);
+
+    assert!(!blocks.is_empty());
+
+    // Nothing is stored in the database.
+    let mut conn = repo.db().acquire().await.unwrap();
+    let db_count: u32 = sqlx::query("SELECT COUNT(*) FROM blocks")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(db_count, 0);
+    drop(conn);
+
+    assert!(repo.check_integrity().await.unwrap());
+
+    // Blocks that are no longer referenced get removed from the custom store too.
+    let count_before = blocks.len();
+    repo.remove_entry("test.dat").await.unwrap();
+    run_maintenance(&repo, MaintenanceKind::Prune).await;
+    run_maintenance(&repo, MaintenanceKind::Trash).await;
+    assert!(blocks.len() < count_before);
+
+    // Deleting the repository removes the remaining blocks from the custom store.
+    assert!(!blocks.is_empty());
+    repo.close().await.unwrap();
     drop(repo);
 
-    let params_b = RepositoryParams::with_pool(pool, "test").with_device_id(rand::random());
-    let repo = Repository::open(&params_b, None, AccessMode::Read)
+    delete_with_block_store(base_dir.path().join("repo.db"), &blocks)
         .await
         .unwrap();
-
-    let mut file = repo.open_file("public.txt").await.unwrap();
-    let content = file.read_to_end().await.unwrap();
-    assert_eq!(content, b"hello world");
+    assert!(blocks.is_empty());
+    assert!(!base_dir.path().join("repo.db").exists());
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn truncate_forked_remote_file() {
-    let (_base_dir, repo) = setup().await;
+async fn shared_cache() {
+    test_utils::init_log();
 
-    let remote_id = PublicKey::random();
-    tracing::info!(local_id = ?repo.local_branch().unwrap().id(), ?remote_id);
+    let base_dir = TempDir::new().unwrap();
+    let cache = SharedCache::new(16);
+    let repo = Repository::create(
+        &RepositoryParams::new(base_dir.path().join("repo.db")).with_shared_cache(cache.clone()),
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
 
-    create_remote_file(&repo, remote_id, "test.txt", b"foo").await;
+    let content = random_bytes(3 * BLOCK_SIZE);
 
-    let local_branch = repo.local_branch().unwrap();
-    let mut file = repo.open_file("test.txt").await.unwrap();
-    file.fork(local_branch).await.unwrap();
-    file.truncate(0).unwrap();
+    let mut file = repo.create_file("test.dat").await.unwrap();
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    assert_eq!(read_file(&repo, "test.dat").await, content);
+    assert!(!cache.is_empty());
+
+    // Reading again gives the same content, now (partly) from the cache.
+    assert_eq!(read_file(&repo, "test.dat").await, content);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_create_file() {
-    let (_base_dir, repo) = setup().await;
-    let local_branch = repo.local_branch().unwrap();
+async fn local_unlock() {
+    test_utils::init_log();
 
-    let root_vv_0 = local_branch.version_vector().await.unwrap();
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params =
+        RepositoryParams::with_pool(pool, "test").with_parent_monitor(StateMonitor::make_root());
+    let password = LocalSecret::Password(Password::from("supersecret".to_owned()));
+    let recovery_key = LocalSecret::random();
 
-    let mut file = repo.create_file("parent/test.txt").await.unwrap();
+    let repo = Repository::create(
+        &params,
+        Access::WriteLocked {
+            local_read_secret: password.clone(),
+            local_write_secret: password.clone(),
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
 
-    let root_vv_1 = file
-        .parent()
-        .await
-        .unwrap()
-        .parent()
-        .await
-        .unwrap()
-        .unwrap()
-        .version_vector()
-        .await
-        .unwrap();
-    let parent_vv_1 = file.parent().await.unwrap().version_vector().await.unwrap();
-    let file_vv_1 = file.version_vector().await.unwrap();
+    let writer_id = *repo.local_branch().unwrap().id();
 
-    assert!(root_vv_1 > root_vv_0);
+    repo.add_local_unlock(&recovery_key).await.unwrap();
+    drop(repo);
 
-    file.write_all(b"blah").await.unwrap();
-    file.flush().await.unwrap();
+    // Both the password and the recovery key unlock the repository.
+    for local_secret in [&password, &recovery_key] {
+        let repo = Repository::open(&params, Some(local_secret.clone()), AccessMode::Write)
+            .await
+            .unwrap();
+        assert_eq!(repo.access_mode(), AccessMode::Write);
+        assert_eq!(*repo.local_branch().unwrap().id(), writer_id);
+    }
 
-    let root_vv_2 = file
-        .parent()
-        .await
-        .unwrap()
-        .parent()
+    let repo = Repository::open(&params, Some(password.clone()), AccessMode::Write)
         .await
-        .unwrap()
-        .unwrap()
-        .version_vector()
+        .unwrap();
+    repo.remove_local_unlock(&recovery_key).await.unwrap();
+    assert_matches!(
+        repo.remove_local_unlock(&recovery_key).await,
+        Err(Error::EntryNotFound)
+    );
+    drop(repo);
+
+    let repo = Repository::open(&params, Some(recovery_key), AccessMode::Write)
         .await
         .unwrap();
-    let parent_vv_2 = file.parent().await.unwrap().version_vector().await.unwrap();
-    let file_vv_2 = file.version_vector().await.unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Blind);
+    drop(repo);
 
-    assert!(root_vv_2 > root_vv_1);
-    assert!(parent_vv_2 > parent_vv_1);
-    assert!(file_vv_2 > file_vv_1);
+    let repo = Repository::open(&params, Some(password), AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Write);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_deep_hierarchy() {
-    let (_base_dir, repo) = setup().await;
-    let local_branch = repo.local_branch().unwrap();
-    let local_id = *local_branch.id();
+async fn verify_read_key() {
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params =
+        RepositoryParams::with_pool(pool, "test").with_parent_monitor(StateMonitor::make_root());
+    let read_password = LocalSecret::Password(Password::from("read".to_owned()));
+    let write_password = LocalSecret::Password(Password::from("write".to_owned()));
+    let wrong_password = LocalSecret::Password(Password::from("wrong".to_owned()));
 
-    let depth = 10;
-    let mut dirs = Vec::new();
-    dirs.push(local_branch.open_or_create_root().await.unwrap());
+    let repo = Repository::create(
+        &params,
+        Access::WriteLocked {
+            local_read_secret: read_password.clone(),
+            local_write_secret: write_password.clone(),
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
 
-    for i in 0..depth {
-        let dir = dirs
-            .last_mut()
-            .unwrap()
-            .create_directory(format!("dir-{}", i), rand::random(), &VersionVector::new())
-            .await
-            .unwrap();
-        dirs.push(dir);
-    }
+    assert!(repo.verify_read_key(&read_password).await.unwrap());
+    assert!(repo.verify_read_key(&write_password).await.unwrap());
+    assert!(!repo.verify_read_key(&wrong_password).await.unwrap());
+    assert!(!repo.verify_read_key(&LocalSecret::random()).await.unwrap());
 
-    // Each directory's local version is one less than its parent.
-    for (index, dir) in dirs.iter().skip(1).enumerate() {
-        assert_eq!(
-            dir.version_vector().await.unwrap(),
-            vv![local_id => (depth - index) as u64]
-        );
-    }
+    // The check doesn't change the access.
+    assert_eq!(repo.access_mode(), AccessMode::Write);
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_recreate_deleted_file() {
-    let (_base_dir, repo) = setup().await;
+async fn rename_store() {
+    test_utils::init_log();
 
-    let local_id = *repo.local_branch().unwrap().id();
+    let base_dir = TempDir::new().unwrap();
+    let src = base_dir.path().join("src.db");
+    let dst = base_dir.path().join("sub").join("dst.db");
 
-    let file = repo.create_file("test.txt").await.unwrap();
-    drop(file);
+    let repo = Repository::create(
+        &RepositoryParams::new(&src),
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
 
-    repo.remove_entry("test.txt").await.unwrap();
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-    let file = repo.create_file("test.txt").await.unwrap();
+    // Can't rename an open repository.
     assert_eq!(
-        file.version_vector().await.unwrap(),
-        vv![local_id => 3 /* = 1*create + 1*remove + 1*create again */]
+        rename(&src, &dst).await.unwrap_err().kind(),
+        io::ErrorKind::WouldBlock
     );
-}
 
-#[tokio::test(flavor = "multi_thread")]
-async fn version_vector_fork() {
+    repo.close().await.unwrap();
+    drop(repo);
+
+    rename(&src, &dst).await.unwrap();
+
+    for suffix in STORE_FILE_SUFFIXES {
+        assert!(!store_file_path(&src, suffix).exists());
+    }
+
+    let repo = Repository::open(&RepositoryParams::new(&dst), None, AccessMode::Write)
+        .await
+        .unwrap();
+
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"hello world");
+    drop(file);
+
+    repo.close().await.unwrap();
+    drop(repo);
+
+    // Can't rename onto an existing repository.
+    Repository::create(
+        &RepositoryParams::new(&src),
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap()
+    .close()
+    .await
+    .unwrap();
+
+    assert_eq!(
+        rename(&src, &dst).await.unwrap_err().kind(),
+        io::ErrorKind::AlreadyExists
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_access_same_replica() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test");
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut file = repo.create_file("public.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+
+    drop(file);
+    drop(repo);
+
+    // Reopen the repo in read-only mode.
+    let repo = Repository::open(&params, None, AccessMode::Read)
+        .await
+        .unwrap();
+
+    // Reading files is allowed.
+    let mut file = repo.open_file("public.txt").await.unwrap();
+
+    let content = file.read_to_end().await.unwrap();
+    assert_eq!(content, b"hello world");
+
+    // Writing is not allowed.
+    file.seek(SeekFrom::Start(0));
+    // short writes that don't cross block boundaries don't trigger the permission check which is
+    // why the following works...
+    file.write_all(b"hello universe").await.unwrap();
+    // ...but flushing the file is not allowed.
+    assert_matches!(file.flush().await, Err(Error::PermissionDenied));
+
+    drop(file);
+
+    // Creating files is not allowed.
+    assert_matches!(
+        repo.create_file("hack.txt").await,
+        Err(Error::PermissionDenied)
+    );
+
+    // Removing files is not allowed.
+    assert_matches!(
+        repo.remove_entry("public.txt").await,
+        Err(Error::PermissionDenied)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn downgrade_access() {
+    let (_base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    repo.downgrade_access(AccessMode::Read).unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Read);
+
+    // Reading is still allowed...
+    assert_eq!(read_file(&repo, "test.txt").await, b"hello world");
+
+    // ...but writing is not.
+    assert_matches!(
+        repo.create_file("hack.txt").await,
+        Err(Error::PermissionDenied)
+    );
+    assert_matches!(
+        repo.remove_entry("test.txt").await,
+        Err(Error::PermissionDenied)
+    );
+
+    // Upgrading is not allowed.
+    assert_matches!(
+        repo.downgrade_access(AccessMode::Write),
+        Err(Error::PermissionDenied)
+    );
+    assert_eq!(repo.access_mode(), AccessMode::Read);
+
+    repo.downgrade_access(AccessMode::Blind).unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Blind);
+
+    assert_matches!(repo.local_branch(), Err(Error::PermissionDenied));
+    assert_matches!(
+        repo.open_file("test.txt").await,
+        Err(Error::PermissionDenied)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_access_different_replica() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+
+    let params_a = RepositoryParams::with_pool(pool.clone(), "test").with_device_id(rand::random());
+    let repo = Repository::create(
+        &params_a,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut file = repo.create_file("public.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+
+    drop(file);
+    drop(repo);
+
+    let params_b = RepositoryParams::with_pool(pool, "test").with_device_id(rand::random());
+    let repo = Repository::open(&params_b, None, AccessMode::Read)
+        .await
+        .unwrap();
+
+    let mut file = repo.open_file("public.txt").await.unwrap();
+    let content = file.read_to_end().await.unwrap();
+    assert_eq!(content, b"hello world");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reset_device_id() {
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+
+    let device_id = rand::random();
+    let params = RepositoryParams::with_pool(pool, "test").with_device_id(device_id);
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(repo.device_id().await.unwrap(), Some(device_id));
+    let writer_id = *repo.local_branch().unwrap().id();
+
+    // Reopening on the same device keeps the writer id.
+    drop(repo);
+    let repo = Repository::open(&params, None, AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(*repo.local_branch().unwrap().id(), writer_id);
+
+    repo.reset_device_id().await.unwrap();
+    assert_eq!(repo.device_id().await.unwrap(), None);
+
+    // Reopening after the reset generates a new writer id.
+    drop(repo);
+    let repo = Repository::open(&params, None, AccessMode::Write)
+        .await
+        .unwrap();
+    assert_ne!(*repo.local_branch().unwrap().id(), writer_id);
+    assert_eq!(repo.device_id().await.unwrap(), Some(device_id));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn create_blind_without_writer_id() {
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+
+    let device_id = rand::random();
+    let params = RepositoryParams::with_pool(pool, "test").with_device_id(device_id);
+    let secrets = WriteSecrets::random();
+
+    let repo = Repository::create(&params, Access::Blind { id: secrets.id })
+        .await
+        .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Blind);
+    assert_eq!(repo.device_id().await.unwrap(), None);
+
+    // The writer id is generated once the repository gets write access.
+    repo.set_access(&Access::WriteUnlocked { secrets })
+        .await
+        .unwrap();
+    drop(repo);
+
+    let repo = Repository::open(&params, None, AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(repo.access_mode(), AccessMode::Write);
+    assert_eq!(repo.device_id().await.unwrap(), Some(device_id));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn truncate_forked_remote_file() {
+    let (_base_dir, repo) = setup().await;
+
+    let remote_id = PublicKey::random();
+    tracing::info!(local_id = ?repo.local_branch().unwrap().id(), ?remote_id);
+
+    create_remote_file(&repo, remote_id, "test.txt", b"foo").await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    file.fork(local_branch).await.unwrap();
+    file.truncate(0).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_create_file() {
+    let (_base_dir, repo) = setup().await;
+    let local_branch = repo.local_branch().unwrap();
+
+    let root_vv_0 = local_branch.version_vector().await.unwrap();
+
+    let mut file = repo.create_file("parent/test.txt").await.unwrap();
+
+    let root_vv_1 = file
+        .parent()
+        .await
+        .unwrap()
+        .parent()
+        .await
+        .unwrap()
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+    let parent_vv_1 = file.parent().await.unwrap().version_vector().await.unwrap();
+    let file_vv_1 = file.version_vector().await.unwrap();
+
+    assert!(root_vv_1 > root_vv_0);
+
+    file.write_all(b"blah").await.unwrap();
+    file.flush().await.unwrap();
+
+    let root_vv_2 = file
+        .parent()
+        .await
+        .unwrap()
+        .parent()
+        .await
+        .unwrap()
+        .unwrap()
+        .version_vector()
+        .await
+        .unwrap();
+    let parent_vv_2 = file.parent().await.unwrap().version_vector().await.unwrap();
+    let file_vv_2 = file.version_vector().await.unwrap();
+
+    assert!(root_vv_2 > root_vv_1);
+    assert!(parent_vv_2 > parent_vv_1);
+    assert!(file_vv_2 > file_vv_1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_deep_hierarchy() {
+    let (_base_dir, repo) = setup().await;
+    let local_branch = repo.local_branch().unwrap();
+    let local_id = *local_branch.id();
+
+    let depth = 10;
+    let mut dirs = Vec::new();
+    dirs.push(local_branch.open_or_create_root().await.unwrap());
+
+    for i in 0..depth {
+        let dir = dirs
+            .last_mut()
+            .unwrap()
+            .create_directory(format!("dir-{}", i), rand::random(), &VersionVector::new())
+            .await
+            .unwrap();
+        dirs.push(dir);
+    }
+
+    // Each directory's local version is one less than its parent.
+    for (index, dir) in dirs.iter().skip(1).enumerate() {
+        assert_eq!(
+            dir.version_vector().await.unwrap(),
+            vv![local_id => (depth - index) as u64]
+        );
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_recreate_deleted_file() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_id = *repo.local_branch().unwrap().id();
+
+    let file = repo.create_file("test.txt").await.unwrap();
+    drop(file);
+
+    repo.remove_entry("test.txt").await.unwrap();
+
+    let file = repo.create_file("test.txt").await.unwrap();
+    assert_eq!(
+        file.version_vector().await.unwrap(),
+        vv![local_id => 3 /* = 1*create + 1*remove + 1*create again */]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_fork() {
     use tokio::time;
 
-    // TODO: this test would be more precise without merger. Consider converting it to a
-    // joint_directory test.
+    // TODO: this test would be more precise without merger. Consider converting it to a
+    // joint_directory test.
+
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_branch = repo
+        .get_branch(PublicKey::random())
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    tracing::info!(local_id = ?local_branch.id(), remote_id = ?remote_branch.id());
+
+    time::timeout(Duration::from_secs(5), async move {
+        let mut remote_root = remote_branch.open_or_create_root().await.unwrap();
+        let mut remote_parent = remote_root
+            .create_directory("parent".into(), rand::random(), &VersionVector::new())
+            .await
+            .unwrap();
+        let mut file = create_file_in_directory(&mut remote_parent, "foo.txt", &[]).await;
+
+        remote_parent.refresh().await.unwrap();
+        let remote_parent_vv = remote_parent.version_vector().await.unwrap();
+        let remote_file_vv = file.version_vector().await.unwrap();
+
+        tracing::info!("step 1");
+        file.fork(local_branch.clone()).await.unwrap();
+        tracing::info!("step 2");
+
+        let local_file_vv_0 = file.version_vector().await.unwrap();
+        assert_eq!(local_file_vv_0, remote_file_vv);
+
+        let local_parent_vv_0 = local_branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("parent")
+            .unwrap()
+            .version_vector()
+            .clone();
+
+        assert!(local_parent_vv_0 <= remote_parent_vv);
+
+        drop(file);
+
+        // modify the file and fork again
+        let mut file = remote_parent
+            .lookup("foo.txt")
+            .unwrap()
+            .file()
+            .unwrap()
+            .open()
+            .await
+            .unwrap();
+        file.write_all(b"hello").await.unwrap();
+        file.flush().await.unwrap();
+
+        remote_parent.refresh().await.unwrap();
+        let remote_parent_vv = remote_parent.version_vector().await.unwrap();
+        let remote_file_vv = file.version_vector().await.unwrap();
+
+        file.fork(local_branch.clone()).await.unwrap();
+
+        let local_file_vv_1 = file.version_vector().await.unwrap();
+        assert_eq!(local_file_vv_1, remote_file_vv);
+        assert!(local_file_vv_1 > local_file_vv_0);
+
+        let local_parent_vv_1 = local_branch
+            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+            .await
+            .unwrap()
+            .lookup("parent")
+            .unwrap()
+            .version_vector()
+            .clone();
+
+        assert!(local_parent_vv_1 <= remote_parent_vv);
+        assert!(local_parent_vv_1 >= local_parent_vv_0);
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_empty_directory() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let local_id = *local_branch.id();
+
+    let dir = repo.create_directory("stuff").await.unwrap();
+    assert_eq!(dir.version_vector().await.unwrap(), vv![local_id => 1]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_moved_non_empty_directory() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("foo").await.unwrap();
+    repo.create_file("foo/stuff.txt").await.unwrap();
+
+    let vv_0 = repo
+        .local_branch()
+        .unwrap()
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap()
+        .lookup("foo")
+        .unwrap()
+        .version_vector()
+        .clone();
+
+    repo.move_entry("/", "foo", "/", "bar").await.unwrap();
+
+    let vv_1 = repo
+        .local_branch()
+        .unwrap()
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap()
+        .lookup("bar")
+        .unwrap()
+        .version_vector()
+        .clone();
+
+    assert!(vv_1 > vv_0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn version_vector_file_moved_over_tombstone() {
+    let (_base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("old.txt").await.unwrap();
+    file.write_all(b"a").await.unwrap();
+    file.flush().await.unwrap();
+
+    let vv_0 = file.version_vector().await.unwrap();
+
+    drop(file);
+    repo.remove_entry("old.txt").await.unwrap();
+
+    // The tombstone's vv is the original vv incremented by 1.
+    let branch_id = *repo.local_branch().unwrap().id();
+    let vv_1 = vv_0.incremented(branch_id);
+
+    repo.create_file("new.txt").await.unwrap();
+    repo.move_entry("/", "new.txt", "/", "old.txt")
+        .await
+        .unwrap();
+
+    let vv_2 = repo
+        .local_branch()
+        .unwrap()
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap()
+        .lookup("old.txt")
+        .unwrap()
+        .version_vector()
+        .clone();
+
+    assert!(vv_2 > vv_1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_conflict_modify_local() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let local_id = *local_branch.id();
+
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    // Create two concurrent versions of the same file.
+    let local_file = create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
+
+    assert_eq!(
+        local_file.version_vector().await.unwrap(),
+        vv![local_id => 2]
+    );
+    drop(local_file);
+
+    let remote_file = create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
+    assert_eq!(
+        remote_file.version_vector().await.unwrap(),
+        vv![remote_id => 2]
+    );
+    drop(remote_file);
+
+    // Modify the local version.
+    let mut local_file = repo.open_file_version("test.txt", &local_id).await.unwrap();
+    local_file.write_all(b"local v2").await.unwrap();
+    local_file.flush().await.unwrap();
+    drop(local_file);
+
+    let mut local_file = repo.open_file_version("test.txt", &local_id).await.unwrap();
+    assert_eq!(local_file.read_to_end().await.unwrap(), b"local v2");
+    assert_eq!(
+        local_file.version_vector().await.unwrap(),
+        vv![local_id => 3]
+    );
+
+    let mut remote_file = repo
+        .open_file_version("test.txt", &remote_id)
+        .await
+        .unwrap();
+    assert_eq!(remote_file.read_to_end().await.unwrap(), b"remote v1");
+    assert_eq!(
+        remote_file.version_vector().await.unwrap(),
+        vv![remote_id => 2]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn list_conflicts() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let local_id = *local_branch.id();
+
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    assert!(repo
+        .conflicts()
+        .try_collect::<Vec<_>>()
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Create two concurrent versions of the same file in a subdirectory.
+    let mut local_dir = local_branch
+        .ensure_directory_exists("dir".into())
+        .await
+        .unwrap();
+    create_file_in_directory(&mut local_dir, "test.txt", b"local").await;
+
+    let mut remote_dir = remote_branch
+        .ensure_directory_exists("dir".into())
+        .await
+        .unwrap();
+    create_file_in_directory(&mut remote_dir, "test.txt", b"remote").await;
+
+    let conflicts: Vec<_> = repo.conflicts().try_collect().await.unwrap();
+    assert_eq!(conflicts.len(), 1);
+
+    let conflict = &conflicts[0];
+    assert_eq!(conflict.path, "/dir/test.txt");
+
+    let mut branch_ids: Vec<_> = conflict
+        .versions
+        .iter()
+        .map(|version| version.branch_id)
+        .collect();
+    branch_ids.sort();
+
+    let mut expected = vec![local_id, remote_id];
+    expected.sort();
+
+    assert_eq!(branch_ids, expected);
+    assert!(conflict
+        .versions
+        .iter()
+        .all(|version| version.entry_type == EntryType::File));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn file_conflict_attempt_to_fork_and_modify_remote() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    // Create two concurrent versions of the same file.
+    create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
+    create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
+
+    // Attempt to fork the remote version (fork is required to modify it)
+    let mut remote_file = repo
+        .open_file_version("test.txt", &remote_id)
+        .await
+        .unwrap();
+    assert_matches!(
+        remote_file.fork(local_branch).await,
+        Err(Error::EntryExists)
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resolve_file_conflict_pick_remote() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
+    create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
+
+    repo.resolve_conflict("test.txt", &remote_id).await.unwrap();
+
+    let conflicts: Vec<_> = repo.conflicts().try_collect().await.unwrap();
+    assert!(conflicts.is_empty());
+
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    assert_eq!(file.branch().id(), local_branch.id());
+    assert_eq!(file.read_to_end().await.unwrap(), b"remote v1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn resolve_file_conflict_pick_local() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let local_id = *local_branch.id();
+
+    let remote_id = PublicKey::random();
+    let remote_branch = repo
+        .get_branch(remote_id)
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
+    create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
+
+    repo.resolve_conflict("test.txt", &local_id).await.unwrap();
+
+    let conflicts: Vec<_> = repo.conflicts().try_collect().await.unwrap();
+    assert!(conflicts.is_empty());
+
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    assert_eq!(file.branch().id(), &local_id);
+    assert_eq!(file.read_to_end().await.unwrap(), b"local v1");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_maintenance_merge() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+
+    create_remote_file(&repo, remote_id, "test.txt", b"remote").await;
+
+    // The job might be already running in the background worker. In that case retry until we
+    // get to run it ourselves.
+    loop {
+        match repo.run_maintenance(MaintenanceKind::Merge).await {
+            Ok(()) => break,
+            Err(Error::Busy) => time::sleep(Duration::from_millis(10)).await,
+            Err(error) => panic!("unexpected error: {error:?}"),
+        }
+    }
+
+    let mut file = local_branch
+        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+        .await
+        .unwrap()
+        .lookup("test.txt")
+        .unwrap()
+        .file()
+        .unwrap()
+        .open()
+        .await
+        .unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"remote");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_strategy_prefer_local() {
+    let (_base_dir, repo) = setup().await;
+    assert_eq!(repo.merge_strategy(), MergeStrategy::KeepAllVersions);
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_id = PublicKey::random();
+
+    create_file_in_branch(&local_branch, "test.txt", b"local").await;
+    create_remote_file(&repo, remote_id, "test.txt", b"remote").await;
+
+    repo.set_merge_strategy(MergeStrategy::PreferLocal);
+
+    loop {
+        match repo.run_maintenance(MaintenanceKind::Merge).await {
+            Ok(()) => break,
+            Err(Error::Busy) => time::sleep(Duration::from_millis(10)).await,
+            Err(error) => panic!("unexpected error: {error:?}"),
+        }
+    }
+
+    let conflicts: Vec<_> = repo.conflicts().try_collect().await.unwrap();
+    assert!(conflicts.is_empty());
+
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    assert_eq!(file.branch().id(), local_branch.id());
+    assert_eq!(file.read_to_end().await.unwrap(), b"local");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn merge_strategy_prefer_local_in_subdirectory() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_branch = repo.local_branch().unwrap();
+    let remote_branch = repo
+        .get_branch(PublicKey::random())
+        .unwrap()
+        .reopen(repo.secrets().keys().unwrap());
+
+    repo.create_directory("dir").await.unwrap();
+    let mut file = repo.create_file("dir/test.txt").await.unwrap();
+    file.write_all(b"local").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let mut remote_dir = remote_branch
+        .open_or_create_root()
+        .await
+        .unwrap()
+        .create_directory("dir".into(), rand::random(), &VersionVector::new())
+        .await
+        .unwrap();
+    create_file_in_directory(&mut remote_dir, "test.txt", b"remote").await;
+
+    repo.set_merge_strategy(MergeStrategy::PreferLocal);
+
+    loop {
+        match repo.run_maintenance(MaintenanceKind::Merge).await {
+            Ok(()) => break,
+            Err(Error::Busy) => time::sleep(Duration::from_millis(10)).await,
+            Err(error) => panic!("unexpected error: {error:?}"),
+        }
+    }
+
+    let conflicts: Vec<_> = repo.conflicts().try_collect().await.unwrap();
+    assert!(conflicts.is_empty());
+
+    let mut file = repo.open_file("dir/test.txt").await.unwrap();
+    assert_eq!(file.branch().id(), local_branch.id());
+    assert_eq!(file.read_to_end().await.unwrap(), b"local");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_tar() {
+    let (_base_dir, repo) = setup().await;
+
+    let long_name = "x".repeat(150);
+    let large_content = random_bytes(3 * BLOCK_SIZE + 7);
+
+    let mut file = repo.create_file("a.txt").await.unwrap();
+    file.write_all(b"hello").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    repo.create_directory("dir").await.unwrap();
+
+    let mut file = repo.create_file(format!("dir/{long_name}")).await.unwrap();
+    file.write_all(&large_content).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let mut archive = Vec::new();
+    repo.export_tar("/", &mut archive).await.unwrap();
+
+    let mut entries: Vec<_> = tar::Archive::new(&archive[..])
+        .entries()
+        .unwrap()
+        .map(|entry| {
+            let mut entry = entry.unwrap();
+            let path = entry.path().unwrap().to_str().unwrap().to_owned();
+            let mut content = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut content).unwrap();
+            (path, content)
+        })
+        .collect();
+    entries.sort();
+
+    assert_eq!(
+        entries,
+        [
+            ("a.txt".to_owned(), b"hello".to_vec()),
+            ("dir/".to_owned(), vec![]),
+            (format!("dir/{long_name}"), large_content),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_path_depth() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool.clone(), "test").with_max_path_depth(2);
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    repo.create_directory("a/b").await.unwrap();
+    repo.create_file("a/b/file.txt").await.unwrap();
+
+    assert_matches!(
+        repo.create_directory("a/b/c").await,
+        Err(Error::PathTooDeep)
+    );
+    assert_matches!(
+        repo.create_file("a/b/c/file.txt").await,
+        Err(Error::PathTooDeep)
+    );
+    assert_matches!(repo.cd("a/b/c").await, Err(Error::PathTooDeep));
+
+    drop(repo);
+
+    // Reopen with a lower limit so the existing directories exceed it.
+    let params = RepositoryParams::with_pool(pool, "test").with_max_path_depth(1);
+    let repo = Repository::open(&params, None, AccessMode::Write)
+        .await
+        .unwrap();
+
+    assert_matches!(repo.cd("a/b").await, Err(Error::PathTooDeep));
+    assert_matches!(
+        repo.export_tar("/", &mut Vec::new()).await,
+        Err(Error::PathTooDeep)
+    );
+    assert_matches!(
+        repo.remove_entry_recursively("a").await,
+        Err(Error::PathTooDeep)
+    );
+
+    // Nothing was removed.
+    repo.cd("a").await.unwrap().lookup_unique("b").unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn snapshot() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("dir").await.unwrap();
+
+    let mut file = repo.create_file("dir/a.txt").await.unwrap();
+    file.write_all(b"old").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let snapshot = repo.snapshot().await.unwrap();
+
+    // Modify the repository after the snapshot was taken.
+    let mut file = repo.open_file("dir/a.txt").await.unwrap();
+    file.truncate(0).unwrap();
+    file.write_all(b"new content").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    repo.create_file("dir/b.txt").await.unwrap();
+
+    // The snapshot still sees the old state...
+    let mut file = snapshot.open_file("dir/a.txt").await.unwrap();
+    assert_eq!(file.len(), 3);
+    assert_eq!(file.read_to_end().await.unwrap(), b"old");
+
+    file.seek(SeekFrom::Start(1));
+    let mut buffer = [0; 8];
+    assert_eq!(file.read_all(&mut buffer).await.unwrap(), 2);
+    assert_eq!(&buffer[..2], b"ld");
+    drop(file);
+
+    assert_eq!(
+        snapshot.read_dir("dir").await.unwrap(),
+        [("a.txt".to_owned(), EntryType::File)]
+    );
+
+    assert_matches!(
+        snapshot.open_file("dir/b.txt").await,
+        Err(Error::EntryNotFound)
+    );
+
+    // ...while the repository sees the new one.
+    let mut file = repo.open_file("dir/a.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"new content");
+
+    let current_vv = repo.local_branch().unwrap().version_vector().await.unwrap();
+    assert!(snapshot.version_vector() < current_vv);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn snapshot_limit() {
+    test_utils::init_log();
+
+    let base_dir = TempDir::new().unwrap();
+    let repo = Repository::create(
+        &RepositoryParams::new(base_dir.path().join("repo.db")).with_max_snapshots(3),
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    repo.create_file("a.txt").await.unwrap();
+
+    let snapshots: Vec<_> = future::try_join_all((0..3).map(|_| repo.snapshot()))
+        .await
+        .unwrap();
+    assert_matches!(repo.snapshot().await, Err(Error::TooManySnapshots));
+
+    // Files opened from a snapshot keep it alive.
+    let file = snapshots[0].open_file("a.txt").await.unwrap();
+    drop(snapshots);
+
+    let _snapshots: Vec<_> = future::try_join_all((0..2).map(|_| repo.snapshot()))
+        .await
+        .unwrap();
+    assert_matches!(repo.snapshot().await, Err(Error::TooManySnapshots));
+
+    drop(file);
+    repo.snapshot().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn import_dir() {
+    let (base_dir, repo) = setup().await;
+
+    let src = base_dir.path().join("import");
+    let large_content = random_bytes(2 * BLOCK_SIZE + 3);
+
+    fs::create_dir_all(src.join("dir")).await.unwrap();
+    fs::write(src.join("a.txt"), b"hello").await.unwrap();
+    fs::write(src.join("dir/b.dat"), &large_content)
+        .await
+        .unwrap();
+
+    let mut last_progress = None;
+    repo.import_dir(&src, "/imported", |progress| last_progress = Some(progress))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        last_progress,
+        Some(Progress {
+            value: 5 + large_content.len() as u64,
+            total: 5 + large_content.len() as u64,
+        })
+    );
+
+    let mut file = repo.open_file("imported/a.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"hello");
+
+    let mut file = repo.open_file("imported/dir/b.dat").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), large_content);
+    let vv = file.version_vector().await.unwrap();
+    drop(file);
+
+    // Re-import updates only the modified files.
+    fs::write(src.join("a.txt"), b"hello world").await.unwrap();
+
+    repo.import_dir(&src, "/imported", |_| ()).await.unwrap();
+
+    let mut file = repo.open_file("imported/a.txt").await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"hello world");
+
+    let file = repo.open_file("imported/dir/b.dat").await.unwrap();
+    assert_eq!(file.version_vector().await.unwrap(), vv);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remove_entries() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_file("a.txt").await.unwrap();
+    repo.create_file("b.txt").await.unwrap();
+    repo.create_directory("dir").await.unwrap();
+    repo.create_file("dir/c.txt").await.unwrap();
+    repo.create_file("dir/d.txt").await.unwrap();
+
+    repo.remove_entries(&["a.txt".into(), "dir/c.txt".into(), "dir/d.txt".into()])
+        .await
+        .unwrap();
+
+    let root = repo.open_directory("/").await.unwrap();
+    let names: Vec<_> = root
+        .entries()
+        .map(|entry| entry.name().to_owned())
+        .collect();
+    assert_eq!(names, ["b.txt", "dir"]);
+
+    let dir = repo.open_directory("dir").await.unwrap();
+    assert!(dir.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remove_entries_rollback_on_failure() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_file("a.txt").await.unwrap();
+    repo.create_directory("dir").await.unwrap();
+    repo.create_file("dir/b.txt").await.unwrap();
+
+    // "dir" is not empty so the whole root directory group fails.
+    assert_matches!(
+        repo.remove_entries(&["a.txt".into(), "dir".into()]).await,
+        Err(Error::DirectoryNotEmpty)
+    );
+
+    repo.open_file("a.txt").await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn remove_entries_normalizes_parents() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_file("a.txt").await.unwrap();
+    repo.create_directory("dir").await.unwrap();
+    repo.create_file("dir/b.txt").await.unwrap();
+
+    // Both entries are in the root so they are removed in the same group which fails as a whole.
+    assert_matches!(
+        repo.remove_entries(&["/a.txt".into(), "./dir".into()])
+            .await,
+        Err(Error::DirectoryNotEmpty)
+    );
+
+    repo.open_file("a.txt").await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_dir_stream() {
+    let (_base_dir, repo) = setup().await;
+
+    // Use long names so the directory spans multiple blocks.
+    let names: Vec<_> = (0..120)
+        .map(|i| format!("{i:03}{}", "x".repeat(250)))
+        .collect();
+
+    repo.create_directory("dir").await.unwrap();
+
+    for name in &names {
+        repo.create_file(format!("dir/{name}")).await.unwrap();
+    }
+
+    repo.remove_entry(format!("dir/{}", names[0]))
+        .await
+        .unwrap();
+    repo.create_directory("dir/sub").await.unwrap();
+
+    let expected: Vec<_> = repo
+        .open_directory("dir")
+        .await
+        .unwrap()
+        .entries()
+        .map(|entry| (entry.unique_name().into_owned(), entry.entry_type()))
+        .collect();
+    let actual: Vec<_> = repo
+        .read_dir_stream("dir")
+        .map_ok(|entry| (entry.unique_name(), entry.entry_type()))
+        .try_collect()
+        .await
+        .unwrap();
+
+    assert_eq!(actual.len(), names.len());
+    assert_eq!(actual, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_dir_stream_conflict() {
+    let (_base_dir, repo) = setup().await;
+
+    let local_id = *repo.local_branch().unwrap().id();
+    let remote_id = PublicKey::random();
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"local").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    // Keep the file open so the remote branch is not pruned in the meantime.
+    let _remote_file = create_remote_file(&repo, remote_id, "test.txt", b"remote").await;
+
+    let entries: Vec<_> = repo.read_dir_stream("/").try_collect().await.unwrap();
+
+    let mut branch_ids: Vec<_> = entries.iter().map(|entry| *entry.branch_id()).collect();
+    branch_ids.sort();
+    let mut expected_branch_ids = vec![local_id, remote_id];
+    expected_branch_ids.sort();
+    assert_eq!(branch_ids, expected_branch_ids);
+
+    for entry in &entries {
+        assert_eq!(entry.name(), "test.txt");
+        assert_eq!(entry.entry_type(), EntryType::File);
+        assert_ne!(entry.unique_name(), "test.txt");
+    }
+
+    let expected: Vec<_> = repo
+        .open_directory("/")
+        .await
+        .unwrap()
+        .entries()
+        .map(|entry| entry.unique_name().into_owned())
+        .collect();
+    let actual: Vec<_> = entries.iter().map(|entry| entry.unique_name()).collect();
+    assert_eq!(actual, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_file_shared() {
+    let (_base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let mut file0 = repo.open_file_shared("test.txt").await.unwrap();
+    let mut file1 = repo.open_file_shared("test.txt").await.unwrap();
+    assert!(file0.ptr_eq(&file1));
+
+    // Unflushed writes are visible through the other handle.
+    file0.write_all(b"hello world").await.unwrap();
+    assert_eq!(file1.len().await, 11);
+    assert_eq!(file1.read_to_end().await.unwrap(), b"hello world");
+
+    // Each handle has its own position.
+    file1.seek(SeekFrom::Start(6)).await;
+    file1.write_all(b"there").await.unwrap();
+    file0.write_all(b"!").await.unwrap();
+
+    // The shared write lock prevents writing through a non-shared file.
+    let mut other = repo.open_file("test.txt").await.unwrap();
+    assert_matches!(other.write_all(b"x").await, Err(Error::Locked));
+    drop(other);
+
+    file1.flush().await.unwrap();
+    drop(file0);
+    drop(file1);
+
+    assert_eq!(read_file(&repo, "test.txt").await, b"hello there!");
+
+    // Once all the handles are dropped, the file can be written to again.
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    file.write_all(b"x").await.unwrap();
+    file.flush().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn flush_shared_files_and_close() {
+    let base_dir = TempDir::new().unwrap();
+    let params = RepositoryParams::new(base_dir.path().join("repo.db"));
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    // The write is not flushed explicitly.
+    let mut file = repo.open_file_shared("test.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+
+    repo.flush_shared_files_and_close().await.unwrap();
+    drop(file);
+    drop(repo);
+
+    let repo = Repository::open(&params, None, AccessMode::Write)
+        .await
+        .unwrap();
+    assert_eq!(read_file(&repo, "test.txt").await, b"hello world");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn max_file_size() {
+    let base_dir = TempDir::new().unwrap();
+    let repo = Repository::create(
+        &RepositoryParams::new(base_dir.path().join("repo.db")).with_max_file_size(10),
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"0123456789").await.unwrap();
+    assert_matches!(file.write_all(b"x").await, Err(Error::FileTooLarge));
+    assert_eq!(file.len(), 10);
+
+    // Overwriting within the limit still works.
+    file.seek(SeekFrom::Start(0));
+    file.write_all(b"abc").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    assert_eq!(read_file(&repo, "test.txt").await, b"abc3456789");
+}
 
+#[tokio::test(flavor = "multi_thread")]
+async fn directory_acl() {
     let (_base_dir, repo) = setup().await;
 
-    let local_branch = repo.local_branch().unwrap();
-    let remote_branch = repo
-        .get_branch(PublicKey::random())
+    let local_id = *repo.local_branch().unwrap().id();
+    let other_id = PublicKey::random();
+
+    repo.create_directory("dir").await.unwrap();
+    repo.create_directory("other").await.unwrap();
+
+    // Listed writers can modify the directory.
+    repo.set_directory_acl("dir", Some([local_id, other_id].into()))
+        .await
+        .unwrap();
+    repo.create_file("dir/a.txt").await.unwrap();
+    repo.create_file("dir/b.txt").await.unwrap();
+    repo.remove_entry("dir/b.txt").await.unwrap();
+
+    let dir = repo
+        .local_branch()
         .unwrap()
-        .reopen(repo.secrets().keys().unwrap());
+        .ensure_directory_exists("dir".into())
+        .await
+        .unwrap();
+    assert_eq!(dir.acl(), Some(&[local_id, other_id].into()));
 
-    tracing::info!(local_id = ?local_branch.id(), remote_id = ?remote_branch.id());
+    // Unlisted writers can't.
+    repo.set_directory_acl("dir", Some([other_id].into()))
+        .await
+        .unwrap();
 
-    time::timeout(Duration::from_secs(5), async move {
-        let mut remote_root = remote_branch.open_or_create_root().await.unwrap();
-        let mut remote_parent = remote_root
-            .create_directory("parent".into(), rand::random(), &VersionVector::new())
-            .await
-            .unwrap();
-        let mut file = create_file_in_directory(&mut remote_parent, "foo.txt", &[]).await;
+    assert_matches!(
+        repo.create_file("dir/c.txt").await,
+        Err(Error::PermissionDenied)
+    );
+    assert_matches!(
+        repo.create_directory("dir/sub").await,
+        Err(Error::PermissionDenied)
+    );
+    assert_matches!(
+        repo.remove_entry("dir/a.txt").await,
+        Err(Error::PermissionDenied)
+    );
+    assert_matches!(
+        repo.move_entry("dir", "a.txt", "other", "a.txt").await,
+        Err(Error::PermissionDenied)
+    );
+    assert_matches!(
+        repo.set_directory_acl("dir", None).await,
+        Err(Error::PermissionDenied)
+    );
 
-        remote_parent.refresh().await.unwrap();
-        let remote_parent_vv = remote_parent.version_vector().await.unwrap();
-        let remote_file_vv = file.version_vector().await.unwrap();
+    // Other directories are not affected.
+    repo.create_file("other/e.txt").await.unwrap();
+    assert_matches!(
+        repo.move_entry("other", "e.txt", "dir", "e.txt").await,
+        Err(Error::PermissionDenied)
+    );
+    repo.open_file("other/e.txt").await.unwrap();
+}
 
-        tracing::info!("step 1");
-        file.fork(local_branch.clone()).await.unwrap();
-        tracing::info!("step 2");
+#[tokio::test(flavor = "multi_thread")]
+async fn list_and_forget_writers() {
+    let (_base_dir, repo) = setup().await;
 
-        let local_file_vv_0 = file.version_vector().await.unwrap();
-        assert_eq!(local_file_vv_0, remote_file_vv);
+    let local_id = *repo.local_branch().unwrap().id();
+    let remote_id = PublicKey::random();
 
-        let local_parent_vv_0 = local_branch
-            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
-            .await
-            .unwrap()
-            .lookup("parent")
-            .unwrap()
-            .version_vector()
-            .clone();
+    // Keep the file open so the remote branch is not pruned in the meantime.
+    let remote_file = create_remote_file(&repo, remote_id, "test.txt", b"remote").await;
 
-        assert!(local_parent_vv_0 <= remote_parent_vv);
+    let writers = repo.list_writers().await.unwrap();
+    let remote = writers
+        .iter()
+        .find(|writer| writer.id == remote_id)
+        .unwrap();
+    assert_eq!(
+        remote.version_vector,
+        repo.get_branch_version_vector(&remote_id).await.unwrap()
+    );
+    // 2 blocks: 1 for the file and 1 for the root dir
+    assert_eq!(remote.block_count, 2);
 
-        drop(file);
+    assert_matches!(
+        repo.forget_writer(&local_id).await,
+        Err(Error::OperationNotSupported)
+    );
 
-        // modify the file and fork again
-        let mut file = remote_parent
-            .lookup("foo.txt")
-            .unwrap()
-            .file()
-            .unwrap()
-            .open()
-            .await
-            .unwrap();
-        file.write_all(b"hello").await.unwrap();
-        file.flush().await.unwrap();
+    drop(remote_file);
 
-        remote_parent.refresh().await.unwrap();
-        let remote_parent_vv = remote_parent.version_vector().await.unwrap();
-        let remote_file_vv = file.version_vector().await.unwrap();
+    // Merge the remote branch into the local one so the local one dominates it.
+    loop {
+        match repo.run_maintenance(MaintenanceKind::Merge).await {
+            Ok(()) => break,
+            Err(Error::Busy) => time::sleep(Duration::from_millis(10)).await,
+            Err(error) => panic!("unexpected error: {error:?}"),
+        }
+    }
 
-        file.fork(local_branch.clone()).await.unwrap();
+    // The branch might have been already pruned by the background worker.
+    assert_matches!(
+        repo.forget_writer(&remote_id).await,
+        Ok(()) | Err(Error::Store(store::Error::BranchNotFound))
+    );
 
-        let local_file_vv_1 = file.version_vector().await.unwrap();
-        assert_eq!(local_file_vv_1, remote_file_vv);
-        assert!(local_file_vv_1 > local_file_vv_0);
+    let writers = repo.list_writers().await.unwrap();
+    assert!(writers.iter().all(|writer| writer.id != remote_id));
+    assert!(writers.iter().any(|writer| writer.id == local_id));
+}
 
-        let local_parent_vv_1 = local_branch
-            .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
-            .await
-            .unwrap()
-            .lookup("parent")
-            .unwrap()
-            .version_vector()
-            .clone();
+#[tokio::test(flavor = "multi_thread")]
+async fn prune_policy() {
+    let (_base_dir, repo) = setup().await;
 
-        assert!(local_parent_vv_1 <= remote_parent_vv);
-        assert!(local_parent_vv_1 >= local_parent_vv_0);
-    })
+    let remote_id = PublicKey::random();
+
+    repo.set_prune_policy(PrunePolicy {
+        min_age: Duration::from_secs(3600),
+        keep_writers: BTreeSet::new(),
+    });
+
+    let remote_file = create_remote_file(&repo, remote_id, "test.txt", b"remote").await;
+    drop(remote_file);
+
+    // Merge the remote branch into the local one so it becomes outdated.
+    run_maintenance(&repo, MaintenanceKind::Merge).await;
+    run_maintenance(&repo, MaintenanceKind::Prune).await;
+
+    // Outdated but not old enough to be pruned.
+    let writers = repo.list_writers().await.unwrap();
+    assert!(writers.iter().any(|writer| writer.id == remote_id));
+
+    repo.set_prune_policy(PrunePolicy {
+        min_age: Duration::ZERO,
+        keep_writers: [remote_id].into(),
+    });
+    run_maintenance(&repo, MaintenanceKind::Prune).await;
+
+    // Explicitly kept.
+    let writers = repo.list_writers().await.unwrap();
+    assert!(writers.iter().any(|writer| writer.id == remote_id));
+
+    repo.set_prune_policy(PrunePolicy::default());
+    run_maintenance(&repo, MaintenanceKind::Prune).await;
+
+    let writers = repo.list_writers().await.unwrap();
+    assert!(writers.iter().all(|writer| writer.id != remote_id));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn size() {
+    let (_base_dir, repo) = setup().await;
+    assert_eq!(repo.size().await.unwrap().to_bytes(), 0);
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+
+    // 2 blocks: 1 for the file and 1 for the root dir
+    assert_eq!(
+        repo.size().await.unwrap().to_bytes(),
+        2 * (BLOCK_SIZE + BlockId::SIZE + BLOCK_NONCE_SIZE) as u64
+    );
+
+    // 1 block size + 1 byte == 2 blocks
+    let content = random_bytes(BLOCK_SIZE - blob::HEADER_SIZE + 1);
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+
+    // 3 blocks: 2 for the file and 1 for the root dir
+    assert_eq!(
+        repo.size().await.unwrap().to_bytes(),
+        3 * (BLOCK_SIZE + BlockId::SIZE + BLOCK_NONCE_SIZE) as u64
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watch_size() {
+    let (_base_dir, repo) = setup().await;
+    let mut rx = repo.watch_size();
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    let content = random_bytes(BLOCK_SIZE - blob::HEADER_SIZE + 1);
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+
+    // 3 blocks: 2 for the file and 1 for the root dir
+    timeout(
+        Duration::from_secs(10),
+        rx.wait_for(|size| *size == StorageSize::from_blocks(3)),
+    )
     .await
+    .expect("timeout waiting for size change")
     .unwrap();
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_empty_directory() {
+async fn watch_size_subscribe_after_changes() {
     let (_base_dir, repo) = setup().await;
 
-    let local_branch = repo.local_branch().unwrap();
-    let local_id = *local_branch.id();
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(&random_bytes(BLOCK_SIZE)).await.unwrap();
+    file.flush().await.unwrap();
 
-    let dir = repo.create_directory("stuff").await.unwrap();
-    assert_eq!(dir.version_vector().await.unwrap(), vv![local_id => 1]);
+    // The size is not computed while no one is watching but it's computed as soon as someone
+    // subscribes.
+    let expected = repo.size().await.unwrap();
+    let mut rx = repo.watch_size();
+
+    timeout(
+        Duration::from_secs(10),
+        rx.wait_for(|size| *size == expected),
+    )
+    .await
+    .expect("timeout waiting for size")
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn watch_sync_progress() {
+    test_utils::init_log();
+
+    let base_dir = TempDir::new().unwrap();
+    let repo = Repository::create(
+        &RepositoryParams::new(base_dir.path().join("repo.db"))
+            .with_sync_progress_interval(Duration::from_millis(10)),
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+    let mut rx = repo.watch_sync_progress();
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    let content = random_bytes(BLOCK_SIZE - blob::HEADER_SIZE + 1);
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+
+    // 3 blocks: 2 for the file and 1 for the root dir, all of them present locally.
+    timeout(
+        Duration::from_secs(10),
+        rx.wait_for(|progress| *progress == Progress { value: 3, total: 3 }),
+    )
+    .await
+    .expect("timeout waiting for progress change")
+    .unwrap();
+
+    assert_eq!(
+        repo.sync_progress().await.unwrap(),
+        Progress { value: 3, total: 3 }
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_file_wait() {
+    let (_base_dir, repo) = setup().await;
+
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    let mut file = repo
+        .open_file_wait("test.txt", Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"hello world");
+
+    // Missing file fails after the timeout.
+    assert_matches!(
+        repo.open_file_wait("missing.txt", Duration::from_millis(100))
+            .await,
+        Err(Error::EntryNotFound)
+    );
+
+    // Wait for a file created concurrently.
+    let (file, ()) = future::join(
+        repo.open_file_wait("later.txt", Duration::from_secs(10)),
+        async {
+            let mut file = repo.create_file("later.txt").await.unwrap();
+            file.flush().await.unwrap();
+        },
+    )
+    .await;
+    file.unwrap();
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_moved_non_empty_directory() {
+async fn raw_blocks() {
     let (_base_dir, repo) = setup().await;
 
-    repo.create_directory("foo").await.unwrap();
-    repo.create_file("foo/stuff.txt").await.unwrap();
+    // 3 blocks: 2 for the file and 1 for the root dir
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    let content = random_bytes(BLOCK_SIZE - blob::HEADER_SIZE + 1);
+    file.write_all(&content).await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
 
-    let vv_0 = repo
-        .local_branch()
-        .unwrap()
-        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+    assert!(repo
+        .list_missing_block_ids(None, u32::MAX)
         .await
         .unwrap()
-        .lookup("foo")
+        .is_empty());
+
+    let mut all_ids: Vec<BlockId> = sqlx::query("SELECT id FROM blocks")
+        .fetch_all(&mut *repo.db().acquire().await.unwrap())
+        .await
         .unwrap()
-        .version_vector()
-        .clone();
+        .into_iter()
+        .map(|row| row.get(0))
+        .collect();
+    all_ids.sort();
+    assert_eq!(all_ids.len(), 3);
 
-    repo.move_entry("/", "foo", "/", "bar").await.unwrap();
+    let mut blocks = Vec::new();
 
-    let vv_1 = repo
-        .local_branch()
-        .unwrap()
-        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
+    for id in &all_ids {
+        let block = repo.read_raw_block(id).await.unwrap();
+        assert_eq!(block.id(), *id);
+
+        // Already present
+        repo.receive_raw_block(block.clone()).await.unwrap();
+
+        blocks.push(block);
+    }
+
+    // Remove the blocks so they are tracked as missing in the index.
+    let mut tx = repo.shared.vault.store().begin_write().await.unwrap();
+    for id in &all_ids {
+        tx.remove_block(id).await.unwrap();
+    }
+    tx.commit().await.unwrap();
+
+    assert_eq!(
+        repo.list_missing_block_ids(None, u32::MAX).await.unwrap(),
+        all_ids
+    );
+
+    // Paginate
+    let page0 = repo.list_missing_block_ids(None, 2).await.unwrap();
+    let page1 = repo
+        .list_missing_block_ids(page0.last().copied(), 2)
+        .await
+        .unwrap();
+    let page2 = repo
+        .list_missing_block_ids(page1.last().copied(), 2)
+        .await
+        .unwrap();
+    assert_eq!([page0, page1].concat(), all_ids);
+    assert!(page2.is_empty());
+
+    // Received blocks are no longer listed.
+    let mut blocks = blocks.into_iter();
+    repo.receive_raw_block(blocks.next().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(
+        repo.list_missing_block_ids(None, u32::MAX).await.unwrap(),
+        all_ids[1..]
+    );
+
+    for block in blocks {
+        repo.receive_raw_block(block).await.unwrap();
+    }
+
+    assert!(repo
+        .list_missing_block_ids(None, u32::MAX)
         .await
         .unwrap()
-        .lookup("bar")
-        .unwrap()
-        .version_vector()
-        .clone();
+        .is_empty());
 
-    assert!(vv_1 > vv_0);
+    // Not referenced from the index of an unrelated repo.
+    let (_other_base_dir, other_repo) = setup().await;
+    let block = repo.read_raw_block(&all_ids[0]).await.unwrap();
+    assert_matches!(
+        other_repo.receive_raw_block(block).await,
+        Err(Error::Store(store::Error::BlockNotReferenced))
+    );
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn version_vector_file_moved_over_tombstone() {
+async fn quota_usage() {
     let (_base_dir, repo) = setup().await;
+    let local_id = *repo.local_branch().unwrap().id();
 
-    let mut file = repo.create_file("old.txt").await.unwrap();
-    file.write_all(b"a").await.unwrap();
+    let usage = repo.quota_usage().await.unwrap();
+    assert_eq!(usage.used.to_bytes(), 0);
+    assert_eq!(usage.quota, None);
+    assert!(usage.by_branch.is_empty());
+
+    // 3 blocks: 2 for the file and 1 for the root dir
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    let content = random_bytes(BLOCK_SIZE - blob::HEADER_SIZE + 1);
+    file.write_all(&content).await.unwrap();
     file.flush().await.unwrap();
 
-    let vv_0 = file.version_vector().await.unwrap();
+    let quota = StorageSize::from_blocks(10);
+    repo.set_quota(Some(quota)).await.unwrap();
 
+    let usage = repo.quota_usage().await.unwrap();
+    assert_eq!(usage.used, StorageSize::from_blocks(3));
+    assert_eq!(usage.quota, Some(quota));
+    assert_eq!(usage.by_branch.len(), 1);
+    assert_eq!(
+        usage.by_branch.get(&local_id),
+        Some(&StorageSize::from_blocks(3))
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn open_blob() {
+    let (_base_dir, repo) = setup().await;
+
+    repo.create_directory("dir").await.unwrap();
+
+    let mut file = repo.create_file("dir/a.txt").await.unwrap();
+    file.write_all(b"hello").await.unwrap();
+    file.flush().await.unwrap();
+    let blob_id = *file.blob_id();
     drop(file);
-    repo.remove_entry("old.txt").await.unwrap();
 
-    // The tombstone's vv is the original vv incremented by 1.
-    let branch_id = *repo.local_branch().unwrap().id();
-    let vv_1 = vv_0.incremented(branch_id);
+    // The file can be opened even after it's been moved.
+    repo.move_entry("dir", "a.txt", "/", "b.txt").await.unwrap();
 
-    repo.create_file("new.txt").await.unwrap();
-    repo.move_entry("/", "new.txt", "/", "old.txt")
-        .await
-        .unwrap();
+    let mut file = repo.open_blob(blob_id).await.unwrap();
+    assert_eq!(file.read_to_end().await.unwrap(), b"hello");
+    drop(file);
 
-    let vv_2 = repo
-        .local_branch()
-        .unwrap()
-        .open_root(DirectoryLocking::Enabled, DirectoryFallback::Disabled)
-        .await
-        .unwrap()
-        .lookup("old.txt")
-        .unwrap()
-        .version_vector()
-        .clone();
+    repo.remove_entry("b.txt").await.unwrap();
 
-    assert!(vv_2 > vv_1);
+    assert_matches!(repo.open_blob(blob_id).await, Err(Error::EntryNotFound));
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn file_conflict_modify_local() {
+async fn dedup_stats() {
     let (_base_dir, repo) = setup().await;
 
-    let local_branch = repo.local_branch().unwrap();
-    let local_id = *local_branch.id();
+    assert_eq!(repo.dedup_stats().await.unwrap(), DedupStats::default());
 
+    // 2 blocks in the remote branch: 1 for the file and 1 for the root dir
     let remote_id = PublicKey::random();
-    let remote_branch = repo
-        .get_branch(remote_id)
-        .unwrap()
-        .reopen(repo.secrets().keys().unwrap());
+    create_remote_file(&repo, remote_id, "test.txt", b"foo").await;
 
-    // Create two concurrent versions of the same file.
-    let local_file = create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
+    let stats = repo.dedup_stats().await.unwrap();
+    assert_eq!(stats.unique_blocks, 2);
+    assert_eq!(stats.referenced_blocks, 2);
+    assert_eq!(stats.shared_bytes, 0);
 
-    assert_eq!(
-        local_file.version_vector().await.unwrap(),
-        vv![local_id => 2]
-    );
-    drop(local_file);
+    // Forking the file into the local branch shares its block but creates a new root dir.
+    let local_branch = repo.local_branch().unwrap();
+    let mut file = repo.open_file("test.txt").await.unwrap();
+    file.fork(local_branch).await.unwrap();
 
-    let remote_file = create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
-    assert_eq!(
-        remote_file.version_vector().await.unwrap(),
-        vv![remote_id => 2]
-    );
-    drop(remote_file);
+    let stats = repo.dedup_stats().await.unwrap();
+    assert_eq!(stats.unique_blocks, 3);
+    assert_eq!(stats.referenced_blocks, 4);
+    assert_eq!(stats.shared_bytes, StorageSize::from_blocks(1).to_bytes());
+}
 
-    // Modify the local version.
-    let mut local_file = repo.open_file_version("test.txt", &local_id).await.unwrap();
-    local_file.write_all(b"local v2").await.unwrap();
-    local_file.flush().await.unwrap();
-    drop(local_file);
+#[tokio::test(flavor = "multi_thread")]
+async fn repair_without_peers() {
+    let (_base_dir, repo) = setup().await;
 
-    let mut local_file = repo.open_file_version("test.txt", &local_id).await.unwrap();
-    assert_eq!(local_file.read_to_end().await.unwrap(), b"local v2");
+    let mut file = repo.create_file("test.txt").await.unwrap();
+    file.write_all(b"hello world").await.unwrap();
+    file.flush().await.unwrap();
+    drop(file);
+
+    // Nothing to repair
     assert_eq!(
-        local_file.version_vector().await.unwrap(),
-        vv![local_id => 3]
+        repo.repair(Duration::from_millis(100)).await.unwrap(),
+        RepairStats::default()
     );
 
-    let mut remote_file = repo
-        .open_file_version("test.txt", &remote_id)
+    // Remove one of the blocks
+    let mut tx = repo.shared.vault.store().db().begin_write().await.unwrap();
+    sqlx::query("DELETE FROM blocks WHERE id = (SELECT id FROM blocks LIMIT 1)")
+        .execute(&mut tx)
         .await
         .unwrap();
-    assert_eq!(remote_file.read_to_end().await.unwrap(), b"remote v1");
+    tx.commit().await.unwrap();
+
+    assert!(!repo.check_integrity().await.unwrap());
+
+    // There are no peers to download the block from
     assert_eq!(
-        remote_file.version_vector().await.unwrap(),
-        vv![remote_id => 2]
+        repo.repair(Duration::from_millis(100)).await.unwrap(),
+        RepairStats {
+            repaired: 0,
+            missing: 1
+        }
     );
+
+    // The block is now tracked as missing in the index so the store is consistent again.
+    assert!(repo.check_integrity().await.unwrap());
 }
 
+#[cfg(feature = "prometheus")]
 #[tokio::test(flavor = "multi_thread")]
-async fn file_conflict_attempt_to_fork_and_modify_remote() {
-    let (_base_dir, repo) = setup().await;
+async fn prometheus_metrics() {
+    test_utils::init_log();
 
-    let local_branch = repo.local_branch().unwrap();
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test").with_prometheus();
 
-    let remote_id = PublicKey::random();
-    let remote_branch = repo
-        .get_branch(remote_id)
-        .unwrap()
-        .reopen(repo.secrets().keys().unwrap());
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
 
-    // Create two concurrent versions of the same file.
-    create_file_in_branch(&local_branch, "test.txt", b"local v1").await;
-    create_file_in_branch(&remote_branch, "test.txt", b"remote v1").await;
+    let content = repo.metrics_handle().unwrap().render();
+    assert!(content.contains("block_requests_sent"));
+    assert!(content.contains("requests_pending"));
+}
 
-    // Attempt to fork the remote version (fork is required to modify it)
-    let mut remote_file = repo
-        .open_file_version("test.txt", &remote_id)
-        .await
-        .unwrap();
-    assert_matches!(
-        remote_file.fork(local_branch).await,
-        Err(Error::EntryExists)
-    );
+#[tokio::test(flavor = "multi_thread")]
+async fn event_capacity() {
+    test_utils::init_log();
+
+    let (_base_dir, pool) = db::create_temp().await.unwrap();
+    let params = RepositoryParams::with_pool(pool, "test").with_event_capacity(1);
+
+    let repo = Repository::create(
+        &params,
+        Access::WriteUnlocked {
+            secrets: WriteSecrets::random(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut rx = repo.subscribe();
+
+    for i in 0..3 {
+        repo.create_file(format!("{i}.txt")).await.unwrap();
+    }
+
+    assert_matches!(rx.recv().await, Err(RecvError::Lagged(_)));
 }
 
 #[tokio::test(flavor = "multi_thread")]
-async fn size() {
+async fn secret_metadata() {
     let (_base_dir, repo) = setup().await;
-    assert_eq!(repo.size().await.unwrap().to_bytes(), 0);
+    let metadata = repo.metadata();
 
-    let mut file = repo.create_file("test.txt").await.unwrap();
+    metadata.set_secret("config", b"secret").await.unwrap();
+    metadata.set("config", "public").await.unwrap();
 
-    // 2 blocks: 1 for the file and 1 for the root dir
+    // The secret and the public values don't clash.
     assert_eq!(
-        repo.size().await.unwrap().to_bytes(),
-        2 * (BLOCK_SIZE + BlockId::SIZE + BLOCK_NONCE_SIZE) as u64
+        metadata.get_secret("config").await.unwrap().as_deref(),
+        Some(&b"secret"[..])
+    );
+    assert_eq!(
+        metadata.get::<String>("config").await.unwrap().as_deref(),
+        Some("public")
     );
 
-    // 1 block size + 1 byte == 2 blocks
-    let content = random_bytes(BLOCK_SIZE - blob::HEADER_SIZE + 1);
-    file.write_all(&content).await.unwrap();
-    file.flush().await.unwrap();
+    // The secret value is not stored in plaintext.
+    let mut conn = repo.db().acquire().await.unwrap();
+    let value: Vec<u8> = sqlx::query("SELECT value FROM metadata_secret WHERE name = ?")
+        .bind(&b"user/config"[..])
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap()
+        .get(0);
+    assert_ne!(value, b"secret");
+    drop(conn);
 
-    // 3 blocks: 2 for the file and 1 for the root dir
-    assert_eq!(
-        repo.size().await.unwrap().to_bytes(),
-        3 * (BLOCK_SIZE + BlockId::SIZE + BLOCK_NONCE_SIZE) as u64
+    // Secret values are not accessible in the blind mode, not even via an accessor obtained before
+    // the downgrade.
+    repo.downgrade_access(AccessMode::Blind).unwrap();
+
+    assert_matches!(
+        metadata.get_secret("config").await,
+        Err(Error::PermissionDenied)
+    );
+    assert_matches!(
+        metadata.set_secret("config", b"other").await,
+        Err(Error::PermissionDenied)
     );
+
+    metadata.remove_secret("config").await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn seeded_rng_source() {
+    test_utils::init_log();
+
+    let base_dir = TempDir::new().unwrap();
+    let secrets = WriteSecrets::random();
+
+    let create = |name: &str, rng_source| {
+        let params = RepositoryParams::new(base_dir.path().join(name)).with_rng_source(rng_source);
+        let secrets = secrets.clone();
+
+        async move {
+            Repository::create(&params, Access::WriteUnlocked { secrets })
+                .await
+                .unwrap()
+        }
+    };
+
+    let repo_a = create("a.db", RngSource::Seeded(42)).await;
+    let repo_b = create("b.db", RngSource::Seeded(42)).await;
+    let repo_c = create("c.db", RngSource::Seeded(43)).await;
+
+    let writer_id_a = *repo_a.local_branch().unwrap().id();
+    let writer_id_b = *repo_b.local_branch().unwrap().id();
+    let writer_id_c = *repo_c.local_branch().unwrap().id();
+
+    assert_eq!(writer_id_a, writer_id_b);
+    assert_ne!(writer_id_a, writer_id_c);
+
+    repo_a.close().await.unwrap();
+    repo_b.close().await.unwrap();
+    repo_c.close().await.unwrap();
 }
 
 async fn setup() -> (TempDir, Repository) {
@@ -1137,3 +2931,78 @@ where
     .await
     .expect("timeout waiting for condition")
 }
+
+// Runs the maintenance job, retrying if it's already running in the background worker.
+async fn run_maintenance(repo: &Repository, kind: MaintenanceKind) {
+    loop {
+        match repo.run_maintenance(kind).await {
+            Ok(()) => break,
+            Err(Error::Busy) => time::sleep(Duration::from_millis(10)).await,
+            Err(error) => panic!("unexpected error: {error:?}"),
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+struct MemoryBlockStore {
+    blocks: Arc<BlockingMutex<HashMap<BlockId, ([u8; BLOCK_NONCE_SIZE], Vec<u8>)>>>,
+}
+
+impl MemoryBlockStore {
+    fn len(&self) -> usize {
+        self.blocks.lock().unwrap().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[async_trait]
+impl BlockStore for MemoryBlockStore {
+    async fn read(
+        &self,
+        id: &BlockId,
+        content: &mut [u8],
+    ) -> io::Result<Option<[u8; BLOCK_NONCE_SIZE]>> {
+        let blocks = self.blocks.lock().unwrap();
+        let Some((nonce, data)) = blocks.get(id) else {
+            return Ok(None);
+        };
+
+        content.copy_from_slice(data);
+        Ok(Some(*nonce))
+    }
+
+    async fn write(
+        &self,
+        id: &BlockId,
+        nonce: &[u8; BLOCK_NONCE_SIZE],
+        content: &[u8],
+    ) -> io::Result<()> {
+        self.blocks
+            .lock()
+            .unwrap()
+            .entry(*id)
+            .or_insert_with(|| (*nonce, content.to_vec()));
+        Ok(())
+    }
+
+    async fn remove(&self, id: &BlockId) -> io::Result<()> {
+        self.blocks.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    async fn exists(&self, id: &BlockId) -> io::Result<bool> {
+        Ok(self.blocks.lock().unwrap().contains_key(id))
+    }
+
+    async fn count(&self) -> io::Result<u64> {
+        Ok(self.len() as u64)
+    }
+
+    async fn clear(&self) -> io::Result<()> {
+        self.blocks.lock().unwrap().clear();
+        Ok(())
+    }
+}
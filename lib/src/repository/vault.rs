@@ -2,9 +2,10 @@
 
 use super::{quota, LocalId, Metadata, RepositoryId, RepositoryMonitor};
 use crate::{
-    block_tracker::{BlockPromise, BlockTracker, OfferState},
+    blob::BlobId,
+    block_tracker::{BlockPromise, BlockTracker, OfferState, QueueDepth},
+    collections::{HashMap, HashSet},
     crypto::{sign::PublicKey, CacheHash},
-    db,
     debug::DebugPrinter,
     error::Result,
     event::{EventSender, Payload},
@@ -14,22 +15,61 @@ use crate::{
     },
     storage_size::StorageSize,
     store::{
-        self, InnerNodeReceiveStatus, LeafNodeReceiveStatus, ReceiveFilter, RootNodeReceiveStatus,
-        Store, WriteTransaction,
+        self, InnerNodeReceiveStatus, LeafNodeReceiveStatus, QuotaExceeded, ReceiveFilter,
+        RootNodeReceiveStatus, Store, WriteTransaction,
     },
+    sync::resizable_semaphore::ResizableSemaphore,
+    version_vector::VersionVector,
 };
+use deadlock::BlockingMutex;
 use futures_util::TryStreamExt;
-use sqlx::Row;
+use state_monitor::MonitoredValue;
 use std::{sync::Arc, time::Duration};
 use tracing::Instrument;
 
+// Default maximum number of block requests (across all peers) that have been sent but for which
+// responses haven't been received yet. Unlimited by default, so only the per-peer limits apply.
+const DEFAULT_MAX_INFLIGHT_BLOCK_REQUESTS: usize = usize::MAX;
+
+/// Storage usage of a repository together with its quota.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QuotaUsage {
+    /// Total size of the stored data.
+    pub used: StorageSize,
+    /// The storage quota or `None` if no quota is set.
+    pub quota: Option<StorageSize>,
+    /// Size of the data referenced from the latest snapshot of each branch. Data shared by
+    /// multiple branches is counted in each of them, so the values don't necessarily add up to
+    /// `used`.
+    pub by_branch: HashMap<PublicKey, StorageSize>,
+}
+
+/// Statistics about the blocks shared by multiple branches.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DedupStats {
+    /// Number of distinct blocks referenced from the latest snapshot of any branch.
+    pub unique_blocks: u64,
+    /// Number of blocks referenced from the latest snapshot of each branch, summed over all the
+    /// branches. Blocks shared by multiple branches are counted once for each of them.
+    pub referenced_blocks: u64,
+    /// Size of the data that would have to be stored again if the shared blocks weren't shared,
+    /// i.e. the storage saved by the sharing.
+    pub shared_bytes: u64,
+}
+
 #[derive(Clone)]
 pub(crate) struct Vault {
     repository_id: RepositoryId,
     store: Store,
     pub event_tx: EventSender,
     pub block_tracker: BlockTracker,
-    pub block_request_mode: BlockRequestMode,
+    // Blobs (files) whose missing blocks should be requested before the others.
+    pub prioritized_blobs: Arc<BlockingMutex<HashSet<BlobId>>>,
+    _block_queue_depth: MonitoredValue<QueueDepth>,
+    pub block_request_limiter: Arc<ResizableSemaphore>,
+    // Additionally limits the requests of blocks that are not required but requested only because
+    // of the greedy mode.
+    pub greedy_block_request_limiter: Arc<ResizableSemaphore>,
     pub local_id: LocalId,
     pub monitor: Arc<RepositoryMonitor>,
 }
@@ -38,18 +78,30 @@ impl Vault {
     pub fn new(
         repository_id: RepositoryId,
         event_tx: EventSender,
-        pool: db::Pool,
+        store: Store,
         block_request_mode: BlockRequestMode,
         monitor: RepositoryMonitor,
     ) -> Self {
-        let store = Store::new(pool);
+        let block_tracker = BlockTracker::new();
+        block_tracker.set_greedy(matches!(block_request_mode, BlockRequestMode::Greedy));
+
+        let block_queue_depth = monitor
+            .node()
+            .make_value("block queue depth", block_tracker.queue_depth());
 
         Self {
             repository_id,
             store,
             event_tx,
-            block_tracker: BlockTracker::new(),
-            block_request_mode,
+            block_tracker,
+            prioritized_blobs: Arc::new(BlockingMutex::new(HashSet::default())),
+            _block_queue_depth: block_queue_depth,
+            block_request_limiter: Arc::new(ResizableSemaphore::new(
+                DEFAULT_MAX_INFLIGHT_BLOCK_REQUESTS,
+            )),
+            greedy_block_request_limiter: Arc::new(ResizableSemaphore::new(
+                DEFAULT_MAX_INFLIGHT_BLOCK_REQUESTS,
+            )),
             local_id: LocalId::new(),
             monitor: Arc::new(monitor),
         }
@@ -63,6 +115,19 @@ impl Vault {
         &self.store
     }
 
+    pub fn block_request_mode(&self) -> BlockRequestMode {
+        if self.block_tracker.is_greedy() {
+            BlockRequestMode::Greedy
+        } else {
+            BlockRequestMode::Lazy
+        }
+    }
+
+    pub fn set_block_request_mode(&self, mode: BlockRequestMode) {
+        self.block_tracker
+            .set_greedy(matches!(mode, BlockRequestMode::Greedy));
+    }
+
     /// Receive `RootNode` from other replica and store it into the db. Returns whether the
     /// received node has any new information compared to all the nodes already stored locally.
     pub async fn receive_root_node(
@@ -85,7 +150,8 @@ impl Vault {
 
         let mut tx = self.store().begin_write().await?;
         let status = tx.receive_root_node(proof, block_presence).await?;
-        self.finalize_receive(tx, &status.new_approved).await?;
+        self.finalize_receive(tx, &status.new_approved, &status.new_branches, &[])
+            .await?;
 
         Ok(status)
     }
@@ -101,7 +167,13 @@ impl Vault {
     ) -> Result<InnerNodeReceiveStatus> {
         let mut tx = self.store().begin_write().await?;
         let status = tx.receive_inner_nodes(nodes, receive_filter, quota).await?;
-        self.finalize_receive(tx, &status.new_approved).await?;
+        self.finalize_receive(
+            tx,
+            &status.new_approved,
+            &status.new_branches,
+            &status.quota_exceeded,
+        )
+        .await?;
 
         Ok(status)
     }
@@ -116,7 +188,13 @@ impl Vault {
     ) -> Result<LeafNodeReceiveStatus> {
         let mut tx = self.store().begin_write().await?;
         let status = tx.receive_leaf_nodes(nodes, quota).await?;
-        self.finalize_receive(tx, &status.new_approved).await?;
+        self.finalize_receive(
+            tx,
+            &status.new_approved,
+            &status.new_branches,
+            &status.quota_exceeded,
+        )
+        .await?;
 
         Ok(status)
     }
@@ -201,19 +279,65 @@ impl Vault {
 
     /// Total size of the stored data
     pub async fn size(&self) -> Result<StorageSize> {
-        let mut conn = self.store().db().acquire().await?;
-
         // Note: for simplicity, we are currently counting only blocks (content + id + nonce)
-        let count = db::decode_u64(
-            sqlx::query("SELECT COUNT(*) FROM blocks")
-                .fetch_one(&mut *conn)
-                .await?
-                .get(0),
-        );
+        let count = self.store().count_blocks().await?;
 
         Ok(StorageSize::from_blocks(count))
     }
 
+    pub async fn quota_usage(&self) -> Result<QuotaUsage> {
+        let used = self.size().await?;
+        let quota = self.quota().await?;
+        let by_branch = self
+            .store()
+            .acquire_read()
+            .await?
+            .count_referenced_blocks_by_branch()
+            .await?
+            .into_iter()
+            .map(|(branch_id, count)| (branch_id, StorageSize::from_blocks(count)))
+            .collect();
+
+        Ok(QuotaUsage {
+            used,
+            quota,
+            by_branch,
+        })
+    }
+
+    pub async fn dedup_stats(&self) -> Result<DedupStats> {
+        // Both counts must come from the same snapshots, otherwise a concurrent commit could make
+        // the unique count exceed the total one.
+        let mut tx = self.store().begin_read().await?;
+
+        let unique_blocks = tx.count_unique_referenced_blocks().await?;
+        let referenced_blocks: u64 = tx
+            .count_referenced_blocks_by_branch()
+            .await?
+            .into_iter()
+            .map(|(_, count)| count)
+            .sum();
+        let shared_bytes = StorageSize::from_blocks(referenced_blocks - unique_blocks).to_bytes();
+
+        Ok(DedupStats {
+            unique_blocks,
+            referenced_blocks,
+            shared_bytes,
+        })
+    }
+
+    /// Loads the version vectors of the latest approved snapshots of all branches.
+    pub async fn load_version_vectors(&self) -> Result<HashMap<PublicKey, VersionVector>> {
+        Ok(self
+            .store()
+            .acquire_read()
+            .await?
+            .load_root_nodes()
+            .map_ok(|node| (node.proof.writer_id, node.proof.version_vector.clone()))
+            .try_collect()
+            .await?)
+    }
+
     pub async fn set_quota(&self, quota: Option<StorageSize>) -> Result<()> {
         let mut tx = self.store().db().begin_write().await?;
 
@@ -266,15 +390,36 @@ impl Vault {
         &self,
         tx: WriteTransaction,
         new_approved: &[PublicKey],
+        new_branches: &[PublicKey],
+        quota_exceeded: &[QuotaExceeded],
     ) -> Result<()> {
         tx.commit_and_then({
             let new_approved = new_approved.to_vec();
+            let new_branches = new_branches.to_vec();
+            let quota_exceeded = quota_exceeded.to_vec();
             let event_tx = self.event_tx.clone();
 
             move || {
+                for branch_id in new_branches {
+                    event_tx.send(Payload::BranchCreated(branch_id));
+                }
+
                 for branch_id in new_approved {
                     event_tx.send(Payload::BranchChanged(branch_id));
                 }
+
+                for QuotaExceeded {
+                    branch_id,
+                    requested,
+                    available,
+                } in quota_exceeded
+                {
+                    event_tx.send(Payload::QuotaExceeded {
+                        branch_id,
+                        requested,
+                        available,
+                    });
+                }
             }
         })
         .await?;
@@ -283,10 +428,11 @@ impl Vault {
     }
 }
 
-#[derive(Clone, Copy)]
-pub(crate) enum BlockRequestMode {
-    // Request only required blocks
+/// Which blocks to request from other replicas.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum BlockRequestMode {
+    /// Request only the blocks that are required (e.g., by reading a file).
     Lazy,
-    // Request all blocks
+    /// Request all blocks.
     Greedy,
 }
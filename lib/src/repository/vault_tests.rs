@@ -16,7 +16,7 @@ use crate::{
         Block, BlockContent, BlockId, Locator, MultiBlockPresence, NodeState, Proof,
         RootNodeFilter, SingleBlockPresence, EMPTY_INNER_HASH,
     },
-    store::{self, Changeset, ReadTransaction},
+    store::{self, Changeset, ReadTransaction, ReceiveFilter, Store},
     test_utils,
     version_vector::VersionVector,
 };
@@ -880,6 +880,43 @@ async fn receive_existing_snapshot() {
     }
 }
 
+#[tokio::test]
+async fn receive_new_branch() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let (_base_dir, vault, secrets) = setup_with_rng(&mut rng).await;
+
+    let remote_id = PublicKey::generate(&mut rng);
+    let receive_filter = vault.store().receive_filter();
+
+    // The first snapshot of the branch makes it new.
+    let vv = VersionVector::first(remote_id);
+    let snapshot = Snapshot::generate(&mut rng, 1);
+    let new_branches = receive_nodes_and_collect_new_branches(
+        &vault,
+        &secrets.write_keys,
+        remote_id,
+        vv.clone(),
+        &receive_filter,
+        &snapshot,
+    )
+    .await;
+    assert_eq!(new_branches, [remote_id]);
+
+    // The subsequent snapshots don't.
+    let vv = vv.incremented(remote_id);
+    let snapshot = Snapshot::generate(&mut rng, 1);
+    let new_branches = receive_nodes_and_collect_new_branches(
+        &vault,
+        &secrets.write_keys,
+        remote_id,
+        vv,
+        &receive_filter,
+        &snapshot,
+    )
+    .await;
+    assert!(new_branches.is_empty());
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn block_ids_local() {
     let (_base_dir, vault, secrets) = setup().await;
@@ -1173,7 +1210,7 @@ async fn setup_with_rng(rng: &mut StdRng) -> (TempDir, Vault, WriteSecrets) {
     let vault = Vault::new(
         repository_id,
         EventSender::new(1),
-        pool,
+        Store::new(pool),
         BlockRequestMode::Lazy,
         RepositoryMonitor::new(StateMonitor::make_root(), &NoopRecorder),
     );
@@ -1206,6 +1243,45 @@ async fn receive_snapshot(
     receive_nodes(vault, write_keys, writer_id, vv, &receive_filter, snapshot).await
 }
 
+// Same as `receive_nodes` but returns the branches reported as new by the receive statuses.
+async fn receive_nodes_and_collect_new_branches(
+    vault: &Vault,
+    write_keys: &Keypair,
+    branch_id: PublicKey,
+    version_vector: VersionVector,
+    receive_filter: &ReceiveFilter,
+    snapshot: &Snapshot,
+) -> Vec<PublicKey> {
+    let mut new_branches = Vec::new();
+
+    let proof = Proof::new(branch_id, version_vector, *snapshot.root_hash(), write_keys);
+    let status = vault
+        .receive_root_node(proof.into(), MultiBlockPresence::Full)
+        .await
+        .unwrap();
+    new_branches.extend(status.new_branches);
+
+    for layer in snapshot.inner_layers() {
+        for (_, nodes) in layer.inner_maps() {
+            let status = vault
+                .receive_inner_nodes(nodes.clone().into(), receive_filter, None)
+                .await
+                .unwrap();
+            new_branches.extend(status.new_branches);
+        }
+    }
+
+    for (_, nodes) in snapshot.leaf_sets() {
+        let status = vault
+            .receive_leaf_nodes(nodes.clone().into(), None)
+            .await
+            .unwrap();
+        new_branches.extend(status.new_branches);
+    }
+
+    new_branches
+}
+
 async fn receive_block(vault: &Vault, block: &Block) {
     let mut tx = vault.store().begin_write().await.unwrap();
     tx.receive_block(block).await.unwrap();
@@ -1,8 +1,13 @@
+pub(super) use self::scan::{deprioritize_missing_blocks, require_missing_blocks};
+
 use self::utils::{unlock, Command, Counter};
-use super::Shared;
+use super::{resolve_conflict, Shared};
 use crate::{
     blob::{BlobId, BlockIds},
+    block_tracker::BlockPriority,
     branch::Branch,
+    collections::HashMap,
+    crypto::sign::PublicKey,
     directory::{DirectoryFallback, DirectoryLocking},
     error::{Error, Result},
     event::{self, Event, EventScope, Lagged, Payload},
@@ -10,9 +15,71 @@ use crate::{
     store, versioned,
 };
 use async_recursion::async_recursion;
+use deadlock::BlockingMutex;
 use futures_util::{stream, StreamExt};
-use std::{future, sync::Arc};
-use tokio::select;
+use std::{
+    collections::BTreeSet,
+    future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{select, sync::watch, time};
+
+/// Maintenance job that can be run manually (see `Repository::run_maintenance`).
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum MaintenanceKind {
+    /// Merge remote branches into the local one.
+    Merge,
+    /// Remove outdated branches and snapshots.
+    Prune,
+    /// Remove unreachable blocks.
+    Trash,
+}
+
+/// Policy controlling which outdated branches get pruned (see `Repository::set_prune_policy`).
+///
+/// The default policy prunes every outdated branch as soon as possible.
+#[derive(Clone, Default, Eq, PartialEq, Debug)]
+pub struct PrunePolicy {
+    /// How long a branch has to be outdated before it's pruned. Measured from the time this
+    /// replica first found the branch outdated, since the repository was opened.
+    pub min_age: Duration,
+    /// Branches of these writers are never pruned.
+    pub keep_writers: BTreeSet<PublicKey>,
+}
+
+/// Strategy for handling concurrent versions of a file that can't be merged automatically (see
+/// `Repository::set_merge_strategy`).
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub enum MergeStrategy {
+    /// Keep all the concurrent versions and let the user resolve the conflict (see
+    /// `Repository::resolve_conflict`). This is the default and the only strategy that never
+    /// discards any data.
+    #[default]
+    KeepAllVersions,
+    /// Resolve the conflict in favor of the local version, discarding the remote ones. Conflicts
+    /// the local branch has no version in are kept.
+    PreferLocal,
+}
+
+/// State of the prune job that is preserved between its runs.
+pub(super) struct PruneState {
+    pub policy: watch::Sender<PrunePolicy>,
+    // Time when the next prune is due, if any branch is being kept only because of `min_age`.
+    next: watch::Sender<Option<Instant>>,
+    // Time when each currently outdated branch was first found outdated.
+    outdated_since: BlockingMutex<HashMap<PublicKey, Instant>>,
+}
+
+impl PruneState {
+    pub fn new() -> Self {
+        Self {
+            policy: watch::channel(PrunePolicy::default()).0,
+            next: watch::channel(None).0,
+            outdated_since: BlockingMutex::new(HashMap::default()),
+        }
+    }
+}
 
 /// Background worker to perform various jobs on the repository:
 /// - merge remote branches into the local one
@@ -47,7 +114,11 @@ pub(super) async fn run(shared: Arc<Shared>, local_branch: Option<Branch>) {
                     })
                     | Err(Lagged) => Some(Command::Wait),
                     Ok(Event {
-                        payload: Payload::MaintenanceCompleted,
+                        payload:
+                            Payload::BranchCreated(_)
+                            | Payload::BranchRemoved(_)
+                            | Payload::MaintenanceCompleted
+                            | Payload::QuotaExceeded { .. },
                         ..
                     }) => None,
                 })
@@ -62,7 +133,49 @@ pub(super) async fn run(shared: Arc<Shared>, local_branch: Option<Branch>) {
             }
         });
 
-        let commands = stream::select(events, unlocks);
+        // Rerun when the prune policy or the merge strategy changes...
+        let policy_changes = stream::unfold(shared.prune.policy.subscribe(), |mut rx| async move {
+            rx.changed().await.ok()?;
+            Some((Command::Wait, rx))
+        });
+        let strategy_changes =
+            stream::unfold(shared.merge_strategy.subscribe(), |mut rx| async move {
+                rx.changed().await.ok()?;
+                Some((Command::Wait, rx))
+            });
+
+        // ...or when a branch kept because of `min_age` becomes old enough.
+        let prune_timer = stream::unfold(
+            (shared.prune.next.subscribe(), None::<Instant>),
+            |(mut rx, mut fired)| async move {
+                loop {
+                    let next = *rx.borrow_and_update();
+
+                    match next {
+                        // Fire at most once per deadline, otherwise we would keep firing until
+                        // the prune job updates it.
+                        Some(next) if Some(next) != fired => {
+                            select! {
+                                _ = time::sleep_until(next.into()) => {
+                                    fired = Some(next);
+                                    return Some((Command::Wait, (rx, fired)));
+                                }
+                                result = rx.changed() => result.ok()?,
+                            }
+                        }
+                        _ => rx.changed().await.ok()?,
+                    }
+                }
+            },
+        );
+
+        let commands = stream::select(
+            stream::select(events, unlocks),
+            stream::select(
+                stream::select(policy_changes, strategy_changes),
+                prune_timer,
+            ),
+        );
 
         utils::run(
             || maintain(&shared, local_branch.as_ref(), &unlock_tx, &prune_counter),
@@ -93,7 +206,11 @@ pub(super) async fn run(shared: Arc<Shared>, local_branch: Option<Branch>) {
                     })
                     | Err(Lagged) => Some(Command::Wait),
                     Ok(Event {
-                        payload: Payload::MaintenanceCompleted,
+                        payload:
+                            Payload::BranchCreated(_)
+                            | Payload::BranchRemoved(_)
+                            | Payload::MaintenanceCompleted
+                            | Payload::QuotaExceeded { .. },
                         ..
                     }) => None,
                 })
@@ -109,6 +226,50 @@ pub(super) async fn run(shared: Arc<Shared>, local_branch: Option<Branch>) {
     }
 }
 
+/// Runs the given maintenance job once, outside of the regular schedule, and waits for it to
+/// complete. Returns `Error::Busy` if the job is already running.
+pub(super) async fn run_maintenance(shared: &Shared, kind: MaintenanceKind) -> Result<()> {
+    // Nobody listens to the unlock notifications, the job is not going to be restarted.
+    let (unlock_tx, _) = unlock::channel();
+    let monitor = &shared.vault.monitor;
+
+    let result = match kind {
+        MaintenanceKind::Merge => {
+            if !shared.secrets().can_write() {
+                return Err(Error::PermissionDenied);
+            }
+
+            let local_branch = shared.local_branch()?;
+
+            monitor
+                .merge_job
+                .try_run(merge::run(shared, &local_branch))
+                .await
+        }
+        MaintenanceKind::Prune => {
+            monitor
+                .prune_job
+                .try_run(prune::run(shared, &unlock_tx, &Counter::new()))
+                .await
+        }
+        MaintenanceKind::Trash => {
+            if !shared.secrets().can_read() {
+                return Err(Error::PermissionDenied);
+            }
+
+            let can_write = shared.secrets().can_write();
+            let local_branch = can_write.then(|| shared.local_branch()).transpose()?;
+
+            monitor
+                .trash_job
+                .try_run(trash::run(shared, local_branch.as_ref(), &unlock_tx))
+                .await
+        }
+    };
+
+    result.unwrap_or(Err(Error::Busy))
+}
+
 async fn maintain(
     shared: &Shared,
     local_branch: Option<&Branch>,
@@ -117,15 +278,19 @@ async fn maintain(
 ) {
     let mut success = true;
 
+    // Note: `try_run` returns `None` if the job is currently being run manually (see
+    // `run_maintenance`). Such job is not considered successful here, so that
+    // `MaintenanceCompleted` is not sent prematurely.
+
     // Merge branches
     if let Some(local_branch) = local_branch {
         let job_success = shared
             .vault
             .monitor
             .merge_job
-            .run(merge::run(shared, local_branch))
+            .try_run(merge::run(shared, local_branch))
             .await;
-        success = success && job_success;
+        success = success && matches!(job_success, Some(Ok(())));
     }
 
     // Prune outdated branches and snapshots
@@ -133,19 +298,19 @@ async fn maintain(
         .vault
         .monitor
         .prune_job
-        .run(prune::run(shared, unlock_tx, prune_counter))
+        .try_run(prune::run(shared, unlock_tx, prune_counter))
         .await;
-    success = success && job_success;
+    success = success && matches!(job_success, Some(Ok(())));
 
     // Collect unreachable blocks
-    if shared.secrets.can_read() {
+    if shared.secrets().can_read() {
         let job_success = shared
             .vault
             .monitor
             .trash_job
-            .run(trash::run(shared, local_branch, unlock_tx))
+            .try_run(trash::run(shared, local_branch, unlock_tx))
             .await;
-        success = success && job_success;
+        success = success && matches!(job_success, Some(Ok(())));
     }
 
     if success {
@@ -209,11 +374,12 @@ mod scan {
             }
         }
 
-        traverse(shared, JointDirectory::new(None, versions)).await
+        traverse(shared, JointDirectory::new(None, versions), 0).await
     }
 
+    // `depth` is the depth of `dir` relative to the root.
     #[async_recursion]
-    async fn traverse(shared: &Shared, dir: JointDirectory) -> Result<()> {
+    async fn traverse(shared: &Shared, dir: JointDirectory, depth: usize) -> Result<()> {
         let mut subdirs = Vec::new();
 
         for entry in dir.entries() {
@@ -232,6 +398,12 @@ mod scan {
                             .await?;
                     }
 
+                    // Directories deeper than the max path depth can't be opened anyway.
+                    if depth + 1 > shared.max_path_depth {
+                        tracing::warn!(entry = entry.name(), depth, "Directory is too deep");
+                        continue;
+                    }
+
                     match entry
                         .open_with(MissingVersionStrategy::Fail, DirectoryFallback::Disabled)
                         .await
@@ -251,18 +423,30 @@ mod scan {
         }
 
         for dir in subdirs {
-            traverse(shared, dir).await?;
+            traverse(shared, dir, depth + 1).await?;
         }
 
         Ok(())
     }
 
     #[instrument(skip(shared, branch), fields(branch_id = ?branch.id()))]
-    async fn require_missing_blocks(
+    pub(in crate::repository) async fn require_missing_blocks(
         shared: &Shared,
         branch: &Branch,
         blob_id: BlobId,
     ) -> Result<()> {
+        let priority = if shared
+            .vault
+            .prioritized_blobs
+            .lock()
+            .unwrap()
+            .contains(&blob_id)
+        {
+            BlockPriority::High
+        } else {
+            BlockPriority::Normal
+        };
+
         let mut blob_block_ids =
             BlockIds::open(branch.clone(), blob_id)
                 .await
@@ -285,7 +469,10 @@ mod scan {
                 .block_exists(&block_id)
                 .await?
             {
-                shared.vault.block_tracker.require(block_id);
+                shared
+                    .vault
+                    .block_tracker
+                    .require_with_priority(block_id, priority);
 
                 if !file_progress_cache_reset {
                     file_progress_cache_reset = true;
@@ -298,14 +485,132 @@ mod scan {
 
         Ok(())
     }
+
+    /// Lowers the priority of the missing blocks of the given blob which are already required.
+    pub(in crate::repository) async fn deprioritize_missing_blocks(
+        shared: &Shared,
+        branch: &Branch,
+        blob_id: BlobId,
+    ) -> Result<()> {
+        let mut blob_block_ids = BlockIds::open(branch.clone(), blob_id).await?;
+
+        while let Some(block_id) = blob_block_ids.try_next().await? {
+            shared.vault.block_tracker.deprioritize(&block_id);
+        }
+
+        Ok(())
+    }
 }
 
 /// Merge remote branches into the local one.
 mod merge {
     use super::*;
-    use crate::store;
+    use crate::{directory::EntryType, joint_directory::JointDirectoryRef, store};
+    use camino::Utf8PathBuf;
+    use std::collections::VecDeque;
 
     pub(super) async fn run(shared: &Shared, local_branch: &Branch) -> Result<()> {
+        merge(shared, local_branch).await?;
+
+        let strategy = *shared.merge_strategy.borrow();
+
+        match strategy {
+            MergeStrategy::KeepAllVersions => Ok(()),
+            MergeStrategy::PreferLocal => {
+                // Merge again if any conflict got resolved, so the losing versions can be
+                // discarded.
+                if resolve_conflicts(shared, local_branch).await? {
+                    merge(shared, local_branch).await?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    async fn merge(shared: &Shared, local_branch: &Branch) -> Result<()> {
+        match open_root(shared, local_branch).await?.merge().await {
+            Ok(_) | Err(Error::AmbiguousEntry) => Ok(()),
+            Err(error) => Err(error),
+        }
+    }
+
+    // Resolves all file conflicts the local branch has a version in by picking the local version.
+    // Returns whether any conflict was resolved. Descends only into the directories that can
+    // contain such conflicts and skips those that are not yet available.
+    async fn resolve_conflicts(shared: &Shared, local_branch: &Branch) -> Result<bool> {
+        let mut dirs = VecDeque::from([(
+            Utf8PathBuf::from("/"),
+            open_root(shared, local_branch).await?,
+        )]);
+        let mut resolved = false;
+
+        while let Some((path, dir)) = dirs.pop_front() {
+            for conflict in dir.conflicts(&path).collect::<Vec<_>>() {
+                let resolvable = conflict
+                    .versions
+                    .iter()
+                    .all(|version| version.entry_type == EntryType::File)
+                    && conflict
+                        .versions
+                        .iter()
+                        .any(|version| version.branch_id == *local_branch.id());
+
+                if !resolvable {
+                    continue;
+                }
+
+                // unwrap is ok because the conflict path is always joined with the entry name.
+                let name = conflict.path.file_name().unwrap();
+
+                tracing::trace!(path = %conflict.path, "resolving conflict in favor of local");
+
+                resolve_conflict(&dir, name, local_branch.clone(), local_branch.id()).await?;
+                resolved = true;
+            }
+
+            for entry in dir.entries() {
+                let JointEntryRef::Directory(entry) = entry else {
+                    continue;
+                };
+
+                if !has_remote_changes(&entry, local_branch) {
+                    continue;
+                }
+
+                match entry.open().await {
+                    Ok(subdir) => dirs.push_back((path.join(entry.name()), subdir)),
+                    Err(Error::Store(store::Error::BlockNotFound)) => {
+                        // Not fully downloaded yet. Will be revisited on a later merge run.
+                        continue;
+                    }
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    // Whether the directory has a local version and a remote one with changes the local one doesn't
+    // have. Only such directories can contain conflicts resolvable in favor of the local branch,
+    // because a directory with a conflict is not marked as merged (see `JointDirectory::merge`).
+    fn has_remote_changes(entry: &JointDirectoryRef, local_branch: &Branch) -> bool {
+        let Some(local) = entry
+            .versions()
+            .iter()
+            .find(|version| version.branch().id() == local_branch.id())
+        else {
+            return false;
+        };
+
+        entry
+            .versions()
+            .iter()
+            .any(|version| !(version.version_vector() <= local.version_vector()))
+    }
+
+    async fn open_root(shared: &Shared, local_branch: &Branch) -> Result<JointDirectory> {
         let branches: Vec<_> = shared.load_branches().await?;
         let mut roots = Vec::with_capacity(branches.len());
 
@@ -327,13 +632,7 @@ mod merge {
             }
         }
 
-        match JointDirectory::new(Some(local_branch.clone()), roots)
-            .merge()
-            .await
-        {
-            Ok(_) | Err(Error::AmbiguousEntry) => Ok(()),
-            Err(error) => Err(error),
-        }
+        Ok(JointDirectory::new(Some(local_branch.clone()), roots))
     }
 }
 
@@ -343,6 +642,7 @@ mod prune {
 
     use super::*;
     use futures_util::TryStreamExt;
+    use std::time::SystemTime;
 
     pub(super) async fn run(
         shared: &Shared,
@@ -361,6 +661,28 @@ mod prune {
         let (uptodate, outdated): (Vec<_>, Vec<_>) =
             versioned::partition(all, PreferBranch(Some(&shared.this_writer_id)));
 
+        let policy = shared.prune.policy.borrow().clone();
+        let now = Instant::now();
+
+        let outdated_since = {
+            let mut outdated_since = shared.prune.outdated_since.lock().unwrap();
+
+            // Forget branches that are no longer outdated (or no longer exist).
+            outdated_since.retain(|writer_id, _| {
+                outdated
+                    .iter()
+                    .any(|node| node.proof.writer_id == *writer_id)
+            });
+
+            for node in &outdated {
+                outdated_since.entry(node.proof.writer_id).or_insert(now);
+            }
+
+            outdated_since.clone()
+        };
+
+        let mut next = None;
+
         // Remove outdated branches
         for node in outdated {
             // Never remove local branch
@@ -368,6 +690,26 @@ mod prune {
                 continue;
             }
 
+            if policy.keep_writers.contains(&node.proof.writer_id) {
+                continue;
+            }
+
+            // Keep the branch until it's been outdated for at least `min_age`.
+            let prune_at = outdated_since
+                .get(&node.proof.writer_id)
+                .copied()
+                .unwrap_or(now)
+                + policy.min_age;
+
+            if prune_at > now {
+                tracing::trace!(
+                    id = ?node.proof.writer_id,
+                    "outdated branch not removed - too recent"
+                );
+                next = Some(next.map_or(prune_at, |next: Instant| next.min(prune_at)));
+                continue;
+            }
+
             // Try to acquire a unique lock on the root directory of the branch. If any file or
             // directory from the branch is locked, the root will be locked as well and so this
             // acquire will fail, preventing us from pruning a branch that's still being used.
@@ -391,6 +733,11 @@ mod prune {
             tx.remove_branch(&node).await?;
             tx.commit().await?;
 
+            shared
+                .vault
+                .event_tx
+                .send(Payload::BranchRemoved(node.proof.writer_id));
+
             tracing::trace!(
                 branch_id = ?node.proof.writer_id,
                 vv = ?node.proof.version_vector,
@@ -399,6 +746,10 @@ mod prune {
             );
         }
 
+        shared.prune.next.send_replace(next);
+        *shared.vault.monitor.next_prune.get() =
+            next.map(|next| (SystemTime::now() + next.saturating_duration_since(now)).into());
+
         // Remove outdated snapshots.
         for node in uptodate {
             shared
@@ -0,0 +1,93 @@
+//! Listing and removal of the branches of individual writers.
+
+use super::Shared;
+use crate::{
+    blob::BlobId,
+    crypto::sign::PublicKey,
+    error::{Error, Result},
+    event::Payload,
+    protocol::RootNodeFilter,
+    store,
+    version_vector::VersionVector,
+};
+use futures_util::TryStreamExt;
+use std::cmp::Ordering;
+
+/// Information about a writer (replica with write access) whose branch is stored in the
+/// repository.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WriterInfo {
+    /// Id of the writer (same as the id of its branch).
+    pub id: PublicKey,
+    /// Version vector of the latest snapshot of the writer's branch seen by this replica.
+    pub version_vector: VersionVector,
+    /// Number of blocks referenced from the latest snapshot of the writer's branch.
+    pub block_count: u64,
+}
+
+pub(super) async fn list(shared: &Shared) -> Result<Vec<WriterInfo>> {
+    let mut reader = shared.vault.store().acquire_read().await?;
+
+    let nodes: Vec<_> = reader.load_root_nodes().try_collect().await?;
+    let counts = reader.count_referenced_blocks_by_branch().await?;
+
+    let writers = nodes
+        .into_iter()
+        .map(|node| WriterInfo {
+            id: node.proof.writer_id,
+            block_count: counts
+                .iter()
+                .find(|(id, _)| *id == node.proof.writer_id)
+                .map(|(_, count)| *count)
+                .unwrap_or(0),
+            version_vector: node.proof.into_version_vector(),
+        })
+        .collect();
+
+    Ok(writers)
+}
+
+pub(super) async fn forget(shared: &Shared, writer_id: &PublicKey) -> Result<()> {
+    // Never remove the local branch.
+    if *writer_id == shared.this_writer_id {
+        return Err(Error::OperationNotSupported);
+    }
+
+    let mut tx = shared.vault.store().begin_write().await?;
+
+    let node = tx.load_root_node(writer_id, RootNodeFilter::Any).await?;
+    let local_vv = match tx
+        .load_root_node(&shared.this_writer_id, RootNodeFilter::Any)
+        .await
+    {
+        Ok(node) => node.proof.into_version_vector(),
+        Err(store::Error::BranchNotFound) => VersionVector::new(),
+        Err(error) => return Err(error.into()),
+    };
+
+    // Removing a branch that has changes the local branch doesn't have would lose data.
+    match local_vv.partial_cmp(&node.proof.version_vector) {
+        Some(Ordering::Greater | Ordering::Equal) => (),
+        Some(Ordering::Less) | None => return Err(Error::OperationNotSupported),
+    }
+
+    // Don't remove the branch while any file or directory from it is in use (same as prune).
+    let _lock = shared
+        .branch_shared
+        .locker
+        .branch(*writer_id)
+        .try_unique(BlobId::ROOT)
+        .map_err(|_| Error::Locked)?;
+
+    tx.remove_branch(&node).await?;
+    tx.commit().await?;
+
+    shared
+        .vault
+        .event_tx
+        .send(Payload::BranchRemoved(*writer_id));
+
+    tracing::debug!(branch_id = ?writer_id, vv = ?node.proof.version_vector, "writer forgotten");
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+use rand::{
+    rngs::{OsRng, StdRng},
+    CryptoRng, RngCore, SeedableRng,
+};
+
+/// Source of the randomness used to generate some of the ids of a repository (see
+/// [`RepositoryParams::with_rng_source`](crate::RepositoryParams::with_rng_source)) or of the
+/// network (see [`NetworkOptions::rng_source`](crate::network::NetworkOptions::rng_source)). Those
+/// document exactly what is covered; everything else uses the OS generator.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub enum RngSource {
+    /// Secure random number generator of the operating system. This is the only source suitable
+    /// for production use.
+    #[default]
+    Os,
+    /// Deterministic generator seeded with the given seed. Meant for tests and simulations only,
+    /// to make them reproducible: everything generated from it is predictable from the seed.
+    Seeded(u64),
+}
+
+impl RngSource {
+    /// Creates a generator from this source. Seeded generators created with different `context`
+    /// produce independent sequences, so the different components don't generate the same values.
+    pub(crate) fn make(self, context: &str) -> SourceRng {
+        match self {
+            Self::Os => SourceRng::Os(OsRng),
+            Self::Seeded(seed) => {
+                let seed = blake3::Hasher::new()
+                    .update(&seed.to_le_bytes())
+                    .update(context.as_bytes())
+                    .finalize();
+
+                SourceRng::Seeded(StdRng::from_seed(seed.into()))
+            }
+        }
+    }
+}
+
+/// Random number generator created from a [`RngSource`].
+pub(crate) enum SourceRng {
+    Os(OsRng),
+    Seeded(StdRng),
+}
+
+impl RngCore for SourceRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Self::Os(rng) => rng.next_u32(),
+            Self::Seeded(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Self::Os(rng) => rng.next_u64(),
+            Self::Seeded(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Self::Os(rng) => rng.fill_bytes(dest),
+            Self::Seeded(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            Self::Os(rng) => rng.try_fill_bytes(dest),
+            Self::Seeded(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+impl CryptoRng for SourceRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn seeded() {
+        let a: [u8; 32] = RngSource::Seeded(0).make("a").gen();
+        let b: [u8; 32] = RngSource::Seeded(0).make("a").gen();
+        let c: [u8; 32] = RngSource::Seeded(0).make("b").gen();
+        let d: [u8; 32] = RngSource::Seeded(1).make("a").gen();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, d);
+    }
+}
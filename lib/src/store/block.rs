@@ -1,4 +1,5 @@
 use super::{
+    block_store::BlockStore,
     cache::CacheTransaction,
     error::Error,
     index::{self, UpdateSummaryReason},
@@ -6,15 +7,72 @@ use super::{
 };
 use crate::{
     db,
-    protocol::{Block, BlockContent, BlockId, BlockNonce, BLOCK_SIZE},
+    protocol::{Block, BlockContent, BlockId, BlockNonce, SingleBlockPresence, BLOCK_SIZE},
 };
 use futures_util::TryStreamExt;
 use sqlx::Row;
+use std::sync::Arc;
+
+/// Where the block contents are stored.
+#[derive(Clone, Default)]
+pub(super) enum Backend {
+    /// In the `blocks` table of the database, alongside the index. Writes and removals are part of
+    /// the same transaction as the corresponding index updates.
+    #[default]
+    Db,
+    /// In a custom store. Blocks are written to it immediately but removed only when the
+    /// transaction that removed them from the index (or deleted the last leaf node referencing
+    /// them) is being committed.
+    Custom(Arc<dyn BlockStore>),
+}
+
+/// Prepares the database for use with the given backend. Must be called before the first write
+/// transaction.
+pub(super) async fn init(db: &db::Pool, backend: &Backend) -> Result<(), Error> {
+    let Backend::Custom(_) = backend else {
+        return Ok(());
+    };
+
+    // Read-only databases never remove anything.
+    if db.is_read_only() {
+        return Ok(());
+    }
+
+    // The `blocks_delete_on_leaf_node_deleted` trigger can't reach the custom store so record the
+    // orphaned blocks instead and remove them from the store on commit (see `remove_orphans`).
+    // Using temp objects because they exist only on the (single) write connection and are never
+    // persisted so the same database can still be opened without the custom store.
+    let mut tx = db.begin_write().await?;
+
+    sqlx::query(
+        "CREATE TEMP TABLE IF NOT EXISTS orphaned_blocks (
+             id BLOB NOT NULL PRIMARY KEY
+         ) WITHOUT ROWID",
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "CREATE TEMP TRIGGER IF NOT EXISTS orphaned_blocks_insert_on_leaf_node_deleted
+         AFTER DELETE ON main.snapshot_leaf_nodes
+         WHEN NOT EXISTS (SELECT 0 FROM main.snapshot_leaf_nodes WHERE block_id = old.block_id)
+         BEGIN
+             INSERT INTO orphaned_blocks (id) VALUES (old.block_id) ON CONFLICT DO NOTHING;
+         END",
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
 
 /// Write a block received from a remote replica.
 pub(super) async fn receive(
     write_tx: &mut db::WriteTransaction,
     cache_tx: &mut CacheTransaction,
+    backend: &Backend,
     block: &Block,
 ) -> Result<(), Error> {
     if !leaf_node::set_present(write_tx, &block.id).await? {
@@ -27,7 +85,7 @@ pub(super) async fn receive(
 
     index::update_summaries(write_tx, cache_tx, nodes, UpdateSummaryReason::Other).await?;
 
-    write(write_tx, block).await?;
+    write(write_tx, backend, block).await?;
 
     Ok(())
 }
@@ -39,6 +97,7 @@ pub(super) async fn receive(
 /// Panics if `buffer` length is less than [`BLOCK_SIZE`].
 pub(super) async fn read(
     conn: &mut db::Connection,
+    backend: &Backend,
     id: &BlockId,
     content: &mut BlockContent,
 ) -> Result<BlockNonce, Error> {
@@ -47,14 +106,32 @@ pub(super) async fn read(
         "insufficient buffer length for block read"
     );
 
-    let row = sqlx::query("SELECT nonce, content FROM blocks WHERE id = ?")
+    let nonce = match backend {
+        Backend::Db => read_from_db(conn, id, content).await?,
+        Backend::Custom(store) => store
+            .read(id, &mut content[..BLOCK_SIZE])
+            .await
+            .map_err(Error::BlockStore)?,
+    };
+
+    nonce.ok_or_else(|| {
+        tracing::trace!(?id, "Block not found");
+        Error::BlockNotFound
+    })
+}
+
+async fn read_from_db(
+    conn: &mut db::Connection,
+    id: &BlockId,
+    content: &mut BlockContent,
+) -> Result<Option<BlockNonce>, Error> {
+    let Some(row) = sqlx::query("SELECT nonce, content FROM blocks WHERE id = ?")
         .bind(id)
         .fetch_optional(conn)
         .await?
-        .ok_or_else(|| {
-            tracing::trace!(?id, "Block not found");
-            Error::BlockNotFound
-        })?;
+    else {
+        return Ok(None);
+    };
 
     let nonce: &[u8] = row.get(0);
     let nonce = BlockNonce::try_from(nonce).map_err(|_| Error::MalformedData)?;
@@ -71,7 +148,7 @@ pub(super) async fn read(
 
     content.copy_from_slice(src_content);
 
-    Ok(nonce)
+    Ok(Some(nonce))
 }
 
 /// Writes a block into the store.
@@ -82,53 +159,138 @@ pub(super) async fn read(
 ///
 /// Panics if buffer length is not equal to [`BLOCK_SIZE`].
 ///
-pub(super) async fn write(tx: &mut db::WriteTransaction, block: &Block) -> Result<(), Error> {
+pub(super) async fn write(
+    tx: &mut db::WriteTransaction,
+    backend: &Backend,
+    block: &Block,
+) -> Result<(), Error> {
     assert_eq!(
         block.content.len(),
         BLOCK_SIZE,
         "incorrect buffer length for block write"
     );
 
-    sqlx::query(
-        "INSERT INTO blocks (id, nonce, content)
-         VALUES (?, ?, ?)
-         ON CONFLICT (id) DO NOTHING",
-    )
-    .bind(&block.id)
-    .bind(&block.nonce[..])
-    .bind(&block.content[..])
-    .execute(tx)
-    .await?;
+    match backend {
+        Backend::Db => {
+            sqlx::query(
+                "INSERT INTO blocks (id, nonce, content)
+                 VALUES (?, ?, ?)
+                 ON CONFLICT (id) DO NOTHING",
+            )
+            .bind(&block.id)
+            .bind(&block.nonce[..])
+            .bind(&block.content[..])
+            .execute(tx)
+            .await?;
+        }
+        Backend::Custom(store) => {
+            store
+                .write(&block.id, &block.nonce, &block.content)
+                .await
+                .map_err(Error::BlockStore)?;
+        }
+    }
 
     Ok(())
 }
 
-pub(super) async fn remove(tx: &mut db::WriteTransaction, id: &BlockId) -> Result<(), Error> {
-    sqlx::query("DELETE FROM blocks WHERE id = ?")
-        .bind(id)
-        .execute(tx)
+/// Removes a block from the store. With a custom backend, the block is only recorded for removal
+/// by [`remove_orphans`].
+pub(super) async fn remove(
+    tx: &mut db::WriteTransaction,
+    backend: &Backend,
+    id: &BlockId,
+) -> Result<(), Error> {
+    match backend {
+        Backend::Db => {
+            sqlx::query("DELETE FROM blocks WHERE id = ?")
+                .bind(id)
+                .execute(tx)
+                .await?;
+        }
+        Backend::Custom(_) => {
+            sqlx::query("INSERT INTO orphaned_blocks (id) VALUES (?) ON CONFLICT DO NOTHING")
+                .bind(id)
+                .execute(tx)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the blocks that were removed (see [`remove`]) or orphaned since the last call from the
+/// custom backend. Does nothing with the db backend where the blocks are removed directly.
+///
+/// Call this just before committing `tx`. The removal is done before the commit (while still
+/// holding the write connection) so it can't race with a concurrent write of the same block. The
+/// downside is that if the commit fails, the index claims the removed blocks are still present.
+/// Such blocks are reported as missing when read and can be restored with `Repository::repair`.
+pub(super) async fn remove_orphans(
+    tx: &mut db::WriteTransaction,
+    backend: &Backend,
+) -> Result<(), Error> {
+    let Backend::Custom(store) = backend else {
+        return Ok(());
+    };
+
+    let ids: Vec<BlockId> = sqlx::query("DELETE FROM orphaned_blocks RETURNING id")
+        .fetch(&mut *tx)
+        .map_ok(|row| row.get(0))
+        .try_collect()
         .await?;
 
+    for id in ids {
+        // The block might have been received or linked again since being recorded.
+        let referenced = sqlx::query(
+            "SELECT 0 FROM snapshot_leaf_nodes WHERE block_id = ? AND block_presence = ?",
+        )
+        .bind(&id)
+        .bind(SingleBlockPresence::Present)
+        .fetch_optional(&mut *tx)
+        .await?
+        .is_some();
+
+        if referenced {
+            continue;
+        }
+
+        // Failing to remove the block only leaks it so don't fail the whole transaction.
+        if let Err(error) = store.remove(&id).await {
+            tracing::error!(?id, ?error, "Failed to remove block from the block store");
+        }
+    }
+
     Ok(())
 }
 
 /// Returns the total number of blocks in the store.
-pub(super) async fn count(conn: &mut db::Connection) -> Result<u64, Error> {
-    Ok(db::decode_u64(
-        sqlx::query("SELECT COUNT(*) FROM blocks")
-            .fetch_one(conn)
-            .await?
-            .get(0),
-    ))
+pub(super) async fn count(conn: &mut db::Connection, backend: &Backend) -> Result<u64, Error> {
+    match backend {
+        Backend::Db => Ok(db::decode_u64(
+            sqlx::query("SELECT COUNT(*) FROM blocks")
+                .fetch_one(conn)
+                .await?
+                .get(0),
+        )),
+        Backend::Custom(store) => store.count().await.map_err(Error::BlockStore),
+    }
 }
 
 /// Checks whether the block exists in the store.
-pub(super) async fn exists(conn: &mut db::Connection, id: &BlockId) -> Result<bool, Error> {
-    Ok(sqlx::query("SELECT 0 FROM blocks WHERE id = ?")
-        .bind(id)
-        .fetch_optional(conn)
-        .await?
-        .is_some())
+pub(super) async fn exists(
+    conn: &mut db::Connection,
+    backend: &Backend,
+    id: &BlockId,
+) -> Result<bool, Error> {
+    match backend {
+        Backend::Db => Ok(sqlx::query("SELECT 0 FROM blocks WHERE id = ?")
+            .bind(id)
+            .fetch_optional(conn)
+            .await?
+            .is_some()),
+        Backend::Custom(store) => store.exists(id).await.map_err(Error::BlockStore),
+    }
 }
 
 #[cfg(test)]
@@ -144,10 +306,12 @@ mod tests {
 
         let mut tx = pool.begin_write().await.unwrap();
 
-        write(&mut tx, &block).await.unwrap();
+        write(&mut tx, &Backend::Db, &block).await.unwrap();
 
         let mut content = BlockContent::new();
-        read(&mut tx, &block.id, &mut content).await.unwrap();
+        read(&mut tx, &Backend::Db, &block.id, &mut content)
+            .await
+            .unwrap();
 
         assert_eq!(&content[..], &block.content[..]);
     }
@@ -162,7 +326,7 @@ mod tests {
 
         let mut conn = pool.acquire().await.unwrap();
 
-        match read(&mut conn, &id, &mut content).await {
+        match read(&mut conn, &Backend::Db, &id, &mut content).await {
             Err(Error::BlockNotFound) => (),
             Err(error) => panic!("unexpected error: {:?}", error),
             Ok(_) => panic!("unexpected success"),
@@ -177,8 +341,8 @@ mod tests {
 
         let mut tx = pool.begin_write().await.unwrap();
 
-        write(&mut tx, &block).await.unwrap();
-        write(&mut tx, &block).await.unwrap();
+        write(&mut tx, &Backend::Db, &block).await.unwrap();
+        write(&mut tx, &Backend::Db, &block).await.unwrap();
     }
 
     async fn setup() -> (TempDir, db::Pool) {
@@ -0,0 +1,111 @@
+use crate::protocol::{BlockContent, BlockId, BlockNonce};
+use deadlock::BlockingMutex;
+use lru::LruCache;
+use std::{fmt, num::NonZeroUsize, sync::Arc};
+
+/// In-memory cache of block contents that can be shared by multiple repositories (see
+/// [`RepositoryParams::with_shared_cache`](crate::RepositoryParams::with_shared_cache)).
+///
+/// Blocks are looked up in the cache before being read from the store of the repository and are
+/// put into it after being read from the store. The cache is keyed by the block id which is the
+/// hash of the block ciphertext and nonce, so a block cached by one repository is safe to be read
+/// by any other repository that references the same id. Only the encrypted contents are cached.
+///
+/// Cloning the handle yields another handle to the same cache.
+#[derive(Clone)]
+pub struct SharedCache {
+    blocks: Arc<BlockingMutex<LruCache<BlockId, (BlockNonce, BlockContent)>>>,
+}
+
+impl SharedCache {
+    /// Creates a cache holding up to `capacity` blocks, evicting the least recently used ones
+    /// when full. Each cached block takes [`BLOCK_SIZE`](crate::BLOCK_SIZE) bytes of memory. Zero
+    /// capacity is treated as one.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+
+        Self {
+            blocks: Arc::new(BlockingMutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    /// Number of the blocks currently in the cache.
+    pub fn len(&self) -> usize {
+        self.blocks.lock().unwrap().len()
+    }
+
+    /// Returns whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copies the content of the given block into `content` and returns its nonce, if cached.
+    pub(super) fn get(&self, id: &BlockId, content: &mut BlockContent) -> Option<BlockNonce> {
+        let mut blocks = self.blocks.lock().unwrap();
+        let (nonce, cached) = blocks.get(id)?;
+        content.copy_from_slice(cached);
+
+        Some(*nonce)
+    }
+
+    pub(super) fn insert(&self, id: BlockId, nonce: BlockNonce, content: &BlockContent) {
+        self.blocks
+            .lock()
+            .unwrap()
+            .put(id, (nonce, content.clone()));
+    }
+
+    pub(super) fn remove(&self, id: &BlockId) {
+        self.blocks.lock().unwrap().pop(id);
+    }
+}
+
+impl fmt::Debug for SharedCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let blocks = self.blocks.lock().unwrap();
+
+        f.debug_struct("SharedCache")
+            .field("len", &blocks.len())
+            .field("capacity", &blocks.cap())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Block;
+    use rand::Rng;
+
+    #[test]
+    fn get_insert_evict() {
+        let mut rng = rand::thread_rng();
+        let cache = SharedCache::new(2);
+
+        let blocks: Vec<Block> = (0..3).map(|_| rng.gen()).collect();
+        let mut content = BlockContent::new();
+
+        assert_eq!(cache.get(&blocks[0].id, &mut content), None);
+
+        for block in &blocks[..2] {
+            cache.insert(block.id, block.nonce, &block.content);
+        }
+
+        assert_eq!(
+            cache.get(&blocks[0].id, &mut content),
+            Some(blocks[0].nonce)
+        );
+        assert_eq!(&content[..], &blocks[0].content[..]);
+
+        // The least recently used block is evicted.
+        cache.insert(blocks[2].id, blocks[2].nonce, &blocks[2].content);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&blocks[1].id, &mut content), None);
+
+        // Other handles share the same cache.
+        let other = cache.clone();
+        other.remove(&blocks[0].id);
+        assert_eq!(cache.get(&blocks[0].id, &mut content), None);
+        assert_eq!(cache.len(), 1);
+    }
+}
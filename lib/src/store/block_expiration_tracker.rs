@@ -1,5 +1,5 @@
 use super::{
-    block,
+    block::{self, Backend},
     cache::{Cache, CacheTransaction},
     error::Error,
     index::{self, UpdateSummaryReason},
@@ -63,6 +63,7 @@ pub(crate) struct BlockExpirationTracker {
 impl BlockExpirationTracker {
     pub(super) async fn enable_expiration(
         pool: db::Pool,
+        blocks: Backend,
         expiration_time: Duration,
         block_download_tracker: BlockDownloadTracker,
         client_reload_index_tx: broadcast_hash_set::Sender<PublicKey>,
@@ -101,6 +102,7 @@ impl BlockExpirationTracker {
                 if let Err(err) = run_task(
                     shared,
                     pool,
+                    blocks,
                     watch_rx,
                     expiration_time_rx,
                     block_download_tracker,
@@ -285,9 +287,11 @@ impl Shared {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn run_task(
     shared: Arc<BlockingMutex<Shared>>,
     pool: db::Pool,
+    blocks: Backend,
     mut watch_rx: uninitialized_watch::Receiver<()>,
     mut expiration_time_rx: watch::Receiver<Duration>,
     block_download_tracker: BlockDownloadTracker,
@@ -374,7 +378,8 @@ async fn run_task(
             return Ok(());
         }
 
-        block::remove(&mut tx, &block_id).await?;
+        block::remove(&mut tx, &blocks, &block_id).await?;
+        block::remove_orphans(&mut tx, &blocks).await?;
 
         tx.commit().await?;
 
@@ -509,6 +514,7 @@ mod test {
 
         let tracker = BlockExpirationTracker::enable_expiration(
             store.db().clone(),
+            Backend::Db,
             Duration::from_secs(1),
             BlockDownloadTracker::new(),
             broadcast_hash_set::channel().0,
@@ -618,9 +624,13 @@ mod test {
                 .unwrap()
                 .has_block(&block.id);
 
-            let is_in_db = block::exists(&mut store.db().acquire().await.unwrap(), &block.id)
-                .await
-                .unwrap();
+            let is_in_db = block::exists(
+                &mut store.db().acquire().await.unwrap(),
+                &Backend::Db,
+                &block.id,
+            )
+            .await
+            .unwrap();
 
             assert!(
                 !is_in_db || is_in_expiration_tracker,
@@ -653,7 +663,7 @@ mod test {
     }
 
     async fn count_blocks(pool: &db::Pool) -> u64 {
-        block::count(&mut pool.acquire().await.unwrap())
+        block::count(&mut pool.acquire().await.unwrap(), &Backend::Db)
             .await
             .unwrap()
     }
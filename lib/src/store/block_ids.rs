@@ -12,6 +12,7 @@ pub(crate) struct BlockIdsPage {
     db: db::Pool,
     lower_bound: Option<BlockId>,
     page_size: u32,
+    block_presence: SingleBlockPresence,
 }
 
 impl BlockIdsPage {
@@ -20,6 +21,24 @@ impl BlockIdsPage {
             db,
             lower_bound: None,
             page_size,
+            block_presence: SingleBlockPresence::Present,
+        }
+    }
+
+    /// Makes the pages contain the ids of the referenced blocks that are missing instead of those
+    /// that are present.
+    pub fn missing(self) -> Self {
+        Self {
+            block_presence: SingleBlockPresence::Missing,
+            ..self
+        }
+    }
+
+    /// Makes the pages start after the given block id instead of from the beginning.
+    pub fn starting_after(self, lower_bound: BlockId) -> Self {
+        Self {
+            lower_bound: Some(lower_bound),
+            ..self
         }
     }
 
@@ -50,7 +69,7 @@ impl BlockIdsPage {
                  LIMIT ?",
         )
         .bind(NodeState::Approved)
-        .bind(SingleBlockPresence::Present)
+        .bind(self.block_presence)
         .bind(self.lower_bound.as_ref())
         .bind(self.page_size)
         .fetch(&mut *conn)
@@ -0,0 +1,45 @@
+use crate::protocol::{BlockId, BLOCK_NONCE_SIZE};
+use async_trait::async_trait;
+use std::io;
+
+/// Custom storage of the block contents, to be used instead of the repository database (see
+/// [`RepositoryParams::with_block_store`](crate::RepositoryParams::with_block_store)).
+///
+/// Only the block contents are stored here. The index and the metadata are still stored in the
+/// database. Note the block writes are not atomic with the corresponding index updates: if the
+/// database transaction fails, the blocks written during it stay in this store without being
+/// referenced from the index.
+///
+/// All blocks are [`BLOCK_SIZE`](crate::BLOCK_SIZE) bytes long.
+#[async_trait]
+pub trait BlockStore: Send + Sync + 'static {
+    /// Reads the content of the block with the given id into `content` and returns its nonce, or
+    /// `None` if the block doesn't exist.
+    async fn read(
+        &self,
+        id: &BlockId,
+        content: &mut [u8],
+    ) -> io::Result<Option<[u8; BLOCK_NONCE_SIZE]>>;
+
+    /// Writes the block with the given id, nonce and content. Does nothing if the block already
+    /// exists.
+    async fn write(
+        &self,
+        id: &BlockId,
+        nonce: &[u8; BLOCK_NONCE_SIZE],
+        content: &[u8],
+    ) -> io::Result<()>;
+
+    /// Removes the block with the given id. Does nothing if the block doesn't exist.
+    async fn remove(&self, id: &BlockId) -> io::Result<()>;
+
+    /// Returns whether the block with the given id exists.
+    async fn exists(&self, id: &BlockId) -> io::Result<bool>;
+
+    /// Returns the total number of blocks.
+    async fn count(&self) -> io::Result<u64>;
+
+    /// Removes all the blocks. Called when the repository is deleted (see
+    /// [`delete_repository_with_block_store`](crate::delete_repository_with_block_store)).
+    async fn clear(&self) -> io::Result<()>;
+}
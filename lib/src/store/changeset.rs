@@ -59,8 +59,10 @@ impl Changeset {
             patch.save(tx, self.bump, write_keys).await?;
         }
 
+        let blocks = tx.blocks.clone();
+
         for block in self.blocks {
-            block::write(tx.db(), &block).await?;
+            block::write(tx.db(), &blocks, &block).await?;
 
             if let Some(tracker) = &tx.block_expiration_tracker {
                 tracker.handle_block_update(&block.id, false);
@@ -1,3 +1,4 @@
+use std::io;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -18,4 +19,6 @@ pub enum Error {
     BlockNotFound,
     #[error("block is not referenced from the index")]
     BlockNotReferenced,
+    #[error("block store")]
+    BlockStore(#[source] io::Error),
 }
@@ -4,7 +4,7 @@ use super::{
     cache::CacheTransaction,
     error::Error,
     inner_node,
-    quota::{self, QuotaError},
+    quota::{self, QuotaError, QuotaExceeded},
     receive_filter, root_node,
 };
 use crate::{
@@ -24,6 +24,10 @@ pub(super) struct ReceiveStatus {
     pub old_approved: bool,
     /// List of branches whose snapshots have been approved.
     pub new_approved: Vec<PublicKey>,
+    /// List of branches that weren't known before, i.e. whose first snapshot has been approved.
+    pub new_branches: Vec<PublicKey>,
+    /// List of snapshots that have been rejected because they would exceed the quota.
+    pub quota_exceeded: Vec<QuotaExceeded>,
 }
 
 /// Reason for updating the summary
@@ -114,6 +118,8 @@ pub(super) async fn finalize(
 
     let mut old_approved = false;
     let mut new_approved = Vec::new();
+    let mut new_branches = Vec::new();
+    let mut quota_exceeded = Vec::new();
 
     for (hash, state) in states {
         match state {
@@ -130,6 +136,20 @@ pub(super) async fn finalize(
                 Ok(()) => true,
                 Err(QuotaError::Exceeded(size)) => {
                     tracing::warn!(?hash, quota = %quota, size = %size, "snapshot rejected - quota exceeded");
+
+                    let mut branch_ids = Vec::new();
+                    try_collect_into(
+                        root_node::load_writer_ids_by_hash(write_tx, &hash),
+                        &mut branch_ids,
+                    )
+                    .await?;
+
+                    quota_exceeded.extend(branch_ids.into_iter().map(|branch_id| QuotaExceeded {
+                        branch_id,
+                        requested: size,
+                        available: quota,
+                    }));
+
                     false
                 }
                 Err(QuotaError::Outdated) => {
@@ -145,12 +165,24 @@ pub(super) async fn finalize(
         if approve {
             // TODO: put node to cache?
 
-            root_node::approve(write_tx, &hash).await?;
+            let mut branch_ids = Vec::new();
             try_collect_into(
                 root_node::load_writer_ids_by_hash(write_tx, &hash),
-                &mut new_approved,
+                &mut branch_ids,
             )
             .await?;
+
+            // Branches with no approved snapshot yet are seen for the first time.
+            for branch_id in &branch_ids {
+                match root_node::load(write_tx, branch_id).await {
+                    Ok(_) => (),
+                    Err(Error::BranchNotFound) => new_branches.push(*branch_id),
+                    Err(error) => return Err(error),
+                }
+            }
+
+            root_node::approve(write_tx, &hash).await?;
+            new_approved.extend(branch_ids);
         } else {
             root_node::reject(write_tx, &hash).await?;
         }
@@ -159,6 +191,8 @@ pub(super) async fn finalize(
     Ok(ReceiveStatus {
         old_approved,
         new_approved,
+        new_branches,
+        quota_exceeded,
     })
 }
 
@@ -576,7 +610,7 @@ mod tests {
         let mut received_blocks = 0;
 
         for block in snapshot.blocks().values() {
-            block::receive(&mut write_tx, &mut cache_tx, block)
+            block::receive(&mut write_tx, &mut cache_tx, &block::Backend::Db, block)
                 .await
                 .unwrap();
             received_blocks += 1;
@@ -1,4 +1,4 @@
-use super::{error::Error, leaf_node, ReceiveFilter};
+use super::{error::Error, leaf_node, quota::QuotaExceeded, ReceiveFilter};
 use crate::{
     crypto::{sign::PublicKey, Hash},
     db,
@@ -12,6 +12,10 @@ use std::convert::TryInto;
 pub(crate) struct ReceiveStatus {
     /// List of branches whose snapshots have been approved.
     pub new_approved: Vec<PublicKey>,
+    /// List of branches that weren't known before, i.e. whose first snapshot has been approved.
+    pub new_branches: Vec<PublicKey>,
+    /// List of snapshots that have been rejected because they would exceed the quota.
+    pub quota_exceeded: Vec<QuotaExceeded>,
     /// Which of the received nodes should we request the children of.
     pub request_children: Vec<InnerNode>,
 }
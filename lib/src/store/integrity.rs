@@ -1,53 +1,243 @@
-use super::error::Error;
-use crate::db;
+use super::{
+    block::{self, Backend},
+    error::Error,
+    inner_node, leaf_node,
+};
+use crate::{
+    crypto::{sign::PublicKey, Hash, Hashable},
+    db,
+    protocol::{BlockContent, BlockId, SingleBlockPresence, UntrustedProof},
+    repository::RepositoryId,
+};
+use futures_util::TryStreamExt;
 use sqlx::Row;
 use tracing::instrument;
 
+/// Integrity violation found in the store.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum IntegrityViolation {
+    /// Root node whose signature is not valid.
+    InvalidSignature { branch_id: PublicKey, hash: Hash },
+    /// Node whose hash doesn't match the hash of its children.
+    NodeHashMismatch { hash: Hash },
+    /// Inner node whose parent doesn't exist.
+    DanglingInnerNode { hash: Hash },
+    /// Leaf node whose parent doesn't exist.
+    DanglingLeafNode { block_id: BlockId },
+    /// Block that is marked as present in the index but doesn't exist in the store.
+    MissingBlock { block_id: BlockId },
+    /// Block whose id doesn't match its content.
+    BlockIdMismatch { block_id: BlockId },
+    /// Block that isn't referenced from the index.
+    UnreferencedBlock { block_id: BlockId },
+}
+
 #[instrument(skip_all)]
-pub(super) async fn check(conn: &mut db::Connection) -> Result<bool, Error> {
-    // Check orphaned nodes
-    let count = db::decode_u64(
-        sqlx::query(
-            "SELECT COUNT(*)
-             FROM snapshot_inner_nodes
-             WHERE parent NOT IN (
-                 SELECT hash FROM snapshot_root_nodes UNION SELECT hash FROM snapshot_inner_nodes
-             )
-             UNION
-             SELECT COUNT(*)
-             FROM snapshot_leaf_nodes
-             WHERE parent NOT IN (SELECT hash FROM snapshot_inner_nodes)",
-        )
-        .fetch_one(&mut *conn)
-        .await?
-        .get(0),
-    );
+pub(super) async fn check(
+    conn: &mut db::Connection,
+    blocks: &Backend,
+    repository_id: &RepositoryId,
+) -> Result<Vec<IntegrityViolation>, Error> {
+    let mut violations = Vec::new();
+
+    check_signatures(conn, repository_id, &mut violations).await?;
+    check_hashes(conn, &mut violations).await?;
+    check_dangling_nodes(conn, &mut violations).await?;
+    match blocks {
+        Backend::Db => check_blocks(conn, &mut violations).await?,
+        Backend::Custom(_) => check_custom_blocks(conn, blocks, &mut violations).await?,
+    }
 
-    if count > 0 {
-        tracing::warn!("Found {} orphaned nodes", count);
-        return Ok(false);
+    for violation in &violations {
+        tracing::warn!(?violation, "Integrity violation");
     }
 
-    // Check orphaned blocks
-    let count = db::decode_u64(
-        sqlx::query(
-            "SELECT COUNT(*)
-             FROM blocks
-             WHERE id NOT IN (SELECT block_id FROM snapshot_leaf_nodes)",
-        )
-        .fetch_one(&mut *conn)
-        .await?
-        .get(0),
+    Ok(violations)
+}
+
+// Check for root nodes with invalid signatures
+async fn check_signatures(
+    conn: &mut db::Connection,
+    repository_id: &RepositoryId,
+    violations: &mut Vec<IntegrityViolation>,
+) -> Result<(), Error> {
+    let proofs: Vec<_> =
+        sqlx::query("SELECT writer_id, versions, hash, signature FROM snapshot_root_nodes")
+            .fetch(conn)
+            .map_ok(|row| UntrustedProof {
+                writer_id: row.get(0),
+                version_vector: row.get(1),
+                hash: row.get(2),
+                signature: row.get(3),
+            })
+            .try_collect()
+            .await?;
+
+    violations.extend(
+        proofs
+            .into_iter()
+            .filter_map(|proof| proof.verify(repository_id).err())
+            .map(|error| IntegrityViolation::InvalidSignature {
+                branch_id: error.0.writer_id,
+                hash: error.0.hash,
+            }),
     );
 
-    if count > 0 {
-        tracing::warn!("Found {} orphaned blocks", count);
-        return Ok(false);
+    Ok(())
+}
+
+// Check for nodes whose hash doesn't match their children. Nodes without children are skipped
+// because they might legitimately be incomplete.
+async fn check_hashes(
+    conn: &mut db::Connection,
+    violations: &mut Vec<IntegrityViolation>,
+) -> Result<(), Error> {
+    let parents: Vec<Hash> = sqlx::query("SELECT DISTINCT parent FROM snapshot_inner_nodes")
+        .fetch(&mut *conn)
+        .map_ok(|row| row.get(0))
+        .try_collect()
+        .await?;
+
+    for parent in parents {
+        if inner_node::load_children(conn, &parent).await?.hash() != parent {
+            violations.push(IntegrityViolation::NodeHashMismatch { hash: parent });
+        }
+    }
+
+    let parents: Vec<Hash> = sqlx::query("SELECT DISTINCT parent FROM snapshot_leaf_nodes")
+        .fetch(&mut *conn)
+        .map_ok(|row| row.get(0))
+        .try_collect()
+        .await?;
+
+    for parent in parents {
+        if leaf_node::load_children(conn, &parent).await?.hash() != parent {
+            violations.push(IntegrityViolation::NodeHashMismatch { hash: parent });
+        }
+    }
+
+    Ok(())
+}
+
+// Check for nodes whose parent doesn't exist
+async fn check_dangling_nodes(
+    conn: &mut db::Connection,
+    violations: &mut Vec<IntegrityViolation>,
+) -> Result<(), Error> {
+    let hashes: Vec<_> = sqlx::query(
+        "SELECT hash
+         FROM snapshot_inner_nodes
+         WHERE parent NOT IN (
+             SELECT hash FROM snapshot_root_nodes UNION SELECT hash FROM snapshot_inner_nodes
+         )",
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| IntegrityViolation::DanglingInnerNode { hash: row.get(0) })
+    .try_collect()
+    .await?;
+    violations.extend(hashes);
+
+    let block_ids: Vec<_> = sqlx::query(
+        "SELECT block_id
+         FROM snapshot_leaf_nodes
+         WHERE parent NOT IN (SELECT hash FROM snapshot_inner_nodes)",
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| IntegrityViolation::DanglingLeafNode {
+        block_id: row.get(0),
+    })
+    .try_collect()
+    .await?;
+    violations.extend(block_ids);
+
+    Ok(())
+}
+
+// Check for missing, unreferenced and corrupted blocks
+async fn check_blocks(
+    conn: &mut db::Connection,
+    violations: &mut Vec<IntegrityViolation>,
+) -> Result<(), Error> {
+    let missing: Vec<_> = sqlx::query(
+        "SELECT DISTINCT block_id
+         FROM snapshot_leaf_nodes
+         WHERE block_presence = ? AND block_id NOT IN (SELECT id FROM blocks)",
+    )
+    .bind(SingleBlockPresence::Present)
+    .fetch(&mut *conn)
+    .map_ok(|row| IntegrityViolation::MissingBlock {
+        block_id: row.get(0),
+    })
+    .try_collect()
+    .await?;
+    violations.extend(missing);
+
+    let unreferenced: Vec<_> = sqlx::query(
+        "SELECT id
+         FROM blocks
+         WHERE id NOT IN (SELECT block_id FROM snapshot_leaf_nodes)",
+    )
+    .fetch(&mut *conn)
+    .map_ok(|row| IntegrityViolation::UnreferencedBlock {
+        block_id: row.get(0),
+    })
+    .try_collect()
+    .await?;
+    violations.extend(unreferenced);
+
+    let ids: Vec<BlockId> = sqlx::query("SELECT id FROM blocks")
+        .fetch(&mut *conn)
+        .map_ok(|row| row.get(0))
+        .try_collect()
+        .await?;
+
+    let mut content = BlockContent::new();
+
+    for id in ids {
+        let valid = match block::read(conn, &Backend::Db, &id, &mut content).await {
+            Ok(nonce) => BlockId::new(&content, &nonce) == id,
+            Err(Error::MalformedData) => false,
+            Err(error) => return Err(error),
+        };
+
+        if !valid {
+            violations.push(IntegrityViolation::BlockIdMismatch { block_id: id });
+        }
     }
 
-    // TODO: Check for root nodes with invalid signatures
-    // TODO: Check for child nodes with invalid hashes
-    // TODO: Check for blocks with invalid ids
+    Ok(())
+}
+
+// Check for missing and corrupted blocks in a custom block store. The custom store can't be
+// enumerated so only the blocks referenced from the index are checked and unreferenced blocks
+// can't be detected.
+async fn check_custom_blocks(
+    conn: &mut db::Connection,
+    blocks: &Backend,
+    violations: &mut Vec<IntegrityViolation>,
+) -> Result<(), Error> {
+    let ids: Vec<BlockId> =
+        sqlx::query("SELECT DISTINCT block_id FROM snapshot_leaf_nodes WHERE block_presence = ?")
+            .bind(SingleBlockPresence::Present)
+            .fetch(&mut *conn)
+            .map_ok(|row| row.get(0))
+            .try_collect()
+            .await?;
+
+    let mut content = BlockContent::new();
+
+    for id in ids {
+        match block::read(conn, blocks, &id, &mut content).await {
+            Ok(nonce) if BlockId::new(&content, &nonce) == id => (),
+            Ok(_) | Err(Error::MalformedData) => {
+                violations.push(IntegrityViolation::BlockIdMismatch { block_id: id })
+            }
+            Err(Error::BlockNotFound) => {
+                violations.push(IntegrityViolation::MissingBlock { block_id: id })
+            }
+            Err(error) => return Err(error),
+        }
+    }
 
-    Ok(true)
+    Ok(())
 }
@@ -1,4 +1,4 @@
-use super::error::Error;
+use super::{error::Error, quota::QuotaExceeded};
 use crate::{
     crypto::{sign::PublicKey, Hash},
     db,
@@ -16,6 +16,10 @@ pub(crate) struct ReceiveStatus {
     pub old_approved: bool,
     /// List of branches whose snapshots have been approved.
     pub new_approved: Vec<PublicKey>,
+    /// List of branches that weren't known before, i.e. whose first snapshot has been approved.
+    pub new_branches: Vec<PublicKey>,
+    /// List of snapshots that have been rejected because they would exceed the quota.
+    pub quota_exceeded: Vec<QuotaExceeded>,
     /// Which of the received nodes should we request the blocks of.
     pub request_blocks: Vec<LeafNode>,
 }
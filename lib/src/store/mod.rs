@@ -1,6 +1,8 @@
 mod block;
+mod block_cache;
 mod block_expiration_tracker;
 mod block_ids;
+mod block_store;
 mod cache;
 mod changeset;
 mod error;
@@ -17,17 +19,21 @@ mod root_node;
 #[cfg(test)]
 mod tests;
 
+pub use block_cache::SharedCache;
+pub use block_store::BlockStore;
 pub use error::Error;
+pub use integrity::IntegrityViolation;
 pub use migrations::DATA_VERSION;
 
 pub(crate) use {
     block_ids::BlockIdsPage, changeset::Changeset,
     inner_node::ReceiveStatus as InnerNodeReceiveStatus,
-    leaf_node::ReceiveStatus as LeafNodeReceiveStatus, receive_filter::ReceiveFilter,
-    root_node::ReceiveStatus as RootNodeReceiveStatus,
+    leaf_node::ReceiveStatus as LeafNodeReceiveStatus, quota::QuotaExceeded,
+    receive_filter::ReceiveFilter, root_node::ReceiveStatus as RootNodeReceiveStatus,
 };
 
 use self::{
+    block::Backend as BlockBackend,
     block_expiration_tracker::BlockExpirationTracker,
     cache::{Cache, CacheTransaction},
     index::UpdateSummaryReason,
@@ -46,6 +52,7 @@ use crate::{
         get_bucket, Block, BlockContent, BlockId, BlockNonce, InnerNodes, LeafNodes,
         MultiBlockPresence, NodeState, Proof, RootNode, RootNodeFilter, Summary, INNER_LAYER_COUNT,
     },
+    repository::RepositoryId,
     storage_size::StorageSize,
     sync::broadcast_hash_set,
 };
@@ -66,6 +73,8 @@ pub(crate) struct Store {
     cache: Arc<Cache>,
     pub client_reload_index_tx: broadcast_hash_set::Sender<PublicKey>,
     block_expiration_tracker: Arc<RwLock<Option<Arc<BlockExpirationTracker>>>>,
+    blocks: BlockBackend,
+    block_cache: Option<SharedCache>,
 }
 
 impl Store {
@@ -77,9 +86,29 @@ impl Store {
             cache: Arc::new(Cache::new()),
             client_reload_index_tx,
             block_expiration_tracker: Arc::new(RwLock::new(None)),
+            blocks: BlockBackend::Db,
+            block_cache: None,
         }
     }
 
+    /// Looks up the blocks in the given cache (possibly shared with other stores) before reading
+    /// them from this store.
+    pub fn with_block_cache(self, block_cache: SharedCache) -> Self {
+        Self {
+            block_cache: Some(block_cache),
+            ..self
+        }
+    }
+
+    /// Stores the block contents in the given custom store instead of the database. Must be
+    /// called before the store is used.
+    pub async fn with_block_store(self, block_store: Arc<dyn BlockStore>) -> Result<Self, Error> {
+        let blocks = BlockBackend::Custom(block_store);
+        block::init(&self.db, &blocks).await?;
+
+        Ok(Self { blocks, ..self })
+    }
+
     /// Runs data migrations. Does nothing if already at the latest version.
     pub async fn migrate_data(
         &self,
@@ -89,9 +118,23 @@ impl Store {
         migrations::run_data(self, this_writer_id, write_keys).await
     }
 
-    /// Check data integrity
-    pub async fn check_integrity(&self) -> Result<bool, Error> {
-        integrity::check(self.acquire_read().await?.db()).await
+    /// Check data integrity. Returns all the found violations.
+    pub async fn check_integrity(
+        &self,
+        repository_id: &RepositoryId,
+    ) -> Result<Vec<IntegrityViolation>, Error> {
+        integrity::check(self.acquire_read().await?.db(), &self.blocks, repository_id).await
+    }
+
+    /// Check data integrity of a snapshot of the database taken when the check starts. Runs inside
+    /// a single read transaction, so it isn't confused by the concurrent writes, but the changes
+    /// made while it runs are not checked. Blocks in a custom block store are read outside of the
+    /// snapshot.
+    pub async fn check_integrity_snapshot(
+        &self,
+        repository_id: &RepositoryId,
+    ) -> Result<Vec<IntegrityViolation>, Error> {
+        integrity::check(self.begin_read().await?.db(), &self.blocks, repository_id).await
     }
 
     pub async fn set_block_expiration(
@@ -116,6 +159,7 @@ impl Store {
 
         let tracker = BlockExpirationTracker::enable_expiration(
             self.db.clone(),
+            self.blocks.clone(),
             expiration_time,
             block_download_tracker,
             self.client_reload_index_tx.clone(),
@@ -147,6 +191,8 @@ impl Store {
             inner: Handle::Connection(self.db.acquire().await?),
             cache: self.cache.begin(),
             block_expiration_tracker: self.block_expiration_tracker.read().await.clone(),
+            blocks: self.blocks.clone(),
+            block_cache: self.block_cache.clone(),
         })
     }
 
@@ -157,6 +203,8 @@ impl Store {
                 inner: Handle::ReadTransaction(self.db.begin_read().await?),
                 cache: self.cache.begin(),
                 block_expiration_tracker: self.block_expiration_tracker.read().await.clone(),
+                blocks: self.blocks.clone(),
+                block_cache: self.block_cache.clone(),
             },
         })
     }
@@ -169,6 +217,8 @@ impl Store {
                     inner: Handle::WriteTransaction(self.db.begin_write().await?),
                     cache: self.cache.begin(),
                     block_expiration_tracker: self.block_expiration_tracker.read().await.clone(),
+                    blocks: self.blocks.clone(),
+                    block_cache: self.block_cache.clone(),
                 },
             },
             untrack_blocks: None,
@@ -290,6 +340,8 @@ pub(crate) struct Reader {
     inner: Handle,
     cache: CacheTransaction,
     block_expiration_tracker: Option<Arc<BlockExpirationTracker>>,
+    blocks: BlockBackend,
+    block_cache: Option<SharedCache>,
 }
 
 impl Reader {
@@ -303,7 +355,22 @@ impl Reader {
         id: &BlockId,
         content: &mut BlockContent,
     ) -> Result<BlockNonce, Error> {
-        let result = block::read(self.db(), id, content).await;
+        let cached = self
+            .block_cache
+            .as_ref()
+            .and_then(|cache| cache.get(id, content));
+
+        let result = if let Some(nonce) = cached {
+            Ok(nonce)
+        } else {
+            let result = block::read(&mut self.inner, &self.blocks, id, content).await;
+
+            if let (Ok(nonce), Some(cache)) = (&result, &self.block_cache) {
+                cache.insert(*id, *nonce, content);
+            }
+
+            result
+        };
 
         if let Some(expiration_tracker) = &self.block_expiration_tracker {
             let is_missing = matches!(result, Err(Error::BlockNotFound));
@@ -315,7 +382,7 @@ impl Reader {
 
     /// Checks whether the block exists in the store.
     pub async fn block_exists(&mut self, id: &BlockId) -> Result<bool, Error> {
-        block::exists(self.db(), id).await
+        block::exists(&mut self.inner, &self.blocks, id).await
     }
 
     /// Checks whether the block is missing - that is, it's referenced from some snapshot but
@@ -329,7 +396,19 @@ impl Reader {
 
     /// Returns the total number of blocks in the store.
     pub async fn count_blocks(&mut self) -> Result<u64, Error> {
-        block::count(self.db()).await
+        block::count(&mut self.inner, &self.blocks).await
+    }
+
+    /// Returns the number of blocks referenced from the latest snapshot of each branch.
+    pub async fn count_referenced_blocks_by_branch(
+        &mut self,
+    ) -> Result<Vec<(PublicKey, u64)>, Error> {
+        quota::count_referenced_blocks_by_branch(self.db()).await
+    }
+
+    /// Returns the number of distinct blocks referenced from the latest snapshot of any branch.
+    pub async fn count_unique_referenced_blocks(&mut self) -> Result<u64, Error> {
+        quota::count_unique_referenced_blocks(self.db()).await
     }
 
     pub async fn count_leaf_nodes(&mut self) -> Result<u64, Error> {
@@ -529,9 +608,14 @@ pub(crate) struct WriteTransaction {
 impl WriteTransaction {
     /// Removes the specified block from the store and marks it as missing in the index.
     pub async fn remove_block(&mut self, id: &BlockId) -> Result<(), Error> {
+        if let Some(cache) = &self.block_cache {
+            cache.remove(id);
+        }
+
+        let blocks = self.blocks.clone();
         let (db, cache) = self.db_and_cache();
 
-        block::remove(db, id).await?;
+        block::remove(db, &blocks, id).await?;
         leaf_node::set_missing(db, id).await?;
 
         let parent_hashes: Vec<_> = leaf_node::load_parent_hashes(db, id).try_collect().await?;
@@ -600,12 +684,14 @@ impl WriteTransaction {
 
             Ok(RootNodeReceiveStatus {
                 new_approved: status.new_approved,
+                new_branches: status.new_branches,
                 new_snapshot: true,
                 request_children: action.request_children,
             })
         } else {
             Ok(RootNodeReceiveStatus {
                 new_approved: Vec::new(),
+                new_branches: Vec::new(),
                 new_snapshot: false,
                 request_children: action.request_children,
             })
@@ -637,6 +723,8 @@ impl WriteTransaction {
 
         Ok(InnerNodeReceiveStatus {
             new_approved: status.new_approved,
+            new_branches: status.new_branches,
+            quota_exceeded: status.quota_exceeded,
             request_children,
         })
     }
@@ -665,6 +753,8 @@ impl WriteTransaction {
         Ok(LeafNodeReceiveStatus {
             old_approved: status.old_approved,
             new_approved: status.new_approved,
+            new_branches: status.new_branches,
+            quota_exceeded: status.quota_exceeded,
             request_blocks,
         })
     }
@@ -673,8 +763,9 @@ impl WriteTransaction {
     /// The block must already be referenced by the index, otherwise an `BlockNotReferenced` error
     /// is returned.
     pub async fn receive_block(&mut self, block: &Block) -> Result<(), Error> {
+        let blocks = self.blocks.clone();
         let (db, cache) = self.db_and_cache();
-        let result = block::receive(db, cache, block).await;
+        let result = block::receive(db, cache, &blocks, block).await;
 
         if let Some(tracker) = &self.block_expiration_tracker {
             tracker.handle_block_update(&block.id, false);
@@ -700,7 +791,9 @@ impl WriteTransaction {
         Ok(root_node)
     }
 
-    pub async fn commit(self) -> Result<(), Error> {
+    pub async fn commit(mut self) -> Result<(), Error> {
+        self.remove_orphaned_blocks().await?;
+
         let inner = self.inner.inner.inner.into_write();
         let cache = self.inner.inner.cache;
 
@@ -772,11 +865,13 @@ impl WriteTransaction {
     /// given closure.
     ///
     /// See `db::WriteTransaction::commit_and_then` for explanation why this is necessary.
-    pub async fn commit_and_then<F, R>(self, f: F) -> Result<R, Error>
+    pub async fn commit_and_then<F, R>(mut self, f: F) -> Result<R, Error>
     where
         F: FnOnce() -> R + Send + 'static,
         R: Send + 'static,
     {
+        self.remove_orphaned_blocks().await?;
+
         let inner = self.inner.inner.inner.into_write();
         let cache = self.inner.inner.cache;
 
@@ -812,6 +907,11 @@ impl WriteTransaction {
         //Ok(inner.commit_and_then(then).await?)
     }
 
+    async fn remove_orphaned_blocks(&mut self) -> Result<(), Error> {
+        let blocks = self.blocks.clone();
+        block::remove_orphans(self.db(), &blocks).await
+    }
+
     // Access the underlying database transaction.
     fn db(&mut self) -> &mut db::WriteTransaction {
         self.inner.inner.inner.as_write()
@@ -1,5 +1,12 @@
 use super::{error::Error as StoreError, root_node};
-use crate::{crypto::Hash, db, future::try_collect_into, storage_size::StorageSize, versioned};
+use crate::{
+    crypto::{sign::PublicKey, Hash},
+    db,
+    future::try_collect_into,
+    storage_size::StorageSize,
+    versioned,
+};
+use futures_util::TryStreamExt;
 use sqlx::{QueryBuilder, Row};
 use thiserror::Error;
 
@@ -27,6 +34,17 @@ pub(super) async fn check(
     }
 }
 
+/// Information about a snapshot that was rejected because approving it would exceed the quota.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct QuotaExceeded {
+    /// Branch of the rejected snapshot.
+    pub branch_id: PublicKey,
+    /// Total size the repository would have if the snapshot was approved.
+    pub requested: StorageSize,
+    /// The quota.
+    pub available: StorageSize,
+}
+
 #[derive(Debug, Error)]
 pub(super) enum QuotaError {
     #[error("quota exceeded")]
@@ -60,6 +78,35 @@ async fn load_candidate_latest_root_hashes(
     Ok(hashes)
 }
 
+/// Count blocks referenced from the latest approved snapshot of each branch. Blocks shared by more
+/// than one branch are counted in each of them.
+pub(super) async fn count_referenced_blocks_by_branch(
+    conn: &mut db::Connection,
+) -> Result<Vec<(PublicKey, u64)>, StoreError> {
+    let nodes: Vec<_> = root_node::load_all(conn).try_collect().await?;
+    let mut counts = Vec::with_capacity(nodes.len());
+
+    for node in nodes {
+        let count = count_referenced_blocks(conn, &[node.proof.hash]).await?;
+        counts.push((node.proof.writer_id, count));
+    }
+
+    Ok(counts)
+}
+
+/// Count blocks referenced from the latest approved snapshot of any branch. Blocks shared by more
+/// than one branch are counted only once.
+pub(super) async fn count_unique_referenced_blocks(
+    conn: &mut db::Connection,
+) -> Result<u64, StoreError> {
+    let hashes: Vec<_> = root_node::load_all(conn)
+        .map_ok(|node| node.proof.hash)
+        .try_collect()
+        .await?;
+
+    count_referenced_blocks(conn, &hashes).await
+}
+
 /// Count blocks referenced from the given root nodes. Blocks referenced from more than one
 /// node are counted only once.
 async fn count_referenced_blocks(
@@ -18,6 +18,8 @@ use std::{cmp::Ordering, future};
 pub(crate) struct ReceiveStatus {
     /// List of branches whose snapshots became approved.
     pub new_approved: Vec<PublicKey>,
+    /// List of branches that weren't known before, i.e. whose first snapshot has been approved.
+    pub new_branches: Vec<PublicKey>,
     /// Did the received node create new snapshot?
     pub new_snapshot: bool,
     /// Should we request the children of the incoming node?
@@ -4,6 +4,7 @@ use crate::{
     protocol::{Bump, Locator, SingleBlockPresence, EMPTY_INNER_HASH},
     test_utils,
 };
+use assert_matches::assert_matches;
 use proptest::{arbitrary::any, collection::vec};
 use rand::{
     rngs::StdRng,
@@ -14,6 +15,7 @@ use sqlx::Row;
 use std::collections::BTreeMap;
 use tempfile::TempDir;
 use test_strategy::{proptest, Arbitrary};
+use tokio::task;
 
 #[tokio::test(flavor = "multi_thread")]
 async fn link_and_find_block() {
@@ -181,6 +183,92 @@ async fn remove_block() {
     assert!(!tx.block_exists(&block_id).await.unwrap());
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn block_cache() {
+    let (_base_dir, store) = setup().await;
+    let cache = SharedCache::new(1);
+    let store = store.with_block_cache(cache.clone());
+
+    let read_key = SecretKey::random();
+    let write_keys = Keypair::random();
+    let branch_id = PublicKey::random();
+
+    let blocks: Vec<Block> = (0..2).map(|_| rand::random()).collect();
+
+    let mut tx = store.begin_write().await.unwrap();
+    let mut changeset = Changeset::new();
+
+    for block in &blocks {
+        changeset.write_block(block.clone());
+        changeset.link_block(
+            random_head_locator().encode(&read_key),
+            block.id,
+            SingleBlockPresence::Present,
+        );
+    }
+
+    changeset
+        .apply(&mut tx, &branch_id, &write_keys)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    // Writing doesn't populate the cache.
+    assert!(cache.is_empty());
+
+    let mut reader = store.acquire_read().await.unwrap();
+    let mut content = BlockContent::new();
+
+    // Miss: read from the db and put into the cache.
+    assert_eq!(
+        reader
+            .read_block(&blocks[0].id, &mut content)
+            .await
+            .unwrap(),
+        blocks[0].nonce
+    );
+    assert_eq!(&content[..], &blocks[0].content[..]);
+    assert_eq!(
+        cache.get(&blocks[0].id, &mut content),
+        Some(blocks[0].nonce)
+    );
+
+    // Hit: served from the cache, the same as from the db.
+    content = BlockContent::new();
+    assert_eq!(
+        reader
+            .read_block(&blocks[0].id, &mut content)
+            .await
+            .unwrap(),
+        blocks[0].nonce
+    );
+    assert_eq!(&content[..], &blocks[0].content[..]);
+
+    // Reading another block evicts the least recently used one.
+    reader
+        .read_block(&blocks[1].id, &mut content)
+        .await
+        .unwrap();
+    assert_eq!(cache.get(&blocks[0].id, &mut content), None);
+    assert_eq!(cache.len(), 1);
+
+    drop(reader);
+
+    // Removing the block removes it from the cache too.
+    let mut tx = store.begin_write().await.unwrap();
+    tx.remove_block(&blocks[1].id).await.unwrap();
+    tx.commit().await.unwrap();
+
+    assert!(cache.is_empty());
+
+    let mut reader = store.acquire_read().await.unwrap();
+    assert_matches!(
+        reader.read_block(&blocks[1].id, &mut content).await,
+        Err(Error::BlockNotFound)
+    );
+    assert!(cache.is_empty());
+}
+
 #[tokio::test(flavor = "multi_thread")]
 async fn overwrite_block() {
     let (_base_dir, store) = setup().await;
@@ -229,6 +317,169 @@ async fn overwrite_block() {
     assert_eq!(tx.count_blocks().await.unwrap(), 1);
 }
 
+#[tokio::test(flavor = "multi_thread")]
+async fn check_integrity() {
+    let (_base_dir, store) = setup().await;
+    let mut rng = rand::thread_rng();
+
+    let read_key = SecretKey::random();
+    let write_keys = Keypair::random();
+    let repository_id = RepositoryId::from(write_keys.public_key());
+
+    let branch_id = PublicKey::random();
+
+    let block0: Block = rng.gen();
+    let block0_id = block0.id;
+    let block1: Block = rng.gen();
+    let block1_id = block1.id;
+
+    let mut tx = store.begin_write().await.unwrap();
+    let mut changeset = Changeset::new();
+
+    for block in [block0, block1] {
+        let locator = Locator::head(rng.gen()).encode(&read_key);
+        changeset.link_block(locator, block.id, SingleBlockPresence::Present);
+        changeset.write_block(block);
+    }
+
+    changeset
+        .apply(&mut tx, &branch_id, &write_keys)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    assert_eq!(store.check_integrity(&repository_id).await.unwrap(), []);
+
+    // Signed with different keys
+    let other_repository_id = RepositoryId::random();
+    assert_matches!(
+        store.check_integrity(&other_repository_id).await.unwrap()[..],
+        [IntegrityViolation::InvalidSignature { branch_id: id, .. }] if id == branch_id
+    );
+
+    // Corrupt one block and remove the other one
+    let mut tx = store.db().begin_write().await.unwrap();
+    sqlx::query("UPDATE blocks SET nonce = ? WHERE id = ?")
+        .bind(&rng.gen::<BlockNonce>()[..])
+        .bind(&block0_id)
+        .execute(&mut tx)
+        .await
+        .unwrap();
+    sqlx::query("DELETE FROM blocks WHERE id = ?")
+        .bind(&block1_id)
+        .execute(&mut tx)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let violations = store.check_integrity(&repository_id).await.unwrap();
+    assert_eq!(violations.len(), 2);
+    assert!(violations.contains(&IntegrityViolation::BlockIdMismatch {
+        block_id: block0_id
+    }));
+    assert!(violations.contains(&IntegrityViolation::MissingBlock {
+        block_id: block1_id
+    }));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn check_integrity_snapshot() {
+    let (_base_dir, store) = setup().await;
+    let mut rng = rand::thread_rng();
+
+    let read_key = SecretKey::random();
+    let write_keys = Keypair::random();
+    let repository_id = RepositoryId::from(write_keys.public_key());
+
+    let branch_id = PublicKey::random();
+
+    let block: Block = rng.gen();
+    let locator = Locator::head(rng.gen()).encode(&read_key);
+
+    let mut tx = store.begin_write().await.unwrap();
+    let mut changeset = Changeset::new();
+    changeset.link_block(locator, block.id, SingleBlockPresence::Present);
+    changeset.write_block(block);
+    changeset
+        .apply(&mut tx, &branch_id, &write_keys)
+        .await
+        .unwrap();
+
+    // The check doesn't wait for the pending write transaction and doesn't see its changes.
+    assert_eq!(
+        store
+            .check_integrity_snapshot(&repository_id)
+            .await
+            .unwrap(),
+        []
+    );
+
+    tx.commit().await.unwrap();
+
+    assert_eq!(
+        store
+            .check_integrity_snapshot(&repository_id)
+            .await
+            .unwrap(),
+        []
+    );
+}
+
+// Commits made while the check runs don't show up as violations, because the check sees the store
+// as it was when it started.
+#[tokio::test(flavor = "multi_thread")]
+async fn check_integrity_snapshot_during_commits() {
+    let (_base_dir, store) = setup().await;
+
+    let read_key = SecretKey::random();
+    let write_keys = Keypair::random();
+    let repository_id = RepositoryId::from(write_keys.public_key());
+
+    let branch_id = PublicKey::random();
+    let locator = random_head_locator().encode(&read_key);
+
+    // Keep replacing the block at the same locator, removing the previous one.
+    let writer = task::spawn({
+        let store = store.clone();
+
+        async move {
+            let mut prev_block_id = None;
+
+            for _ in 0..64 {
+                let block: Block = rand::random();
+                let block_id = block.id;
+
+                let mut tx = store.begin_write().await.unwrap();
+                let mut changeset = Changeset::new();
+                changeset.link_block(locator, block_id, SingleBlockPresence::Present);
+                changeset.write_block(block);
+                changeset
+                    .apply(&mut tx, &branch_id, &write_keys)
+                    .await
+                    .unwrap();
+
+                if let Some(prev_block_id) = prev_block_id.replace(block_id) {
+                    tx.remove_block(&prev_block_id).await.unwrap();
+                }
+
+                tx.commit().await.unwrap();
+            }
+        }
+    });
+
+    while !writer.is_finished() {
+        assert_eq!(
+            store
+                .check_integrity_snapshot(&repository_id)
+                .await
+                .unwrap(),
+            []
+        );
+    }
+
+    writer.await.unwrap();
+}
+
 #[ignore]
 #[tokio::test(flavor = "multi_thread")]
 async fn fallback() {
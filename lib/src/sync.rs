@@ -163,6 +163,168 @@ pub(crate) mod atomic_slot {
     }
 }
 
+/// Semaphore whose number of permits can be changed after it's been created.
+pub(crate) mod resizable_semaphore {
+    use deadlock::BlockingMutex;
+    use std::sync::Arc;
+    use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+    pub struct ResizableSemaphore {
+        semaphore: Arc<Semaphore>,
+        state: BlockingMutex<State>,
+    }
+
+    struct State {
+        capacity: usize,
+        // Number of permits that should have been removed from the semaphore when it was shrunk
+        // but couldn't because they were acquired at the time. They are forgotten as soon as
+        // they are released.
+        debt: usize,
+    }
+
+    impl ResizableSemaphore {
+        /// Creates the semaphore with the given number of permits, capped at
+        /// [`Semaphore::MAX_PERMITS`].
+        pub fn new(capacity: usize) -> Self {
+            let capacity = capacity.min(Semaphore::MAX_PERMITS);
+
+            Self {
+                semaphore: Arc::new(Semaphore::new(capacity)),
+                state: BlockingMutex::new(State { capacity, debt: 0 }),
+            }
+        }
+
+        pub async fn acquire(self: Arc<Self>) -> Permit {
+            // Unwrap OK because we never `close()` the semaphore.
+            let permit = self.semaphore.clone().acquire_owned().await.unwrap();
+
+            Permit {
+                permit: Some(permit),
+                owner: self,
+            }
+        }
+
+        /// Acquires a permit if one is available, otherwise returns `None`.
+        pub fn try_acquire(self: Arc<Self>) -> Option<Permit> {
+            let permit = self.semaphore.clone().try_acquire_owned().ok()?;
+
+            Some(Permit {
+                permit: Some(permit),
+                owner: self,
+            })
+        }
+
+        pub fn capacity(&self) -> usize {
+            self.state.lock().unwrap().capacity
+        }
+
+        /// Changes the number of permits (capped at [`Semaphore::MAX_PERMITS`]). If shrinking and
+        /// some of the permits are currently acquired, the effect is delayed until they are
+        /// released.
+        pub fn resize(&self, capacity: usize) {
+            let capacity = capacity.min(Semaphore::MAX_PERMITS);
+            let mut state = self.state.lock().unwrap();
+
+            if capacity > state.capacity {
+                let diff = capacity - state.capacity;
+                let repaid = diff.min(state.debt);
+
+                state.debt -= repaid;
+                self.semaphore.add_permits(diff - repaid);
+            } else {
+                let diff = state.capacity - capacity;
+                let forgotten = self.semaphore.forget_permits(diff);
+
+                state.debt += diff - forgotten;
+            }
+
+            state.capacity = capacity;
+        }
+    }
+
+    pub struct Permit {
+        permit: Option<OwnedSemaphorePermit>,
+        owner: Arc<ResizableSemaphore>,
+    }
+
+    impl Drop for Permit {
+        fn drop(&mut self) {
+            let mut state = self.owner.state.lock().unwrap();
+
+            if let Some(permit) = self.permit.take() {
+                if state.debt > 0 {
+                    state.debt -= 1;
+                    permit.forget();
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[tokio::test]
+        async fn grow() {
+            let semaphore = Arc::new(ResizableSemaphore::new(1));
+            let _permit = semaphore.clone().acquire().await;
+            assert_eq!(semaphore.semaphore.available_permits(), 0);
+
+            semaphore.resize(3);
+            assert_eq!(semaphore.capacity(), 3);
+            assert_eq!(semaphore.semaphore.available_permits(), 2);
+        }
+
+        #[tokio::test]
+        async fn shrink_with_acquired_permits() {
+            let semaphore = Arc::new(ResizableSemaphore::new(3));
+            let permit_a = semaphore.clone().acquire().await;
+            let permit_b = semaphore.clone().acquire().await;
+
+            semaphore.resize(1);
+            assert_eq!(semaphore.capacity(), 1);
+            assert_eq!(semaphore.semaphore.available_permits(), 0);
+
+            drop(permit_a);
+            assert_eq!(semaphore.semaphore.available_permits(), 0);
+
+            drop(permit_b);
+            assert_eq!(semaphore.semaphore.available_permits(), 1);
+        }
+
+        #[tokio::test]
+        async fn shrink_then_grow() {
+            let semaphore = Arc::new(ResizableSemaphore::new(2));
+            let permit = semaphore.clone().acquire().await;
+
+            semaphore.resize(0);
+            semaphore.resize(2);
+            assert_eq!(semaphore.semaphore.available_permits(), 1);
+
+            drop(permit);
+            assert_eq!(semaphore.semaphore.available_permits(), 2);
+        }
+
+        #[tokio::test]
+        async fn unlimited() {
+            let semaphore = Arc::new(ResizableSemaphore::new(usize::MAX));
+            assert_eq!(semaphore.capacity(), Semaphore::MAX_PERMITS);
+
+            let _permit = semaphore.clone().acquire().await;
+
+            semaphore.resize(1);
+            assert_eq!(semaphore.capacity(), 1);
+
+            semaphore.resize(usize::MAX);
+            assert_eq!(semaphore.capacity(), Semaphore::MAX_PERMITS);
+            assert_eq!(
+                semaphore.semaphore.available_permits(),
+                Semaphore::MAX_PERMITS - 1
+            );
+        }
+    }
+}
+
 /// Similar to `tokio::sync::broadcast` but does not enqueue the values. Instead, it "accumulates"
 /// them into a set. Advantages include that one doesn't need to specify the recv buffer size and
 /// the `insert` (analogue to `broadcast::send`) is non-async and never fails.
@@ -657,6 +819,10 @@ pub(crate) mod delay_map {
             self.items.len()
         }
 
+        pub fn keys(&self) -> impl Iterator<Item = &K> {
+            self.items.keys()
+        }
+
         /// Poll for the next expired item. This can be wrapped in `future::poll_fn` and awaited.
         /// Returns `Poll::Ready(None)` if the map is empty.
         pub fn poll_expired(&mut self, cx: &mut Context<'_>) -> Poll<Option<(K, V)>> {
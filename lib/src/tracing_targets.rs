@@ -0,0 +1,26 @@
+/// Targets of the `tracing` spans and events emitted by this library, including the ones emitted
+/// by the workspace crates and the dependencies that do work on its behalf. Useful to build a
+/// filter (e.g., `tracing_subscriber::filter::Targets`) that routes the ouisync logs separately
+/// from the logs of the host application, without having to initialize a global subscriber just for
+/// ouisync.
+///
+/// The targets are prefixes: most events are emitted with the module path of their origin as the
+/// target (e.g., `ouisync::network::message_broker`). `btdht` logs through the `log` crate so its
+/// records are visible to `tracing` only with the `tracing-log` bridge installed. Logs of the
+/// generic dependencies which are likely to be used by the host application too (e.g., `sqlx`) are
+/// not included.
+pub const fn tracing_targets() -> &'static [&'static str] {
+    &["ouisync", "deadlock", "state_monitor", "btdht"]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn includes_own_target() {
+        assert!(tracing_targets()
+            .iter()
+            .any(|target| module_path!().starts_with(target)));
+    }
+}
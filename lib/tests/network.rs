@@ -6,9 +6,11 @@
 mod common;
 
 use self::common::{actor, Env, Proto, DEFAULT_REPO, TEST_TIMEOUT};
-use ouisync::network::{Network, PeerState};
-use std::sync::Arc;
-use tokio::{sync::Barrier, time};
+use ouisync::network::{
+    BoundTransports, ConnectivityMode, Network, NetworkOptions, PeerSource, PeerState,
+};
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+use tokio::{io::AsyncWriteExt, net::TcpStream, sync::Barrier, time};
 
 // This test requires QUIC which is not yet supported in simulation
 #[test]
@@ -45,6 +47,7 @@ fn peer_exchange() {
             reg.set_pex_enabled(true).await;
 
             expect_peer_known(&network, "carol").await;
+            expect_introduced_by(&network, "alice").await;
             barrier.wait().await;
         }
     });
@@ -60,6 +63,7 @@ fn peer_exchange() {
             reg.set_pex_enabled(true).await;
 
             expect_peer_known(&network, "bob").await;
+            expect_introduced_by(&network, "alice").await;
             barrier.wait().await;
         }
     });
@@ -143,6 +147,190 @@ fn add_peer_before_bind() {
     });
 }
 
+#[test]
+fn refresh_connections_quic() {
+    refresh_connections_case(Proto::Quic)
+}
+
+#[test]
+fn refresh_connections_tcp() {
+    refresh_connections_case(Proto::Tcp)
+}
+
+fn refresh_connections_case(proto: Proto) {
+    let mut env = Env::new();
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let _network = actor::create_network(proto).await;
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_network(proto).await;
+
+            let peer_addr = actor::lookup_addr("alice").await;
+            network.add_user_provided_peer(&peer_addr);
+            expect_peer_active(&network, "alice").await;
+
+            let addrs = network.listener_local_addrs();
+            assert!(!addrs.is_empty());
+
+            // Rebinding closes the connections but they get reestablished and the listeners keep
+            // their ports.
+            network.refresh_connections().await;
+            assert_eq!(network.listener_local_addrs(), addrs);
+            expect_peer_active(&network, "alice").await;
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn outbound_only() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+    let barrier = Arc::new(Barrier::new(2));
+
+    env.actor("alice", {
+        let barrier = barrier.clone();
+
+        async move {
+            let _network = actor::create_network(proto).await;
+            barrier.wait().await;
+        }
+    });
+
+    env.actor("bob", {
+        async move {
+            let network = actor::create_unbound_network();
+            network.set_options(NetworkOptions {
+                connectivity: ConnectivityMode::OutboundOnly,
+                ..NetworkOptions::default()
+            });
+            network
+                .bind(&[proto.wrap((Ipv4Addr::UNSPECIFIED, 0))])
+                .await;
+
+            // No listener is bound but outgoing connections still work.
+            assert!(network.listener_local_addrs().is_empty());
+
+            let peer_addr = actor::lookup_addr("alice").await;
+            network.add_user_provided_peer(&peer_addr);
+            expect_peer_active(&network, "alice").await;
+
+            barrier.wait().await;
+        }
+    });
+}
+
+#[test]
+fn bound_transports() {
+    let mut env = Env::new();
+
+    env.actor("alice", async move {
+        let network = actor::create_unbound_network();
+        let bound = network
+            .bind(&[
+                Proto::Quic.wrap((Ipv4Addr::LOCALHOST, 0)),
+                Proto::Tcp.wrap((Ipv4Addr::LOCALHOST, 0)),
+            ])
+            .await;
+
+        // No DHT because bound to a local address only.
+        assert_eq!(
+            bound,
+            BoundTransports {
+                quic_v4: true,
+                tcp_v4: true,
+                ..BoundTransports::default()
+            }
+        );
+    });
+}
+
+#[test]
+fn allow_self_address_connections() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+
+    env.actor("alice", async move {
+        let network = actor::create_unbound_network();
+        network.set_options(NetworkOptions {
+            allow_self_address_connections: true,
+            ..NetworkOptions::default()
+        });
+        actor::bind(&network, proto).await;
+
+        let addr = actor::lookup_addr("alice").await;
+
+        // The connection to self is discarded after the handshake but the address is not
+        // remembered so connecting to it again is still attempted.
+        for _ in 0..2 {
+            network.add_user_provided_peer(&addr);
+            expect_peer_known(&network, "alice").await;
+            expect_peer_gone(&network, "alice").await;
+
+            // Give the connection task time to finish so the address can be added again.
+            time::sleep(Duration::from_millis(100)).await;
+        }
+    });
+}
+
+#[test]
+fn protocol_version_mismatch() {
+    let mut env = Env::new();
+    let proto = Proto::Tcp;
+
+    env.actor("alice", async move {
+        let network = actor::create_network(proto).await;
+        let mut rx = network.on_protocol_version_mismatch();
+
+        // Pretend to be a peer with a newer protocol version.
+        let their_version = network.current_protocol_version() + 1;
+        let port = actor::lookup_addr("alice").await.port();
+
+        let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        stream.write_all(b"OUISYNC").await.unwrap();
+        stream
+            .write_all(vint64::encode(their_version.into()).as_ref())
+            .await
+            .unwrap();
+
+        let version = time::timeout(*TEST_TIMEOUT, rx.changed())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(version, their_version);
+        assert_eq!(network.highest_seen_protocol_version(), their_version);
+    });
+}
+
+// Checks that all the peers found via peer exchange were introduced by the given peer.
+async fn expect_introduced_by(network: &Network, introducer_name: &str) {
+    let introducer_addr = actor::lookup_addr(introducer_name).await;
+    let introducer_id = match network.peer_info(introducer_addr).map(|info| info.state) {
+        Some(PeerState::Active(id)) => id,
+        state => panic!("unexpected introducer state: {state:?}"),
+    };
+
+    for info in network.peer_info_collector().collect() {
+        if info.source == PeerSource::PeerExchange {
+            assert_eq!(info.introduced_by, Some(introducer_id));
+        } else {
+            assert_eq!(info.introduced_by, None);
+        }
+    }
+}
+
 async fn expect_peer_known(network: &Network, peer_name: &str) {
     expect_peer_state(network, peer_name, |_| true).await
 }
@@ -154,6 +342,19 @@ async fn expect_peer_active(network: &Network, peer_name: &str) {
     .await
 }
 
+async fn expect_peer_gone(network: &Network, peer_name: &str) {
+    time::timeout(*TEST_TIMEOUT, async move {
+        let mut rx = network.on_peer_set_change();
+        let peer_addr = actor::lookup_addr(peer_name).await;
+
+        while network.peer_info(peer_addr).is_some() {
+            rx.changed().await.unwrap();
+        }
+    })
+    .await
+    .unwrap()
+}
+
 async fn expect_peer_state<F>(network: &Network, peer_name: &str, expected_state_fn: F)
 where
     F: Fn(&PeerState) -> bool,
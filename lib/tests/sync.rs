@@ -8,14 +8,14 @@ use self::common::{
 };
 use assert_matches::assert_matches;
 use ouisync::{
-    Access, AccessMode, EntryType, Error, Repository, StorageSize, StoreError, VersionVector,
-    BLOB_HEADER_SIZE, BLOCK_SIZE,
+    network::SyncState, Access, AccessMode, EntryType, Error, Event, Payload, Repository,
+    StorageSize, StoreError, VersionVector, BLOB_HEADER_SIZE, BLOCK_SIZE,
 };
 use rand::Rng;
 use std::{cmp::Ordering, io::SeekFrom, sync::Arc, time::Duration};
 use tokio::{
     sync::{broadcast, mpsc, Barrier},
-    time::sleep,
+    time::{self, sleep},
 };
 use tracing::{instrument, Instrument};
 
@@ -283,6 +283,105 @@ fn relink_repository() {
     });
 }
 
+#[test]
+fn suspend_and_resume_sync() {
+    let mut env = Env::new();
+
+    let (writer_tx, mut writer_rx) = mpsc::channel(1);
+    let (reader_tx, mut reader_rx) = mpsc::channel(1);
+
+    env.actor("writer", async move {
+        let (_network, repo, _reg) = actor::setup().await;
+
+        let mut file = repo.create_file("test.txt").await.unwrap();
+        file.write_all(b"first").await.unwrap();
+        file.flush().await.unwrap();
+
+        // Wait for the reader to see the file and suspend the sync
+        reader_rx.recv().await;
+
+        // Modify the file while the reader is suspended
+        file.truncate(0).unwrap();
+        file.write_all(b"second").await.unwrap();
+        file.flush().await.unwrap();
+
+        writer_tx.send(()).await.unwrap();
+
+        // Wait until reader is done
+        reader_rx.recv().await;
+    });
+
+    env.actor("reader", async move {
+        let (network, repo, reg) = actor::setup().await;
+
+        let peer_addr = actor::lookup_addr("writer").await;
+        network.add_user_provided_peer(&peer_addr);
+
+        common::expect_file_content(&repo, "test.txt", b"first").await;
+
+        reg.suspend();
+        assert!(reg.is_suspended());
+        reader_tx.send(()).await.unwrap();
+
+        writer_rx.recv().await;
+
+        // The already downloaded content is still readable but the update is not synced.
+        sleep(Duration::from_secs(1)).await;
+        common::expect_file_content(&repo, "test.txt", b"first").await;
+
+        reg.resume();
+        assert!(!reg.is_suspended());
+
+        common::expect_file_content(&repo, "test.txt", b"second").await;
+
+        reader_tx.send(()).await.unwrap();
+    });
+}
+
+#[test]
+fn peer_sync_states() {
+    let mut env = Env::new();
+    let (tx, mut rx) = mpsc::channel(1);
+
+    env.actor("writer", async move {
+        let (_network, repo, _reg) = actor::setup().await;
+
+        let mut file = repo.create_file("test.txt").await.unwrap();
+        file.write_all(&common::random_bytes(2 * BLOCK_SIZE))
+            .await
+            .unwrap();
+        file.flush().await.unwrap();
+
+        rx.recv().await;
+    });
+
+    env.actor("reader", async move {
+        let (network, repo, reg) = actor::setup().await;
+
+        // Not connected to any peer yet.
+        assert!(reg.peer_sync_states().await.is_empty());
+
+        let peer_addr = actor::lookup_addr("writer").await;
+        network.add_user_provided_peer(&peer_addr);
+
+        common::expect_entry_exists(&repo, "test.txt", EntryType::File).await;
+
+        // Eventually we get caught up with the writer.
+        loop {
+            let states = reg.peer_sync_states().await;
+
+            if let [(_, SyncState::InSync)] = states[..] {
+                break;
+            }
+
+            assert_eq!(states.len(), 1);
+            sleep(Duration::from_millis(100)).await;
+        }
+
+        tx.send(()).await.unwrap();
+    });
+}
+
 #[test]
 fn remove_remote_file() {
     let mut env = Env::new();
@@ -314,6 +413,46 @@ fn remove_remote_file() {
     });
 }
 
+#[test]
+fn wait_until_synced() {
+    let mut env = Env::new();
+    let (tx, mut rx) = mpsc::channel(1);
+    let content = Arc::new(common::random_bytes(LARGE_SIZE));
+
+    env.actor("writer", {
+        let content = content.clone();
+
+        async move {
+            let (_network, repo, _reg) = actor::setup().await;
+
+            let mut file = repo.create_file("test.dat").await.unwrap();
+            common::write_in_chunks(&mut file, &content, 4096).await;
+            file.flush().await.unwrap();
+            drop(file);
+
+            rx.recv().await;
+        }
+    });
+
+    env.actor("reader", async move {
+        let (network, repo, _reg) = actor::setup().await;
+        network.add_user_provided_peer(&actor::lookup_addr("writer").await);
+
+        common::expect_entry_exists(&repo, "test.dat", EntryType::File).await;
+
+        time::timeout(*common::TEST_TIMEOUT, repo.wait_until_synced())
+            .await
+            .unwrap()
+            .unwrap();
+
+        // All the blocks are already here so this doesn't need to wait.
+        let mut file = repo.open_file("test.dat").await.unwrap();
+        assert_eq!(file.read_to_end().await.unwrap(), *content);
+
+        tx.send(()).await.unwrap();
+    });
+}
+
 #[test]
 fn relay_write() {
     let file_size = LARGE_SIZE;
@@ -374,6 +513,72 @@ fn relay_case(proto: Proto, file_size: usize, relay_access_mode: AccessMode) {
     });
 }
 
+// The blind replica serves the blocks it stores even after the peer it got them from goes offline.
+// The writer and the reader are never online at the same time.
+#[test]
+fn relay_blind_offline_writer() {
+    let mut env = Env::new();
+    let (block_count_tx, mut block_count_rx) = mpsc::channel(1);
+    let (relay_synced_tx, mut relay_synced_rx) = mpsc::channel(1);
+    let (writer_gone_tx, mut writer_gone_rx) = mpsc::channel(1);
+    let (done_tx, mut done_rx) = mpsc::channel(1);
+
+    let content = Arc::new(common::random_bytes(LARGE_SIZE));
+
+    env.actor("relay", async move {
+        let network = actor::create_network(Proto::Tcp).await;
+        let repo = actor::create_repo_with_mode(DEFAULT_REPO, AccessMode::Blind).await;
+        let _reg = network.register(repo.handle()).await;
+
+        let block_count = block_count_rx.recv().await.unwrap();
+
+        common::eventually(&repo, || async {
+            repo.count_blocks().await.unwrap() == block_count
+        })
+        .await;
+
+        relay_synced_tx.send(()).await.unwrap();
+        done_rx.recv().await.unwrap();
+    });
+
+    env.actor("writer", {
+        let content = content.clone();
+
+        async move {
+            let (network, repo, reg) = actor::setup().await;
+            network.add_user_provided_peer(&actor::lookup_addr("relay").await);
+
+            let mut file = repo.create_file("test.dat").await.unwrap();
+            common::write_in_chunks(&mut file, &content, 4096).await;
+            file.flush().await.unwrap();
+            drop(file);
+
+            block_count_tx
+                .send(repo.count_blocks().await.unwrap())
+                .await
+                .unwrap();
+            relay_synced_rx.recv().await.unwrap();
+
+            drop(reg);
+            drop(network);
+            repo.close().await.unwrap();
+
+            writer_gone_tx.send(()).await.unwrap();
+        }
+    });
+
+    env.actor("reader", async move {
+        writer_gone_rx.recv().await.unwrap();
+
+        let (network, repo, _reg) = actor::setup().await;
+        network.add_user_provided_peer(&actor::lookup_addr("relay").await);
+
+        common::expect_file_content(&repo, "test.dat", &content).await;
+
+        done_tx.send(()).await.unwrap();
+    });
+}
+
 // Test for an edge case where a sync happens while we are in the middle of writing a file.
 // This test makes sure that when the sync happens, the partially written file content is not
 // garbage collected prematurelly.
@@ -1126,6 +1331,7 @@ fn quota_exceed() {
             let repo = actor::create_repo_with_mode(DEFAULT_REPO, AccessMode::Read).await;
             repo.set_quota(Some(quota)).await.unwrap();
 
+            let mut events = repo.subscribe();
             let _reg = network.register(repo.handle()).await;
 
             // The first file is within the quota
@@ -1144,6 +1350,29 @@ fn quota_exceed() {
             let size1 = repo.size().await.unwrap();
             assert_eq!(size1, size0);
 
+            // ... and the rejection is reported
+            let mut quota_exceeded = false;
+            loop {
+                match events.try_recv() {
+                    Ok(Event {
+                        payload:
+                            Payload::QuotaExceeded {
+                                requested,
+                                available,
+                                ..
+                            },
+                        ..
+                    }) => {
+                        assert!(requested > quota);
+                        assert_eq!(available, quota);
+                        quota_exceeded = true;
+                    }
+                    Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => continue,
+                    Err(_) => break,
+                }
+            }
+            assert!(quota_exceeded);
+
             info!("not read 1.dat");
             tx.send(()).await.unwrap();
 
@@ -0,0 +1,57 @@
+//! Checks that the temporary files of the database are created in the directory specified by the
+//! `SQLITE_TMPDIR` environment variable. This is in its own test binary because sqlite reads the
+//! variable only once, so it must be set before any database is opened in the process.
+
+#![cfg(target_os = "linux")]
+
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteConnection},
+    Connection,
+};
+use std::{env, fs, path::PathBuf};
+use tempfile::TempDir;
+
+#[test]
+fn temp_files_are_created_in_sqlite_tmpdir() {
+    let base_dir = TempDir::new().unwrap();
+    let temp_dir = base_dir.path().join("tmp");
+    fs::create_dir(&temp_dir).unwrap();
+
+    // This is the only test in this binary and no other threads are running yet, so setting the
+    // variable here is safe.
+    env::set_var("SQLITE_TMPDIR", &temp_dir);
+
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(async {
+            let options = SqliteConnectOptions::new()
+                .filename(base_dir.path().join("repo.db"))
+                .create_if_missing(true);
+            let mut conn = SqliteConnection::connect_with(&options).await.unwrap();
+
+            // Force the temp table to be spilled into a temporary file.
+            for query in [
+                "PRAGMA temp_store = FILE",
+                "PRAGMA cache_size = 1",
+                "CREATE TEMP TABLE scratch (value BLOB)",
+                "WITH RECURSIVE seq(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM seq WHERE n < 1000)
+                 INSERT INTO scratch SELECT randomblob(1024) FROM seq",
+            ] {
+                sqlx::query(query).execute(&mut conn).await.unwrap();
+            }
+
+            // Sqlite unlinks the temporary files right after creating them, so look for them
+            // among the open file descriptors.
+            assert!(open_files().any(|path| path.starts_with(&temp_dir)));
+
+            conn.close().await.unwrap();
+        });
+}
+
+fn open_files() -> impl Iterator<Item = PathBuf> {
+    fs::read_dir("/proc/self/fd")
+        .unwrap()
+        .filter_map(|entry| fs::read_link(entry.ok()?.path()).ok())
+}
@@ -24,6 +24,39 @@ const MAX_IDLE_TIMEOUT_MS: u32 = 3 * KEEP_ALIVE_INTERVAL_MS + 2_000;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+//------------------------------------------------------------------------------
+/// Configuration of the QUIC transport.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TransportConfig {
+    /// Connection is closed if no packet is received from the peer for this long. Zero disables
+    /// the timeout. Note the peers negotiate the idle timeout and the smaller of the two is used.
+    pub idle_timeout: Duration,
+    /// Interval at which keep-alive packets are sent to prevent the connection from idling out.
+    /// `None` disables the keep-alive. It's only sent on the connections we initiated as only one
+    /// side needs to send it.
+    pub keep_alive_interval: Option<Duration>,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_millis(MAX_IDLE_TIMEOUT_MS.into()),
+            keep_alive_interval: Some(Duration::from_millis(KEEP_ALIVE_INTERVAL_MS.into())),
+        }
+    }
+}
+
+impl TransportConfig {
+    fn max_idle_timeout(&self) -> Option<quinn::IdleTimeout> {
+        if self.idle_timeout.is_zero() {
+            return None;
+        }
+
+        // Timeouts too large to be encoded are treated as infinite.
+        quinn::IdleTimeout::try_from(self.idle_timeout).ok()
+    }
+}
+
 //------------------------------------------------------------------------------
 pub struct Connector {
     endpoint: quinn::Endpoint,
@@ -67,6 +100,10 @@ pub struct Connecting {
 }
 
 impl Connecting {
+    pub fn remote_address(&self) -> SocketAddr {
+        self.connecting.remote_address()
+    }
+
     pub async fn finish(self) -> Result<Connection> {
         let connection = self.connecting.await?;
         let (tx, rx) = connection.accept_bi().await?;
@@ -321,8 +358,11 @@ impl Drop for OwnedWriteHalf {
 }
 
 //------------------------------------------------------------------------------
-pub async fn configure(bind_addr: SocketAddr) -> Result<(Connector, Acceptor, SideChannelMaker)> {
-    let server_config = make_server_config()?;
+pub async fn configure(
+    bind_addr: SocketAddr,
+    transport_config: TransportConfig,
+) -> Result<(Connector, Acceptor, SideChannelMaker)> {
+    let server_config = make_server_config(&transport_config)?;
     let custom_socket = CustomUdpSocket::bind(bind_addr).await?;
     let side_channel_maker = custom_socket.side_channel_maker();
 
@@ -333,7 +373,7 @@ pub async fn configure(bind_addr: SocketAddr) -> Result<(Connector, Acceptor, Si
         Arc::new(quinn::TokioRuntime),
     )?;
 
-    endpoint.set_default_client_config(make_client_config());
+    endpoint.set_default_client_config(make_client_config(&transport_config));
 
     let local_addr = endpoint.local_addr()?;
 
@@ -387,7 +427,7 @@ impl rustls::client::ServerCertVerifier for SkipServerVerification {
     }
 }
 
-fn make_client_config() -> quinn::ClientConfig {
+fn make_client_config(config: &TransportConfig) -> quinn::ClientConfig {
     let crypto = rustls::ClientConfig::builder()
         .with_safe_defaults()
         .with_custom_certificate_verifier(Arc::new(SkipServerVerification {}))
@@ -403,14 +443,14 @@ fn make_client_config() -> quinn::ClientConfig {
         // to be on the client side with the reasoning that the server side has a better chance of
         // being behind a non restrictive NAT, and so that sending the packets from the client side
         // shall assist in hole punching.
-        .keep_alive_interval(Some(Duration::from_millis(KEEP_ALIVE_INTERVAL_MS.into())))
-        .max_idle_timeout(Some(quinn::VarInt::from_u32(MAX_IDLE_TIMEOUT_MS).into()));
+        .keep_alive_interval(config.keep_alive_interval)
+        .max_idle_timeout(config.max_idle_timeout());
 
     client_config.transport_config(Arc::new(transport_config));
     client_config
 }
 
-fn make_server_config() -> Result<quinn::ServerConfig> {
+fn make_server_config(config: &TransportConfig) -> Result<quinn::ServerConfig> {
     // Generate a self signed certificate.
     let cert = rcgen::generate_simple_self_signed(vec![CERT_DOMAIN.into()]).unwrap();
     let cert_der = cert.serialize_der().unwrap();
@@ -424,7 +464,7 @@ fn make_server_config() -> Result<quinn::ServerConfig> {
 
     transport_config
         .max_concurrent_uni_streams(0_u8.into())
-        .max_idle_timeout(Some(quinn::VarInt::from_u32(MAX_IDLE_TIMEOUT_MS).into()));
+        .max_idle_timeout(config.max_idle_timeout());
 
     server_config.transport_config(Arc::new(transport_config));
 
@@ -648,7 +688,9 @@ mod tests {
     #[tokio::test(flavor = "multi_thread")]
     async fn small_data_exchange() {
         let (connector, mut acceptor, _) =
-            configure((Ipv4Addr::LOCALHOST, 0).into()).await.unwrap();
+            configure((Ipv4Addr::LOCALHOST, 0).into(), TransportConfig::default())
+                .await
+                .unwrap();
 
         let addr = *acceptor.local_addr();
 
@@ -674,7 +716,9 @@ mod tests {
     #[tokio::test(flavor = "multi_thread")]
     async fn side_channel() {
         let (_connector, mut acceptor, side_channel_maker) =
-            configure((Ipv4Addr::LOCALHOST, 0).into()).await.unwrap();
+            configure((Ipv4Addr::LOCALHOST, 0).into(), TransportConfig::default())
+                .await
+                .unwrap();
         let addr = *acceptor.local_addr();
         let side_channel = side_channel_maker.make();
 
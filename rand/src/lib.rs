@@ -6,7 +6,9 @@ pub mod prelude {
 
 #[cfg(not(feature = "simulation"))]
 mod implementation {
-    pub use rand::{distributions, random, seq, thread_rng, CryptoRng, Rng, SeedableRng};
+    pub use rand::{
+        distributions, random, seq, thread_rng, CryptoRng, Error, Rng, RngCore, SeedableRng,
+    };
     pub use std::collections::hash_map::{DefaultHasher, RandomState};
 
     pub mod rngs {
@@ -17,7 +19,7 @@ mod implementation {
 #[cfg(feature = "simulation")]
 mod implementation {
     pub use self::rngs::thread::thread_rng;
-    pub use rand::{distributions, seq, CryptoRng, Rng, SeedableRng};
+    pub use rand::{distributions, seq, CryptoRng, Error, Rng, RngCore, SeedableRng};
 
     use self::distributions::{Distribution, Standard};
     use siphasher::sip::SipHasher13;
@@ -282,6 +282,16 @@ impl StateMonitor {
     pub fn subscribe(&self) -> watch::Receiver<()> {
         self.shared.subscribe()
     }
+
+    /// Recursively dumps this monitor and all its descendants into a JSON value of the form
+    /// `{"values": {<name>: <value>, ...}, "children": {<id>: <child>, ...}}`. Values are
+    /// rendered using their `Debug` impl.
+    ///
+    /// Each node is snapshotted while holding only its own lock, so concurrent updates are safe
+    /// but the resulting tree is not necessarily consistent across different nodes.
+    pub fn to_json(&self) -> serde_json::Value {
+        self.shared.to_json()
+    }
 }
 
 impl Clone for StateMonitor {
@@ -360,6 +370,45 @@ impl StateMonitorShared {
         child.and_then(|child| child.locate(path))
     }
 
+    fn to_json(&self) -> serde_json::Value {
+        let (values, children) = {
+            let lock = self.lock_inner();
+
+            let values: serde_json::Map<_, _> = lock
+                .values
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.clone(),
+                        format!("{:?}", &*value.ptr.lock().unwrap()).into(),
+                    )
+                })
+                .collect();
+
+            // Children which are being concurrently dropped might not be upgradable anymore. Skip
+            // those.
+            let children: Vec<_> = lock
+                .children
+                .iter()
+                .filter_map(|(id, entry)| Some((id.to_string(), entry.child.upgrade()?)))
+                .collect();
+
+            (values, children)
+        };
+
+        // Recurse only after the lock is released to avoid locking self and a child at the same
+        // time.
+        let children: serde_json::Map<_, _> = children
+            .into_iter()
+            .map(|(id, child)| (id, child.to_json()))
+            .collect();
+
+        serde_json::json!({
+            "values": values,
+            "children": children,
+        })
+    }
+
     fn subscribe(self: &Arc<Self>) -> watch::Receiver<()> {
         self.lock_inner().on_change.subscribe()
     }
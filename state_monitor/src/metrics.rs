@@ -54,13 +54,13 @@ impl Recorder for MetricsRecorder {
 
     fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
         let unit = self.descriptions.lock().unwrap().get(key.name()).copied();
-        let value = make_value_recursive(&self.parent_node, key.name(), Formatted(0u64, unit));
+        let value = make_value_recursive(&self.parent_node, &key_path(key), Formatted(0u64, unit));
         Counter::from_arc(Arc::new(value))
     }
 
     fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
         let unit = self.descriptions.lock().unwrap().get(key.name()).copied();
-        let value = make_value_recursive(&self.parent_node, key.name(), Formatted(0.0, unit));
+        let value = make_value_recursive(&self.parent_node, &key_path(key), Formatted(0.0, unit));
 
         Gauge::from_arc(Arc::new(value))
     }
@@ -68,7 +68,7 @@ impl Recorder for MetricsRecorder {
     fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
         let unit = self.descriptions.lock().unwrap().get(key.name()).copied();
         let node = HistogramNode::new(
-            make_node_recursive(&self.parent_node, key.name()).into_owned(),
+            make_node_recursive(&self.parent_node, &key_path(key)).into_owned(),
             unit,
         );
 
@@ -76,6 +76,25 @@ impl Recorder for MetricsRecorder {
     }
 }
 
+// Labels are mapped to extra path components (`name/label=value`) so that metrics which differ
+// only in their labels don't end up in the same node.
+fn key_path(key: &Key) -> Cow<'_, str> {
+    if key.labels().len() == 0 {
+        return Cow::Borrowed(key.name());
+    }
+
+    let mut path = key.name().to_owned();
+
+    for label in key.labels() {
+        path.push('/');
+        path.push_str(label.key());
+        path.push('=');
+        path.push_str(label.value());
+    }
+
+    Cow::Owned(path)
+}
+
 fn make_node_recursive<'a>(root: &'a StateMonitor, path: &'_ str) -> Cow<'a, StateMonitor> {
     path.split('/')
         .fold(Cow::Borrowed(root), |parent, component| {
@@ -248,7 +267,7 @@ impl Human for f64 {
 mod tests {
     use super::*;
     use crate::{MonitorId, StateMonitor, ValueError};
-    use metrics::Level;
+    use metrics::{Label, Level};
 
     #[tokio::test]
     async fn counter() {
@@ -320,6 +339,42 @@ mod tests {
         assert_eq!(parent.get_value("max"), Ok(Formatted(2.0, None)));
     }
 
+    #[tokio::test]
+    async fn histogram_with_labels() {
+        let root = StateMonitor::make_root();
+        let recorder = MetricsRecorder::new(root.clone());
+        let metadata = Metadata::new(module_path!(), Level::INFO, None);
+
+        let histogram_a = recorder.register_histogram(
+            &Key::from_parts("test_histogram", vec![Label::new("peer", "a")]),
+            &metadata,
+        );
+        let histogram_b = recorder.register_histogram(
+            &Key::from_parts("test_histogram", vec![Label::new("peer", "b")]),
+            &metadata,
+        );
+
+        histogram_a.record(1.0);
+        histogram_b.record(2.0);
+        histogram_b.record(3.0);
+
+        let parent_a = root
+            .locate([
+                MonitorId::new("test_histogram".to_string(), 0),
+                MonitorId::new("peer=a".to_string(), 0),
+            ])
+            .unwrap();
+        let parent_b = root
+            .locate([
+                MonitorId::new("test_histogram".to_string(), 0),
+                MonitorId::new("peer=b".to_string(), 0),
+            ])
+            .unwrap();
+
+        assert_eq!(parent_a.get_value("count"), Ok(1u64));
+        assert_eq!(parent_b.get_value("count"), Ok(2u64));
+    }
+
     #[tokio::test]
     async fn description() {
         let root = StateMonitor::make_root();
@@ -56,6 +56,34 @@ fn serialize_in_insertion_order() {
     }
 }
 
+#[test]
+fn to_json_recursive() {
+    let root = StateMonitor::make_root();
+    let _a = root.make_value("a", 1);
+    let child = root.make_child("foo");
+    let _b = child.make_value("b", "hello");
+    let grandchild = child.make_non_unique_child("bar", 1);
+    let _c = grandchild.make_value("c", Some(2));
+
+    assert_eq!(
+        root.to_json(),
+        serde_json::json!({
+            "values": { "a": "1" },
+            "children": {
+                "foo:0": {
+                    "values": { "b": "\"hello\"" },
+                    "children": {
+                        "bar:1": {
+                            "values": { "c": "Some(2)" },
+                            "children": {},
+                        },
+                    },
+                },
+            },
+        })
+    );
+}
+
 #[test]
 fn test_parse_monitor_id() {
     let id: MonitorId = "foo".parse().unwrap();
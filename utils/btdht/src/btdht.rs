@@ -47,7 +47,7 @@ async fn main() -> io::Result<()> {
     env_logger::init();
 
     let info_hash = if let Some(token) = &options.token {
-        Some(network::repository_info_hash(token.id()))
+        Some(network::repository_info_hash(token.id(), None))
     } else {
         options
             .swarm_name
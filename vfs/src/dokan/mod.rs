@@ -1118,9 +1118,13 @@ impl From<Error> for i32 {
                     E::InvalidArgument | E::OffsetOutOfRange => STATUS_INVALID_PARAMETER,
                     E::DirectoryNotEmpty => STATUS_DIRECTORY_NOT_EMPTY,
                     E::OperationNotSupported => STATUS_NOT_IMPLEMENTED,
-                    E::Writer(_) => STATUS_IO_DEVICE_ERROR,
+                    E::PathTooDeep => STATUS_NAME_TOO_LONG,
+                    E::Writer(_) | E::Reader(_) | E::Io(_) => STATUS_IO_DEVICE_ERROR,
                     E::StorageVersionMismatch => STATUS_IO_DEVICE_ERROR,
+                    E::KdfParamsMismatch => STATUS_INVALID_PARAMETER,
                     E::Locked => STATUS_LOCK_NOT_GRANTED,
+                    E::Busy | E::TooManySnapshots => STATUS_DEVICE_BUSY,
+                    E::FileTooLarge => STATUS_FILE_TOO_LARGE,
                 }
             }
         }
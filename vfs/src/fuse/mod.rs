@@ -307,12 +307,12 @@ impl fuser::Filesystem for VirtualFilesystem {
         handle: FileHandle,
         flags: i32,
         _lock_owner: Option<u64>,
-        flush: bool,
+        _flush: bool,
         reply: ReplyEmpty,
     ) {
         try_request!(
             self.rt
-                .block_on(self.inner.release(inode, handle, flags.into(), flush)),
+                .block_on(self.inner.release(inode, handle, flags.into())),
             reply
         );
         reply.ok()
@@ -733,21 +733,15 @@ impl Inner {
     }
 
     #[instrument(skip(self, inode, flags), fields(path, ?flags), err(Debug))]
-    async fn release(
-        &mut self,
-        inode: Inode,
-        handle: FileHandle,
-        flags: OpenFlags,
-        flush: bool,
-    ) -> Result<()> {
+    async fn release(&mut self, inode: Inode, handle: FileHandle, flags: OpenFlags) -> Result<()> {
         self.record_path(inode, None);
 
         // TODO: what about `flags`?
         let file = self.entries.get_file_mut(handle)?;
 
-        if flush {
-            file.flush().await?;
-        }
+        // Write back any pending modifications regardless of whether the kernel asked for a flush,
+        // otherwise they would be lost when the handle is removed. No-op if the file is clean.
+        file.flush().await?;
 
         self.entries.remove(handle);
 
@@ -969,7 +963,10 @@ fn to_error_code(error: &Error) -> libc::c_int {
         | Error::MalformedData
         | Error::MalformedDirectory
         | Error::Writer(_)
-        | Error::StorageVersionMismatch => libc::EIO,
+        | Error::Reader(_)
+        | Error::Io(_)
+        | Error::StorageVersionMismatch
+        | Error::KdfParamsMismatch => libc::EIO,
         Error::EntryNotFound | Error::AmbiguousEntry => libc::ENOENT,
         Error::EntryExists => libc::EEXIST,
         Error::EntryIsFile => libc::ENOTDIR,
@@ -979,7 +976,9 @@ fn to_error_code(error: &Error) -> libc::c_int {
         Error::PermissionDenied => libc::EACCES,
         Error::DirectoryNotEmpty => libc::ENOTEMPTY,
         Error::OperationNotSupported => libc::ENOTSUP,
-        Error::Locked => libc::EBUSY,
+        Error::PathTooDeep => libc::ENAMETOOLONG,
+        Error::Locked | Error::Busy | Error::TooManySnapshots => libc::EBUSY,
+        Error::FileTooLarge => libc::EFBIG,
     }
 }
 